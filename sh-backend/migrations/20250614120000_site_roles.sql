@@ -0,0 +1,22 @@
+-- Table: AppUser
+CREATE TABLE IF NOT EXISTS AppUser (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    is_admin BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Table: SiteRole
+CREATE TABLE IF NOT EXISTS SiteRole (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL,
+    site_id INTEGER NOT NULL,
+    role TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (user_id, site_id),
+    FOREIGN KEY (user_id) REFERENCES AppUser(id) ON DELETE CASCADE,
+    FOREIGN KEY (site_id) REFERENCES Site(id) ON DELETE CASCADE
+);
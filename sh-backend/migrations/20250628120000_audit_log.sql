@@ -0,0 +1,31 @@
+-- Table: AuditLog
+-- Deliberately without foreign keys, entries have to outlive the entities they describe.
+CREATE TABLE IF NOT EXISTS AuditLog (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    site_id INTEGER,
+    actor_type TEXT NOT NULL,
+    actor_id INTEGER,
+    actor_name TEXT,
+    operation TEXT NOT NULL,
+    arguments TEXT NOT NULL,
+    entity_type TEXT NOT NULL,
+    entity_id INTEGER,
+    before TEXT,
+    after TEXT,
+    timestamp DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_site_timestamp ON AuditLog (site_id, timestamp);
+CREATE INDEX IF NOT EXISTS idx_audit_log_entity ON AuditLog (entity_type, entity_id);
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_update
+BEFORE UPDATE ON AuditLog
+BEGIN
+    SELECT RAISE(ABORT, 'AuditLog is append-only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_delete
+BEFORE DELETE ON AuditLog
+BEGIN
+    SELECT RAISE(ABORT, 'AuditLog is append-only');
+END;
@@ -0,0 +1,4 @@
+ALTER TABLE ControlSetpoint ADD COLUMN mode TEXT NOT NULL DEFAULT 'Scheduled';
+ALTER TABLE ControlSetpoint ADD COLUMN expires_at DATETIME;
+
+CREATE INDEX IF NOT EXISTS idx_control_setpoint_device_timestamp ON ControlSetpoint (device_id, timestamp);
@@ -0,0 +1,45 @@
+-- Table: AutomationRule
+-- Trigger, conditions and actions are stored as JSON, see `automation.rs` for their shape.
+CREATE TABLE IF NOT EXISTS AutomationRule (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    site_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    trigger TEXT NOT NULL,
+    conditions TEXT NOT NULL DEFAULT '[]',
+    actions TEXT NOT NULL,
+    last_triggered_at DATETIME,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (site_id) REFERENCES Site(id) ON DELETE CASCADE
+);
+
+-- Table: RuleExecution
+CREATE TABLE IF NOT EXISTS RuleExecution (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    rule_id INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    event TEXT NOT NULL,
+    message TEXT,
+    timestamp DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (rule_id) REFERENCES AutomationRule(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_rule_execution_rule_timestamp ON RuleExecution (rule_id, timestamp);
+
+-- Table: Alert
+CREATE TABLE IF NOT EXISTS Alert (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    site_id INTEGER NOT NULL,
+    rule_id INTEGER,
+    severity TEXT NOT NULL,
+    message TEXT NOT NULL,
+    raised_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    acknowledged_at DATETIME,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (site_id) REFERENCES Site(id) ON DELETE CASCADE,
+    FOREIGN KEY (rule_id) REFERENCES AutomationRule(id) ON DELETE SET NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_alert_site_raised_at ON Alert (site_id, raised_at);
@@ -0,0 +1,33 @@
+-- Table: WebhookSubscription
+-- Subscriptions without a site receive the events of every site.
+CREATE TABLE IF NOT EXISTS WebhookSubscription (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    site_id INTEGER,
+    url TEXT NOT NULL,
+    event_types TEXT NOT NULL,
+    secret TEXT NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (site_id) REFERENCES Site(id) ON DELETE CASCADE
+);
+
+-- Table: WebhookDelivery
+CREATE TABLE IF NOT EXISTS WebhookDelivery (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    subscription_id INTEGER NOT NULL,
+    event_type TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'Pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at DATETIME,
+    last_response_status INTEGER,
+    last_error TEXT,
+    delivered_at DATETIME,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (subscription_id) REFERENCES WebhookSubscription(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_delivery_due ON WebhookDelivery (status, next_attempt_at);
+CREATE INDEX IF NOT EXISTS idx_webhook_delivery_subscription ON WebhookDelivery (subscription_id, created_at);
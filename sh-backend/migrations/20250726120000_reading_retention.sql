@@ -0,0 +1,48 @@
+-- Table: RetentionPolicy
+-- Policies without a site apply to every site, policies without a device type to every device type.
+-- The most specific policy matching a device wins.
+CREATE TABLE IF NOT EXISTS RetentionPolicy (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    site_id INTEGER,
+    device_type TEXT,
+    raw_retention_days INTEGER NOT NULL,
+    hourly_retention_days INTEGER,
+    daily_retention_days INTEGER,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (site_id) REFERENCES Site(id) ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_retention_policy_scope
+    ON RetentionPolicy (COALESCE(site_id, 0), COALESCE(device_type, ''));
+
+-- Table: SensorReadingRollup
+-- Hourly and daily summaries of raw readings that were rolled up before being deleted.
+CREATE TABLE IF NOT EXISTS SensorReadingRollup (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    device_id INTEGER NOT NULL,
+    resolution TEXT NOT NULL,
+    bucket_start DATETIME NOT NULL,
+    unit TEXT,
+    sample_count INTEGER NOT NULL,
+    min_value REAL NOT NULL,
+    max_value REAL NOT NULL,
+    sum_value REAL NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (device_id) REFERENCES Device(id) ON DELETE CASCADE,
+    UNIQUE (device_id, resolution, bucket_start)
+);
+
+-- Table: ReadingRetentionState
+-- How far the raw readings and hourly rollups of a device have been pruned,
+-- so reads know which resolution still covers a point in time.
+CREATE TABLE IF NOT EXISTS ReadingRetentionState (
+    device_id INTEGER PRIMARY KEY,
+    raw_pruned_before DATETIME,
+    hourly_pruned_before DATETIME,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (device_id) REFERENCES Device(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_sensor_reading_device_timestamp ON SensorReading (device_id, timestamp);
@@ -0,0 +1,49 @@
+-- Table: Building
+CREATE TABLE IF NOT EXISTS Building (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    site_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (site_id) REFERENCES Site(id) ON DELETE CASCADE
+);
+
+-- Table: Floor
+-- The level orders the floors of a building, 0 for the ground floor and negative below it.
+CREATE TABLE IF NOT EXISTS Floor (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    building_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    level INTEGER NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (building_id) REFERENCES Building(id) ON DELETE CASCADE
+);
+
+-- Table: Zone
+-- Zones, such as wings or HVAC zones, group rooms of a site regardless of their floor.
+CREATE TABLE IF NOT EXISTS Zone (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    site_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (site_id) REFERENCES Site(id) ON DELETE CASCADE
+);
+
+-- Table: RoomZone
+CREATE TABLE IF NOT EXISTS RoomZone (
+    room_id INTEGER NOT NULL,
+    zone_id INTEGER NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (room_id, zone_id),
+    FOREIGN KEY (room_id) REFERENCES Room(id) ON DELETE CASCADE,
+    FOREIGN KEY (zone_id) REFERENCES Zone(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_room_zone_zone ON RoomZone (zone_id);
+
+-- Rooms created before floors existed keep no floor.
+ALTER TABLE Room ADD COLUMN floor_id INTEGER REFERENCES Floor(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_room_floor ON Room (floor_id);
@@ -0,0 +1,46 @@
+-- Free-form metadata, such as installation notes or vendor details, as a JSON object.
+ALTER TABLE Site ADD COLUMN metadata TEXT NOT NULL DEFAULT '{}';
+ALTER TABLE Room ADD COLUMN metadata TEXT NOT NULL DEFAULT '{}';
+ALTER TABLE Device ADD COLUMN metadata TEXT NOT NULL DEFAULT '{}';
+
+-- Tags are keys with an optional value, such as "north-facing" or "vendor" = "Danfoss",
+-- and an entity holds each key at most once.
+
+-- Table: SiteTag
+CREATE TABLE IF NOT EXISTS SiteTag (
+    site_id INTEGER NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (site_id, key),
+    FOREIGN KEY (site_id) REFERENCES Site(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_site_tag_key ON SiteTag (key, value);
+
+-- Table: RoomTag
+CREATE TABLE IF NOT EXISTS RoomTag (
+    room_id INTEGER NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (room_id, key),
+    FOREIGN KEY (room_id) REFERENCES Room(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_room_tag_key ON RoomTag (key, value);
+
+-- Table: DeviceTag
+CREATE TABLE IF NOT EXISTS DeviceTag (
+    device_id INTEGER NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (device_id, key),
+    FOREIGN KEY (device_id) REFERENCES Device(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_device_tag_key ON DeviceTag (key, value);
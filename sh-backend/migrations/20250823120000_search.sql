@@ -0,0 +1,88 @@
+-- Table: SearchIndex
+-- A full-text index of the names of sites, rooms and devices, kept up to date by the triggers below.
+-- The details hold a site's address and a device's unique identifier.
+-- Diacritics are folded, so that "Goteborg" finds "Göteborg".
+CREATE VIRTUAL TABLE IF NOT EXISTS SearchIndex USING fts5(
+    entity_type UNINDEXED,
+    entity_id UNINDEXED,
+    site_id UNINDEXED,
+    name,
+    details,
+    tokenize = 'unicode61 remove_diacritics 2',
+    prefix = '2 3'
+);
+
+INSERT INTO SearchIndex (entity_type, entity_id, site_id, name, details)
+SELECT 'Site', id, id, name, COALESCE(address, '') FROM Site;
+
+INSERT INTO SearchIndex (entity_type, entity_id, site_id, name, details)
+SELECT 'Room', id, site_id, name, '' FROM Room;
+
+INSERT INTO SearchIndex (entity_type, entity_id, site_id, name, details)
+SELECT 'Device', Device.id, Room.site_id, Device.name, COALESCE(Device.unique_identifier, '')
+FROM Device
+JOIN Room ON Room.id = Device.room_id;
+
+CREATE TRIGGER IF NOT EXISTS search_index_site_insert
+AFTER INSERT ON Site
+BEGIN
+    INSERT INTO SearchIndex (entity_type, entity_id, site_id, name, details)
+    VALUES ('Site', NEW.id, NEW.id, NEW.name, COALESCE(NEW.address, ''));
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_index_site_update
+AFTER UPDATE OF name, address ON Site
+BEGIN
+    UPDATE SearchIndex SET name = NEW.name, details = COALESCE(NEW.address, '')
+    WHERE entity_type = 'Site' AND entity_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_index_site_delete
+AFTER DELETE ON Site
+BEGIN
+    DELETE FROM SearchIndex WHERE entity_type = 'Site' AND entity_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_index_room_insert
+AFTER INSERT ON Room
+BEGIN
+    INSERT INTO SearchIndex (entity_type, entity_id, site_id, name, details)
+    VALUES ('Room', NEW.id, NEW.site_id, NEW.name, '');
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_index_room_update
+AFTER UPDATE OF name ON Room
+BEGIN
+    UPDATE SearchIndex SET name = NEW.name
+    WHERE entity_type = 'Room' AND entity_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_index_room_delete
+AFTER DELETE ON Room
+BEGIN
+    DELETE FROM SearchIndex WHERE entity_type = 'Room' AND entity_id = OLD.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_index_device_insert
+AFTER INSERT ON Device
+BEGIN
+    INSERT INTO SearchIndex (entity_type, entity_id, site_id, name, details)
+    SELECT 'Device', NEW.id, site_id, NEW.name, COALESCE(NEW.unique_identifier, '')
+    FROM Room WHERE id = NEW.room_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_index_device_update
+AFTER UPDATE OF room_id, name, unique_identifier ON Device
+BEGIN
+    UPDATE SearchIndex
+    SET site_id = (SELECT site_id FROM Room WHERE id = NEW.room_id),
+        name = NEW.name,
+        details = COALESCE(NEW.unique_identifier, '')
+    WHERE entity_type = 'Device' AND entity_id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS search_index_device_delete
+AFTER DELETE ON Device
+BEGIN
+    DELETE FROM SearchIndex WHERE entity_type = 'Device' AND entity_id = OLD.id;
+END;
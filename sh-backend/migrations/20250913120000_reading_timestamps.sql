@@ -0,0 +1,6 @@
+-- Readings recorded with an explicit time, such as imported ones, were stored as RFC 3339 (`2025-01-01T10:00:00.5+00:00`)
+-- while the others hold `CURRENT_TIMESTAMP` text (`2025-01-01 10:00:00`), which sort differently as text.
+-- They are now all written in the latter format, keeping the fraction of the second, and so are the existing ones.
+UPDATE SensorReading
+SET timestamp = replace(substr(timestamp, 1, length(timestamp) - 6), 'T', ' ')
+WHERE timestamp LIKE '____-__-__T%+00:00';
@@ -0,0 +1,68 @@
+-- The sites, rooms, devices, readings and setpoints of the SQLite schema, as they stand after its setpoint modes migration.
+
+CREATE TYPE DeviceType AS ENUM ('TemperatureSensor', 'ThermostatController');
+CREATE TYPE SensorUnit AS ENUM ('Celsius', 'Fahrenheit');
+CREATE TYPE SetpointType AS ENUM ('Temperature');
+CREATE TYPE SetpointUnit AS ENUM ('Celsius', 'Fahrenheit');
+CREATE TYPE SetpointMode AS ENUM ('Scheduled', 'Override', 'Hold');
+
+-- Table: Site
+CREATE TABLE IF NOT EXISTS Site (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    name TEXT NOT NULL,
+    address TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Table: Room
+CREATE TABLE IF NOT EXISTS Room (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    site_id BIGINT NOT NULL REFERENCES Site(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Table: Device
+-- Devices are deleted with their room, room_id cannot be set to NULL.
+CREATE TABLE IF NOT EXISTS Device (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    room_id BIGINT NOT NULL REFERENCES Room(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    device_type DeviceType NOT NULL,
+    unique_identifier TEXT UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Table: SensorReading
+-- The timestamp is part of the primary key, as TimescaleDB requires of hypertables.
+CREATE TABLE IF NOT EXISTS SensorReading (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY,
+    device_id BIGINT NOT NULL REFERENCES Device(id) ON DELETE CASCADE,
+    value TEXT NOT NULL,
+    unit SensorUnit,
+    timestamp TIMESTAMPTZ NOT NULL DEFAULT now(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (id, timestamp)
+);
+
+CREATE INDEX IF NOT EXISTS idx_sensor_reading_device_timestamp ON SensorReading (device_id, timestamp DESC);
+
+-- Table: ControlSetpoint
+CREATE TABLE IF NOT EXISTS ControlSetpoint (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    device_id BIGINT NOT NULL REFERENCES Device(id) ON DELETE CASCADE,
+    setpoint_type SetpointType NOT NULL,
+    value TEXT NOT NULL,
+    unit SetpointUnit,
+    mode SetpointMode NOT NULL DEFAULT 'Scheduled',
+    expires_at TIMESTAMPTZ,
+    timestamp TIMESTAMPTZ NOT NULL DEFAULT now(),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_control_setpoint_device_timestamp ON ControlSetpoint (device_id, timestamp);
@@ -0,0 +1,12 @@
+-- Turns SensorReading into a hypertable where TimescaleDB is installed, and leaves it a plain table elsewhere.
+-- Time range queries on readings then only scan the chunks covering the range.
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM pg_available_extensions WHERE name = 'timescaledb') THEN
+        CREATE EXTENSION IF NOT EXISTS timescaledb;
+        PERFORM create_hypertable('SensorReading', 'timestamp', migrate_data => true, if_not_exists => true);
+    ELSE
+        RAISE NOTICE 'TimescaleDB is not available, SensorReading stays a plain table';
+    END IF;
+END
+$$;
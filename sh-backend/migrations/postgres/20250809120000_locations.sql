@@ -0,0 +1,46 @@
+-- The buildings, floors and zones of the SQLite locations migration.
+
+-- Table: Building
+CREATE TABLE IF NOT EXISTS Building (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    site_id BIGINT NOT NULL REFERENCES Site(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Table: Floor
+-- The level orders the floors of a building, 0 for the ground floor and negative below it.
+CREATE TABLE IF NOT EXISTS Floor (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    building_id BIGINT NOT NULL REFERENCES Building(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    level BIGINT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Table: Zone
+-- Zones, such as wings or HVAC zones, group rooms of a site regardless of their floor.
+CREATE TABLE IF NOT EXISTS Zone (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    site_id BIGINT NOT NULL REFERENCES Site(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Table: RoomZone
+CREATE TABLE IF NOT EXISTS RoomZone (
+    room_id BIGINT NOT NULL REFERENCES Room(id) ON DELETE CASCADE,
+    zone_id BIGINT NOT NULL REFERENCES Zone(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (room_id, zone_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_room_zone_zone ON RoomZone (zone_id);
+
+-- Rooms created before floors existed keep no floor.
+ALTER TABLE Room ADD COLUMN IF NOT EXISTS floor_id BIGINT REFERENCES Floor(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_room_floor ON Room (floor_id);
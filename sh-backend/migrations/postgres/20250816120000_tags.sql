@@ -0,0 +1,45 @@
+-- The tags and metadata of the SQLite tags migration.
+
+-- Free-form metadata, such as installation notes or vendor details, as a JSON object.
+ALTER TABLE Site ADD COLUMN IF NOT EXISTS metadata JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE Room ADD COLUMN IF NOT EXISTS metadata JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE Device ADD COLUMN IF NOT EXISTS metadata JSONB NOT NULL DEFAULT '{}';
+
+-- Tags are keys with an optional value, such as "north-facing" or "vendor" = "Danfoss",
+-- and an entity holds each key at most once.
+
+-- Table: SiteTag
+CREATE TABLE IF NOT EXISTS SiteTag (
+    site_id BIGINT NOT NULL REFERENCES Site(id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (site_id, key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_site_tag_key ON SiteTag (key, value);
+
+-- Table: RoomTag
+CREATE TABLE IF NOT EXISTS RoomTag (
+    room_id BIGINT NOT NULL REFERENCES Room(id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (room_id, key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_room_tag_key ON RoomTag (key, value);
+
+-- Table: DeviceTag
+CREATE TABLE IF NOT EXISTS DeviceTag (
+    device_id BIGINT NOT NULL REFERENCES Device(id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (device_id, key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_device_tag_key ON DeviceTag (key, value);
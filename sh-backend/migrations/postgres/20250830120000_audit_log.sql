@@ -0,0 +1,43 @@
+-- The audit log of the SQLite audit log migration.
+
+CREATE TYPE AuditActorType AS ENUM ('Anonymous', 'User', 'Device', 'Automation');
+CREATE TYPE AuditEntityType AS ENUM (
+    'Site', 'Room', 'Building', 'Floor', 'Zone', 'Device', 'SensorReading', 'ControlSetpoint',
+    'User', 'SiteRole', 'DeviceCredential', 'AutomationRule', 'Alert', 'WebhookSubscription', 'RetentionPolicy'
+);
+
+-- Table: AuditLog
+-- Deliberately without foreign keys, entries have to outlive the entities they describe.
+-- The arguments and snapshots are JSON kept as written, like on SQLite.
+CREATE TABLE IF NOT EXISTS AuditLog (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    site_id BIGINT,
+    actor_type AuditActorType NOT NULL,
+    actor_id BIGINT,
+    actor_name TEXT,
+    operation TEXT NOT NULL,
+    arguments TEXT NOT NULL,
+    entity_type AuditEntityType NOT NULL,
+    entity_id BIGINT,
+    before TEXT,
+    after TEXT,
+    timestamp TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_site_timestamp ON AuditLog (site_id, timestamp);
+CREATE INDEX IF NOT EXISTS idx_audit_log_entity ON AuditLog (entity_type, entity_id);
+
+CREATE OR REPLACE FUNCTION audit_log_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'AuditLog is append-only';
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER audit_log_no_change
+BEFORE UPDATE OR DELETE ON AuditLog
+FOR EACH ROW EXECUTE FUNCTION audit_log_append_only();
+
+-- Truncating skips row triggers, so it is refused separately.
+CREATE TRIGGER audit_log_no_truncate
+BEFORE TRUNCATE ON AuditLog
+FOR EACH STATEMENT EXECUTE FUNCTION audit_log_append_only();
@@ -0,0 +1,24 @@
+-- The users and site roles of the SQLite site roles migration.
+
+CREATE TYPE Role AS ENUM ('Viewer', 'Operator', 'Admin');
+
+-- Table: AppUser
+CREATE TABLE IF NOT EXISTS AppUser (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    is_admin BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Table: SiteRole
+CREATE TABLE IF NOT EXISTS SiteRole (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES AppUser(id) ON DELETE CASCADE,
+    site_id BIGINT NOT NULL REFERENCES Site(id) ON DELETE CASCADE,
+    role Role NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (user_id, site_id)
+);
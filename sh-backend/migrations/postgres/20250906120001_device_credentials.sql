@@ -0,0 +1,12 @@
+-- The device credentials of the SQLite device credentials migration.
+
+-- Table: DeviceCredential
+CREATE TABLE IF NOT EXISTS DeviceCredential (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    device_id BIGINT NOT NULL REFERENCES Device(id) ON DELETE CASCADE,
+    key_prefix TEXT NOT NULL,
+    key_hash TEXT NOT NULL UNIQUE,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,47 @@
+-- The automation rules, their executions and alerts of the SQLite automation rules migration.
+
+CREATE TYPE RuleExecutionStatus AS ENUM ('Succeeded', 'Skipped', 'Failed');
+CREATE TYPE AlertSeverity AS ENUM ('Info', 'Warning', 'Critical');
+
+-- Table: AutomationRule
+-- Trigger, conditions and actions are stored as JSON, see `automation.rs` for their shape.
+CREATE TABLE IF NOT EXISTS AutomationRule (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    site_id BIGINT NOT NULL REFERENCES Site(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    trigger JSONB NOT NULL,
+    conditions JSONB NOT NULL DEFAULT '[]',
+    actions JSONB NOT NULL,
+    last_triggered_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Table: RuleExecution
+-- The triggering event is JSON kept as written, like on SQLite.
+CREATE TABLE IF NOT EXISTS RuleExecution (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    rule_id BIGINT NOT NULL REFERENCES AutomationRule(id) ON DELETE CASCADE,
+    status RuleExecutionStatus NOT NULL,
+    event TEXT NOT NULL,
+    message TEXT,
+    timestamp TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_rule_execution_rule_timestamp ON RuleExecution (rule_id, timestamp);
+
+-- Table: Alert
+CREATE TABLE IF NOT EXISTS Alert (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    site_id BIGINT NOT NULL REFERENCES Site(id) ON DELETE CASCADE,
+    rule_id BIGINT REFERENCES AutomationRule(id) ON DELETE SET NULL,
+    severity AlertSeverity NOT NULL,
+    message TEXT NOT NULL,
+    raised_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    acknowledged_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_alert_site_raised_at ON Alert (site_id, raised_at);
@@ -0,0 +1,37 @@
+-- The webhook subscriptions and deliveries of the SQLite webhooks migration.
+
+CREATE TYPE WebhookEventType AS ENUM ('SensorReadingCreated', 'ControlSetpointCreated', 'AlertRaised');
+CREATE TYPE WebhookDeliveryStatus AS ENUM ('Pending', 'Succeeded', 'Failed');
+
+-- Table: WebhookSubscription
+-- Subscriptions without a site receive the events of every site.
+CREATE TABLE IF NOT EXISTS WebhookSubscription (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    site_id BIGINT REFERENCES Site(id) ON DELETE CASCADE,
+    url TEXT NOT NULL,
+    event_types JSONB NOT NULL,
+    secret TEXT NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+-- Table: WebhookDelivery
+-- The payload is the JSON body exactly as it is signed and sent.
+CREATE TABLE IF NOT EXISTS WebhookDelivery (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    subscription_id BIGINT NOT NULL REFERENCES WebhookSubscription(id) ON DELETE CASCADE,
+    event_type WebhookEventType NOT NULL,
+    payload TEXT NOT NULL,
+    status WebhookDeliveryStatus NOT NULL DEFAULT 'Pending',
+    attempts BIGINT NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMPTZ,
+    last_response_status BIGINT,
+    last_error TEXT,
+    delivered_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_delivery_due ON WebhookDelivery (status, next_attempt_at);
+CREATE INDEX IF NOT EXISTS idx_webhook_delivery_subscription ON WebhookDelivery (subscription_id, created_at);
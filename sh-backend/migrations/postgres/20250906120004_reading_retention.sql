@@ -0,0 +1,47 @@
+-- The retention policies and rollups of the SQLite reading retention migration.
+
+CREATE TYPE ReadingResolution AS ENUM ('Raw', 'Hourly', 'Daily');
+
+-- Table: RetentionPolicy
+-- Policies without a site apply to every site, policies without a device type to every device type.
+-- The most specific policy matching a device wins.
+CREATE TABLE IF NOT EXISTS RetentionPolicy (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    site_id BIGINT REFERENCES Site(id) ON DELETE CASCADE,
+    device_type DeviceType,
+    raw_retention_days BIGINT NOT NULL,
+    hourly_retention_days BIGINT,
+    daily_retention_days BIGINT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_retention_policy_scope
+    ON RetentionPolicy (site_id, device_type) NULLS NOT DISTINCT;
+
+-- Table: SensorReadingRollup
+-- Hourly and daily summaries of raw readings that were rolled up before being deleted.
+CREATE TABLE IF NOT EXISTS SensorReadingRollup (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    device_id BIGINT NOT NULL REFERENCES Device(id) ON DELETE CASCADE,
+    resolution ReadingResolution NOT NULL,
+    bucket_start TIMESTAMPTZ NOT NULL,
+    unit SensorUnit,
+    sample_count BIGINT NOT NULL,
+    min_value DOUBLE PRECISION NOT NULL,
+    max_value DOUBLE PRECISION NOT NULL,
+    sum_value DOUBLE PRECISION NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (device_id, resolution, bucket_start)
+);
+
+-- Table: ReadingRetentionState
+-- How far the raw readings and hourly rollups of a device have been pruned,
+-- so reads know which resolution still covers a point in time.
+CREATE TABLE IF NOT EXISTS ReadingRetentionState (
+    device_id BIGINT PRIMARY KEY REFERENCES Device(id) ON DELETE CASCADE,
+    raw_pruned_before TIMESTAMPTZ,
+    hourly_pruned_before TIMESTAMPTZ,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
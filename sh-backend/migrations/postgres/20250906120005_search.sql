@@ -0,0 +1,89 @@
+-- The search index of the SQLite search migration, as a full-text document per site, room and device.
+
+CREATE EXTENSION IF NOT EXISTS unaccent;
+
+CREATE TYPE TaggedEntity AS ENUM ('Site', 'Room', 'Device');
+
+-- Diacritics are folded, so that "Goteborg" finds "Göteborg", and punctuation separates words,
+-- so that "ab:cd:ef-01" is indexed as the words "ab", "cd", "ef" and "01", like the SQLite tokenizer does.
+-- Names weigh A and details D, see `search.rs` for how they are ranked.
+CREATE OR REPLACE FUNCTION search_document(name TEXT, details TEXT) RETURNS tsvector AS $$
+    SELECT setweight(to_tsvector('simple', regexp_replace(unaccent(name), '[[:punct:][:space:]]+', ' ', 'g')), 'A')
+        || setweight(to_tsvector('simple', regexp_replace(unaccent(details), '[[:punct:][:space:]]+', ' ', 'g')), 'D')
+$$ LANGUAGE sql STABLE;
+
+-- Table: SearchIndex
+-- Kept up to date by the triggers below.
+-- The details hold a site's address and a device's unique identifier.
+CREATE TABLE IF NOT EXISTS SearchIndex (
+    entity_type TaggedEntity NOT NULL,
+    entity_id BIGINT NOT NULL,
+    site_id BIGINT NOT NULL,
+    document tsvector NOT NULL,
+    PRIMARY KEY (entity_type, entity_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_search_index_document ON SearchIndex USING GIN (document);
+
+INSERT INTO SearchIndex (entity_type, entity_id, site_id, document)
+SELECT 'Site', id, id, search_document(name, COALESCE(address, '')) FROM Site;
+
+INSERT INTO SearchIndex (entity_type, entity_id, site_id, document)
+SELECT 'Room', id, site_id, search_document(name, '') FROM Room;
+
+INSERT INTO SearchIndex (entity_type, entity_id, site_id, document)
+SELECT 'Device', Device.id, Room.site_id, search_document(Device.name, COALESCE(Device.unique_identifier, ''))
+FROM Device
+JOIN Room ON Room.id = Device.room_id;
+
+CREATE OR REPLACE FUNCTION search_index_site() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        DELETE FROM SearchIndex WHERE entity_type = 'Site' AND entity_id = OLD.id;
+        RETURN OLD;
+    END IF;
+    INSERT INTO SearchIndex (entity_type, entity_id, site_id, document)
+    VALUES ('Site', NEW.id, NEW.id, search_document(NEW.name, COALESCE(NEW.address, '')))
+    ON CONFLICT (entity_type, entity_id) DO UPDATE SET document = excluded.document;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER search_index_site
+AFTER INSERT OR UPDATE OF name, address OR DELETE ON Site
+FOR EACH ROW EXECUTE FUNCTION search_index_site();
+
+CREATE OR REPLACE FUNCTION search_index_room() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        DELETE FROM SearchIndex WHERE entity_type = 'Room' AND entity_id = OLD.id;
+        RETURN OLD;
+    END IF;
+    INSERT INTO SearchIndex (entity_type, entity_id, site_id, document)
+    VALUES ('Room', NEW.id, NEW.site_id, search_document(NEW.name, ''))
+    ON CONFLICT (entity_type, entity_id) DO UPDATE SET document = excluded.document;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER search_index_room
+AFTER INSERT OR UPDATE OF name OR DELETE ON Room
+FOR EACH ROW EXECUTE FUNCTION search_index_room();
+
+CREATE OR REPLACE FUNCTION search_index_device() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        DELETE FROM SearchIndex WHERE entity_type = 'Device' AND entity_id = OLD.id;
+        RETURN OLD;
+    END IF;
+    INSERT INTO SearchIndex (entity_type, entity_id, site_id, document)
+    SELECT 'Device', NEW.id, site_id, search_document(NEW.name, COALESCE(NEW.unique_identifier, ''))
+    FROM Room WHERE id = NEW.room_id
+    ON CONFLICT (entity_type, entity_id) DO UPDATE SET site_id = excluded.site_id, document = excluded.document;
+    RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER search_index_device
+AFTER INSERT OR UPDATE OF room_id, name, unique_identifier OR DELETE ON Device
+FOR EACH ROW EXECUTE FUNCTION search_index_device();
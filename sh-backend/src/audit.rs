@@ -0,0 +1,110 @@
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::auth::Identity;
+use crate::db::{Database, Transaction};
+use crate::models::{AuditActorType, AuditEntityType};
+use crate::with_transaction;
+
+/// A mutation to be appended to the `AuditLog` table,
+/// ideally in the same transaction as the change it describes.
+pub struct AuditRecord {
+    operation: &'static str,
+    arguments: Value,
+    entity_type: AuditEntityType,
+    entity_id: Option<i64>,
+    site_id: Option<i64>,
+    before: Option<Value>,
+    after: Option<Value>,
+}
+
+impl AuditRecord {
+    pub fn new(
+        operation: &'static str,
+        entity_type: AuditEntityType,
+        arguments: &impl Serialize,
+    ) -> Self {
+        Self {
+            operation,
+            arguments: to_json(arguments),
+            entity_type,
+            entity_id: None,
+            site_id: None,
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn entity_id(mut self, entity_id: i64) -> Self {
+        self.entity_id = Some(entity_id);
+        self
+    }
+
+    pub fn site_id(mut self, site_id: i64) -> Self {
+        self.site_id = Some(site_id);
+        self
+    }
+
+    pub fn before(mut self, snapshot: &impl Serialize) -> Self {
+        self.before = Some(to_json(snapshot));
+        self
+    }
+
+    pub fn after(mut self, snapshot: &impl Serialize) -> Self {
+        self.after = Some(to_json(snapshot));
+        self
+    }
+
+    /// Appends the record on its own, for changes made outside of a repository transaction.
+    pub async fn insert(self, database: &Database, actor: &Identity) -> sqlx::Result<()> {
+        let mut transaction = database.begin().await?;
+        self.insert_in(&mut transaction, actor).await?;
+        transaction.commit().await
+    }
+
+    /// Appends the record within the transaction of the change it describes.
+    pub async fn insert_in(
+        self,
+        transaction: &mut Transaction<'_>,
+        actor: &Identity,
+    ) -> sqlx::Result<()> {
+        let (actor_type, actor_id, actor_name) = match actor {
+            Identity::Anonymous => (AuditActorType::Anonymous, None, None),
+            Identity::User(user) => (AuditActorType::User, Some(user.id), Some(user.name.clone())),
+            Identity::Device(credential) => (
+                AuditActorType::Device,
+                Some(credential.device_id),
+                Some(credential.key_prefix.clone()),
+            ),
+            Identity::Automation(rule_id) => (AuditActorType::Automation, Some(*rule_id), None),
+        };
+
+        let sql = r#"
+            INSERT INTO AuditLog (site_id, actor_type, actor_id, actor_name, operation, arguments, entity_type, entity_id, before, after, timestamp)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            "#;
+        with_transaction!(transaction, connection => {
+            sqlx::query(sql)
+                .bind(self.site_id)
+                .bind(actor_type)
+                .bind(actor_id)
+                .bind(&actor_name)
+                .bind(self.operation)
+                .bind(self.arguments.to_string())
+                .bind(self.entity_type)
+                .bind(self.entity_id)
+                .bind(self.before.as_ref().map(|snapshot| snapshot.to_string()))
+                .bind(self.after.as_ref().map(|snapshot| snapshot.to_string()))
+                .bind(Utc::now())
+                .execute(connection)
+                .await?;
+            Ok(())
+        })
+    }
+}
+
+fn to_json(value: &impl Serialize) -> Value {
+    // Models and inputs only hold plain data, so serializing them cannot fail.
+    serde_json::to_value(value).unwrap_or_default()
+}
@@ -0,0 +1,196 @@
+use async_graphql::{Context, ErrorExtensions, FieldError, Result};
+use log::error;
+use rand::Rng;
+use rocket::State;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+use crate::db::{Database, Transaction};
+use crate::models::{DeviceCredential, DeviceCredentialWithKey, Role, User};
+
+/// Device keys carry this prefix, which tells them apart from user tokens.
+pub const DEVICE_KEY_PREFIX: &str = "shd_";
+
+/// The caller behind a request, resolved from the `Authorization: Bearer <token>` header.
+#[derive(Debug, Clone)]
+pub enum Identity {
+    Anonymous,
+    User(User),
+    Device(DeviceCredential),
+    /// An automation rule acting on its own, never resolved from a request.
+    Automation(i64),
+}
+
+static ANONYMOUS: Identity = Identity::Anonymous;
+
+#[derive(Debug)]
+pub enum AuthError {
+    InvalidToken,
+    Database,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Identity {
+    type Error = AuthError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(header) = request.headers().get_one("Authorization") else {
+            return Outcome::Success(Identity::Anonymous);
+        };
+        let Some(token) = header.strip_prefix("Bearer ") else {
+            return Outcome::Error((Status::Unauthorized, AuthError::InvalidToken));
+        };
+        let Outcome::Success(database) = request.guard::<&State<Database>>().await else {
+            return Outcome::Error((Status::InternalServerError, AuthError::Database));
+        };
+
+        let identity = if token.starts_with(DEVICE_KEY_PREFIX) {
+            let device_keys = request
+                .rocket()
+                .state::<Config>()
+                .is_none_or(|config| config.auth.device_keys);
+            if !device_keys {
+                return Outcome::Error((Status::Unauthorized, AuthError::InvalidToken));
+            }
+            find_device_credential_by_key(database, token)
+                .await
+                .map(|credential| credential.map(Identity::Device))
+        } else {
+            find_user_by_token(database, token)
+                .await
+                .map(|user| user.map(Identity::User))
+        };
+
+        match identity {
+            Ok(Some(identity)) => Outcome::Success(identity),
+            Ok(None) => Outcome::Error((Status::Unauthorized, AuthError::InvalidToken)),
+            Err(err) => {
+                error!("Failed to look up identity by token: {}", err);
+                Outcome::Error((Status::InternalServerError, AuthError::Database))
+            }
+        }
+    }
+}
+
+pub fn generate_token() -> String {
+    let bytes: [u8; 32] = rand::rng().random();
+    hex::encode(bytes)
+}
+
+pub fn generate_device_key() -> String {
+    format!("{}{}", DEVICE_KEY_PREFIX, generate_token())
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+pub async fn find_user_by_token(database: &Database, token: &str) -> sqlx::Result<Option<User>> {
+    database
+        .users()
+        .find_by_token_hash(&hash_token(token))
+        .await
+}
+
+pub async fn find_device_credential_by_key(
+    database: &Database,
+    key: &str,
+) -> sqlx::Result<Option<DeviceCredential>> {
+    database
+        .credentials()
+        .find_active_by_key_hash(&hash_token(key))
+        .await
+}
+
+pub async fn insert_device_credential(
+    database: &Database,
+    transaction: &mut Transaction<'_>,
+    device_id: i64,
+) -> sqlx::Result<DeviceCredentialWithKey> {
+    let key = generate_device_key();
+    let credential = database
+        .credentials()
+        .create(
+            transaction,
+            device_id,
+            &key[..DEVICE_KEY_PREFIX.len() + 8],
+            &hash_token(&key),
+        )
+        .await?;
+    Ok(DeviceCredentialWithKey { credential, key })
+}
+
+pub fn unauthenticated() -> FieldError {
+    FieldError::new("Authentication required").extend_with(|_, e| e.set("code", "UNAUTHENTICATED"))
+}
+
+pub fn forbidden(message: impl Into<String>) -> FieldError {
+    FieldError::new(message).extend_with(|_, e| e.set("code", "FORBIDDEN"))
+}
+
+pub fn identity<'a>(ctx: &'a Context<'_>) -> &'a Identity {
+    ctx.data_opt::<Identity>().unwrap_or(&ANONYMOUS)
+}
+
+pub fn require_user<'a>(ctx: &'a Context<'_>) -> Result<&'a User> {
+    match identity(ctx) {
+        Identity::User(user) => Ok(user),
+        Identity::Device(_) => Err(forbidden(
+            "Device keys can only be used to record sensor readings",
+        )),
+        Identity::Automation(_) => Err(forbidden("Automation rules cannot act as users")),
+        Identity::Anonymous => Err(unauthenticated()),
+    }
+}
+
+pub fn require_admin<'a>(ctx: &'a Context<'_>) -> Result<&'a User> {
+    let user = require_user(ctx)?;
+    if !user.is_admin {
+        return Err(forbidden("Administrator rights required"));
+    }
+    Ok(user)
+}
+
+/// The role a user holds on a site, admins implicitly holding `Role::Admin` everywhere.
+pub async fn site_role(database: &Database, user: &User, site_id: i64) -> Result<Option<Role>> {
+    if user.is_admin {
+        return Ok(Some(Role::Admin));
+    }
+    Ok(database.site_roles().role(user.id, site_id).await?)
+}
+
+pub async fn require_site_role(ctx: &Context<'_>, site_id: i64, required: Role) -> Result<()> {
+    let user = require_user(ctx)?;
+    let database = ctx.data::<Database>()?;
+    match site_role(database, user, site_id).await? {
+        Some(role) if role >= required => Ok(()),
+        _ => Err(forbidden(format!(
+            "{:?} rights on site {} required",
+            required, site_id
+        ))),
+    }
+}
+
+/// Readings may be recorded with the device's own key, or by a user with operator rights on its site.
+pub async fn require_ingest_rights(ctx: &Context<'_>, device_id: i64) -> Result<()> {
+    if let Identity::Device(credential) = identity(ctx) {
+        if credential.device_id == device_id {
+            return Ok(());
+        }
+        return Err(forbidden(format!(
+            "Device key does not belong to device {}",
+            device_id
+        )));
+    }
+
+    require_user(ctx)?;
+    let Some(site_id) = ctx.data::<Database>()?.devices().site_id(device_id).await? else {
+        return Err(FieldError::new(format!(
+            "Device with ID {} does not exist",
+            device_id
+        )));
+    };
+    require_site_role(ctx, site_id, Role::Operator).await
+}
@@ -0,0 +1,489 @@
+use std::time::Duration as StdDuration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use log::{debug, error, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+
+use crate::audit::AuditRecord;
+use crate::auth::Identity;
+use crate::db::Database;
+use crate::events::{Event, EventBus};
+use crate::models::{
+    AlertSeverity, AuditEntityType, AutomationRule, ControlSetpointInput, RuleExecutionStatus,
+    SetpointMode, SetpointType, SetpointUnit,
+};
+use crate::webhooks::{check_address, http_client, validate_url};
+
+/// How often time of day triggers are checked, which is also how late they may fire.
+const CLOCK_INTERVAL: StdDuration = StdDuration::from_secs(30);
+const WEBHOOK_TIMEOUT: StdDuration = StdDuration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    Above,
+    AtLeast,
+    Below,
+    AtMost,
+}
+
+impl Comparison {
+    pub fn matches(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Above => value > threshold,
+            Comparison::AtLeast => value >= threshold,
+            Comparison::Below => value < threshold,
+            Comparison::AtMost => value <= threshold,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleTrigger {
+    /// Fires when a reading of the device crosses the threshold,
+    /// that is when it compares to the value while the previous reading did not.
+    SensorThreshold {
+        device_id: i64,
+        comparison: Comparison,
+        value: f64,
+    },
+    /// Fires once a day at the given UTC time.
+    TimeOfDay { time: NaiveTime },
+    /// Fires when someone sets a new setpoint on the device, optionally only for a given mode.
+    /// Setpoints created by rules are ignored, so rules cannot trigger each other in a loop.
+    DeviceState {
+        device_id: i64,
+        #[serde(default)]
+        mode: Option<SetpointMode>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleCondition {
+    /// The latest reading of the device compares to the value.
+    DeviceReading {
+        device_id: i64,
+        comparison: Comparison,
+        value: f64,
+    },
+    /// The average of the latest readings of the devices in the room compares to the value.
+    RoomAverage {
+        room_id: i64,
+        comparison: Comparison,
+        value: f64,
+    },
+    /// The current UTC time lies between start and end, wrapping around midnight when end is before start.
+    TimeBetween { start: NaiveTime, end: NaiveTime },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleAction {
+    CreateSetpoint {
+        device_id: i64,
+        #[serde(default = "default_setpoint_type")]
+        setpoint_type: SetpointType,
+        value: String,
+        #[serde(default)]
+        unit: Option<SetpointUnit>,
+        #[serde(default)]
+        mode: SetpointMode,
+        /// How long an override lasts, required for overrides.
+        #[serde(default)]
+        duration_minutes: Option<i64>,
+    },
+    RaiseAlert {
+        #[serde(default)]
+        severity: AlertSeverity,
+        message: String,
+    },
+    /// Posts the rule and the triggering event as JSON to the URL.
+    CallWebhook { url: String },
+}
+
+fn default_setpoint_type() -> SetpointType {
+    SetpointType::Temperature
+}
+
+/// Checks that a rule only refers to devices and rooms of its own site, and that its actions make sense.
+pub async fn validate_rule(
+    database: &Database,
+    site_id: i64,
+    trigger: &RuleTrigger,
+    conditions: &[RuleCondition],
+    actions: &[RuleAction],
+) -> Result<()> {
+    if actions.is_empty() {
+        bail!("Rules need at least one action");
+    }
+
+    let mut device_ids = Vec::new();
+    let mut room_ids = Vec::new();
+    match trigger {
+        RuleTrigger::SensorThreshold { device_id, .. }
+        | RuleTrigger::DeviceState { device_id, .. } => device_ids.push(*device_id),
+        RuleTrigger::TimeOfDay { .. } => {}
+    }
+    for condition in conditions {
+        match condition {
+            RuleCondition::DeviceReading { device_id, .. } => device_ids.push(*device_id),
+            RuleCondition::RoomAverage { room_id, .. } => room_ids.push(*room_id),
+            RuleCondition::TimeBetween { .. } => {}
+        }
+    }
+    for action in actions {
+        match action {
+            RuleAction::CreateSetpoint {
+                device_id,
+                mode,
+                duration_minutes,
+                ..
+            } => {
+                device_ids.push(*device_id);
+                match (mode, duration_minutes) {
+                    (SetpointMode::Override, Some(minutes)) if *minutes > 0 => {}
+                    (SetpointMode::Override, _) => {
+                        bail!("Override setpoints need a positive duration_minutes")
+                    }
+                    (_, Some(_)) => bail!("Only override setpoints can have a duration"),
+                    (_, None) => {}
+                }
+            }
+            RuleAction::RaiseAlert { .. } => {}
+            RuleAction::CallWebhook { url } => validate_url(url).await?,
+        }
+    }
+
+    for device_id in device_ids {
+        if database.devices().site_id(device_id).await? != Some(site_id) {
+            bail!(
+                "Device with ID {} does not belong to site {}",
+                device_id,
+                site_id
+            );
+        }
+    }
+    for room_id in room_ids {
+        if database.rooms().site_id(room_id).await? != Some(site_id) {
+            bail!(
+                "Room with ID {} does not belong to site {}",
+                room_id,
+                site_id
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Evaluates the enabled rules of each site against the events published by the resolvers,
+/// and checks time of day triggers on a fixed interval.
+pub struct AutomationEngine {
+    database: Database,
+    events: EventBus,
+    http: reqwest::Client,
+}
+
+impl AutomationEngine {
+    pub fn new(database: Database, events: EventBus) -> Result<Self> {
+        let http = http_client(WEBHOOK_TIMEOUT).context("Failed to build the HTTP client")?;
+        Ok(Self {
+            database,
+            events,
+            http,
+        })
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        // Subscribe right away, so no event published after spawning is missed.
+        let receiver = self.events.subscribe();
+        tokio::spawn(self.run(receiver))
+    }
+
+    async fn run(self, mut receiver: tokio::sync::broadcast::Receiver<Event>) {
+        let mut clock = tokio::time::interval(CLOCK_INTERVAL);
+        loop {
+            tokio::select! {
+                event = receiver.recv() => match event {
+                    Ok(event) => {
+                        if let Err(err) = self.handle_event(&event).await {
+                            error!("Failed to evaluate automation rules: {}", err);
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Automation engine fell behind, skipped {} events", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = clock.tick() => {
+                    if let Err(err) = self.handle_clock(Utc::now()).await {
+                        error!("Failed to evaluate time of day rules: {}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn handle_event(&self, event: &Event) -> Result<()> {
+        if matches!(event, Event::AlertRaised { .. }) {
+            return Ok(());
+        }
+
+        let rules = self
+            .database
+            .automation_rules()
+            .enabled(Some(event.site_id()))
+            .await?;
+
+        // A rule that fails is logged, and does not keep the other rules from running.
+        let payload = serde_json::to_value(event)?;
+        for rule in rules {
+            let result = match self.is_triggered_by(&rule.trigger, event).await {
+                Ok(true) => self.execute(&rule, payload.clone()).await,
+                Ok(false) => Ok(()),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                error!("Failed to run automation rule {}: {}", rule.id, err);
+            }
+        }
+        Ok(())
+    }
+
+    async fn is_triggered_by(&self, trigger: &RuleTrigger, event: &Event) -> Result<bool> {
+        match (trigger, event) {
+            (
+                RuleTrigger::SensorThreshold {
+                    device_id,
+                    comparison,
+                    value: threshold,
+                },
+                Event::SensorReadingCreated { reading, .. },
+            ) if reading.device_id == *device_id => {
+                let Ok(value) = reading.value.parse::<f64>() else {
+                    return Ok(false);
+                };
+                if !comparison.matches(value, *threshold) {
+                    return Ok(false);
+                }
+
+                let previous = self
+                    .database
+                    .readings()
+                    .previous(reading.device_id, reading.id)
+                    .await?;
+                let previously_matched = previous
+                    .and_then(|previous| previous.value.parse::<f64>().ok())
+                    .is_some_and(|previous| comparison.matches(previous, *threshold));
+                Ok(!previously_matched)
+            }
+            (
+                RuleTrigger::DeviceState { device_id, mode },
+                Event::ControlSetpointCreated {
+                    setpoint,
+                    rule_id: None,
+                    ..
+                },
+            ) => {
+                Ok(setpoint.device_id == *device_id
+                    && mode.is_none_or(|mode| mode == setpoint.mode))
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn handle_clock(&self, now: DateTime<Utc>) -> Result<()> {
+        // Triggers are stored as JSON, which the backends query differently,
+        // so the rules are told apart here rather than in SQL.
+        let rules = self.database.automation_rules().enabled(None).await?;
+
+        for rule in rules {
+            let RuleTrigger::TimeOfDay { time } = rule.trigger.0 else {
+                continue;
+            };
+            let due_at = now.date_naive().and_time(time).and_utc();
+            let is_due = due_at <= now && now - due_at < Duration::from_std(CLOCK_INTERVAL)? * 2;
+            let already_fired = rule.last_triggered_at.is_some_and(|last| last >= due_at);
+            if is_due && !already_fired {
+                let event = json!({ "type": "time_of_day", "due_at": due_at });
+                if let Err(err) = self.execute(&rule, event).await {
+                    error!("Failed to run automation rule {}: {}", rule.id, err);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the conditions of a triggered rule, runs its actions and logs the outcome.
+    async fn execute(&self, rule: &AutomationRule, event: Value) -> Result<()> {
+        let now = Utc::now();
+        self.database
+            .automation_rules()
+            .set_last_triggered_at(rule.id, now)
+            .await?;
+
+        let (status, message) = match self.unmet_condition(&rule.conditions).await {
+            Ok(Some(index)) => (
+                RuleExecutionStatus::Skipped,
+                Some(format!("Condition {} did not hold", index + 1)),
+            ),
+            Err(err) => (
+                RuleExecutionStatus::Failed,
+                Some(format!("Failed to evaluate conditions: {}", err)),
+            ),
+            Ok(None) => {
+                let mut failures = Vec::new();
+                for (index, action) in rule.actions.iter().enumerate() {
+                    if let Err(err) = self.run_action(rule, action, &event).await {
+                        failures.push(format!("Action {} failed: {}", index + 1, err));
+                    }
+                }
+                if failures.is_empty() {
+                    (RuleExecutionStatus::Succeeded, None)
+                } else {
+                    (RuleExecutionStatus::Failed, Some(failures.join("; ")))
+                }
+            }
+        };
+        debug!(
+            "Executed automation rule {} ({}): {:?}",
+            rule.id, rule.name, status
+        );
+
+        self.database
+            .automation_rules()
+            .record_execution(rule.id, status, &event.to_string(), message.as_deref(), now)
+            .await?;
+        Ok(())
+    }
+
+    /// The index of the first condition that does not hold, if any.
+    async fn unmet_condition(&self, conditions: &[RuleCondition]) -> Result<Option<usize>> {
+        for (index, condition) in conditions.iter().enumerate() {
+            let holds = match condition {
+                RuleCondition::DeviceReading {
+                    device_id,
+                    comparison,
+                    value,
+                } => self
+                    .latest_value(*device_id)
+                    .await?
+                    .is_some_and(|latest| comparison.matches(latest, *value)),
+                RuleCondition::RoomAverage {
+                    room_id,
+                    comparison,
+                    value,
+                } => {
+                    let devices = self.database.devices().for_room(*room_id, &[]).await?;
+                    let mut values = Vec::new();
+                    for device in devices {
+                        if let Some(latest) = self.latest_value(device.id).await? {
+                            values.push(latest);
+                        }
+                    }
+                    !values.is_empty()
+                        && comparison
+                            .matches(values.iter().sum::<f64>() / values.len() as f64, *value)
+                }
+                RuleCondition::TimeBetween { start, end } => {
+                    let time = Utc::now().time();
+                    if start <= end {
+                        *start <= time && time < *end
+                    } else {
+                        *start <= time || time < *end
+                    }
+                }
+            };
+            if !holds {
+                return Ok(Some(index));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn latest_value(&self, device_id: i64) -> Result<Option<f64>> {
+        let latest = self.database.readings().latest(device_id).await?;
+        Ok(latest.and_then(|latest| latest.value.parse().ok()))
+    }
+
+    async fn run_action(
+        &self,
+        rule: &AutomationRule,
+        action: &RuleAction,
+        event: &Value,
+    ) -> Result<()> {
+        match action {
+            RuleAction::CreateSetpoint {
+                device_id,
+                setpoint_type,
+                value,
+                unit,
+                mode,
+                duration_minutes,
+            } => {
+                let now = Utc::now();
+                let input = ControlSetpointInput {
+                    device_id: *device_id,
+                    setpoint_type: *setpoint_type,
+                    value: value.clone(),
+                    unit: *unit,
+                    mode: *mode,
+                    expires_at: duration_minutes.map(|minutes| now + Duration::minutes(minutes)),
+                };
+
+                let mut tx = self.database.begin().await?;
+                let setpoint = self
+                    .database
+                    .setpoints()
+                    .create(&mut tx, &input, now)
+                    .await?;
+                AuditRecord::new(
+                    "createControlSetpoint",
+                    AuditEntityType::ControlSetpoint,
+                    &input,
+                )
+                .site_id(rule.site_id)
+                .entity_id(setpoint.id)
+                .after(&setpoint)
+                .insert_in(&mut tx, &Identity::Automation(rule.id))
+                .await?;
+                tx.commit().await?;
+
+                self.events.publish(Event::ControlSetpointCreated {
+                    site_id: rule.site_id,
+                    setpoint,
+                    rule_id: Some(rule.id),
+                });
+            }
+            RuleAction::RaiseAlert { severity, message } => {
+                let alert = self
+                    .database
+                    .alerts()
+                    .raise(rule.site_id, rule.id, *severity, message, Utc::now())
+                    .await?;
+
+                self.events.publish(Event::AlertRaised { alert });
+            }
+            RuleAction::CallWebhook { url } => {
+                let payload = json!({
+                    "rule_id": rule.id,
+                    "rule_name": rule.name,
+                    "site_id": rule.site_id,
+                    "event": event,
+                });
+                let url = reqwest::Url::parse(url)?;
+                check_address(&url)?;
+                let response = self.http.post(url.clone()).json(&payload).send().await?;
+                if !response.status().is_success() {
+                    return Err(anyhow!("{} responded with {}", url, response.status()));
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,16 +1,15 @@
 use anyhow::Result;
 use clap::Parser;
 use log::debug;
-use sqlx::sqlite::SqlitePool;
-
-#[path = "../models.rs"] // Adjust path if your models are elsewhere
-mod models;
-#[path = "../seed.rs"] // Adjust path if your seed function is elsewhere
-mod seed;
+use sh_backend::config::{Config, ConfigArgs};
+use sh_backend::db::Database;
+use sh_backend::seed;
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
     #[arg(short, long, default_value_t = false)]
     extend: bool,
 }
@@ -19,23 +18,19 @@ struct Cli {
 async fn main() -> Result<()> {
     dotenvy::dotenv().ok();
 
-    env_logger::init();
-
-    let database_url =
-        std::env::var("DATABASE_URL").expect("DATABASE_URL environment variable is not set");
+    let args = Cli::parse();
+    let config = Config::load(&args.config)?;
 
-    let pool = SqlitePool::connect(&database_url)
-        .await
-        .expect("Failed to connect to SQLite, check out the README for setup instructions");
+    env_logger::Builder::new().parse_filters(&config.log).init();
 
-    let args = Cli::parse();
+    let database = Database::connect(&config.database, false).await?;
 
     let should_extend = args.extend;
     debug!(
         "Running the DB seed command with should_extend: {}",
         should_extend
     );
-    seed::seed_db(&pool, should_extend).await?;
+    seed::seed_db(&database, should_extend).await?;
 
     Ok(())
 }
@@ -0,0 +1,62 @@
+use std::io::Cursor;
+
+use log::error;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Method, Status};
+use rocket::{Request, Response};
+use sha2::{Digest, Sha256};
+
+/// Tags the cacheable responses to `GET` requests, those carrying a `Cache-Control` header,
+/// with an `ETag` of their body, and answers `304 Not Modified` without a body
+/// when the client already holds the same one in its cache.
+///
+/// Attached before `Compression`, so the tag stays the same whatever the encoding.
+pub struct ETags;
+
+#[rocket::async_trait]
+impl Fairing for ETags {
+    fn info(&self) -> Info {
+        Info {
+            name: "ETags",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if request.method() != Method::Get
+            || response.status() != Status::Ok
+            || !response.headers().contains("Cache-Control")
+            || response.body().preset_size().is_none()
+        {
+            return;
+        }
+        let body = match response.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(err) => {
+                error!("Failed to read the response body to tag it: {}", err);
+                return;
+            }
+        };
+        // Weak, as the body is the same but its encoding may differ.
+        let etag = format!("W/\"{}\"", hex::encode(&Sha256::digest(&body)[..16]));
+
+        if request
+            .headers()
+            .get("If-None-Match")
+            .flat_map(|header| header.split(','))
+            .any(|tag| tag.trim() == "*" || weakly_equal(tag.trim(), &etag))
+        {
+            response.set_status(Status::NotModified);
+            response.set_sized_body(0, Cursor::new(Vec::new()));
+            response.remove_header("Content-Type");
+        } else {
+            response.set_sized_body(body.len(), Cursor::new(body));
+        }
+        response.set_header(Header::new("ETag", etag));
+    }
+}
+
+/// Compares two entity tags ignoring whether they are weak, as `If-None-Match` does.
+fn weakly_equal(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
@@ -0,0 +1,128 @@
+use std::io::{Cursor, Write};
+
+use log::error;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Request, Response};
+
+/// Bodies smaller than this gain too little from compression to be worth it.
+const MIN_SIZE: usize = 1024;
+
+/// Brotli's quality ranges from 0 to 11, higher levels compress JSON only slightly better but far slower.
+const BROTLI_QUALITY: u32 = 5;
+
+/// Compresses text and JSON responses with brotli or gzip, whichever the client prefers.
+///
+/// Only bodies of a known size are compressed, the streamed exports are sent as they are.
+pub struct Compression;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    fn compress(self, body: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut writer =
+                    brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, 22);
+                writer.write_all(body)?;
+                Ok(writer.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Compression {
+    fn info(&self) -> Info {
+        Info {
+            name: "Response compression",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if !compressible(response) {
+            return;
+        }
+        // Whether the body is compressed depends on the header, so caches have to tell them apart.
+        response.adjoin_header(Header::new("Vary", "Accept-Encoding"));
+        let Some(encoding) = preferred_encoding(request.headers().get("Accept-Encoding")) else {
+            return;
+        };
+
+        let body = match response.body_mut().to_bytes().await {
+            Ok(body) => body,
+            Err(err) => {
+                error!("Failed to read the response body to compress it: {}", err);
+                return;
+            }
+        };
+        match encoding.compress(&body) {
+            Ok(compressed) => {
+                response.set_header(Header::new("Content-Encoding", encoding.name()));
+                response.set_sized_body(compressed.len(), Cursor::new(compressed));
+            }
+            Err(err) => {
+                error!("Failed to compress the response body: {}", err);
+                response.set_sized_body(body.len(), Cursor::new(body));
+            }
+        }
+    }
+}
+
+fn compressible(response: &Response<'_>) -> bool {
+    let textual = response
+        .content_type()
+        .is_some_and(|content_type| content_type.is_json() || content_type.top() == "text");
+    textual
+        && !response.headers().contains("Content-Encoding")
+        && response
+            .body()
+            .preset_size()
+            .is_some_and(|size| size >= MIN_SIZE)
+}
+
+/// The encoding to compress with, out of those the `Accept-Encoding` headers list,
+/// preferring brotli over gzip when the client weighs them the same.
+fn preferred_encoding<'a>(headers: impl Iterator<Item = &'a str>) -> Option<Encoding> {
+    let mut preferred: Option<(Encoding, f32)> = None;
+    for coding in headers.flat_map(|header| header.split(',')) {
+        let mut parts = coding.split(';').map(str::trim);
+        let encoding = match parts.next() {
+            Some(name) if name.eq_ignore_ascii_case("br") => Encoding::Brotli,
+            Some(name) if name.eq_ignore_ascii_case("gzip") => Encoding::Gzip,
+            _ => continue,
+        };
+        let quality = parts
+            .find_map(|parameter| parameter.strip_prefix("q="))
+            .map_or(Some(1.0), |quality| quality.parse::<f32>().ok())
+            .unwrap_or(0.0);
+        if quality <= 0.0 {
+            continue;
+        }
+        let better = preferred.is_none_or(|(_, current)| {
+            quality > current || (quality == current && encoding == Encoding::Brotli)
+        });
+        if better {
+            preferred = Some((encoding, quality));
+        }
+    }
+    preferred.map(|(encoding, _)| encoding)
+}
@@ -0,0 +1,364 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use clap::Args;
+use rocket::data::{ByteUnit, ToByteUnit};
+use rocket::figment::Figment;
+use rocket::figment::providers::{Env, Format, Serialized, Toml};
+use serde::{Deserialize, Serialize};
+
+use crate::db::Backend;
+
+/// Read when neither `--config` nor `SH_CONFIG` names another file, and skipped if it doesn't exist.
+pub const DEFAULT_CONFIG_FILE: &str = "sh-backend.toml";
+
+/// SQLite binds at most 32766 parameters per statement, and every imported reading takes 4.
+const MAX_IMPORT_CHUNK_SIZE: usize = 8000;
+
+/// The settings of the server and the commands, see `sh-backend.example.toml`.
+///
+/// Each layer overrides the one before it: the defaults, the TOML file, the `DATABASE_URL`
+/// and `RUST_LOG` variables, `SH_` variables such as `SH_HTTP__PORT`, and the command line flags.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// An `env_logger` filter, such as `info` or `sh_backend=debug,sqlx=warn`.
+    pub log: String,
+    pub database: DatabaseConfig,
+    pub http: HttpConfig,
+    pub cors: CorsConfig,
+    pub auth: AuthConfig,
+    pub graphql: GraphqlConfig,
+    pub rate_limits: RateLimitsConfig,
+    pub ingestion: IngestionConfig,
+    pub retention: RetentionConfig,
+    pub workers: WorkersConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    pub url: String,
+    pub max_connections: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub address: IpAddr,
+    pub port: u16,
+    /// Whether text and JSON responses are compressed for clients accepting brotli or gzip.
+    pub compression: bool,
+    /// Reverse proxies whose `X-Real-IP` header is taken as the address of the client,
+    /// anonymous requests are rate limited by the address of the connection otherwise.
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// Origins such as `https://dashboard.example.com` allowed to call the API from a browser.
+    pub allowed_origins: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Whether devices may authenticate with their device keys, turned off to lock all of them out at once.
+    pub device_keys: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GraphqlConfig {
+    /// How deeply fields may be nested, counting the top-level fields as one.
+    pub max_depth: usize,
+    /// The highest complexity an operation may have, see `limits.rs` for what fields cost.
+    pub max_complexity: usize,
+    /// Operations running longer than this are cancelled.
+    pub timeout_seconds: u64,
+    /// How many automatic persisted queries are remembered, the oldest ones are forgotten first.
+    pub persisted_query_cache_size: usize,
+    /// A manifest of persisted operations, the only ones accepted when set.
+    pub operation_allowlist: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitsConfig {
+    pub enabled: bool,
+    /// How many readings every device key, user or address may record per minute in the long run.
+    pub ingest_per_minute: u32,
+    /// How many readings may be recorded at once before the rate above applies.
+    pub ingest_burst: u32,
+    /// The same for every other operation.
+    pub query_per_minute: u32,
+    pub query_burst: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IngestionConfig {
+    /// The largest GraphQL request body accepted, file uploads included.
+    pub request_limit: ByteUnit,
+    /// Imported readings are inserted in transactions of this many rows.
+    pub import_chunk_size: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    pub enabled: bool,
+    /// How often retention policies are applied.
+    pub interval_seconds: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorkersConfig {
+    pub automation: bool,
+    pub webhooks: bool,
+    /// Webhook deliveries are given up as failed after this many attempts.
+    pub webhook_max_attempts: u32,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            log: "info".into(),
+            database: DatabaseConfig::default(),
+            http: HttpConfig::default(),
+            cors: CorsConfig::default(),
+            auth: AuthConfig::default(),
+            graphql: GraphqlConfig::default(),
+            rate_limits: RateLimitsConfig::default(),
+            ingestion: IngestionConfig::default(),
+            retention: RetentionConfig::default(),
+            workers: WorkersConfig::default(),
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            url: "sqlite://development.db".into(),
+            max_connections: 10,
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 8000,
+            compression: true,
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self { device_keys: true }
+    }
+}
+
+impl Default for GraphqlConfig {
+    fn default() -> Self {
+        Self {
+            max_depth: 10,
+            max_complexity: 200_000,
+            timeout_seconds: 30,
+            persisted_query_cache_size: 1000,
+            operation_allowlist: None,
+        }
+    }
+}
+
+impl Default for RateLimitsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ingest_per_minute: 600,
+            ingest_burst: 60,
+            query_per_minute: 600,
+            query_burst: 120,
+        }
+    }
+}
+
+impl Default for IngestionConfig {
+    fn default() -> Self {
+        Self {
+            request_limit: 128.kibibytes(),
+            import_chunk_size: 1000,
+        }
+    }
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_seconds: 60 * 60,
+        }
+    }
+}
+
+impl Default for WorkersConfig {
+    fn default() -> Self {
+        Self {
+            automation: true,
+            webhooks: true,
+            webhook_max_attempts: 8,
+        }
+    }
+}
+
+/// Command line flags overriding the configuration, shared by every command.
+#[derive(Args, Debug, Clone, Default)]
+pub struct ConfigArgs {
+    /// The TOML configuration file, `sh-backend.toml` when left out
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    #[arg(long, global = true)]
+    pub log: Option<String>,
+    #[arg(long, global = true)]
+    pub database_url: Option<String>,
+    #[arg(long, global = true)]
+    pub address: Option<IpAddr>,
+    #[arg(long, global = true)]
+    pub port: Option<u16>,
+}
+
+impl Config {
+    /// Loads and validates the configuration, layered as described on [`Config`].
+    pub fn load(args: &ConfigArgs) -> Result<Self> {
+        let path = args
+            .config
+            .clone()
+            .or_else(|| std::env::var_os("SH_CONFIG").map(PathBuf::from));
+        if let Some(path) = &path
+            && !path.is_file()
+        {
+            bail!("Configuration file {} does not exist", path.display());
+        }
+        let path = path.unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_FILE));
+
+        let config: Config = Self::figment(&path, args)
+            .extract()
+            .context("Failed to load the configuration")?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn figment(path: &Path, args: &ConfigArgs) -> Figment {
+        let mut figment = Figment::from(Serialized::defaults(Config::default()))
+            .merge(Toml::file(path))
+            .merge(Env::raw().only(&["RUST_LOG", "DATABASE_URL"]).map(|key| {
+                match key.as_str().to_uppercase().as_str() {
+                    "RUST_LOG" => "log".into(),
+                    _ => "database.url".into(),
+                }
+            }))
+            .merge(Env::prefixed("SH_").ignore(&["CONFIG"]).split("__"));
+
+        if let Some(log) = &args.log {
+            figment = figment.merge(("log", log));
+        }
+        if let Some(url) = &args.database_url {
+            figment = figment.merge(("database.url", url));
+        }
+        if let Some(address) = args.address {
+            figment = figment.merge(("http.address", address));
+        }
+        if let Some(port) = args.port {
+            figment = figment.merge(("http.port", port));
+        }
+        figment
+    }
+
+    /// Checks the values the types alone don't rule out, reporting every problem at once.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        if Backend::from_url(&self.database.url).is_none() {
+            problems.push(format!(
+                "database.url has to be a sqlite: or postgres:// URL, got \"{}\"",
+                self.database.url
+            ));
+        }
+        if self.database.max_connections == 0 {
+            problems.push("database.max_connections has to be at least 1".into());
+        }
+        for origin in &self.cors.allowed_origins {
+            match reqwest::Url::parse(origin) {
+                Ok(url)
+                    if matches!(url.scheme(), "http" | "https")
+                        && url.path() == "/"
+                        && !origin.ends_with('/') => {}
+                _ => problems.push(format!(
+                    "cors.allowed_origins has to hold origins like https://example.com, got \"{}\"",
+                    origin
+                )),
+            }
+        }
+        if self.graphql.max_depth == 0 {
+            problems.push("graphql.max_depth has to be at least 1".into());
+        }
+        if self.graphql.max_complexity == 0 {
+            problems.push("graphql.max_complexity has to be at least 1".into());
+        }
+        if self.graphql.timeout_seconds == 0 {
+            problems.push("graphql.timeout_seconds has to be at least 1".into());
+        }
+        if self.graphql.persisted_query_cache_size == 0 {
+            problems.push("graphql.persisted_query_cache_size has to be at least 1".into());
+        }
+        for (name, value) in [
+            ("ingest_per_minute", self.rate_limits.ingest_per_minute),
+            ("ingest_burst", self.rate_limits.ingest_burst),
+            ("query_per_minute", self.rate_limits.query_per_minute),
+            ("query_burst", self.rate_limits.query_burst),
+        ] {
+            if value == 0 {
+                problems.push(format!("rate_limits.{} has to be at least 1", name));
+            }
+        }
+        if !(1..=MAX_IMPORT_CHUNK_SIZE).contains(&self.ingestion.import_chunk_size) {
+            problems.push(format!(
+                "ingestion.import_chunk_size has to be between 1 and {}",
+                MAX_IMPORT_CHUNK_SIZE
+            ));
+        }
+        if self.retention.interval_seconds < 60 {
+            problems.push("retention.interval_seconds has to be at least 60".into());
+        }
+        if self.workers.webhook_max_attempts == 0 {
+            problems.push("workers.webhook_max_attempts has to be at least 1".into());
+        }
+
+        if !problems.is_empty() {
+            bail!("Invalid configuration:\n  - {}", problems.join("\n  - "));
+        }
+        Ok(())
+    }
+
+    /// The settings Rocket takes from the configuration, on top of its own defaults.
+    pub fn rocket_figment(&self) -> Figment {
+        rocket::Config::figment()
+            .merge(Serialized::global("address", self.http.address))
+            .merge(Serialized::global("port", self.http.port))
+            .merge(Serialized::global(
+                "limits.graphql",
+                self.ingestion.request_limit,
+            ))
+    }
+
+    pub fn retention_interval(&self) -> Duration {
+        Duration::from_secs(self.retention.interval_seconds)
+    }
+}
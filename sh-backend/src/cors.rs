@@ -0,0 +1,64 @@
+use std::io::Cursor;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::{Header, Method, Status};
+use rocket::{Request, Response};
+
+use crate::config::CorsConfig;
+
+/// Lets browsers on the configured origins call the API, answering their preflight requests.
+///
+/// Requests from other origins are served as usual, just without the headers allowing browsers to read
+/// the response, so the GraphiQL page served by the API itself keeps working without being configured.
+pub struct Cors {
+    allowed_origins: Vec<String>,
+}
+
+impl Cors {
+    pub fn new(config: &CorsConfig) -> Self {
+        Self {
+            allowed_origins: config.allowed_origins.clone(),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Cors {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Some(origin) = request.headers().get_one("Origin") else {
+            return;
+        };
+        response.adjoin_header(Header::new("Vary", "Origin"));
+        if !self.allowed_origins.iter().any(|allowed| allowed == origin) {
+            return;
+        }
+        response.set_header(Header::new(
+            "Access-Control-Allow-Origin",
+            origin.to_string(),
+        ));
+        response.set_header(Header::new("Access-Control-Expose-Headers", "ETag"));
+
+        let preflight = request.method() == Method::Options
+            && request.headers().contains("Access-Control-Request-Method");
+        if preflight {
+            response.set_header(Header::new("Access-Control-Allow-Methods", "GET, POST"));
+            response.set_header(Header::new(
+                "Access-Control-Allow-Headers",
+                "Authorization, Content-Type, If-None-Match",
+            ));
+            response.set_header(Header::new("Access-Control-Max-Age", "3600"));
+            // No route answers OPTIONS, so the preflight ends up as a 404.
+            if request.route().is_none() {
+                response.set_status(Status::NoContent);
+                response.set_sized_body(0, Cursor::new(Vec::new()));
+            }
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::postgres::{PgArgumentBuffer, PgConnectOptions, PgPool, PgPoolOptions, PgTypeInfo};
+use sqlx::sqlite::{
+    SqliteArgumentValue, SqliteConnectOptions, SqlitePool, SqlitePoolOptions, SqliteTypeInfo,
+};
+use sqlx::{Encode, Postgres, Sqlite};
+
+use crate::config::DatabaseConfig;
+use crate::repository::{
+    AlertRepo, AuditLogRepo, AutomationRuleRepo, BuildingRepo, CredentialRepo, DeviceRepo,
+    FloorRepo, ReadingRepo, RetentionPolicyRepo, RoomRepo, SetpointRepo, SiteRepo, SiteRoleRepo,
+    TagRepo, UserRepo, WebhookRepo, ZoneRepo,
+};
+
+/// Runs the same code against whichever pool a [`Database`] holds.
+/// The body is compiled once per backend, so it can only use SQL both of them understand,
+/// `$1` style parameters included.
+#[macro_export]
+macro_rules! with_pool {
+    ($database:expr, $pool:ident => $body:expr) => {
+        match $database {
+            $crate::db::Database::Sqlite($pool) => $body,
+            $crate::db::Database::Postgres($pool) => $body,
+        }
+    };
+}
+
+/// Like [`with_pool!`], but runs the body against the connection of a [`Transaction`].
+#[macro_export]
+macro_rules! with_transaction {
+    ($transaction:expr, $connection:ident => $body:expr) => {
+        match $transaction {
+            $crate::db::Transaction::Sqlite($connection) => {
+                let $connection = &mut **$connection;
+                $body
+            }
+            $crate::db::Transaction::Postgres($connection) => {
+                let $connection = &mut **$connection;
+                $body
+            }
+        }
+    };
+}
+
+/// A timestamp bound the way each backend stores its own. On SQLite that is the format of `CURRENT_TIMESTAMP`
+/// with the fraction of the second kept, so timestamps written with and without one sort alike as text.
+#[derive(Debug, Clone, Copy)]
+pub struct StoredTime(pub DateTime<Utc>);
+
+impl sqlx::Type<Sqlite> for StoredTime {
+    fn type_info() -> SqliteTypeInfo {
+        <NaiveDateTime as sqlx::Type<Sqlite>>::type_info()
+    }
+}
+
+impl Encode<'_, Sqlite> for StoredTime {
+    fn encode_by_ref(&self, buf: &mut Vec<SqliteArgumentValue<'_>>) -> Result<IsNull, BoxDynError> {
+        Encode::<Sqlite>::encode_by_ref(&self.0.naive_utc(), buf)
+    }
+}
+
+impl sqlx::Type<Postgres> for StoredTime {
+    fn type_info() -> PgTypeInfo {
+        <DateTime<Utc> as sqlx::Type<Postgres>>::type_info()
+    }
+}
+
+impl Encode<'_, Postgres> for StoredTime {
+    fn encode_by_ref(&self, buf: &mut PgArgumentBuffer) -> Result<IsNull, BoxDynError> {
+        Encode::<Postgres>::encode_by_ref(&self.0, buf)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+}
+
+impl Backend {
+    pub fn from_url(url: &str) -> Option<Self> {
+        if url.starts_with("sqlite:") {
+            Some(Backend::Sqlite)
+        } else if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+            Some(Backend::Postgres)
+        } else {
+            None
+        }
+    }
+
+    /// An SQL expression for a timestamp column that compares and sorts in chronological order,
+    /// down to the millisecond. SQLite holds timestamps both as `CURRENT_TIMESTAMP` text and as RFC 3339,
+    /// so they are normalized to a common format there.
+    pub fn sortable_time(self, column: &str) -> String {
+        match self {
+            Backend::Sqlite => format!("strftime('%Y-%m-%d %H:%M:%f', {column})"),
+            Backend::Postgres => column.to_string(),
+        }
+    }
+
+    /// An SQL expression reading a text column as a number, or `NULL` when it holds something else,
+    /// so readings like "open" are left out of aggregates rather than counted as 0.
+    /// Numbers are written as in JSON, such as `21`, `-0.5` or `1e3`, on either backend.
+    pub fn numeric(self, column: &str) -> String {
+        match self {
+            // `json_type` fails on malformed JSON, so it only runs once `json_valid` passed.
+            Backend::Sqlite => format!(
+                "CASE WHEN json_valid({column}) THEN CASE WHEN json_type({column}) IN ('integer', 'real') THEN CAST({column} AS REAL) END END"
+            ),
+            Backend::Postgres => format!(
+                r"CASE WHEN {column} ~ '^\s*-?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?\s*$' THEN CAST({column} AS DOUBLE PRECISION) END"
+            ),
+        }
+    }
+}
+
+/// Where the data lives, chosen by the scheme of `database.url`.
+#[derive(Debug, Clone)]
+pub enum Database {
+    Sqlite(SqlitePool),
+    Postgres(PgPool),
+}
+
+impl Database {
+    /// Connects on first use, so a server can start while its database is unreachable.
+    pub fn connect_lazy(config: &DatabaseConfig) -> Result<Self> {
+        Ok(match backend(&config.url)? {
+            Backend::Sqlite => Database::Sqlite(
+                SqlitePoolOptions::new()
+                    .max_connections(config.max_connections)
+                    .connect_lazy_with(SqliteConnectOptions::from_str(&config.url)?),
+            ),
+            Backend::Postgres => Database::Postgres(
+                PgPoolOptions::new()
+                    .max_connections(config.max_connections)
+                    .connect_lazy_with(PgConnectOptions::from_str(&config.url)?),
+            ),
+        })
+    }
+
+    /// Connects right away, creating the SQLite file if it doesn't exist and `create_if_missing` is set.
+    pub async fn connect(config: &DatabaseConfig, create_if_missing: bool) -> Result<Self> {
+        Ok(match backend(&config.url)? {
+            Backend::Sqlite => Database::Sqlite(
+                SqlitePoolOptions::new()
+                    .max_connections(config.max_connections)
+                    .connect_with(
+                        SqliteConnectOptions::from_str(&config.url)?
+                            .create_if_missing(create_if_missing),
+                    )
+                    .await
+                    .context(
+                        "Failed to connect to SQLite, check out the README for setup instructions",
+                    )?,
+            ),
+            Backend::Postgres => Database::Postgres(
+                PgPoolOptions::new()
+                    .max_connections(config.max_connections)
+                    .connect_with(PgConnectOptions::from_str(&config.url)?)
+                    .await
+                    .context("Failed to connect to PostgreSQL")?,
+            ),
+        })
+    }
+
+    pub fn backend(&self) -> Backend {
+        match self {
+            Database::Sqlite(_) => Backend::Sqlite,
+            Database::Postgres(_) => Backend::Postgres,
+        }
+    }
+
+    pub async fn begin(&self) -> sqlx::Result<Transaction<'static>> {
+        Ok(match self {
+            Database::Sqlite(pool) => Transaction::Sqlite(pool.begin().await?),
+            Database::Postgres(pool) => Transaction::Postgres(pool.begin().await?),
+        })
+    }
+
+    pub fn sites(&self) -> SiteRepo<'_> {
+        SiteRepo::new(self)
+    }
+
+    pub fn buildings(&self) -> BuildingRepo<'_> {
+        BuildingRepo::new(self)
+    }
+
+    pub fn floors(&self) -> FloorRepo<'_> {
+        FloorRepo::new(self)
+    }
+
+    pub fn zones(&self) -> ZoneRepo<'_> {
+        ZoneRepo::new(self)
+    }
+
+    pub fn rooms(&self) -> RoomRepo<'_> {
+        RoomRepo::new(self)
+    }
+
+    pub fn devices(&self) -> DeviceRepo<'_> {
+        DeviceRepo::new(self)
+    }
+
+    pub fn readings(&self) -> ReadingRepo<'_> {
+        ReadingRepo::new(self)
+    }
+
+    pub fn setpoints(&self) -> SetpointRepo<'_> {
+        SetpointRepo::new(self)
+    }
+
+    pub fn tags(&self) -> TagRepo<'_> {
+        TagRepo::new(self)
+    }
+
+    pub fn users(&self) -> UserRepo<'_> {
+        UserRepo::new(self)
+    }
+
+    pub fn site_roles(&self) -> SiteRoleRepo<'_> {
+        SiteRoleRepo::new(self)
+    }
+
+    pub fn credentials(&self) -> CredentialRepo<'_> {
+        CredentialRepo::new(self)
+    }
+
+    pub fn automation_rules(&self) -> AutomationRuleRepo<'_> {
+        AutomationRuleRepo::new(self)
+    }
+
+    pub fn alerts(&self) -> AlertRepo<'_> {
+        AlertRepo::new(self)
+    }
+
+    pub fn webhooks(&self) -> WebhookRepo<'_> {
+        WebhookRepo::new(self)
+    }
+
+    pub fn retention_policies(&self) -> RetentionPolicyRepo<'_> {
+        RetentionPolicyRepo::new(self)
+    }
+
+    pub fn audit_logs(&self) -> AuditLogRepo<'_> {
+        AuditLogRepo::new(self)
+    }
+}
+
+/// A transaction on either backend, rolled back when dropped without [`Transaction::commit`].
+pub enum Transaction<'c> {
+    Sqlite(sqlx::Transaction<'c, Sqlite>),
+    Postgres(sqlx::Transaction<'c, Postgres>),
+}
+
+impl Transaction<'_> {
+    pub async fn commit(self) -> sqlx::Result<()> {
+        match self {
+            Transaction::Sqlite(transaction) => transaction.commit().await,
+            Transaction::Postgres(transaction) => transaction.commit().await,
+        }
+    }
+}
+
+fn backend(url: &str) -> Result<Backend> {
+    Backend::from_url(url).with_context(|| {
+        format!(
+            "Unsupported database URL \"{}\", expected sqlite: or postgres://",
+            url
+        )
+    })
+}
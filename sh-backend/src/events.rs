@@ -0,0 +1,66 @@
+use log::debug;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::models::{Alert, ControlSetpoint, SensorReading};
+
+const CHANNEL_CAPACITY: usize = 1024;
+
+/// Something that happened in the domain, which background workers may want to react to.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    SensorReadingCreated {
+        site_id: i64,
+        reading: SensorReading,
+    },
+    ControlSetpointCreated {
+        site_id: i64,
+        setpoint: ControlSetpoint,
+        /// The automation rule that created the setpoint, if it was not created by a person.
+        rule_id: Option<i64>,
+    },
+    AlertRaised {
+        alert: Alert,
+    },
+}
+
+impl Event {
+    pub fn site_id(&self) -> i64 {
+        match self {
+            Event::SensorReadingCreated { site_id, .. } => *site_id,
+            Event::ControlSetpointCreated { site_id, .. } => *site_id,
+            Event::AlertRaised { alert } => alert.site_id,
+        }
+    }
+}
+
+/// Fans events out to every subscribed worker.
+/// Publishing never blocks, and events are dropped when nobody is listening.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+        Self { sender }
+    }
+
+    pub fn publish(&self, event: Event) {
+        if self.sender.send(event).is_err() {
+            debug!("Dropped event, no subscribers are listening");
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
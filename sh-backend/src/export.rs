@@ -0,0 +1,284 @@
+use std::sync::LazyLock;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use clap::{Args, ValueEnum};
+use log::error;
+use rocket::FromFormField;
+use rocket::State;
+use rocket::futures::stream::BoxStream;
+use rocket::futures::{StreamExt, TryStreamExt};
+use rocket::http::{ContentType, Status};
+use rocket::response::stream::ByteStream;
+use serde::Serialize;
+use sqlx::FromRow;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::auth::{Identity, site_role};
+use crate::db::{Backend, Database};
+use crate::models::Role;
+use crate::with_pool;
+
+const CSV_COLUMNS: [&str; 12] = [
+    "record_type",
+    "timestamp",
+    "site_id",
+    "site_name",
+    "room_id",
+    "room_name",
+    "device_id",
+    "device_name",
+    "value",
+    "unit",
+    "setpoint_type",
+    "mode",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, FromFormField)]
+pub enum ExportFormat {
+    Csv,
+    /// One JSON object per line.
+    #[value(name = "jsonl")]
+    #[field(value = "jsonl")]
+    JsonLines,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> ContentType {
+        match self {
+            ExportFormat::Csv => ContentType::CSV,
+            ExportFormat::JsonLines => ContentType::new("application", "jsonl"),
+        }
+    }
+
+    /// The lines written before the first row, if the format has any.
+    pub fn header(self) -> Option<Vec<u8>> {
+        match self {
+            ExportFormat::Csv => Some(csv_line(CSV_COLUMNS).unwrap_or_default()),
+            ExportFormat::JsonLines => None,
+        }
+    }
+
+    pub fn row(self, row: &ExportRow) -> Result<Vec<u8>> {
+        match self {
+            ExportFormat::Csv => {
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(Vec::new());
+                writer.serialize(row)?;
+                Ok(writer.into_inner()?)
+            }
+            ExportFormat::JsonLines => {
+                let mut line = serde_json::to_vec(row)?;
+                line.push(b'\n');
+                Ok(line)
+            }
+        }
+    }
+}
+
+/// Which readings and setpoints to export, all filters being optional.
+#[derive(Args, Debug, Clone, Default)]
+pub struct ExportFilter {
+    #[arg(long)]
+    pub site_id: Option<i64>,
+    #[arg(long)]
+    pub room_id: Option<i64>,
+    #[arg(long)]
+    pub device_id: Option<i64>,
+    /// Only rows at or after this RFC 3339 timestamp.
+    #[arg(long)]
+    pub from: Option<DateTime<Utc>>,
+    /// Only rows before this RFC 3339 timestamp.
+    #[arg(long)]
+    pub to: Option<DateTime<Utc>>,
+}
+
+/// A sensor reading or control setpoint, joined with the names of its device, room and site.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ExportRow {
+    /// Either `sensor_reading` or `control_setpoint`.
+    pub record_type: String,
+    pub timestamp: DateTime<Utc>,
+    pub site_id: i64,
+    pub site_name: String,
+    pub room_id: i64,
+    pub room_name: String,
+    pub device_id: i64,
+    pub device_name: String,
+    pub value: String,
+    pub unit: Option<String>,
+    pub setpoint_type: Option<String>,
+    pub mode: Option<String>,
+}
+
+/// Streams the matching rows in chronological order, fetching them from the database as they are consumed.
+/// Timestamps are exported as they were stored, their text formats only being normalized to compare them.
+pub fn export_rows<'a>(
+    database: &'a Database,
+    filter: &ExportFilter,
+) -> BoxStream<'a, sqlx::Result<ExportRow>> {
+    let sql = match database.backend() {
+        Backend::Sqlite => &*SQLITE_EXPORT_SQL,
+        Backend::Postgres => &*POSTGRES_EXPORT_SQL,
+    };
+    with_pool!(database, pool => {
+        sqlx::query_as::<_, ExportRow>(sql)
+            .bind(filter.site_id)
+            .bind(filter.room_id)
+            .bind(filter.device_id)
+            .bind(filter.from)
+            .bind(filter.to)
+            .fetch(pool)
+    })
+}
+
+/// The rows are streamed, so the statement has to outlive the call, and is only built once per backend.
+static SQLITE_EXPORT_SQL: LazyLock<String> = LazyLock::new(|| export_sql(Backend::Sqlite));
+static POSTGRES_EXPORT_SQL: LazyLock<String> = LazyLock::new(|| export_sql(Backend::Postgres));
+
+fn export_sql(backend: Backend) -> String {
+    let time = backend.sortable_time("timestamp");
+    format!(
+        r#"
+        SELECT record_type, timestamp, site_id, site_name, room_id, room_name, device_id, device_name, value, unit, setpoint_type, mode
+        FROM (
+            SELECT 'sensor_reading' AS record_type, SensorReading.timestamp AS timestamp,
+                Site.id AS site_id, Site.name AS site_name, Room.id AS room_id, Room.name AS room_name,
+                Device.id AS device_id, Device.name AS device_name,
+                SensorReading.value AS value, CAST(SensorReading.unit AS TEXT) AS unit,
+                CAST(NULL AS TEXT) AS setpoint_type, CAST(NULL AS TEXT) AS mode
+            FROM SensorReading
+            JOIN Device ON Device.id = SensorReading.device_id
+            JOIN Room ON Room.id = Device.room_id
+            JOIN Site ON Site.id = Room.site_id
+            UNION ALL
+            SELECT 'control_setpoint', ControlSetpoint.timestamp,
+                Site.id, Site.name, Room.id, Room.name, Device.id, Device.name,
+                ControlSetpoint.value, CAST(ControlSetpoint.unit AS TEXT),
+                CAST(ControlSetpoint.setpoint_type AS TEXT), CAST(ControlSetpoint.mode AS TEXT)
+            FROM ControlSetpoint
+            JOIN Device ON Device.id = ControlSetpoint.device_id
+            JOIN Room ON Room.id = Device.room_id
+            JOIN Site ON Site.id = Room.site_id
+        ) AS records
+        WHERE ($1 IS NULL OR site_id = $1)
+            AND ($2 IS NULL OR room_id = $2)
+            AND ($3 IS NULL OR device_id = $3)
+            AND ($4 IS NULL OR {time} >= {from})
+            AND ($5 IS NULL OR {time} < {to})
+        ORDER BY {time}, record_type
+        "#,
+        from = backend.sortable_time("$4"),
+        to = backend.sortable_time("$5"),
+    )
+}
+
+/// Writes an export to a file or stdout, returning how many rows were written.
+pub async fn write_export(
+    database: &Database,
+    filter: &ExportFilter,
+    format: ExportFormat,
+    mut output: impl AsyncWrite + Unpin,
+) -> Result<u64> {
+    if let Some(header) = format.header() {
+        output.write_all(&header).await?;
+    }
+    let mut rows = export_rows(database, filter);
+    let mut written = 0;
+    while let Some(row) = rows.try_next().await? {
+        output.write_all(&format.row(&row)?).await?;
+        written += 1;
+    }
+    output.flush().await?;
+    Ok(written)
+}
+
+/// Exports are limited to a single site the caller can view,
+/// and only administrators may export every site at once.
+async fn authorize(
+    database: &Database,
+    identity: &Identity,
+    filter: &ExportFilter,
+) -> Result<(), Status> {
+    let Identity::User(user) = identity else {
+        return Err(Status::Unauthorized);
+    };
+    let site_id = if let Some(device_id) = filter.device_id {
+        database.devices().site_id(device_id).await
+    } else if let Some(room_id) = filter.room_id {
+        database.rooms().site_id(room_id).await
+    } else {
+        Ok(filter.site_id)
+    }
+    .map_err(|_| Status::InternalServerError)?;
+
+    let Some(site_id) = site_id else {
+        if filter.device_id.is_some() || filter.room_id.is_some() {
+            return Err(Status::NotFound);
+        }
+        return if user.is_admin {
+            Ok(())
+        } else {
+            Err(Status::Forbidden)
+        };
+    };
+    match site_role(database, user, site_id).await {
+        Ok(Some(role)) if role >= Role::Viewer => Ok(()),
+        Ok(_) => Err(Status::Forbidden),
+        Err(_) => Err(Status::InternalServerError),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[rocket::get("/export/readings?<site_id>&<room_id>&<device_id>&<from>&<to>&<format>")]
+pub async fn export_readings(
+    database: &State<Database>,
+    identity: Identity,
+    site_id: Option<i64>,
+    room_id: Option<i64>,
+    device_id: Option<i64>,
+    from: Option<&str>,
+    to: Option<&str>,
+    format: Option<ExportFormat>,
+) -> Result<(ContentType, ByteStream![Vec<u8>]), Status> {
+    let parse = |time: Option<&str>| {
+        time.map(|time| time.parse::<DateTime<Utc>>())
+            .transpose()
+            .map_err(|_| Status::BadRequest)
+    };
+    let filter = ExportFilter {
+        site_id,
+        room_id,
+        device_id,
+        from: parse(from)?,
+        to: parse(to)?,
+    };
+    authorize(database, &identity, &filter).await?;
+
+    let database = database.inner().clone();
+    let format = format.unwrap_or(ExportFormat::Csv);
+    let stream = ByteStream! {
+        if let Some(header) = format.header() {
+            yield header;
+        }
+        let mut rows = export_rows(&database, &filter);
+        while let Some(row) = rows.next().await {
+            match row.map_err(anyhow::Error::from).and_then(|row| format.row(&row)) {
+                Ok(line) => yield line,
+                Err(err) => {
+                    // The response has already started, so the export can only be cut short.
+                    error!("Failed to export readings: {}", err);
+                    break;
+                }
+            }
+        }
+    };
+    Ok((format.content_type(), stream))
+}
+
+fn csv_line<const N: usize>(fields: [&str; N]) -> Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(fields)?;
+    Ok(writer.into_inner()?)
+}
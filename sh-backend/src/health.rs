@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use rocket::State;
+use rocket::http::Status;
+use rocket::serde::json::Json;
+use serde::Serialize;
+use tokio::task::JoinHandle;
+
+use crate::db::Database;
+use crate::migrations::pending_migrations;
+use crate::with_pool;
+
+/// How long the database may take to answer before it counts as unreachable.
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The background workers of a running server, checked by `/readyz`.
+pub struct Health {
+    started_at: DateTime<Utc>,
+    workers: Vec<(&'static str, JoinHandle<()>)>,
+}
+
+impl Health {
+    pub fn new(workers: Vec<(&'static str, JoinHandle<()>)>) -> Self {
+        Self {
+            started_at: Utc::now(),
+            workers,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Liveness {
+    status: &'static str,
+    started_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    status: &'static str,
+    database: Check,
+    migrations: MigrationsCheck,
+    workers: Vec<WorkerCheck>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MigrationsCheck {
+    ok: bool,
+    pending: Vec<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WorkerCheck {
+    name: &'static str,
+    running: bool,
+}
+
+/// Answers as long as the process is alive, without touching the database.
+#[rocket::get("/healthz")]
+pub fn healthz(health: &State<Health>) -> Json<Liveness> {
+    Json(Liveness {
+        status: "alive",
+        started_at: health.started_at,
+    })
+}
+
+/// Ready when the database is reachable, its schema is up to date, and every background worker is running.
+#[rocket::get("/readyz")]
+pub async fn readyz(health: &State<Health>, db: &State<Database>) -> (Status, Json<Readiness>) {
+    let database = match tokio::time::timeout(DATABASE_TIMEOUT, async {
+        with_pool!(db.inner(), pool => sqlx::query("SELECT 1").execute(pool).await.map(|_| ()))
+    })
+    .await
+    {
+        Ok(Ok(_)) => Check {
+            ok: true,
+            error: None,
+        },
+        Ok(Err(err)) => Check {
+            ok: false,
+            error: Some(err.to_string()),
+        },
+        Err(_) => Check {
+            ok: false,
+            error: Some("Timed out".into()),
+        },
+    };
+
+    let migrations = if database.ok {
+        match pending_migrations(db).await {
+            Ok(pending) => MigrationsCheck {
+                ok: pending.is_empty(),
+                pending,
+                error: None,
+            },
+            Err(err) => MigrationsCheck {
+                ok: false,
+                pending: Vec::new(),
+                error: Some(err.to_string()),
+            },
+        }
+    } else {
+        MigrationsCheck {
+            ok: false,
+            pending: Vec::new(),
+            error: Some("Database is unreachable".into()),
+        }
+    };
+
+    let workers: Vec<_> = health
+        .workers
+        .iter()
+        .map(|(name, handle)| WorkerCheck {
+            name,
+            running: !handle.is_finished(),
+        })
+        .collect();
+
+    let ready = database.ok && migrations.ok && workers.iter().all(|worker| worker.running);
+    let status = if ready {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    };
+    (
+        status,
+        Json(Readiness {
+            status: if ready { "ready" } else { "not_ready" },
+            database,
+            migrations,
+            workers,
+        }),
+    )
+}
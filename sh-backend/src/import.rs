@@ -0,0 +1,261 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Read;
+
+use anyhow::{Result, anyhow};
+use async_graphql::{InputObject, SimpleObject};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use clap::Args;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::db::Database;
+use crate::models::{SensorReadingInput, SensorUnit};
+
+/// The CSV header names the readings are taken from.
+#[derive(Args, InputObject, Debug, Clone, Serialize)]
+pub struct ImportColumns {
+    /// The column holding the `uniqueIdentifier` of the device.
+    #[arg(long = "device-column", default_value = "device")]
+    #[graphql(default_with = "String::from(\"device\")")]
+    pub device: String,
+    #[arg(long = "value-column", default_value = "value")]
+    #[graphql(default_with = "String::from(\"value\")")]
+    pub value: String,
+    /// Readings are imported without a unit when the file has no such column.
+    #[arg(long = "unit-column", default_value = "unit")]
+    #[graphql(default_with = "String::from(\"unit\")")]
+    pub unit: String,
+    /// RFC 3339 timestamps, or `YYYY-MM-DD HH:MM:SS` in UTC.
+    #[arg(long = "timestamp-column", default_value = "timestamp")]
+    #[graphql(default_with = "String::from(\"timestamp\")")]
+    pub timestamp: String,
+}
+
+impl Default for ImportColumns {
+    fn default() -> Self {
+        Self {
+            device: "device".into(),
+            value: "value".into(),
+            unit: "unit".into(),
+            timestamp: "timestamp".into(),
+        }
+    }
+}
+
+#[derive(SimpleObject, Debug, Clone, Serialize)]
+pub struct RejectedLine {
+    /// The line of the file, counting the header as line 1.
+    pub line: u64,
+    pub reason: String,
+}
+
+/// The rejected lines an import mutation reports, so a file of mostly invalid rows stays cheap to answer.
+pub const MAX_REPORTED_REJECTIONS: usize = 100;
+
+#[derive(SimpleObject, Debug, Clone, Default, Serialize)]
+pub struct ImportReport {
+    pub imported: u64,
+    /// The first rejected lines, when the import reports only some of them.
+    pub rejected: Vec<RejectedLine>,
+    /// Every rejected line, reported or not.
+    pub rejected_count: u64,
+    /// How many of the readings were imported on each site.
+    #[graphql(skip)]
+    pub imported_by_site: BTreeMap<i64, u64>,
+}
+
+struct ColumnIndexes {
+    device: usize,
+    value: usize,
+    unit: Option<usize>,
+    timestamp: usize,
+}
+
+/// Imports historical sensor readings from a CSV file.
+///
+/// Devices are looked up by their unique identifier, and only devices on `sites` are accepted
+/// when it is given. Invalid rows are rejected one by one and reported, without failing the import,
+/// up to `max_rejected` of them when it is given. All of them are counted either way.
+/// Imported readings are not published as events, so automation rules and webhooks ignore them.
+///
+/// Rows are inserted in transactions of `chunk_size` readings,
+/// so a failing import keeps the chunks before it and never holds a write lock for long.
+///
+/// The file is parsed on a blocking thread, which hands the rows over as they are read.
+pub async fn import_readings(
+    database: &Database,
+    input: impl Read + Send + 'static,
+    columns: &ImportColumns,
+    sites: Option<&HashSet<i64>>,
+    chunk_size: usize,
+    max_rejected: Option<usize>,
+) -> Result<ImportReport> {
+    let (sender, mut records) = mpsc::channel(chunk_size.max(1));
+    let reader = tokio::task::spawn_blocking(move || read_records(input, sender));
+
+    let headers = records
+        .recv()
+        .await
+        .ok_or_else(|| anyhow!("The file could not be read"))??;
+    let position = |name: &str| headers.iter().position(|header| header == name);
+    let required =
+        |name: &str| position(name).ok_or_else(|| anyhow!("The file has no \"{}\" column", name));
+    let indexes = ColumnIndexes {
+        device: required(&columns.device)?,
+        value: required(&columns.value)?,
+        unit: position(&columns.unit),
+        timestamp: required(&columns.timestamp)?,
+    };
+
+    let mut devices = HashMap::new();
+    let mut report = ImportReport::default();
+    let mut chunk = Vec::with_capacity(chunk_size);
+    while let Some(record) = records.recv().await {
+        let (line, parsed) = match record {
+            Ok(record) => {
+                let line = record.position().map_or(0, |position| position.line());
+                let parsed = parse_record(database, &record, &indexes, sites, &mut devices).await?;
+                (line, parsed)
+            }
+            Err(err) => {
+                let line = err.position().map_or(0, |position| position.line());
+                (line, Err(err.to_string()))
+            }
+        };
+        match parsed {
+            Ok((site_id, reading)) => {
+                *report.imported_by_site.entry(site_id).or_default() += 1;
+                chunk.push(reading);
+            }
+            Err(reason) => {
+                if max_rejected.is_none_or(|max| report.rejected.len() < max) {
+                    report.rejected.push(RejectedLine { line, reason });
+                }
+                report.rejected_count += 1;
+            }
+        }
+        if chunk.len() >= chunk_size {
+            report.imported += insert_chunk(database, &chunk).await?;
+            chunk.clear();
+        }
+    }
+    report.imported += insert_chunk(database, &chunk).await?;
+    reader.await?;
+    Ok(report)
+}
+
+/// Sends the header of a CSV file and then its rows, until they run out or the import stops listening.
+fn read_records(input: impl Read, sender: mpsc::Sender<csv::Result<csv::StringRecord>>) {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(input);
+    if sender.blocking_send(reader.headers().cloned()).is_err() {
+        return;
+    }
+    for record in reader.records() {
+        if sender.blocking_send(record).is_err() {
+            return;
+        }
+    }
+}
+
+/// The number of rows of a CSV file below its header, valid or not.
+pub fn count_rows(input: impl Read) -> Result<u64> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(input);
+    let mut record = csv::ByteRecord::new();
+    let mut rows = 0;
+    while reader.read_byte_record(&mut record)? {
+        rows += 1;
+    }
+    Ok(rows)
+}
+
+/// Validates a row into a reading and the site of its device, returning why it was rejected if it is invalid.
+/// Only database failures abort the import.
+async fn parse_record(
+    database: &Database,
+    record: &csv::StringRecord,
+    indexes: &ColumnIndexes,
+    sites: Option<&HashSet<i64>>,
+    devices: &mut HashMap<String, Option<(i64, i64)>>,
+) -> Result<Result<(i64, (SensorReadingInput, DateTime<Utc>)), String>> {
+    let field = |index: usize| record.get(index).unwrap_or_default();
+
+    let identifier = field(indexes.device);
+    if identifier.is_empty() {
+        return Ok(Err("Missing device identifier".into()));
+    }
+    if !devices.contains_key(identifier) {
+        let device = database.devices().by_identifier(identifier).await?;
+        devices.insert(identifier.to_string(), device);
+    }
+    let Some((device_id, site_id)) = devices[identifier] else {
+        return Ok(Err(format!("Unknown device \"{}\"", identifier)));
+    };
+    if sites.is_some_and(|sites| !sites.contains(&site_id)) {
+        return Ok(Err(format!(
+            "Operator rights on site {} required for device \"{}\"",
+            site_id, identifier
+        )));
+    }
+
+    let value = field(indexes.value);
+    if value.parse::<f64>().is_err() {
+        return Ok(Err(format!("Invalid value \"{}\"", value)));
+    }
+
+    let unit = match indexes.unit.map(field).unwrap_or_default() {
+        "" => None,
+        unit => match parse_unit(unit) {
+            Some(unit) => Some(unit),
+            None => return Ok(Err(format!("Unknown unit \"{}\"", unit))),
+        },
+    };
+
+    let timestamp = field(indexes.timestamp);
+    let Some(timestamp) = parse_timestamp(timestamp) else {
+        return Ok(Err(format!("Invalid timestamp \"{}\"", timestamp)));
+    };
+    if timestamp > Utc::now() {
+        return Ok(Err(format!("Timestamp {} is in the future", timestamp)));
+    }
+
+    let input = SensorReadingInput {
+        device_id,
+        value: value.to_string(),
+        unit,
+    };
+    Ok(Ok((site_id, (input, timestamp))))
+}
+
+fn parse_unit(unit: &str) -> Option<SensorUnit> {
+    match unit.to_lowercase().as_str() {
+        "celsius" | "c" | "°c" => Some(SensorUnit::Celsius),
+        "fahrenheit" | "f" | "°f" => Some(SensorUnit::Fahrenheit),
+        _ => None,
+    }
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S")
+                .map(|timestamp| timestamp.and_utc())
+        })
+        .ok()
+}
+
+async fn insert_chunk(
+    database: &Database,
+    chunk: &[(SensorReadingInput, DateTime<Utc>)],
+) -> Result<u64> {
+    if chunk.is_empty() {
+        return Ok(0);
+    }
+    let mut tx = database.begin().await?;
+    let inserted = database.readings().create_many(&mut tx, chunk).await?;
+    tx.commit().await?;
+    Ok(inserted)
+}
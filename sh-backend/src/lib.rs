@@ -0,0 +1,27 @@
+pub mod audit;
+pub mod auth;
+pub mod automation;
+pub mod caching;
+pub mod compression;
+pub mod config;
+pub mod cors;
+pub mod db;
+pub mod events;
+pub mod export;
+pub mod health;
+pub mod import;
+pub mod limits;
+pub mod metrics;
+pub mod migrations;
+pub mod models;
+pub mod parquet_export;
+pub mod persisted_queries;
+pub mod rate_limits;
+pub mod repository;
+pub mod retention;
+pub mod schema;
+pub mod sdl;
+pub mod search;
+pub mod seed;
+pub mod server;
+pub mod webhooks;
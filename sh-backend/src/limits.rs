@@ -0,0 +1,192 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextExecute, NextParseQuery, NextValidation,
+};
+use async_graphql::parser::types::{ExecutableDocument, Selection};
+use async_graphql::{
+    ErrorExtensionValues, Response, ServerError, ServerResult, ValidationResult, Variables,
+};
+
+use crate::config::GraphqlConfig;
+
+/// The cost of a list that holds a handful of items, such as the rooms of a site,
+/// as a multiple of the cost of one item.
+pub const LIST_COST: usize = 10;
+
+/// The cost of a list that grows without bound, such as the readings of a device.
+pub const UNBOUNDED_LIST_COST: usize = 100;
+
+/// The most items a field taking a `limit` argument returns at once.
+pub const MAX_LIST_LIMIT: i64 = 500;
+
+/// How deep introspection queries may nest, enough for the `ofType` chains of the usual introspection query.
+pub const MAX_INTROSPECTION_DEPTH: usize = 15;
+
+/// How complex introspection queries may get, far above the usual introspection query.
+pub const MAX_INTROSPECTION_COMPLEXITY: usize = 10_000;
+
+/// The cost of a list holding at most `limit` items, for fields taking a `limit` argument.
+///
+/// Limits outside `1..=MAX_LIST_LIMIT` cost as much as the largest list, the resolvers reject them
+/// but the cost is computed before they run.
+pub fn limited_list_cost(limit: i64, child_complexity: usize) -> usize {
+    let limit = if (1..=MAX_LIST_LIMIT).contains(&limit) {
+        limit
+    } else {
+        MAX_LIST_LIMIT
+    };
+    // Capped well below `usize::MAX`, so adding up the costs of the other fields can't overflow.
+    (limit as usize)
+        .saturating_mul(child_complexity)
+        .min(u32::MAX as usize)
+}
+
+/// The cost of a list taking an optional `limit` argument, as much as an unbounded list when it is left out.
+pub fn optional_list_cost(limit: Option<i64>, child_complexity: usize) -> usize {
+    match limit {
+        Some(limit) => limited_list_cost(limit, child_complexity),
+        None => UNBOUNDED_LIST_COST.saturating_mul(child_complexity),
+    }
+}
+
+/// Rejects limits outside `1..=MAX_LIST_LIMIT`, for fields taking a `limit` argument.
+pub fn validate_limit(limit: i64) -> async_graphql::Result<()> {
+    if !(1..=MAX_LIST_LIMIT).contains(&limit) {
+        return Err(format!("The limit has to be between 1 and {}", MAX_LIST_LIMIT).into());
+    }
+    Ok(())
+}
+
+/// Rejects operations nested deeper or more complex than configured before running them,
+/// and cancels the ones that run longer than the timeout.
+///
+/// Introspection queries nest deeper than anything else, so they are held to
+/// [`MAX_INTROSPECTION_DEPTH`] and [`MAX_INTROSPECTION_COMPLEXITY`] instead of the configured limits.
+pub struct QueryLimits {
+    max_depth: usize,
+    max_complexity: usize,
+    timeout: Duration,
+}
+
+impl QueryLimits {
+    pub fn new(config: &GraphqlConfig) -> Self {
+        Self {
+            max_depth: config.max_depth,
+            max_complexity: config.max_complexity,
+            timeout: Duration::from_secs(config.timeout_seconds),
+        }
+    }
+}
+
+impl ExtensionFactory for QueryLimits {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(QueryLimitsExtension {
+            max_depth: self.max_depth,
+            max_complexity: self.max_complexity,
+            timeout: self.timeout,
+            introspection: AtomicBool::new(false),
+        })
+    }
+}
+
+/// Created for every request, remembering whether it only introspects the schema.
+struct QueryLimitsExtension {
+    max_depth: usize,
+    max_complexity: usize,
+    timeout: Duration,
+    introspection: AtomicBool,
+}
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for QueryLimitsExtension {
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+        self.introspection
+            .store(is_introspection(&document), Ordering::Relaxed);
+        Ok(document)
+    }
+
+    async fn validation(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        next: NextValidation<'_>,
+    ) -> Result<ValidationResult, Vec<ServerError>> {
+        let result = next.run(ctx).await?;
+        let (max_depth, max_complexity) = if self.introspection.load(Ordering::Relaxed) {
+            (MAX_INTROSPECTION_DEPTH, MAX_INTROSPECTION_COMPLEXITY)
+        } else {
+            (self.max_depth, self.max_complexity)
+        };
+        if result.depth > max_depth {
+            return Err(vec![limit_error(
+                format!(
+                    "The query is nested {} levels deep, more than the {} allowed",
+                    result.depth, max_depth
+                ),
+                "QUERY_TOO_DEEP",
+            )]);
+        }
+        if result.complexity > max_complexity {
+            return Err(vec![limit_error(
+                format!(
+                    "The query has a complexity of {}, more than the {} allowed, request fewer fields or smaller lists",
+                    result.complexity, max_complexity
+                ),
+                "QUERY_TOO_COMPLEX",
+            )]);
+        }
+        Ok(result)
+    }
+
+    async fn execute(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        operation_name: Option<&str>,
+        next: NextExecute<'_>,
+    ) -> Response {
+        match tokio::time::timeout(self.timeout, next.run(ctx, operation_name)).await {
+            Ok(response) => response,
+            // Dropping the operation rolls back the transactions it had open.
+            Err(_) => Response::from_errors(vec![limit_error(
+                format!(
+                    "The request was cancelled after exceeding the {:?} timeout",
+                    self.timeout
+                ),
+                "TIMEOUT",
+            )]),
+        }
+    }
+}
+
+/// Whether every operation of the document only selects introspection fields, such as `__schema`.
+fn is_introspection(document: &ExecutableDocument) -> bool {
+    document.operations.iter().all(|(_, operation)| {
+        operation
+            .node
+            .selection_set
+            .node
+            .items
+            .iter()
+            .all(|selection| match &selection.node {
+                Selection::Field(field) => field.node.name.node.starts_with("__"),
+                Selection::FragmentSpread(_) | Selection::InlineFragment(_) => false,
+            })
+    })
+}
+
+fn limit_error(message: String, code: &str) -> ServerError {
+    let mut extensions = ErrorExtensionValues::default();
+    extensions.set("code", code);
+    let mut error = ServerError::new(message, None);
+    error.extensions = Some(extensions);
+    error
+}
@@ -1,55 +1,334 @@
-mod models;
-mod schema;
-
-use async_graphql::{EmptySubscription, Schema, http::GraphiQLSource};
-use async_graphql_rocket::{GraphQLQuery, GraphQLRequest, GraphQLResponse};
-use rocket::routes;
-use rocket::{State, response::content};
-use schema::{AppSchema, SiteMutationRoot, SiteQueryRoot};
-use sqlx::sqlite::SqlitePool;
-
-#[rocket::get("/graphiql")]
-async fn graphiql() -> content::RawHtml<String> {
-    content::RawHtml(GraphiQLSource::build().endpoint("/graphql").finish())
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, bail};
+use clap::{Parser, Subcommand};
+use log::{error, info, warn};
+use sh_backend::automation::AutomationEngine;
+use sh_backend::config::{Config, ConfigArgs};
+use sh_backend::db::Database;
+use sh_backend::events::EventBus;
+use sh_backend::export::{ExportFilter, ExportFormat, write_export};
+use sh_backend::health::Health;
+use sh_backend::import::{ImportColumns, import_readings};
+use sh_backend::metrics::Metrics;
+use sh_backend::migrations::{MigrationState, migration_status, run_migrations};
+use sh_backend::parquet_export::export_parquet;
+use sh_backend::retention::RetentionJob;
+use sh_backend::sdl::{breaking_changes, export_sdl};
+use sh_backend::server::build_rocket;
+use sh_backend::webhooks::WebhookDispatcher;
+use tokio::io::BufWriter;
+
+/// Runs the server when no command is given.
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    #[command(flatten)]
+    config: ConfigArgs,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[rocket::get("/graphql?<query>")]
-async fn graphql_query(schema: &State<AppSchema>, query: GraphQLQuery) -> GraphQLResponse {
-    query.execute(schema.inner()).await
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Serves the GraphQL API, once the database schema is up to date
+    Serve,
+    /// Applies, lists or checks the database migrations
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateAction,
+    },
+    /// Exports sensor readings and control setpoints, joined with device and room names
+    ExportReadings {
+        #[command(flatten)]
+        filter: ExportFilter,
+        #[arg(long, value_enum, default_value = "csv")]
+        format: ExportFormat,
+        /// The file to write to, stdout when left out
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Imports historical sensor readings from a CSV file
+    Import {
+        /// The CSV file to import
+        file: PathBuf,
+        #[command(flatten)]
+        columns: ImportColumns,
+        /// Writes the rejected lines to this CSV file instead of the log
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Exports sensor readings to Parquet files, partitioned by site and day
+    ExportParquet {
+        #[command(flatten)]
+        filter: ExportFilter,
+        /// The directory the partitions are written to
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Writes the SDL of the GraphQL schema, or checks a committed copy for breaking changes
+    ExportSchema {
+        /// The file to write to, stdout when left out
+        #[arg(short, long, conflicts_with = "check")]
+        output: Option<PathBuf>,
+        /// Compares the schema against this file instead, failing on breaking changes
+        #[arg(long)]
+        check: Option<PathBuf>,
+    },
 }
 
-#[rocket::post("/graphql", data = "<request>", format = "application/json")]
-async fn graphql_request(schema: &State<AppSchema>, request: GraphQLRequest) -> GraphQLResponse {
-    request.execute(schema.inner()).await
+#[derive(Subcommand, Debug)]
+enum MigrateAction {
+    /// Applies the pending migrations, creating the database if it doesn't exist
+    Up,
+    /// Lists every migration and whether it has been applied
+    Status,
+    /// Fails unless every migration has been applied unchanged
+    Verify,
 }
 
-#[rocket::launch]
-async fn rocket() -> _ {
+#[rocket::main]
+async fn main() -> Result<()> {
     dotenvy::dotenv().ok();
 
-    env_logger::init();
+    let cli = Cli::parse();
+    let config = Config::load(&cli.config)?;
+
+    env_logger::Builder::new().parse_filters(&config.log).init();
+
+    let command = cli.command.unwrap_or(Command::Serve);
+    // The schema doesn't depend on the database, so it can be exported without one.
+    if let Command::ExportSchema { output, check } = command {
+        return export_schema(output, check);
+    }
+    // The server starts even while the database is unreachable and reports itself as not ready,
+    // the other commands need the database right away.
+    let database = match command {
+        Command::Serve => Database::connect_lazy(&config.database)?,
+        Command::Migrate { ref action } => {
+            Database::connect(&config.database, matches!(action, MigrateAction::Up)).await?
+        }
+        _ => Database::connect(&config.database, false).await?,
+    };
+    match command {
+        Command::Serve => {
+            serve(database, config).await?;
+        }
+        Command::Migrate { action } => {
+            migrate(&database, action).await?;
+        }
+        Command::ExportReadings {
+            filter,
+            format,
+            output,
+        } => {
+            let written = match output {
+                Some(path) => {
+                    let file = tokio::fs::File::create(&path).await?;
+                    write_export(&database, &filter, format, BufWriter::new(file)).await?
+                }
+                None => write_export(&database, &filter, format, tokio::io::stdout()).await?,
+            };
+            info!("Exported {} rows", written);
+        }
+        Command::Import {
+            file,
+            columns,
+            report: report_path,
+        } => {
+            let report = import_readings(
+                &database,
+                tokio::fs::File::open(&file).await?.into_std().await,
+                &columns,
+                None,
+                config.ingestion.import_chunk_size,
+                None,
+            )
+            .await?;
+            match report_path {
+                Some(path) => {
+                    let mut writer = csv::Writer::from_writer(Vec::new());
+                    for rejected in &report.rejected {
+                        writer.serialize(rejected)?;
+                    }
+                    tokio::fs::write(path, writer.into_inner()?).await?;
+                }
+                None => {
+                    for rejected in &report.rejected {
+                        warn!("Rejected line {}: {}", rejected.line, rejected.reason);
+                    }
+                }
+            }
+            info!(
+                "Imported {} readings, rejected {} lines",
+                report.imported, report.rejected_count
+            );
+        }
+        Command::ExportSchema { .. } => unreachable!("Handled before connecting to the database"),
+        Command::ExportParquet { filter, output } => {
+            let partitions = export_parquet(&database, &filter, &output).await?;
+            for partition in &partitions {
+                info!(
+                    "Wrote {} readings to {}",
+                    partition.rows,
+                    partition.path.display()
+                );
+            }
+            info!("Exported {} partitions", partitions.len());
+        }
+    }
+
+    Ok(())
+}
 
-    let database_url =
-        std::env::var("DATABASE_URL").expect("DATABASE_URL environment variable is not set");
+fn export_schema(output: Option<PathBuf>, check: Option<PathBuf>) -> Result<()> {
+    let sdl = export_sdl();
+    if let Some(path) = check {
+        let committed = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let changes = breaking_changes(&committed, &sdl)?;
+        if !changes.is_empty() {
+            for change in &changes {
+                error!("{}", change);
+            }
+            bail!(
+                "The schema has {} breaking changes compared to {}",
+                changes.len(),
+                path.display()
+            );
+        }
+        if committed != sdl {
+            warn!(
+                "The schema has changed without breaking clients, export it again to update {}",
+                path.display()
+            );
+        } else {
+            info!("The schema matches {}", path.display());
+        }
+        return Ok(());
+    }
+    match output {
+        Some(path) => std::fs::write(&path, sdl)?,
+        None => print!("{}", sdl),
+    }
+    Ok(())
+}
 
-    let pool = SqlitePool::connect(&database_url)
-        .await
-        .expect("Failed to connect to SQLite, check out the README for setup instructions");
+async fn migrate(database: &Database, action: MigrateAction) -> Result<()> {
+    match action {
+        MigrateAction::Up => {
+            let pending = migration_status(database)
+                .await?
+                .into_iter()
+                .filter(|migration| migration.state == MigrationState::Pending)
+                .count();
+            run_migrations(database).await?;
+            info!("Applied {} migrations", pending);
+        }
+        MigrateAction::Status => {
+            for migration in migration_status(database).await? {
+                let installed_on = migration
+                    .installed_on
+                    .map(|installed_on| installed_on.to_rfc3339())
+                    .unwrap_or_default();
+                println!(
+                    "{}  {:<8}  {:<25}  {}",
+                    migration.version,
+                    format!("{:?}", migration.state).to_lowercase(),
+                    installed_on,
+                    migration.description
+                );
+            }
+        }
+        MigrateAction::Verify => {
+            let problems: Vec<_> = migration_status(database)
+                .await?
+                .into_iter()
+                .filter(|migration| migration.state != MigrationState::Applied)
+                .map(|migration| format!("{} {:?}", migration.version, migration.state))
+                .collect();
+            if !problems.is_empty() {
+                bail!(
+                    "The database schema doesn't match the migrations: {}",
+                    problems.join(", ")
+                );
+            }
+            info!("Every migration has been applied");
+        }
+    }
+    Ok(())
+}
 
-    let env = std::env::var("ENVIRONMENT").unwrap_or_else(|_| "production".into());
-    if env == "development" {
-        sqlx::migrate!("./migrations")
-            .run(&pool)
-            .await
-            .expect("Failed to run database migrations");
+async fn serve(database: Database, config: Config) -> Result<()> {
+    match migration_status(&database).await {
+        Ok(status) => {
+            for migration in &status {
+                if matches!(
+                    migration.state,
+                    MigrationState::Modified | MigrationState::Unknown
+                ) {
+                    warn!(
+                        "Migration {} is {:?}, check it with `sh-backend migrate verify`",
+                        migration.version, migration.state
+                    );
+                }
+            }
+            let behind: Vec<_> = status
+                .into_iter()
+                .filter(|migration| {
+                    matches!(
+                        migration.state,
+                        MigrationState::Pending | MigrationState::Failed
+                    )
+                })
+                .map(|migration| migration.version.to_string())
+                .collect();
+            if !behind.is_empty() {
+                bail!(
+                    "The database schema is behind, migrations {} have not been applied, run `sh-backend migrate up` first",
+                    behind.join(", ")
+                );
+            }
+        }
+        Err(err) => error!(
+            "Failed to connect to the database, serving anyway until it becomes reachable: {}",
+            err
+        ),
     }
 
-    let schema = Schema::build(SiteQueryRoot, SiteMutationRoot, EmptySubscription)
-        .data(pool.clone())
-        .finish();
+    let events = EventBus::new();
+    let metrics = Metrics::new()?;
+    let mut workers = vec![(
+        "ingest_metrics",
+        metrics.spawn_ingest_counter(database.clone(), &events),
+    )];
+    // Without an HTTP client the workers that call out can't run, but the API can still be served.
+    if config.workers.automation {
+        match AutomationEngine::new(database.clone(), events.clone()) {
+            Ok(engine) => workers.push(("automation", engine.spawn())),
+            Err(err) => error!("Automation is disabled: {:#}", err),
+        }
+    }
+    if config.workers.webhooks {
+        match WebhookDispatcher::new(
+            database.clone(),
+            events.clone(),
+            config.workers.webhook_max_attempts,
+        ) {
+            Ok(dispatcher) => workers.push(("webhooks", dispatcher.spawn())),
+            Err(err) => error!("Webhooks are disabled: {:#}", err),
+        }
+    }
+    if config.retention.enabled {
+        workers.push((
+            "retention",
+            RetentionJob::new(database.clone(), config.retention_interval()).spawn(),
+        ));
+    }
+    let health = Health::new(workers);
 
-    rocket::build()
-        .manage(pool)
-        .manage(schema)
-        .mount("/", routes![graphql_query, graphql_request, graphiql])
+    build_rocket(database, events, config, metrics, health)?
+        .launch()
+        .await?;
+    Ok(())
 }
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::Result;
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextExecute, NextParseQuery,
+};
+use async_graphql::parser::types::{ExecutableDocument, OperationType, Selection};
+use async_graphql::registry::Registry as SchemaRegistry;
+use async_graphql::{Response, ServerResult, Variables};
+use chrono::{DateTime, Utc};
+use log::{error, warn};
+use prometheus::core::Collector;
+use prometheus::{
+    GaugeVec, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+
+use crate::db::Database;
+use crate::events::{Event, EventBus};
+use crate::models::SensorUnit;
+use crate::with_pool;
+
+/// The label of operations selecting several root fields, or fields the schema doesn't have.
+const OTHER_OPERATIONS: &str = "other";
+
+/// The Prometheus metrics of the service and the buildings it monitors, rendered by the `/metrics` route.
+/// Request metrics are recorded as they happen, pool and latest reading metrics are read at scrape time.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    graphql_requests: IntCounterVec,
+    graphql_request_duration: HistogramVec,
+    readings_ingested: IntCounterVec,
+    pool_connections: IntGauge,
+    pool_idle_connections: IntGauge,
+    latest_reading_value: GaugeVec,
+    latest_reading_timestamp: GaugeVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new_custom(Some("sh_backend".into()), None)?;
+        let metrics = Self {
+            graphql_requests: IntCounterVec::new(
+                Opts::new(
+                    "graphql_requests_total",
+                    "GraphQL requests by root field and outcome",
+                ),
+                &["field", "status"],
+            )?,
+            graphql_request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "graphql_request_duration_seconds",
+                    "GraphQL request latency by root field",
+                ),
+                &["field"],
+            )?,
+            readings_ingested: IntCounterVec::new(
+                Opts::new(
+                    "sensor_readings_ingested_total",
+                    "Sensor readings recorded by device type",
+                ),
+                &["device_type"],
+            )?,
+            pool_connections: IntGauge::new(
+                "db_pool_connections",
+                "Open connections of the database pool",
+            )?,
+            pool_idle_connections: IntGauge::new(
+                "db_pool_idle_connections",
+                "Idle connections of the database pool",
+            )?,
+            latest_reading_value: GaugeVec::new(
+                Opts::new(
+                    "sensor_reading_latest_value",
+                    "The latest reading of each device",
+                ),
+                &["site_id", "room_id", "device_id", "device_name", "unit"],
+            )?,
+            latest_reading_timestamp: GaugeVec::new(
+                Opts::new(
+                    "sensor_reading_latest_timestamp_seconds",
+                    "When the latest reading of each device was taken, as a Unix timestamp",
+                ),
+                &["site_id", "room_id", "device_id", "device_name"],
+            )?,
+            registry,
+        };
+
+        let collectors: [Box<dyn Collector>; 7] = [
+            Box::new(metrics.graphql_requests.clone()),
+            Box::new(metrics.graphql_request_duration.clone()),
+            Box::new(metrics.readings_ingested.clone()),
+            Box::new(metrics.pool_connections.clone()),
+            Box::new(metrics.pool_idle_connections.clone()),
+            Box::new(metrics.latest_reading_value.clone()),
+            Box::new(metrics.latest_reading_timestamp.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector)?;
+        }
+        Ok(metrics)
+    }
+
+    /// Refreshes the metrics read at scrape time, and renders all of them in the Prometheus text format.
+    pub async fn render(&self, database: &Database) -> Result<String> {
+        let (size, idle) = with_pool!(database, pool => (pool.size(), pool.num_idle()));
+        self.pool_connections.set(size as i64);
+        self.pool_idle_connections.set(idle as i64);
+        self.refresh_latest_readings(database).await?;
+        Ok(TextEncoder::new().encode_to_string(&self.registry.gather())?)
+    }
+
+    async fn refresh_latest_readings(&self, database: &Database) -> Result<()> {
+        // One lookup of the device's readings per device, rather than grouping every reading.
+        let sql = format!(
+            r#"
+            SELECT Room.site_id, Room.id, Device.id, Device.name, SensorReading.value, SensorReading.unit, SensorReading.timestamp
+            FROM Device
+            JOIN Room ON Room.id = Device.room_id
+            JOIN SensorReading ON SensorReading.device_id = Device.id AND SensorReading.id = (
+                SELECT latest.id
+                FROM SensorReading AS latest
+                WHERE latest.device_id = Device.id
+                ORDER BY {} DESC, latest.id DESC
+                LIMIT 1
+            )
+            "#,
+            database.backend().sortable_time("latest.timestamp")
+        );
+        let readings = with_pool!(database, pool => {
+            sqlx::query_as::<_, (i64, i64, i64, String, String, Option<SensorUnit>, DateTime<Utc>)>(&sql)
+                .fetch_all(pool)
+                .await
+        })?;
+
+        self.latest_reading_value.reset();
+        self.latest_reading_timestamp.reset();
+        for (site_id, room_id, device_id, device_name, value, unit, timestamp) in readings {
+            let (site_id, room_id, device_id) = (
+                site_id.to_string(),
+                room_id.to_string(),
+                device_id.to_string(),
+            );
+            if let Ok(value) = value.parse::<f64>() {
+                let unit = unit.map(|unit| format!("{:?}", unit)).unwrap_or_default();
+                self.latest_reading_value
+                    .with_label_values(&[
+                        site_id.as_str(),
+                        &room_id,
+                        &device_id,
+                        &device_name,
+                        &unit,
+                    ])
+                    .set(value);
+            }
+            self.latest_reading_timestamp
+                .with_label_values(&[site_id.as_str(), &room_id, &device_id, &device_name])
+                .set(timestamp.timestamp() as f64);
+        }
+        Ok(())
+    }
+
+    /// Counts the readings published on the event bus by device type.
+    pub fn spawn_ingest_counter(&self, database: Database, events: &EventBus) -> JoinHandle<()> {
+        let mut receiver = events.subscribe();
+        let readings_ingested = self.readings_ingested.clone();
+        tokio::spawn(async move {
+            let mut device_types = HashMap::new();
+            loop {
+                let device_id = match receiver.recv().await {
+                    Ok(Event::SensorReadingCreated { reading, .. }) => reading.device_id,
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!("Ingest metrics fell behind, skipped {} events", skipped);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                let device_type = match device_types.get(&device_id) {
+                    Some(device_type) => *device_type,
+                    None => match database.devices().find(device_id).await {
+                        Ok(Some(device)) => {
+                            *device_types.entry(device_id).or_insert(device.device_type)
+                        }
+                        Ok(None) => continue,
+                        Err(err) => {
+                            error!("Failed to look up device type for metrics: {}", err);
+                            continue;
+                        }
+                    },
+                };
+                readings_ingested
+                    .with_label_values(&[format!("{:?}", device_type).as_str()])
+                    .inc();
+            }
+        })
+    }
+
+    pub fn extension(&self) -> MetricsExtension {
+        MetricsExtension(self.clone())
+    }
+}
+
+/// Records the count and latency of every GraphQL operation.
+pub struct MetricsExtension(Metrics);
+
+impl ExtensionFactory for MetricsExtension {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(OperationMetrics {
+            metrics: self.0.clone(),
+            document: Mutex::new(None),
+        })
+    }
+}
+
+/// Created for every request, holding on to its document to label the operation that runs.
+struct OperationMetrics {
+    metrics: Metrics,
+    document: Mutex<Option<ExecutableDocument>>,
+}
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for OperationMetrics {
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+        *self.document.lock().unwrap() = Some(document.clone());
+        Ok(document)
+    }
+
+    async fn execute(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        operation_name: Option<&str>,
+        next: NextExecute<'_>,
+    ) -> Response {
+        let started_at = Instant::now();
+        let response = next.run(ctx, operation_name).await;
+        let field = self
+            .document
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|document| root_field(&ctx.schema_env.registry, document, operation_name))
+            .unwrap_or(OTHER_OPERATIONS);
+        let status = if response.is_ok() { "ok" } else { "error" };
+        self.metrics
+            .graphql_requests
+            .with_label_values(&[field, status])
+            .inc();
+        self.metrics
+            .graphql_request_duration
+            .with_label_values(&[field])
+            .observe(started_at.elapsed().as_secs_f64());
+        response
+    }
+}
+
+/// The root field the operation selects, aliased or not, as named by the schema.
+///
+/// Operations are labelled by it rather than by the name the client gives them,
+/// which would let clients create as many time series as they like.
+/// Operations selecting several root fields, or fields through fragments, are labelled as other.
+fn root_field<'a>(
+    registry: &'a SchemaRegistry,
+    document: &ExecutableDocument,
+    operation_name: Option<&str>,
+) -> Option<&'a str> {
+    let (_, operation) = document.operations.iter().find(|(name, _)| {
+        operation_name.is_none() || name.map(|name| name.as_str()) == operation_name
+    })?;
+    let mut names =
+        operation
+            .node
+            .selection_set
+            .node
+            .items
+            .iter()
+            .map(|selection| match &selection.node {
+                Selection::Field(field) => Some(field.node.name.node.as_str()),
+                Selection::FragmentSpread(_) | Selection::InlineFragment(_) => None,
+            });
+    let name = names.next()??;
+    if !names.all(|other| other == Some(name)) {
+        return None;
+    }
+    let root_type = match operation.node.ty {
+        OperationType::Query => Some(registry.query_type.as_str()),
+        OperationType::Mutation => registry.mutation_type.as_deref(),
+        OperationType::Subscription => registry.subscription_type.as_deref(),
+    }?;
+    if let Some(field) = registry
+        .concrete_type_by_name(root_type)
+        .and_then(|root_type| root_type.field_by_name(name))
+    {
+        return Some(field.name.as_str());
+    }
+    match name {
+        "__schema" => Some("__schema"),
+        "__type" => Some("__type"),
+        "__typename" => Some("__typename"),
+        _ => None,
+    }
+}
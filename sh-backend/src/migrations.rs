@@ -0,0 +1,132 @@
+use chrono::{DateTime, Utc};
+use sqlx::migrate::{MigrateError, Migrator};
+
+use crate::db::Database;
+use crate::with_pool;
+
+/// The migrations embedded into the binary at compile time.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// The PostgreSQL schema, the same as the SQLite one except for the search index, which is a `tsvector` rather than an FTS5 table.
+pub static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+
+pub fn migrator(database: &Database) -> &'static Migrator {
+    match database {
+        Database::Sqlite(_) => &MIGRATOR,
+        Database::Postgres(_) => &POSTGRES_MIGRATOR,
+    }
+}
+
+/// Applies the pending migrations of the database's backend.
+pub async fn run_migrations(database: &Database) -> Result<(), MigrateError> {
+    let migrator = migrator(database);
+    with_pool!(database, pool => migrator.run(pool).await)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied,
+    Pending,
+    /// Started but never finished, the database has to be repaired by hand.
+    Failed,
+    /// Applied, but the embedded migration has been edited since.
+    Modified,
+    /// Applied by a newer build, the binary doesn't know about it.
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub state: MigrationState,
+    pub installed_on: Option<DateTime<Utc>>,
+}
+
+/// The versions of the embedded migrations that have not been applied to the database yet.
+pub async fn pending_migrations(database: &Database) -> sqlx::Result<Vec<i64>> {
+    let applied = applied_migrations(database).await?;
+    Ok(migrator(database)
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| migration.version)
+        .filter(|version| {
+            !applied
+                .iter()
+                .any(|migration| migration.version == *version && migration.success)
+        })
+        .collect())
+}
+
+/// Every embedded and applied migration, ordered by version.
+pub async fn migration_status(database: &Database) -> sqlx::Result<Vec<MigrationStatus>> {
+    let applied = applied_migrations(database).await?;
+    let mut status: Vec<_> = migrator(database)
+        .iter()
+        .filter(|migration| !migration.migration_type.is_down_migration())
+        .map(|migration| {
+            let applied = applied
+                .iter()
+                .find(|applied| applied.version == migration.version);
+            let state = match applied {
+                None => MigrationState::Pending,
+                Some(applied) if !applied.success => MigrationState::Failed,
+                Some(applied) if *applied.checksum != *migration.checksum => {
+                    MigrationState::Modified
+                }
+                Some(_) => MigrationState::Applied,
+            };
+            MigrationStatus {
+                version: migration.version,
+                description: migration.description.to_string(),
+                state,
+                installed_on: applied.map(|applied| applied.installed_on),
+            }
+        })
+        .collect();
+
+    for migration in &applied {
+        if !status
+            .iter()
+            .any(|known| known.version == migration.version)
+        {
+            status.push(MigrationStatus {
+                version: migration.version,
+                description: migration.description.clone(),
+                state: MigrationState::Unknown,
+                installed_on: Some(migration.installed_on),
+            });
+        }
+    }
+    status.sort_by_key(|migration| migration.version);
+    Ok(status)
+}
+
+#[derive(sqlx::FromRow)]
+struct AppliedMigration {
+    version: i64,
+    description: String,
+    success: bool,
+    checksum: Vec<u8>,
+    installed_on: DateTime<Utc>,
+}
+
+async fn applied_migrations(database: &Database) -> sqlx::Result<Vec<AppliedMigration>> {
+    let exists_sql = match database {
+        Database::Sqlite(_) => {
+            "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations')"
+        }
+        Database::Postgres(_) => "SELECT to_regclass('_sqlx_migrations') IS NOT NULL",
+    };
+    with_pool!(database, pool => {
+        let exists = sqlx::query_scalar::<_, bool>(exists_sql).fetch_one(pool).await?;
+        if !exists {
+            return Ok(Vec::new());
+        }
+        sqlx::query_as::<_, AppliedMigration>(
+            "SELECT version, description, success, checksum, installed_on FROM _sqlx_migrations ORDER BY version",
+        )
+        .fetch_all(pool)
+        .await
+    })
+}
@@ -1,60 +1,160 @@
-use async_graphql::{ComplexObject, Context, Enum, InputObject, Result, SimpleObject};
+use async_graphql::{ComplexObject, Context, Enum, InputObject, Json, Result, SimpleObject, Union};
 
-use chrono::{DateTime, Utc};
-use sqlx::{FromRow, SqlitePool};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
 
-#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, sqlx::Type)]
+use crate::auth::require_site_role;
+use crate::automation::{RuleAction, RuleCondition, RuleTrigger};
+use crate::db::Database;
+use crate::limits::{
+    LIST_COST, UNBOUNDED_LIST_COST, limited_list_cost, optional_list_cost, validate_limit,
+};
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, Serialize, sqlx::Type)]
 #[sqlx(rename_all = "PascalCase")]
 pub enum DeviceType {
     TemperatureSensor,
     ThermostatController,
 }
 
-#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, sqlx::Type)]
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, Serialize, sqlx::Type)]
 #[sqlx(rename_all = "PascalCase")]
 pub enum SensorUnit {
     Celsius,
     Fahrenheit,
 }
 
-#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, sqlx::Type)]
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(rename_all = "PascalCase")]
 pub enum SetpointType {
     Temperature,
 }
 
-#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, sqlx::Type)]
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(rename_all = "PascalCase")]
 pub enum SetpointUnit {
     Celsius,
     Fahrenheit,
 }
 
-#[derive(SimpleObject, Debug, Clone, FromRow)]
+/// How a setpoint competes with the others of its device.
+/// Overrides and holds take precedence over scheduled setpoints while they are active,
+/// an override until it expires and a hold until it is released.
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "PascalCase")]
+pub enum SetpointMode {
+    #[default]
+    Scheduled,
+    Override,
+    Hold,
+}
+
+/// The kinds of entities that can be tagged, and searched for.
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "PascalCase")]
+pub enum TaggedEntity {
+    Site,
+    Room,
+    Device,
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Debug, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "PascalCase")]
+pub enum Role {
+    Viewer,
+    Operator,
+    Admin,
+}
+
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
 #[graphql(complex)]
 pub struct Site {
     pub id: i64,
     pub name: String,
     pub address: Option<String>,
     #[graphql(skip)]
+    pub metadata: sqlx::types::Json<serde_json::Value>,
+    #[graphql(skip)]
     pub created_at: DateTime<Utc>,
     #[graphql(skip)]
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(SimpleObject, Debug, Clone, FromRow)]
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
 #[graphql(complex)]
 pub struct Room {
     pub id: i64,
     pub site_id: i64,
+    /// The floor the room is on, left empty until the site's buildings are laid out.
+    pub floor_id: Option<i64>,
     pub name: String,
     #[graphql(skip)]
+    pub metadata: sqlx::types::Json<serde_json::Value>,
+    #[graphql(skip)]
     pub created_at: DateTime<Utc>,
     #[graphql(skip)]
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(SimpleObject, Debug, Clone, FromRow)]
+/// A key, such as `north-facing`, or a key with a value, such as `vendor` = `Danfoss`.
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
+pub struct Tag {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+/// A site, room or device found by `search`.
+#[derive(Union, Debug, Clone)]
+pub enum SearchResult {
+    Site(Site),
+    Room(Room),
+    Device(Device),
+}
+
+/// A building of a site, made up of floors.
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
+#[graphql(complex)]
+pub struct Building {
+    pub id: i64,
+    pub site_id: i64,
+    pub name: String,
+    #[graphql(skip)]
+    pub created_at: DateTime<Utc>,
+    #[graphql(skip)]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
+#[graphql(complex)]
+pub struct Floor {
+    pub id: i64,
+    pub building_id: i64,
+    pub site_id: i64,
+    pub name: String,
+    /// Orders the floors of a building, 0 being the ground floor and negative levels below it.
+    pub level: i64,
+    #[graphql(skip)]
+    pub created_at: DateTime<Utc>,
+    #[graphql(skip)]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A group of rooms of a site, such as a wing or an HVAC zone, regardless of their floor.
+/// A room may belong to several zones.
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
+#[graphql(complex)]
+pub struct Zone {
+    pub id: i64,
+    pub site_id: i64,
+    pub name: String,
+    #[graphql(skip)]
+    pub created_at: DateTime<Utc>,
+    #[graphql(skip)]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
 #[graphql(complex)]
 pub struct Device {
     pub id: i64,
@@ -63,12 +163,14 @@ pub struct Device {
     pub device_type: DeviceType,
     pub unique_identifier: Option<String>,
     #[graphql(skip)]
+    pub metadata: sqlx::types::Json<serde_json::Value>,
+    #[graphql(skip)]
     pub created_at: DateTime<Utc>,
     #[graphql(skip)]
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(SimpleObject, Debug, Clone, FromRow)]
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
 pub struct SensorReading {
     pub id: i64,
     pub device_id: i64,
@@ -81,81 +183,681 @@ pub struct SensorReading {
     pub updated_at: DateTime<Utc>,
 }
 
-#[derive(SimpleObject, Debug, Clone, FromRow)]
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
 pub struct ControlSetpoint {
     pub id: i64,
     pub device_id: i64,
     pub setpoint_type: SetpointType,
     pub value: String,
     pub unit: Option<SetpointUnit>,
+    pub mode: SetpointMode,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub timestamp: DateTime<Utc>,
+    #[graphql(skip)]
+    pub created_at: DateTime<Utc>,
+    #[graphql(skip)]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
+pub struct User {
+    pub id: i64,
+    pub name: String,
+    #[graphql(skip)]
+    #[serde(skip)]
+    pub token_hash: String,
+    pub is_admin: bool,
+    #[graphql(skip)]
+    pub created_at: DateTime<Utc>,
+    #[graphql(skip)]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A freshly created user together with their API token, which is only ever returned once.
+#[derive(SimpleObject, Debug, Clone)]
+pub struct UserWithToken {
+    pub user: User,
+    pub token: String,
+}
+
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
+pub struct SiteRole {
+    pub id: i64,
+    pub user_id: i64,
+    pub site_id: i64,
+    pub role: Role,
+    #[graphql(skip)]
+    pub created_at: DateTime<Utc>,
+    #[graphql(skip)]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
+pub struct DeviceCredential {
+    pub id: i64,
+    pub device_id: i64,
+    /// The leading characters of the key, enough to tell keys apart without revealing them.
+    pub key_prefix: String,
+    #[graphql(skip)]
+    #[serde(skip)]
+    pub key_hash: String,
+    pub revoked_at: Option<DateTime<Utc>>,
+    #[graphql(skip)]
+    pub created_at: DateTime<Utc>,
+    #[graphql(skip)]
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A freshly issued device credential together with its key, which is only ever returned once.
+#[derive(SimpleObject, Debug, Clone)]
+pub struct DeviceCredentialWithKey {
+    pub credential: DeviceCredential,
+    pub key: String,
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "PascalCase")]
+pub enum AuditActorType {
+    Anonymous,
+    User,
+    Device,
+    Automation,
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "PascalCase")]
+pub enum AuditEntityType {
+    Site,
+    Room,
+    Building,
+    Floor,
+    Zone,
+    Device,
+    SensorReading,
+    ControlSetpoint,
+    User,
+    SiteRole,
+    DeviceCredential,
+    AutomationRule,
+    Alert,
+    WebhookSubscription,
+    RetentionPolicy,
+}
+
+/// A recorded mutation, with the arguments and entity snapshots stored as JSON.
+#[derive(SimpleObject, Debug, Clone, FromRow)]
+#[graphql(complex)]
+pub struct AuditLogEntry {
+    pub id: i64,
+    pub site_id: Option<i64>,
+    pub actor_type: AuditActorType,
+    pub actor_id: Option<i64>,
+    pub actor_name: Option<String>,
+    pub operation: String,
+    #[graphql(skip)]
+    pub arguments: String,
+    pub entity_type: AuditEntityType,
+    pub entity_id: Option<i64>,
+    #[graphql(skip)]
+    pub before: Option<String>,
+    #[graphql(skip)]
+    pub after: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[ComplexObject]
+impl AuditLogEntry {
+    async fn arguments(&self) -> Result<Json<serde_json::Value>> {
+        Ok(Json(serde_json::from_str(&self.arguments)?))
+    }
+
+    async fn before(&self) -> Result<Option<Json<serde_json::Value>>> {
+        let snapshot = self
+            .before
+            .as_deref()
+            .map(serde_json::from_str)
+            .transpose()?;
+        Ok(snapshot.map(Json))
+    }
+
+    async fn after(&self) -> Result<Option<Json<serde_json::Value>>> {
+        let snapshot = self
+            .after
+            .as_deref()
+            .map(serde_json::from_str)
+            .transpose()?;
+        Ok(snapshot.map(Json))
+    }
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, Default, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "PascalCase")]
+pub enum AlertSeverity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "PascalCase")]
+pub enum RuleExecutionStatus {
+    Succeeded,
+    /// The rule was triggered, but its conditions did not hold.
+    Skipped,
+    Failed,
+}
+
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
+#[graphql(complex)]
+pub struct AutomationRule {
+    pub id: i64,
+    pub site_id: i64,
+    pub name: String,
+    pub enabled: bool,
+    #[graphql(skip)]
+    pub trigger: sqlx::types::Json<RuleTrigger>,
+    #[graphql(skip)]
+    pub conditions: sqlx::types::Json<Vec<RuleCondition>>,
+    #[graphql(skip)]
+    pub actions: sqlx::types::Json<Vec<RuleAction>>,
+    pub last_triggered_at: Option<DateTime<Utc>>,
+    #[graphql(skip)]
+    pub created_at: DateTime<Utc>,
+    #[graphql(skip)]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[ComplexObject]
+impl AutomationRule {
+    async fn trigger(&self) -> Json<RuleTrigger> {
+        Json(self.trigger.0.clone())
+    }
+
+    async fn conditions(&self) -> Json<Vec<RuleCondition>> {
+        Json(self.conditions.0.clone())
+    }
+
+    async fn actions(&self) -> Json<Vec<RuleAction>> {
+        Json(self.actions.0.clone())
+    }
+}
+
+#[derive(SimpleObject, Debug, Clone, FromRow)]
+#[graphql(complex)]
+pub struct RuleExecution {
+    pub id: i64,
+    pub rule_id: i64,
+    pub status: RuleExecutionStatus,
+    #[graphql(skip)]
+    pub event: String,
+    pub message: Option<String>,
     pub timestamp: DateTime<Utc>,
+}
+
+#[ComplexObject]
+impl RuleExecution {
+    /// The event that triggered the rule.
+    async fn event(&self) -> Result<Json<serde_json::Value>> {
+        Ok(Json(serde_json::from_str(&self.event)?))
+    }
+}
+
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
+pub struct Alert {
+    pub id: i64,
+    pub site_id: i64,
+    pub rule_id: Option<i64>,
+    pub severity: AlertSeverity,
+    pub message: String,
+    pub raised_at: DateTime<Utc>,
+    pub acknowledged_at: Option<DateTime<Utc>>,
+    #[graphql(skip)]
+    pub created_at: DateTime<Utc>,
+    #[graphql(skip)]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "PascalCase")]
+pub enum WebhookEventType {
+    SensorReadingCreated,
+    ControlSetpointCreated,
+    AlertRaised,
+}
+
+impl WebhookEventType {
+    /// The name events are sent with, which matches the GraphQL enum value
+    /// and stays the same if the variants are ever renamed.
+    pub fn name(self) -> &'static str {
+        match self {
+            WebhookEventType::SensorReadingCreated => "SENSOR_READING_CREATED",
+            WebhookEventType::ControlSetpointCreated => "CONTROL_SETPOINT_CREATED",
+            WebhookEventType::AlertRaised => "ALERT_RAISED",
+        }
+    }
+}
+
+/// How finely a sample of sensor history is resolved.
+/// Raw readings are rolled up into hourly and daily summaries once they leave the raw retention window.
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "PascalCase")]
+pub enum ReadingResolution {
+    Raw,
+    Hourly,
+    Daily,
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq, Debug, Serialize, sqlx::Type)]
+#[sqlx(rename_all = "PascalCase")]
+pub enum WebhookDeliveryStatus {
+    Pending,
+    Succeeded,
+    /// Every attempt failed, the delivery will not be retried.
+    Failed,
+}
+
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
+#[graphql(complex)]
+pub struct WebhookSubscription {
+    pub id: i64,
+    /// Subscriptions without a site receive the events of every site.
+    pub site_id: Option<i64>,
+    pub url: String,
+    #[graphql(skip)]
+    pub event_types: sqlx::types::Json<Vec<WebhookEventType>>,
+    #[graphql(skip)]
+    #[serde(skip)]
+    pub secret: String,
+    pub enabled: bool,
+    #[graphql(skip)]
+    pub created_at: DateTime<Utc>,
+    #[graphql(skip)]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[ComplexObject]
+impl WebhookSubscription {
+    async fn event_types(&self) -> Vec<WebhookEventType> {
+        self.event_types.0.clone()
+    }
+}
+
+/// A freshly created webhook subscription together with the secret its payloads are signed with,
+/// which is only ever returned once.
+#[derive(SimpleObject, Debug, Clone)]
+pub struct WebhookSubscriptionWithSecret {
+    pub subscription: WebhookSubscription,
+    pub secret: String,
+}
+
+#[derive(SimpleObject, Debug, Clone, FromRow)]
+#[graphql(complex)]
+pub struct WebhookDelivery {
+    pub id: i64,
+    pub subscription_id: i64,
+    pub event_type: WebhookEventType,
+    #[graphql(skip)]
+    pub payload: String,
+    pub status: WebhookDeliveryStatus,
+    pub attempts: i64,
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub last_response_status: Option<i64>,
+    pub last_error: Option<String>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    #[graphql(skip)]
+    pub created_at: DateTime<Utc>,
+    #[graphql(skip)]
+    pub updated_at: DateTime<Utc>,
+}
+
+#[ComplexObject]
+impl WebhookDelivery {
+    async fn payload(&self) -> Result<Json<serde_json::Value>> {
+        Ok(Json(serde_json::from_str(&self.payload)?))
+    }
+}
+
+/// How long the readings of a site or device type are kept at each resolution.
+/// Retention periods left empty are kept forever.
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
+pub struct RetentionPolicy {
+    pub id: i64,
+    /// Policies without a site apply to every site.
+    pub site_id: Option<i64>,
+    /// Policies without a device type apply to every device type.
+    pub device_type: Option<DeviceType>,
+    pub raw_retention_days: i64,
+    pub hourly_retention_days: Option<i64>,
+    pub daily_retention_days: Option<i64>,
     #[graphql(skip)]
     pub created_at: DateTime<Utc>,
     #[graphql(skip)]
     pub updated_at: DateTime<Utc>,
 }
 
+/// A point of sensor history, either a single raw reading holding a number or a summary of the readings in an hour or day.
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
+pub struct SensorReadingSample {
+    /// The ID of a raw reading, summaries have none.
+    pub id: Option<i64>,
+    pub device_id: i64,
+    /// The time of a raw reading, or the start of the summarized hour or day.
+    pub timestamp: DateTime<Utc>,
+    pub resolution: ReadingResolution,
+    pub unit: Option<SensorUnit>,
+    /// The value of a raw reading as it was recorded, or the average of a summary.
+    pub value: String,
+    pub sample_count: i64,
+    pub min_value: f64,
+    pub max_value: f64,
+    pub avg_value: f64,
+}
+
+/// The numeric readings of the devices on a floor or in a zone, summarized per unit.
+#[derive(SimpleObject, Debug, Clone, Serialize, FromRow)]
+pub struct ReadingSummary {
+    pub unit: Option<SensorUnit>,
+    pub device_count: i64,
+    pub sample_count: i64,
+    pub min_value: f64,
+    pub max_value: f64,
+    pub avg_value: f64,
+}
+
+/// The longest range floors and zones summarize at once.
+const MAX_SUMMARY_DAYS: i64 = 31;
+
+/// The range a summary covers, ending now and starting a day before unless given.
+fn summary_range(
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+) -> Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let to = to.unwrap_or_else(Utc::now);
+    let from = from.unwrap_or(to - Duration::days(1));
+    if from > to {
+        return Err("A summary cannot end before it starts".into());
+    }
+    if to - from > Duration::days(MAX_SUMMARY_DAYS) {
+        return Err(format!("A summary cannot span more than {} days", MAX_SUMMARY_DAYS).into());
+    }
+    Ok((from, to))
+}
+
 #[ComplexObject]
 impl Site {
+    async fn metadata(&self) -> Json<serde_json::Value> {
+        Json(self.metadata.0.clone())
+    }
+
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn tags(&self, ctx: &Context<'_>) -> Result<Vec<Tag>> {
+        require_site_role(ctx, self.id, Role::Viewer).await?;
+        let tags = ctx
+            .data::<Database>()?
+            .tags()
+            .for_entity(TaggedEntity::Site, self.id)
+            .await?;
+        Ok(tags)
+    }
+
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn rooms(&self, ctx: &Context<'_>) -> Result<Vec<Room>> {
+        require_site_role(ctx, self.id, Role::Viewer).await?;
+        let rooms = ctx.data::<Database>()?.rooms().for_site(self.id).await?;
+        Ok(rooms)
+    }
+
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn buildings(&self, ctx: &Context<'_>) -> Result<Vec<Building>> {
+        require_site_role(ctx, self.id, Role::Viewer).await?;
+        let buildings = ctx
+            .data::<Database>()?
+            .buildings()
+            .for_site(self.id)
+            .await?;
+        Ok(buildings)
+    }
+
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn zones(&self, ctx: &Context<'_>) -> Result<Vec<Zone>> {
+        require_site_role(ctx, self.id, Role::Viewer).await?;
+        let zones = ctx.data::<Database>()?.zones().for_site(self.id).await?;
+        Ok(zones)
+    }
+}
+
+#[ComplexObject]
+impl Building {
+    /// Floors from the lowest level up.
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn floors(&self, ctx: &Context<'_>) -> Result<Vec<Floor>> {
+        require_site_role(ctx, self.site_id, Role::Viewer).await?;
+        let floors = ctx
+            .data::<Database>()?
+            .floors()
+            .for_building(self.id)
+            .await?;
+        Ok(floors)
+    }
+}
+
+#[ComplexObject]
+impl Floor {
+    #[graphql(complexity = "LIST_COST * child_complexity")]
     async fn rooms(&self, ctx: &Context<'_>) -> Result<Vec<Room>> {
-        let pool = ctx.data::<SqlitePool>()?;
-        let rooms = sqlx::query_as::<_, Room>("SELECT * FROM Room WHERE site_id = ?")
-            .bind(self.id)
-            .fetch_all(pool)
+        require_site_role(ctx, self.site_id, Role::Viewer).await?;
+        let rooms = ctx.data::<Database>()?.rooms().for_floor(self.id).await?;
+        Ok(rooms)
+    }
+
+    /// The readings taken on the floor between two points in time, the last day unless given,
+    /// read from rollups where the raw readings have already been pruned.
+    /// The range can span at most 31 days.
+    #[graphql(complexity = "UNBOUNDED_LIST_COST * child_complexity")]
+    async fn reading_summary(
+        &self,
+        ctx: &Context<'_>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<ReadingSummary>> {
+        require_site_role(ctx, self.site_id, Role::Viewer).await?;
+        let (from, to) = summary_range(from, to)?;
+        let summary = ctx
+            .data::<Database>()?
+            .readings()
+            .floor_summary(self.id, from, to)
             .await?;
+        Ok(summary)
+    }
+}
+
+#[ComplexObject]
+impl Zone {
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn rooms(&self, ctx: &Context<'_>) -> Result<Vec<Room>> {
+        require_site_role(ctx, self.site_id, Role::Viewer).await?;
+        let rooms = ctx.data::<Database>()?.rooms().for_zone(self.id).await?;
         Ok(rooms)
     }
+
+    /// The readings taken in the zone's rooms between two points in time, the last day unless given,
+    /// read from rollups where the raw readings have already been pruned.
+    /// The range can span at most 31 days.
+    #[graphql(complexity = "UNBOUNDED_LIST_COST * child_complexity")]
+    async fn reading_summary(
+        &self,
+        ctx: &Context<'_>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<ReadingSummary>> {
+        require_site_role(ctx, self.site_id, Role::Viewer).await?;
+        let (from, to) = summary_range(from, to)?;
+        let summary = ctx
+            .data::<Database>()?
+            .readings()
+            .zone_summary(self.id, from, to)
+            .await?;
+        Ok(summary)
+    }
 }
 
 #[ComplexObject]
 impl Room {
+    async fn metadata(&self) -> Json<serde_json::Value> {
+        Json(self.metadata.0.clone())
+    }
+
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn tags(&self, ctx: &Context<'_>) -> Result<Vec<Tag>> {
+        require_site_role(ctx, self.site_id, Role::Viewer).await?;
+        let tags = ctx
+            .data::<Database>()?
+            .tags()
+            .for_entity(TaggedEntity::Room, self.id)
+            .await?;
+        Ok(tags)
+    }
+
+    #[graphql(complexity = "LIST_COST * child_complexity")]
     async fn devices(&self, ctx: &Context<'_>) -> Result<Vec<Device>> {
-        let pool = ctx.data::<SqlitePool>()?;
-        let devices = sqlx::query_as::<_, Device>("SELECT * FROM Device WHERE room_id = ?")
-            .bind(self.id)
-            .fetch_all(pool)
+        require_site_role(ctx, self.site_id, Role::Viewer).await?;
+        let devices = ctx
+            .data::<Database>()?
+            .devices()
+            .for_room(self.id, &[])
             .await?;
         Ok(devices)
     }
+
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn zones(&self, ctx: &Context<'_>) -> Result<Vec<Zone>> {
+        require_site_role(ctx, self.site_id, Role::Viewer).await?;
+        let zones = ctx.data::<Database>()?.zones().for_room(self.id).await?;
+        Ok(zones)
+    }
+}
+
+impl Device {
+    /// Devices are seen with viewer rights on the site of their room, like the rooms themselves.
+    async fn require_viewer(&self, ctx: &Context<'_>) -> Result<()> {
+        let database = ctx.data::<Database>()?;
+        let Some(site_id) = database.rooms().site_id(self.room_id).await? else {
+            return Err(format!("Room with ID {} does not exist", self.room_id).into());
+        };
+        require_site_role(ctx, site_id, Role::Viewer).await
+    }
 }
 
 #[ComplexObject]
 impl Device {
-    async fn sensor_readings(&self, ctx: &Context<'_>) -> Result<Vec<SensorReading>> {
-        let pool = ctx.data::<SqlitePool>()?;
-        let readings =
-            sqlx::query_as::<_, SensorReading>("SELECT * FROM SensorReading WHERE device_id = ?")
-                .bind(self.id)
-                .fetch_all(pool)
-                .await?;
+    async fn metadata(&self) -> Json<serde_json::Value> {
+        Json(self.metadata.0.clone())
+    }
+
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn tags(&self, ctx: &Context<'_>) -> Result<Vec<Tag>> {
+        self.require_viewer(ctx).await?;
+        let tags = ctx
+            .data::<Database>()?
+            .tags()
+            .for_entity(TaggedEntity::Device, self.id)
+            .await?;
+        Ok(tags)
+    }
+
+    /// The raw readings of the device, oldest first, or only the latest `limit` of them.
+    #[graphql(complexity = "optional_list_cost(limit, child_complexity)")]
+    async fn sensor_readings(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<i64>,
+    ) -> Result<Vec<SensorReading>> {
+        self.require_viewer(ctx).await?;
+        if let Some(limit) = limit {
+            validate_limit(limit)?;
+        }
+        let readings = ctx
+            .data::<Database>()?
+            .readings()
+            .for_device(self.id, limit)
+            .await?;
         Ok(readings)
     }
 
+    /// The latest numeric samples between two points in time, oldest first, read from hourly or daily rollups
+    /// where the raw readings have already been pruned.
+    #[graphql(complexity = "limited_list_cost(limit, child_complexity)")]
+    async fn reading_samples(
+        &self,
+        ctx: &Context<'_>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        #[graphql(default = 100)] limit: i64,
+    ) -> Result<Vec<SensorReadingSample>> {
+        self.require_viewer(ctx).await?;
+        validate_limit(limit)?;
+        let samples = ctx
+            .data::<Database>()?
+            .readings()
+            .history(self.id, from, to, limit)
+            .await?;
+        Ok(samples)
+    }
+
+    #[graphql(complexity = "UNBOUNDED_LIST_COST * child_complexity")]
     async fn control_setpoints(&self, ctx: &Context<'_>) -> Result<Vec<ControlSetpoint>> {
-        let pool = ctx.data::<SqlitePool>()?;
-        let setpoints = sqlx::query_as::<_, ControlSetpoint>(
-            "SELECT * FROM ControlSetpoint WHERE device_id = ?",
-        )
-        .bind(self.id)
-        .fetch_all(pool)
-        .await?;
+        self.require_viewer(ctx).await?;
+        let setpoints = ctx
+            .data::<Database>()?
+            .setpoints()
+            .for_device(self.id)
+            .await?;
         Ok(setpoints)
     }
 }
 
-#[derive(InputObject, Debug, Clone)]
+#[derive(InputObject, Debug, Clone, Serialize)]
 pub struct SiteInput {
     pub name: String,
     pub address: Option<String>,
 }
 
-#[derive(InputObject, Debug, Clone)]
+#[derive(InputObject, Debug, Clone, Serialize)]
 pub struct RoomInput {
     pub site_id: i64,
     pub name: String,
+    pub floor_id: Option<i64>,
+}
+
+/// Matches the entities tagged with the key, and with the value if there is one.
+#[derive(InputObject, Debug, Clone, Serialize)]
+pub struct TagFilter {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+#[derive(InputObject, Debug, Clone, Serialize)]
+pub struct BuildingInput {
+    pub site_id: i64,
+    pub name: String,
+}
+
+#[derive(InputObject, Debug, Clone, Serialize)]
+pub struct FloorInput {
+    pub building_id: i64,
+    pub name: String,
+    #[graphql(default)]
+    pub level: i64,
 }
 
-#[derive(InputObject, Debug, Clone)]
+#[derive(InputObject, Debug, Clone, Serialize)]
+pub struct ZoneInput {
+    pub site_id: i64,
+    pub name: String,
+}
+
+#[derive(InputObject, Debug, Clone, Serialize)]
 pub struct DeviceInput {
     pub room_id: i64,
     pub name: String,
@@ -163,17 +865,105 @@ pub struct DeviceInput {
     pub unique_identifier: Option<String>,
 }
 
-#[derive(InputObject, Debug, Clone)]
+#[derive(InputObject, Debug, Clone, Serialize)]
 pub struct SensorReadingInput {
     pub device_id: i64,
     pub value: String,
     pub unit: Option<SensorUnit>,
 }
 
-#[derive(InputObject, Debug, Clone)]
+#[derive(InputObject, Debug, Clone, Serialize)]
 pub struct ControlSetpointInput {
     pub device_id: i64,
     pub setpoint_type: SetpointType,
     pub value: String,
     pub unit: Option<SetpointUnit>,
+    #[graphql(default)]
+    pub mode: SetpointMode,
+    /// Required for overrides, which revert once this time has passed.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// A setpoint for every thermostat controller in the rooms of a zone.
+#[derive(InputObject, Debug, Clone, Serialize)]
+pub struct ZoneSetpointInput {
+    pub zone_id: i64,
+    pub setpoint_type: SetpointType,
+    pub value: String,
+    pub unit: Option<SetpointUnit>,
+    #[graphql(default)]
+    pub mode: SetpointMode,
+    /// Required for overrides, which revert once this time has passed.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl ZoneSetpointInput {
+    /// The setpoint of one of the zone's devices.
+    pub fn for_device(&self, device_id: i64) -> ControlSetpointInput {
+        ControlSetpointInput {
+            device_id,
+            setpoint_type: self.setpoint_type,
+            value: self.value.clone(),
+            unit: self.unit,
+            mode: self.mode,
+            expires_at: self.expires_at,
+        }
+    }
+}
+
+#[derive(InputObject, Debug, Clone, Serialize)]
+pub struct UserInput {
+    pub name: String,
+    #[graphql(default)]
+    pub is_admin: bool,
+}
+
+#[derive(InputObject, Debug, Clone, Serialize)]
+pub struct SiteRoleInput {
+    pub user_id: i64,
+    pub site_id: i64,
+    pub role: Role,
+}
+
+#[derive(InputObject, Debug, Clone, Default)]
+pub struct AuditLogFilter {
+    pub site_id: Option<i64>,
+    pub entity_type: Option<AuditEntityType>,
+    pub entity_id: Option<i64>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(InputObject, Debug, Clone, Serialize)]
+pub struct AutomationRuleInput {
+    pub site_id: i64,
+    pub name: String,
+    #[graphql(default = true)]
+    pub enabled: bool,
+    pub trigger: Json<RuleTrigger>,
+    /// All of these have to hold for the actions to run.
+    #[graphql(default)]
+    pub conditions: Json<Vec<RuleCondition>>,
+    pub actions: Json<Vec<RuleAction>>,
+}
+
+#[derive(InputObject, Debug, Clone, Serialize)]
+pub struct RetentionPolicyInput {
+    pub site_id: Option<i64>,
+    pub device_type: Option<DeviceType>,
+    pub raw_retention_days: i64,
+    pub hourly_retention_days: Option<i64>,
+    pub daily_retention_days: Option<i64>,
+}
+
+#[derive(InputObject, Debug, Clone, Serialize)]
+pub struct WebhookSubscriptionInput {
+    pub site_id: Option<i64>,
+    pub url: String,
+    pub event_types: Vec<WebhookEventType>,
+    /// The secret payloads are signed with, generated when left out.
+    #[serde(skip)]
+    pub secret: Option<String>,
+    #[graphql(default = true)]
+    pub enabled: bool,
 }
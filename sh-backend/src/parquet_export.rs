@@ -0,0 +1,269 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock};
+
+use anyhow::Result;
+use arrow_array::builder::{
+    Float64Builder, Int64Builder, StringBuilder, TimestampMicrosecondBuilder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use chrono::{DateTime, NaiveDate, Utc};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use rocket::futures::TryStreamExt;
+use sqlx::FromRow;
+use tokio::sync::mpsc;
+
+use crate::db::{Backend, Database};
+use crate::export::ExportFilter;
+use crate::models::SensorUnit;
+use crate::with_pool;
+
+/// Rows are buffered into record batches of this size before being written.
+const BATCH_SIZE: usize = 8192;
+const FILE_NAME: &str = "readings.parquet";
+
+/// The schema every exported file has. Columns are only ever appended,
+/// so existing pipelines keep reading older and newer files alike.
+pub fn reading_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("device_id", DataType::Int64, false),
+        Field::new("device_name", DataType::Utf8, false),
+        Field::new("room_id", DataType::Int64, false),
+        Field::new("room_name", DataType::Utf8, false),
+        Field::new("site_id", DataType::Int64, false),
+        Field::new("site_name", DataType::Utf8, false),
+        Field::new("quantity", DataType::Utf8, true),
+        Field::new("value", DataType::Float64, true),
+        Field::new("unit", DataType::Utf8, true),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            false,
+        ),
+    ]))
+}
+
+#[derive(Debug, FromRow)]
+struct ReadingRow {
+    device_id: i64,
+    device_name: String,
+    room_id: i64,
+    room_name: String,
+    site_id: i64,
+    site_name: String,
+    value: String,
+    unit: Option<SensorUnit>,
+    timestamp: DateTime<Utc>,
+}
+
+/// The physical quantity a unit measures.
+fn quantity(unit: SensorUnit) -> &'static str {
+    match unit {
+        SensorUnit::Celsius | SensorUnit::Fahrenheit => "temperature",
+    }
+}
+
+fn unit_name(unit: SensorUnit) -> &'static str {
+    match unit {
+        SensorUnit::Celsius => "Celsius",
+        SensorUnit::Fahrenheit => "Fahrenheit",
+    }
+}
+
+/// A written partition, one file per site and day.
+#[derive(Debug)]
+pub struct ExportedPartition {
+    pub path: PathBuf,
+    pub rows: usize,
+}
+
+/// The readings are streamed, so the statement has to outlive the call, and is only built once per backend.
+static SQLITE_READINGS_SQL: LazyLock<String> = LazyLock::new(|| readings_sql(Backend::Sqlite));
+static POSTGRES_READINGS_SQL: LazyLock<String> = LazyLock::new(|| readings_sql(Backend::Postgres));
+
+fn readings_sql(backend: Backend) -> String {
+    let time = backend.sortable_time("SensorReading.timestamp");
+    format!(
+        r#"
+        SELECT Device.id AS device_id, Device.name AS device_name, Room.id AS room_id, Room.name AS room_name,
+            Site.id AS site_id, Site.name AS site_name, SensorReading.value, SensorReading.unit,
+            SensorReading.timestamp
+        FROM SensorReading
+        JOIN Device ON Device.id = SensorReading.device_id
+        JOIN Room ON Room.id = Device.room_id
+        JOIN Site ON Site.id = Room.site_id
+        WHERE ($1 IS NULL OR Site.id = $1)
+            AND ($2 IS NULL OR Room.id = $2)
+            AND ($3 IS NULL OR Device.id = $3)
+            AND ($4 IS NULL OR {time} >= {from})
+            AND ($5 IS NULL OR {time} < {to})
+        ORDER BY Site.id, {time}, SensorReading.id
+        "#,
+        from = backend.sortable_time("$4"),
+        to = backend.sortable_time("$5"),
+    )
+}
+
+/// Writes sensor readings to `<output>/site_id=<id>/date=<YYYY-MM-DD>/readings.parquet`,
+/// replacing files of earlier exports for the same partitions.
+/// Readings are streamed from the database and written in batches, one partition at a time.
+pub async fn export_parquet(
+    database: &Database,
+    filter: &ExportFilter,
+    output: &Path,
+) -> Result<Vec<ExportedPartition>> {
+    let sql = match database.backend() {
+        Backend::Sqlite => &*SQLITE_READINGS_SQL,
+        Backend::Postgres => &*POSTGRES_READINGS_SQL,
+    };
+    let mut rows = with_pool!(database, pool => {
+        sqlx::query_as::<_, ReadingRow>(sql)
+            .bind(filter.site_id)
+            .bind(filter.room_id)
+            .bind(filter.device_id)
+            .bind(filter.from)
+            .bind(filter.to)
+            .fetch(pool)
+    });
+
+    // Parquet is written synchronously, so the files are written on a blocking thread
+    // that the rows are handed to as they are fetched.
+    let (sender, receiver) = mpsc::channel(BATCH_SIZE);
+    let output = output.to_path_buf();
+    let writer = tokio::task::spawn_blocking(move || write_partitions(&output, receiver));
+    while let Some(row) = rows.try_next().await? {
+        if sender.send(row).await.is_err() {
+            // The writer failed, which it reports below.
+            break;
+        }
+    }
+    drop(sender);
+    writer.await?
+}
+
+fn write_partitions(
+    output: &Path,
+    mut rows: mpsc::Receiver<ReadingRow>,
+) -> Result<Vec<ExportedPartition>> {
+    let mut partitions = Vec::new();
+    let mut current: Option<PartitionWriter> = None;
+    while let Some(row) = rows.blocking_recv() {
+        let key = (row.site_id, row.timestamp.date_naive());
+        if current.as_ref().is_none_or(|writer| writer.key != key) {
+            if let Some(writer) = current.take() {
+                partitions.push(writer.finish()?);
+            }
+            current = Some(PartitionWriter::create(output, key)?);
+        }
+        if let Some(writer) = current.as_mut() {
+            writer.push(row)?;
+        }
+    }
+    if let Some(writer) = current {
+        partitions.push(writer.finish()?);
+    }
+    Ok(partitions)
+}
+
+struct PartitionWriter {
+    key: (i64, NaiveDate),
+    path: PathBuf,
+    writer: ArrowWriter<File>,
+    buffer: Vec<ReadingRow>,
+    rows: usize,
+}
+
+impl PartitionWriter {
+    fn create(output: &Path, (site_id, date): (i64, NaiveDate)) -> Result<Self> {
+        let directory = output
+            .join(format!("site_id={}", site_id))
+            .join(format!("date={}", date.format("%Y-%m-%d")));
+        fs::create_dir_all(&directory)?;
+        let path = directory.join(FILE_NAME);
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .build();
+        let writer =
+            ArrowWriter::try_new(File::create(&path)?, reading_schema(), Some(properties))?;
+        Ok(Self {
+            key: (site_id, date),
+            path,
+            writer,
+            buffer: Vec::with_capacity(BATCH_SIZE),
+            rows: 0,
+        })
+    }
+
+    fn push(&mut self, row: ReadingRow) -> Result<()> {
+        self.buffer.push(row);
+        if self.buffer.len() >= BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let batch = to_record_batch(&self.buffer)?;
+        self.writer.write(&batch)?;
+        self.rows += self.buffer.len();
+        self.buffer.clear();
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<ExportedPartition> {
+        self.flush()?;
+        self.writer.close()?;
+        Ok(ExportedPartition {
+            path: self.path,
+            rows: self.rows,
+        })
+    }
+}
+
+fn to_record_batch(rows: &[ReadingRow]) -> Result<RecordBatch> {
+    let mut device_ids = Int64Builder::with_capacity(rows.len());
+    let mut device_names = StringBuilder::new();
+    let mut room_ids = Int64Builder::with_capacity(rows.len());
+    let mut room_names = StringBuilder::new();
+    let mut site_ids = Int64Builder::with_capacity(rows.len());
+    let mut site_names = StringBuilder::new();
+    let mut quantities = StringBuilder::new();
+    let mut values = Float64Builder::with_capacity(rows.len());
+    let mut units = StringBuilder::new();
+    let mut timestamps =
+        TimestampMicrosecondBuilder::with_capacity(rows.len()).with_timezone("UTC");
+
+    for row in rows {
+        device_ids.append_value(row.device_id);
+        device_names.append_value(&row.device_name);
+        room_ids.append_value(row.room_id);
+        room_names.append_value(&row.room_name);
+        site_ids.append_value(row.site_id);
+        site_names.append_value(&row.site_name);
+        quantities.append_option(row.unit.map(quantity));
+        // Values are stored as text, anything that is not a number is exported as null.
+        values.append_option(row.value.trim().parse::<f64>().ok());
+        units.append_option(row.unit.map(unit_name));
+        timestamps.append_value(row.timestamp.timestamp_micros());
+    }
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(device_ids.finish()),
+        Arc::new(device_names.finish()),
+        Arc::new(room_ids.finish()),
+        Arc::new(room_names.finish()),
+        Arc::new(site_ids.finish()),
+        Arc::new(site_names.finish()),
+        Arc::new(quantities.finish()),
+        Arc::new(values.finish()),
+        Arc::new(units.finish()),
+        Arc::new(timestamps.finish()),
+    ];
+    Ok(RecordBatch::try_new(reading_schema(), columns)?)
+}
@@ -0,0 +1,197 @@
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{Context, Result, bail};
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextPrepareRequest,
+};
+use async_graphql::{ErrorExtensionValues, Request, ServerError, ServerResult, from_value};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::config::GraphqlConfig;
+
+/// The `persistedQuery` extension clients send along with, or instead of, the query.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PersistedQuery {
+    version: i32,
+    sha256_hash: String,
+}
+
+/// Automatic persisted queries: clients send the SHA-256 hash of a query instead of the query itself,
+/// and only send the whole query to register it after the server answered `PersistedQueryNotFound`.
+///
+/// With an allowlist, only the operations it holds are accepted, whether they are sent by hash or in full,
+/// and nothing can be registered.
+#[derive(Clone)]
+pub struct PersistedQueries {
+    allowlist: Option<Arc<HashMap<String, String>>>,
+    registered: Arc<Mutex<RegisteredQueries>>,
+}
+
+/// The queries registered by clients, the oldest of which are forgotten once `capacity` is reached.
+struct RegisteredQueries {
+    queries: HashMap<String, String>,
+    order: VecDeque<String>,
+    capacity: usize,
+}
+
+impl PersistedQueries {
+    pub fn new(config: &GraphqlConfig) -> Result<Self> {
+        let allowlist = match &config.operation_allowlist {
+            Some(path) => Some(Arc::new(load_allowlist(path).with_context(|| {
+                format!("Failed to load the operation allowlist {}", path.display())
+            })?)),
+            None => None,
+        };
+        Ok(Self {
+            allowlist,
+            registered: Arc::new(Mutex::new(RegisteredQueries {
+                queries: HashMap::new(),
+                order: VecDeque::new(),
+                capacity: config.persisted_query_cache_size,
+            })),
+        })
+    }
+
+    fn lookup(&self, hash: &str) -> Option<String> {
+        match &self.allowlist {
+            Some(allowlist) => allowlist.get(hash).cloned(),
+            None => self.registered.lock().unwrap().queries.get(hash).cloned(),
+        }
+    }
+
+    fn register(&self, hash: String, query: &str) {
+        let mut registered = self.registered.lock().unwrap();
+        if registered.queries.contains_key(&hash) {
+            return;
+        }
+        if registered.order.len() >= registered.capacity
+            && let Some(oldest) = registered.order.pop_front()
+        {
+            registered.queries.remove(&oldest);
+        }
+        registered.order.push_back(hash.clone());
+        registered.queries.insert(hash, query.to_string());
+    }
+
+    fn allowed(&self, hash: &str) -> bool {
+        self.allowlist
+            .as_ref()
+            .is_none_or(|allowlist| allowlist.contains_key(hash))
+    }
+}
+
+impl ExtensionFactory for PersistedQueries {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(self.clone())
+    }
+}
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for PersistedQueries {
+    async fn prepare_request(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        mut request: Request,
+        next: NextPrepareRequest<'_>,
+    ) -> ServerResult<Request> {
+        let persisted = match request.extensions.remove("persistedQuery") {
+            Some(value) => Some(from_value::<PersistedQuery>(value).map_err(|_| {
+                persisted_query_error(
+                    "The persistedQuery extension needs a version and a sha256Hash",
+                    "PERSISTED_QUERY_INVALID",
+                )
+            })?),
+            None => None,
+        };
+
+        match persisted {
+            Some(persisted) => {
+                if persisted.version != 1 {
+                    return Err(persisted_query_error(
+                        "PersistedQueryNotSupported",
+                        "PERSISTED_QUERY_NOT_SUPPORTED",
+                    ));
+                }
+                let hash = persisted.sha256_hash.to_ascii_lowercase();
+                if request.query.is_empty() {
+                    match self.lookup(&hash) {
+                        Some(query) => request.query = query,
+                        None => {
+                            return Err(persisted_query_error(
+                                "PersistedQueryNotFound",
+                                "PERSISTED_QUERY_NOT_FOUND",
+                            ));
+                        }
+                    }
+                } else {
+                    if query_hash(&request.query) != hash {
+                        return Err(persisted_query_error(
+                            "The sha256Hash of the persistedQuery extension doesn't match the query",
+                            "PERSISTED_QUERY_HASH_MISMATCH",
+                        ));
+                    }
+                    if !self.allowed(&hash) {
+                        return Err(not_allowed());
+                    }
+                    if self.allowlist.is_none() {
+                        self.register(hash, &request.query);
+                    }
+                }
+            }
+            None => {
+                if !self.allowed(&query_hash(&request.query)) {
+                    return Err(not_allowed());
+                }
+            }
+        }
+        next.run(ctx, request).await
+    }
+}
+
+/// The hex SHA-256 hash of a query, the way clients compute it for the `persistedQuery` extension.
+pub fn query_hash(query: &str) -> String {
+    hex::encode(Sha256::digest(query.as_bytes()))
+}
+
+/// Reads a manifest of persisted operations, a JSON object mapping the hash of every query to the query,
+/// as the frontend build writes it to `src/gql/persisted-documents.json`.
+fn load_allowlist(path: &Path) -> Result<HashMap<String, String>> {
+    let manifest = std::fs::read_to_string(path)?;
+    let operations: HashMap<String, String> =
+        serde_json::from_str(&manifest).context("The manifest isn't a JSON object of queries")?;
+    let mut mismatched: Vec<&str> = operations
+        .iter()
+        .filter(|(hash, query)| query_hash(query) != hash.to_ascii_lowercase())
+        .map(|(hash, _)| hash.as_str())
+        .collect();
+    if !mismatched.is_empty() {
+        mismatched.sort();
+        bail!(
+            "The hashes {} don't match their queries",
+            mismatched.join(", ")
+        );
+    }
+    Ok(operations
+        .into_iter()
+        .map(|(hash, query)| (hash.to_ascii_lowercase(), query))
+        .collect())
+}
+
+fn not_allowed() -> ServerError {
+    persisted_query_error(
+        "Only the operations of the allowlist are accepted",
+        "PERSISTED_QUERY_NOT_ALLOWED",
+    )
+}
+
+fn persisted_query_error(message: &str, code: &str) -> ServerError {
+    let mut extensions = ErrorExtensionValues::default();
+    extensions.set("code", code);
+    let mut error = ServerError::new(message, None);
+    error.extensions = Some(extensions);
+    error
+}
@@ -0,0 +1,331 @@
+use std::convert::Infallible;
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+
+use async_graphql::extensions::{
+    Extension, ExtensionContext, ExtensionFactory, NextExecute, NextParseQuery,
+};
+use async_graphql::parser::types::{ExecutableDocument, OperationType, Selection, SelectionSet};
+use async_graphql::{
+    Context, ErrorExtensionValues, Response, ServerError, ServerResult, Variables,
+};
+use governor::clock::{Clock, DefaultClock};
+use governor::{DefaultKeyedRateLimiter, InsufficientCapacity, Quota};
+use rocket::request::{FromRequest, Outcome, Request};
+
+use crate::auth::Identity;
+use crate::config::{Config, RateLimitsConfig};
+
+/// The mutation recording a reading, which costs one reading from the ingest limit every time it is selected.
+const CREATE_READING: &str = "createSensorReading";
+
+/// The mutation importing readings, which costs one reading from the ingest limit per row of the file.
+/// It is charged by its resolver, which reads the file, see [`charge_imported_readings`].
+const IMPORT_READINGS: &str = "importSensorReadings";
+
+/// Clients that haven't sent a request for a while are forgotten once there are this many.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// The address a request came from, which anonymous requests are limited by.
+///
+/// That is the address of the connection, unless it comes from one of the `http.trusted_proxies`,
+/// which pass the address of the client on in the `X-Real-IP` header.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientAddress(pub Option<IpAddr>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientAddress {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let remote = request.remote().map(|address| address.ip());
+        let trusted = request.rocket().state::<Config>().is_some_and(|config| {
+            remote.is_some_and(|remote| config.http.trusted_proxies.contains(&remote))
+        });
+        let address = if trusted {
+            request.real_ip().or(remote)
+        } else {
+            remote
+        };
+        Outcome::Success(ClientAddress(address))
+    }
+}
+
+/// Who a request counts against: the device key or user it authenticates with,
+/// or the address it came from when it doesn't.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Client {
+    DeviceKey(i64),
+    User(i64),
+    Address(IpAddr),
+}
+
+impl Client {
+    /// Automation rules, and requests from unknown addresses, aren't limited.
+    fn of(identity: Option<&Identity>, address: Option<&ClientAddress>) -> Option<Self> {
+        match (identity, address) {
+            (Some(Identity::Device(credential)), _) => Some(Client::DeviceKey(credential.id)),
+            (Some(Identity::User(user)), _) => Some(Client::User(user.id)),
+            (Some(Identity::Anonymous), Some(ClientAddress(Some(address)))) => {
+                Some(Client::Address(*address))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Token buckets per client, one for recording readings and one for everything else,
+/// so a gateway flooding the API with readings can't lock its users out of the dashboard, or the other way around.
+///
+/// Operations over the limit fail with a `RATE_LIMITED` error code, a `retryAfter` extension in seconds
+/// and a `Retry-After` header.
+#[derive(Clone)]
+pub struct RateLimits {
+    ingest: Arc<DefaultKeyedRateLimiter<Client>>,
+    query: Arc<DefaultKeyedRateLimiter<Client>>,
+}
+
+impl RateLimits {
+    /// Expects a validated configuration, where every rate and burst is at least 1.
+    pub fn new(config: &RateLimitsConfig) -> Self {
+        let quota = |per_minute: u32, burst: u32| {
+            Quota::per_minute(NonZeroU32::new(per_minute).expect("The rate has to be at least 1"))
+                .allow_burst(NonZeroU32::new(burst).expect("The burst has to be at least 1"))
+        };
+        Self {
+            ingest: Arc::new(DefaultKeyedRateLimiter::keyed(quota(
+                config.ingest_per_minute,
+                config.ingest_burst,
+            ))),
+            query: Arc::new(DefaultKeyedRateLimiter::keyed(quota(
+                config.query_per_minute,
+                config.query_burst,
+            ))),
+        }
+    }
+
+    /// Takes `cost` tokens from the bucket of the client, or tells when to retry.
+    fn charge(&self, ingest: bool, client: &Client, cost: u32) -> Result<(), RateLimited> {
+        let limiter = if ingest { &self.ingest } else { &self.query };
+        if limiter.len() > MAX_TRACKED_CLIENTS {
+            limiter.retain_recent();
+        }
+        let Some(cost) = NonZeroU32::new(cost) else {
+            return Ok(());
+        };
+        match limiter.check_key_n(client, cost) {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(not_until)) => {
+                let wait = not_until.wait_time_from(DefaultClock::default().now());
+                // Rounded up, so clients retrying right on time don't come too early.
+                let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+                Err(RateLimited::Wait {
+                    ingest,
+                    retry_after,
+                })
+            }
+            Err(InsufficientCapacity(burst)) => Err(RateLimited::TooMany { ingest, burst }),
+        }
+    }
+}
+
+/// Why an operation was turned down.
+enum RateLimited {
+    /// The bucket holds too few tokens now, but will in `retry_after` seconds.
+    Wait { ingest: bool, retry_after: u64 },
+    /// The operation costs more than the bucket ever holds.
+    TooMany { ingest: bool, burst: u32 },
+}
+
+impl RateLimited {
+    fn into_error(self) -> ServerError {
+        let mut extensions = ErrorExtensionValues::default();
+        extensions.set("code", "RATE_LIMITED");
+        let what = |ingest| if ingest { "readings" } else { "requests" };
+        let message = match self {
+            RateLimited::Wait {
+                ingest,
+                retry_after,
+            } => {
+                extensions.set("retryAfter", retry_after);
+                format!("Too many {}, retry in {}s", what(ingest), retry_after)
+            }
+            RateLimited::TooMany { ingest, burst } => format!(
+                "Too many {} at once, at most {} are allowed",
+                what(ingest),
+                burst
+            ),
+        };
+        let mut error = ServerError::new(message, None);
+        error.extensions = Some(extensions);
+        error
+    }
+
+    fn retry_after(&self) -> Option<u64> {
+        match self {
+            RateLimited::Wait { retry_after, .. } => Some(*retry_after),
+            RateLimited::TooMany { .. } => None,
+        }
+    }
+}
+
+/// Charges an import of `rows` readings against the ingest limit of the caller,
+/// before any of them are recorded.
+pub fn charge_imported_readings(ctx: &Context<'_>, rows: u64) -> async_graphql::Result<()> {
+    let Some(limits) = ctx.data_opt::<RateLimits>() else {
+        return Ok(());
+    };
+    let Some(client) = Client::of(ctx.data_opt::<Identity>(), ctx.data_opt::<ClientAddress>())
+    else {
+        return Ok(());
+    };
+    let cost = u32::try_from(rows).unwrap_or(u32::MAX);
+    limits.charge(true, &client, cost).map_err(|limited| {
+        if let Some(retry_after) = limited.retry_after() {
+            ctx.insert_http_header("Retry-After", retry_after.to_string());
+        }
+        let error = limited.into_error();
+        let mut result = async_graphql::Error::new(error.message);
+        result.extensions = error.extensions;
+        result
+    })
+}
+
+impl ExtensionFactory for RateLimits {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(RateLimitsExtension {
+            limits: self.clone(),
+            document: Mutex::new(None),
+        })
+    }
+}
+
+/// Created for every request, holding on to its document to tell which operation runs.
+struct RateLimitsExtension {
+    limits: RateLimits,
+    document: Mutex<Option<ExecutableDocument>>,
+}
+
+#[async_graphql::async_trait::async_trait]
+impl Extension for RateLimitsExtension {
+    async fn parse_query(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        query: &str,
+        variables: &Variables,
+        next: NextParseQuery<'_>,
+    ) -> ServerResult<ExecutableDocument> {
+        let document = next.run(ctx, query, variables).await?;
+        *self.document.lock().unwrap() = Some(document.clone());
+        Ok(document)
+    }
+
+    async fn execute(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        operation_name: Option<&str>,
+        next: NextExecute<'_>,
+    ) -> Response {
+        let Some(client) = Client::of(ctx.data_opt::<Identity>(), ctx.data_opt::<ClientAddress>())
+        else {
+            return next.run(ctx, operation_name).await;
+        };
+
+        let (ingest, cost) = match self.document.lock().unwrap().as_ref() {
+            Some(document) => operation_cost(document, operation_name),
+            None => (false, 1),
+        };
+        match self.limits.charge(ingest, &client, cost) {
+            Ok(()) => next.run(ctx, operation_name).await,
+            Err(limited) => rate_limited(limited),
+        }
+    }
+}
+
+/// Whether the operation that runs records readings, and how many tokens it costs up front.
+///
+/// Operations recording readings cost one reading per `createSensorReading` they select,
+/// aliases and fragments included, and imports are charged once their file is read.
+/// Every other operation, mutations included, costs one request.
+fn operation_cost(document: &ExecutableDocument, operation_name: Option<&str>) -> (bool, u32) {
+    let operation = document.operations.iter().find(|(name, _)| {
+        operation_name.is_none() || name.map(|name| name.as_str()) == operation_name
+    });
+    let Some((_, operation)) = operation else {
+        return (false, 1);
+    };
+    if operation.node.ty != OperationType::Mutation {
+        return (false, 1);
+    }
+    let mut created = 0;
+    let mut imports = false;
+    count_ingest_fields(
+        document,
+        &operation.node.selection_set.node,
+        &mut Vec::new(),
+        &mut created,
+        &mut imports,
+    );
+    if created == 0 && !imports {
+        (false, 1)
+    } else {
+        (true, created)
+    }
+}
+
+/// Counts the ingest mutations selected at the top level of the operation, following fragments.
+/// Fragments already being followed are skipped, the validation rejects such cycles later on.
+fn count_ingest_fields<'a>(
+    document: &'a ExecutableDocument,
+    selection_set: &'a SelectionSet,
+    following: &mut Vec<&'a str>,
+    created: &mut u32,
+    imports: &mut bool,
+) {
+    for selection in &selection_set.items {
+        match &selection.node {
+            Selection::Field(field) => match field.node.name.node.as_str() {
+                CREATE_READING => *created = created.saturating_add(1),
+                IMPORT_READINGS => *imports = true,
+                _ => {}
+            },
+            Selection::InlineFragment(fragment) => count_ingest_fields(
+                document,
+                &fragment.node.selection_set.node,
+                following,
+                created,
+                imports,
+            ),
+            Selection::FragmentSpread(spread) => {
+                let name = spread.node.fragment_name.node.as_str();
+                let Some(fragment) = document.fragments.get(&spread.node.fragment_name.node) else {
+                    continue;
+                };
+                if following.contains(&name) {
+                    continue;
+                }
+                following.push(name);
+                count_ingest_fields(
+                    document,
+                    &fragment.node.selection_set.node,
+                    following,
+                    created,
+                    imports,
+                );
+                following.pop();
+            }
+        }
+    }
+}
+
+fn rate_limited(limited: RateLimited) -> Response {
+    let retry_after = limited.retry_after();
+    let mut response = Response::from_errors(vec![limited.into_error()]);
+    if let Some(retry_after) = retry_after {
+        response
+            .http_headers
+            .insert("Retry-After", retry_after.into());
+    }
+    response
+}
@@ -0,0 +1,106 @@
+use chrono::{DateTime, Utc};
+
+use crate::db::{Database, Transaction};
+use crate::models::{Alert, AlertSeverity};
+use crate::{with_pool, with_transaction};
+
+const COLUMNS: &str =
+    "id, site_id, rule_id, severity, message, raised_at, acknowledged_at, created_at, updated_at";
+
+/// Alerts raised by automation rules.
+pub struct AlertRepo<'a> {
+    database: &'a Database,
+}
+
+impl<'a> AlertRepo<'a> {
+    pub(crate) fn new(database: &'a Database) -> Self {
+        Self { database }
+    }
+
+    pub async fn find(&self, id: i64) -> sqlx::Result<Option<Alert>> {
+        let sql = format!("SELECT {} FROM Alert WHERE id = $1", COLUMNS);
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Alert>(&sql).bind(id).fetch_optional(pool).await
+        })
+    }
+
+    /// The latest alerts of a site, newest first.
+    pub async fn for_site(
+        &self,
+        site_id: i64,
+        include_acknowledged: bool,
+        limit: i64,
+    ) -> sqlx::Result<Vec<Alert>> {
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM Alert
+            WHERE site_id = $1 AND ($2 OR acknowledged_at IS NULL)
+            ORDER BY raised_at DESC, id DESC
+            LIMIT $3
+            "#,
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Alert>(&sql)
+                .bind(site_id)
+                .bind(include_acknowledged)
+                .bind(limit)
+                .fetch_all(pool)
+                .await
+        })
+    }
+
+    pub async fn raise(
+        &self,
+        site_id: i64,
+        rule_id: i64,
+        severity: AlertSeverity,
+        message: &str,
+        raised_at: DateTime<Utc>,
+    ) -> sqlx::Result<Alert> {
+        let sql = format!(
+            r#"
+            INSERT INTO Alert (site_id, rule_id, severity, message, raised_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING {}
+            "#,
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Alert>(&sql)
+                .bind(site_id)
+                .bind(rule_id)
+                .bind(severity)
+                .bind(message)
+                .bind(raised_at)
+                .fetch_one(pool)
+                .await
+        })
+    }
+
+    /// Acknowledges an alert, keeping the time it was first acknowledged at.
+    pub async fn acknowledge(
+        &self,
+        transaction: &mut Transaction<'_>,
+        id: i64,
+        now: DateTime<Utc>,
+    ) -> sqlx::Result<Alert> {
+        let sql = format!(
+            r#"
+            UPDATE Alert
+            SET acknowledged_at = COALESCE(acknowledged_at, $1), updated_at = CURRENT_TIMESTAMP
+            WHERE id = $2
+            RETURNING {}
+            "#,
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, Alert>(&sql)
+                .bind(now)
+                .bind(id)
+                .fetch_one(connection)
+                .await
+        })
+    }
+}
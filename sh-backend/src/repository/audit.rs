@@ -0,0 +1,49 @@
+use crate::db::Database;
+use crate::models::{AuditLogEntry, AuditLogFilter};
+use crate::with_pool;
+
+const COLUMNS: &str = "id, site_id, actor_type, actor_id, actor_name, operation, arguments, entity_type, entity_id, before, after, timestamp";
+
+/// Reads the audit log, which [`AuditRecord`](crate::audit::AuditRecord) appends to.
+pub struct AuditLogRepo<'a> {
+    database: &'a Database,
+}
+
+impl<'a> AuditLogRepo<'a> {
+    pub(crate) fn new(database: &'a Database) -> Self {
+        Self { database }
+    }
+
+    /// The latest entries matching every criterion of the filter, newest first.
+    pub async fn list(
+        &self,
+        filter: &AuditLogFilter,
+        limit: i64,
+    ) -> sqlx::Result<Vec<AuditLogEntry>> {
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM AuditLog
+            WHERE ($1 IS NULL OR site_id = $1)
+                AND ($2 IS NULL OR entity_type = $2)
+                AND ($3 IS NULL OR entity_id = $3)
+                AND ($4 IS NULL OR timestamp >= $4)
+                AND ($5 IS NULL OR timestamp < $5)
+            ORDER BY timestamp DESC, id DESC
+            LIMIT $6
+            "#,
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, AuditLogEntry>(&sql)
+                .bind(filter.site_id)
+                .bind(filter.entity_type)
+                .bind(filter.entity_id)
+                .bind(filter.from)
+                .bind(filter.to)
+                .bind(limit)
+                .fetch_all(pool)
+                .await
+        })
+    }
+}
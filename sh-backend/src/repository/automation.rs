@@ -0,0 +1,174 @@
+use chrono::{DateTime, Utc};
+
+use crate::db::{Database, Transaction};
+use crate::models::{AutomationRule, AutomationRuleInput, RuleExecution, RuleExecutionStatus};
+use crate::{with_pool, with_transaction};
+
+const COLUMNS: &str = "id, site_id, name, enabled, trigger, conditions, actions, last_triggered_at, created_at, updated_at";
+
+const EXECUTION_COLUMNS: &str = "id, rule_id, status, event, message, timestamp";
+
+/// Automation rules and the log of their executions.
+pub struct AutomationRuleRepo<'a> {
+    database: &'a Database,
+}
+
+impl<'a> AutomationRuleRepo<'a> {
+    pub(crate) fn new(database: &'a Database) -> Self {
+        Self { database }
+    }
+
+    pub async fn find(&self, id: i64) -> sqlx::Result<Option<AutomationRule>> {
+        let sql = format!("SELECT {} FROM AutomationRule WHERE id = $1", COLUMNS);
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, AutomationRule>(&sql).bind(id).fetch_optional(pool).await
+        })
+    }
+
+    pub async fn for_site(&self, site_id: i64) -> sqlx::Result<Vec<AutomationRule>> {
+        let sql = format!(
+            "SELECT {} FROM AutomationRule WHERE site_id = $1 ORDER BY id",
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, AutomationRule>(&sql).bind(site_id).fetch_all(pool).await
+        })
+    }
+
+    /// The enabled rules of a site, or of every site when none is given.
+    pub async fn enabled(&self, site_id: Option<i64>) -> sqlx::Result<Vec<AutomationRule>> {
+        let sql = format!(
+            "SELECT {} FROM AutomationRule WHERE enabled AND ($1 IS NULL OR site_id = $1) ORDER BY id",
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, AutomationRule>(&sql).bind(site_id).fetch_all(pool).await
+        })
+    }
+
+    pub async fn create(
+        &self,
+        transaction: &mut Transaction<'_>,
+        input: &AutomationRuleInput,
+    ) -> sqlx::Result<AutomationRule> {
+        let sql = format!(
+            r#"
+            INSERT INTO AutomationRule (site_id, name, enabled, trigger, conditions, actions)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING {}
+            "#,
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, AutomationRule>(&sql)
+                .bind(input.site_id)
+                .bind(&input.name)
+                .bind(input.enabled)
+                .bind(sqlx::types::Json(&input.trigger.0))
+                .bind(sqlx::types::Json(&input.conditions.0))
+                .bind(sqlx::types::Json(&input.actions.0))
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    /// Replaces the settings of a rule, which stays on its site.
+    pub async fn update(
+        &self,
+        transaction: &mut Transaction<'_>,
+        id: i64,
+        input: &AutomationRuleInput,
+    ) -> sqlx::Result<AutomationRule> {
+        let sql = format!(
+            r#"
+            UPDATE AutomationRule
+            SET name = $1, enabled = $2, trigger = $3, conditions = $4, actions = $5, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $6
+            RETURNING {}
+            "#,
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, AutomationRule>(&sql)
+                .bind(&input.name)
+                .bind(input.enabled)
+                .bind(sqlx::types::Json(&input.trigger.0))
+                .bind(sqlx::types::Json(&input.conditions.0))
+                .bind(sqlx::types::Json(&input.actions.0))
+                .bind(id)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    /// Deletes a rule along with its execution log, the alerts it raised are kept.
+    pub async fn delete(&self, transaction: &mut Transaction<'_>, id: i64) -> sqlx::Result<()> {
+        with_transaction!(transaction, connection => {
+            sqlx::query("DELETE FROM AutomationRule WHERE id = $1")
+                .bind(id)
+                .execute(connection)
+                .await?;
+            Ok(())
+        })
+    }
+
+    pub async fn set_last_triggered_at(
+        &self,
+        id: i64,
+        triggered_at: DateTime<Utc>,
+    ) -> sqlx::Result<()> {
+        with_pool!(self.database, pool => {
+            sqlx::query("UPDATE AutomationRule SET last_triggered_at = $1 WHERE id = $2")
+                .bind(triggered_at)
+                .bind(id)
+                .execute(pool)
+                .await?;
+            Ok(())
+        })
+    }
+
+    /// The latest executions of a rule, newest first.
+    pub async fn executions(&self, rule_id: i64, limit: i64) -> sqlx::Result<Vec<RuleExecution>> {
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM RuleExecution
+            WHERE rule_id = $1
+            ORDER BY timestamp DESC, id DESC
+            LIMIT $2
+            "#,
+            EXECUTION_COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, RuleExecution>(&sql)
+                .bind(rule_id)
+                .bind(limit)
+                .fetch_all(pool)
+                .await
+        })
+    }
+
+    /// Logs an execution of a rule, along with the event that triggered it as JSON.
+    pub async fn record_execution(
+        &self,
+        rule_id: i64,
+        status: RuleExecutionStatus,
+        event: &str,
+        message: Option<&str>,
+        timestamp: DateTime<Utc>,
+    ) -> sqlx::Result<()> {
+        with_pool!(self.database, pool => {
+            sqlx::query(
+                "INSERT INTO RuleExecution (rule_id, status, event, message, timestamp) VALUES ($1, $2, $3, $4, $5)",
+            )
+            .bind(rule_id)
+            .bind(status)
+            .bind(event)
+            .bind(message)
+            .bind(timestamp)
+            .execute(pool)
+            .await?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,90 @@
+use crate::db::{Database, Transaction};
+use crate::models::{Building, BuildingInput};
+use crate::{with_pool, with_transaction};
+
+const COLUMNS: &str = "id, site_id, name, created_at, updated_at";
+
+pub struct BuildingRepo<'a> {
+    database: &'a Database,
+}
+
+impl<'a> BuildingRepo<'a> {
+    pub(crate) fn new(database: &'a Database) -> Self {
+        Self { database }
+    }
+
+    pub async fn find(&self, id: i64) -> sqlx::Result<Option<Building>> {
+        let sql = format!("SELECT {} FROM Building WHERE id = $1", COLUMNS);
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Building>(&sql).bind(id).fetch_optional(pool).await
+        })
+    }
+
+    pub async fn for_site(&self, site_id: i64) -> sqlx::Result<Vec<Building>> {
+        let sql = format!(
+            "SELECT {} FROM Building WHERE site_id = $1 ORDER BY id",
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Building>(&sql).bind(site_id).fetch_all(pool).await
+        })
+    }
+
+    /// The site a building belongs to, or `None` if the building doesn't exist.
+    pub async fn site_id(&self, id: i64) -> sqlx::Result<Option<i64>> {
+        with_pool!(self.database, pool => {
+            sqlx::query_scalar::<_, i64>("SELECT site_id FROM Building WHERE id = $1")
+                .bind(id)
+                .fetch_optional(pool)
+                .await
+        })
+    }
+
+    pub async fn create(
+        &self,
+        transaction: &mut Transaction<'_>,
+        input: &BuildingInput,
+    ) -> sqlx::Result<Building> {
+        let sql = format!(
+            "INSERT INTO Building (site_id, name) VALUES ($1, $2) RETURNING {}",
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, Building>(&sql)
+                .bind(input.site_id)
+                .bind(&input.name)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    pub async fn rename(
+        &self,
+        transaction: &mut Transaction<'_>,
+        id: i64,
+        name: &str,
+    ) -> sqlx::Result<Building> {
+        let sql = format!(
+            "UPDATE Building SET name = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2 RETURNING {}",
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, Building>(&sql)
+                .bind(name)
+                .bind(id)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    /// Deletes a building along with its floors, whose rooms are kept without a floor.
+    pub async fn delete(&self, transaction: &mut Transaction<'_>, id: i64) -> sqlx::Result<()> {
+        with_transaction!(transaction, connection => {
+            sqlx::query("DELETE FROM Building WHERE id = $1")
+                .bind(id)
+                .execute(connection)
+                .await?;
+            Ok(())
+        })
+    }
+}
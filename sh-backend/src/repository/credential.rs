@@ -0,0 +1,109 @@
+use crate::db::{Database, Transaction};
+use crate::models::DeviceCredential;
+use crate::{with_pool, with_transaction};
+
+const COLUMNS: &str = "id, device_id, key_prefix, key_hash, revoked_at, created_at, updated_at";
+
+/// The keys devices record their readings with, stored as hashes.
+pub struct CredentialRepo<'a> {
+    database: &'a Database,
+}
+
+impl<'a> CredentialRepo<'a> {
+    pub(crate) fn new(database: &'a Database) -> Self {
+        Self { database }
+    }
+
+    pub async fn find(&self, id: i64) -> sqlx::Result<Option<DeviceCredential>> {
+        let sql = format!("SELECT {} FROM DeviceCredential WHERE id = $1", COLUMNS);
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, DeviceCredential>(&sql).bind(id).fetch_optional(pool).await
+        })
+    }
+
+    pub async fn for_device(&self, device_id: i64) -> sqlx::Result<Vec<DeviceCredential>> {
+        let sql = format!(
+            "SELECT {} FROM DeviceCredential WHERE device_id = $1 ORDER BY id",
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, DeviceCredential>(&sql).bind(device_id).fetch_all(pool).await
+        })
+    }
+
+    /// The credential a key hashes to, unless it has been revoked.
+    pub async fn find_active_by_key_hash(
+        &self,
+        key_hash: &str,
+    ) -> sqlx::Result<Option<DeviceCredential>> {
+        let sql = format!(
+            "SELECT {} FROM DeviceCredential WHERE key_hash = $1 AND revoked_at IS NULL",
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, DeviceCredential>(&sql).bind(key_hash).fetch_optional(pool).await
+        })
+    }
+
+    pub async fn create(
+        &self,
+        transaction: &mut Transaction<'_>,
+        device_id: i64,
+        key_prefix: &str,
+        key_hash: &str,
+    ) -> sqlx::Result<DeviceCredential> {
+        let sql = format!(
+            "INSERT INTO DeviceCredential (device_id, key_prefix, key_hash) VALUES ($1, $2, $3) RETURNING {}",
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, DeviceCredential>(&sql)
+                .bind(device_id)
+                .bind(key_prefix)
+                .bind(key_hash)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    /// Revokes a credential, keeping the time it was first revoked at.
+    pub async fn revoke(
+        &self,
+        transaction: &mut Transaction<'_>,
+        id: i64,
+    ) -> sqlx::Result<DeviceCredential> {
+        let sql = format!(
+            r#"
+            UPDATE DeviceCredential
+            SET revoked_at = COALESCE(revoked_at, CURRENT_TIMESTAMP), updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+            RETURNING {}
+            "#,
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, DeviceCredential>(&sql)
+                .bind(id)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    /// Revokes a credential unless it already is, returning whether it was active.
+    pub async fn revoke_active(
+        &self,
+        transaction: &mut Transaction<'_>,
+        id: i64,
+    ) -> sqlx::Result<bool> {
+        with_transaction!(transaction, connection => {
+            let revoked = sqlx::query(
+                "UPDATE DeviceCredential SET revoked_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP WHERE id = $1 AND revoked_at IS NULL",
+            )
+            .bind(id)
+            .execute(connection)
+            .await?
+            .rows_affected();
+            Ok(revoked > 0)
+        })
+    }
+}
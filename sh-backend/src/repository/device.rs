@@ -0,0 +1,156 @@
+use serde_json::Value;
+
+use crate::db::{Database, Transaction};
+use crate::models::{Device, DeviceInput, DeviceType, TagFilter, TaggedEntity};
+use crate::repository::{parameter_list_sql, tag_filter_sql};
+use crate::{with_pool, with_transaction};
+
+const COLUMNS: &str =
+    "id, room_id, name, device_type, unique_identifier, metadata, created_at, updated_at";
+
+pub struct DeviceRepo<'a> {
+    database: &'a Database,
+}
+
+impl<'a> DeviceRepo<'a> {
+    pub(crate) fn new(database: &'a Database) -> Self {
+        Self { database }
+    }
+
+    pub async fn find(&self, id: i64) -> sqlx::Result<Option<Device>> {
+        let sql = format!("SELECT {} FROM Device WHERE id = $1", COLUMNS);
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Device>(&sql).bind(id).fetch_optional(pool).await
+        })
+    }
+
+    /// The devices out of `ids` that exist, in no particular order.
+    pub async fn find_many(&self, ids: &[i64]) -> sqlx::Result<Vec<Device>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let sql = format!(
+            "SELECT {} FROM Device WHERE id IN ({})",
+            COLUMNS,
+            parameter_list_sql(1, ids.len())
+        );
+        with_pool!(self.database, pool => {
+            let mut query = sqlx::query_as::<_, Device>(&sql);
+            for id in ids {
+                query = query.bind(id);
+            }
+            query.fetch_all(pool).await
+        })
+    }
+
+    /// The devices of a room carrying every one of the tags.
+    pub async fn for_room(&self, room_id: i64, tags: &[TagFilter]) -> sqlx::Result<Vec<Device>> {
+        let sql = format!(
+            "SELECT {} FROM Device WHERE room_id = $1 AND {} ORDER BY id",
+            COLUMNS,
+            tag_filter_sql(TaggedEntity::Device, "Device.id", tags, 2)
+        );
+        with_pool!(self.database, pool => {
+            let mut query = sqlx::query_as::<_, Device>(&sql).bind(room_id);
+            for tag in tags {
+                query = query.bind(&tag.key).bind(&tag.value);
+            }
+            query.fetch_all(pool).await
+        })
+    }
+
+    /// The devices in the rooms of a zone.
+    pub async fn for_zone(&self, zone_id: i64) -> sqlx::Result<Vec<Device>> {
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM Device
+            WHERE room_id IN (SELECT room_id FROM RoomZone WHERE zone_id = $1)
+            ORDER BY id
+            "#,
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Device>(&sql).bind(zone_id).fetch_all(pool).await
+        })
+    }
+
+    /// The site a device belongs to through its room, or `None` if the device doesn't exist.
+    pub async fn site_id(&self, id: i64) -> sqlx::Result<Option<i64>> {
+        with_pool!(self.database, pool => {
+            sqlx::query_scalar::<_, i64>(
+                "SELECT Room.site_id FROM Device JOIN Room ON Room.id = Device.room_id WHERE Device.id = $1",
+            )
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+        })
+    }
+
+    /// The ID and site of the device with a unique identifier.
+    pub async fn by_identifier(&self, identifier: &str) -> sqlx::Result<Option<(i64, i64)>> {
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, (i64, i64)>(
+                "SELECT Device.id, Room.site_id FROM Device JOIN Room ON Room.id = Device.room_id WHERE Device.unique_identifier = $1",
+            )
+            .bind(identifier)
+            .fetch_optional(pool)
+            .await
+        })
+    }
+
+    /// The ID, site and type of every device.
+    pub async fn all_with_site(&self) -> sqlx::Result<Vec<(i64, i64, DeviceType)>> {
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, (i64, i64, DeviceType)>(
+                "SELECT Device.id, Room.site_id, Device.device_type FROM Device JOIN Room ON Room.id = Device.room_id ORDER BY Device.id",
+            )
+            .fetch_all(pool)
+            .await
+        })
+    }
+
+    pub async fn create(
+        &self,
+        transaction: &mut Transaction<'_>,
+        input: &DeviceInput,
+    ) -> sqlx::Result<Device> {
+        let sql = format!(
+            r#"
+            INSERT INTO Device (room_id, name, device_type, unique_identifier)
+            VALUES ($1, $2, $3, $4)
+            RETURNING {}
+            "#,
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, Device>(&sql)
+                .bind(input.room_id)
+                .bind(&input.name)
+                .bind(input.device_type)
+                .bind(&input.unique_identifier)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    /// Replaces the metadata of a device.
+    pub async fn set_metadata(
+        &self,
+        transaction: &mut Transaction<'_>,
+        id: i64,
+        metadata: &Value,
+    ) -> sqlx::Result<Device> {
+        let sql = format!(
+            "UPDATE Device SET metadata = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2 RETURNING {}",
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, Device>(&sql)
+                .bind(sqlx::types::Json(metadata))
+                .bind(id)
+                .fetch_one(connection)
+                .await
+        })
+    }
+}
@@ -0,0 +1,125 @@
+use crate::db::{Database, Transaction};
+use crate::models::{Floor, FloorInput};
+use crate::{with_pool, with_transaction};
+
+/// Floors are selected along with the site of their building, which permissions are checked against.
+const COLUMNS: &str = "Floor.id, Floor.building_id, Building.site_id, Floor.name, Floor.level, Floor.created_at, Floor.updated_at";
+
+pub struct FloorRepo<'a> {
+    database: &'a Database,
+}
+
+impl<'a> FloorRepo<'a> {
+    pub(crate) fn new(database: &'a Database) -> Self {
+        Self { database }
+    }
+
+    pub async fn find(&self, id: i64) -> sqlx::Result<Option<Floor>> {
+        let sql = format!(
+            "SELECT {} FROM Floor JOIN Building ON Building.id = Floor.building_id WHERE Floor.id = $1",
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Floor>(&sql).bind(id).fetch_optional(pool).await
+        })
+    }
+
+    /// The floors of a building, from the lowest level up.
+    pub async fn for_building(&self, building_id: i64) -> sqlx::Result<Vec<Floor>> {
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM Floor
+            JOIN Building ON Building.id = Floor.building_id
+            WHERE Floor.building_id = $1
+            ORDER BY Floor.level, Floor.id
+            "#,
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Floor>(&sql).bind(building_id).fetch_all(pool).await
+        })
+    }
+
+    /// The site a floor belongs to through its building, or `None` if the floor doesn't exist.
+    pub async fn site_id(&self, id: i64) -> sqlx::Result<Option<i64>> {
+        with_pool!(self.database, pool => {
+            sqlx::query_scalar::<_, i64>(
+                "SELECT Building.site_id FROM Floor JOIN Building ON Building.id = Floor.building_id WHERE Floor.id = $1",
+            )
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+        })
+    }
+
+    pub async fn create(
+        &self,
+        transaction: &mut Transaction<'_>,
+        input: &FloorInput,
+    ) -> sqlx::Result<Floor> {
+        // The site comes from the building, which `RETURNING` cannot join.
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM Floor
+            JOIN Building ON Building.id = Floor.building_id
+            WHERE Floor.id = $1
+            "#,
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            let id = sqlx::query_scalar::<_, i64>(
+                "INSERT INTO Floor (building_id, name, level) VALUES ($1, $2, $3) RETURNING id",
+            )
+            .bind(input.building_id)
+            .bind(&input.name)
+            .bind(input.level)
+            .fetch_one(&mut *connection)
+            .await?;
+            sqlx::query_as::<_, Floor>(&sql)
+                .bind(id)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    pub async fn rename(
+        &self,
+        transaction: &mut Transaction<'_>,
+        id: i64,
+        name: &str,
+    ) -> sqlx::Result<Floor> {
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM Floor
+            JOIN Building ON Building.id = Floor.building_id
+            WHERE Floor.id = $1
+            "#,
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query("UPDATE Floor SET name = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2")
+                .bind(name)
+                .bind(id)
+                .execute(&mut *connection)
+                .await?;
+            sqlx::query_as::<_, Floor>(&sql)
+                .bind(id)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    /// Deletes a floor, whose rooms are kept without a floor.
+    pub async fn delete(&self, transaction: &mut Transaction<'_>, id: i64) -> sqlx::Result<()> {
+        with_transaction!(transaction, connection => {
+            sqlx::query("DELETE FROM Floor WHERE id = $1")
+                .bind(id)
+                .execute(connection)
+                .await?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,54 @@
+//! Typed access to the sites, their buildings, floors and zones, rooms, devices, readings, setpoints and tags,
+//! as well as to users and their roles, device credentials, automation rules and alerts, webhooks,
+//! retention policies and the audit log, for SQLite and PostgreSQL alike. Repositories are borrowed from a [`Database`](crate::db::Database),
+//! such as `database.rooms().for_site(site_id)`.
+//!
+//! Writes take a [`Transaction`](crate::db::Transaction) from `database.begin()`,
+//! so that several of them, and the audit record describing them, commit or roll back together.
+
+mod alert;
+mod audit;
+mod automation;
+mod building;
+mod credential;
+mod device;
+mod floor;
+mod reading;
+mod retention;
+mod room;
+mod setpoint;
+mod site;
+mod site_role;
+mod tag;
+#[cfg(test)]
+mod tests;
+mod user;
+mod webhook;
+mod zone;
+
+pub use alert::AlertRepo;
+pub use audit::AuditLogRepo;
+pub use automation::AutomationRuleRepo;
+pub use building::BuildingRepo;
+pub use credential::CredentialRepo;
+pub use device::DeviceRepo;
+pub use floor::FloorRepo;
+pub use reading::ReadingRepo;
+pub use retention::RetentionPolicyRepo;
+pub use room::RoomRepo;
+pub use setpoint::SetpointRepo;
+pub use site::SiteRepo;
+pub use site_role::SiteRoleRepo;
+pub use tag::TagRepo;
+pub(crate) use tag::tag_filter_sql;
+pub use user::UserRepo;
+pub use webhook::WebhookRepo;
+pub use zone::ZoneRepo;
+
+/// A list of `count` parameters from `$first` on, such as `$1, $2, $3`, for an `IN (...)` condition.
+pub(crate) fn parameter_list_sql(first: usize, count: usize) -> String {
+    (first..first + count)
+        .map(|index| format!("${index}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,512 @@
+use chrono::{DateTime, Utc};
+
+use crate::db::{Backend, Database, StoredTime, Transaction};
+use crate::models::{
+    ReadingResolution, ReadingSummary, SensorReading, SensorReadingInput, SensorReadingSample,
+};
+use crate::{with_pool, with_transaction};
+
+const COLUMNS: &str = "id, device_id, value, unit, timestamp, created_at, updated_at";
+
+/// Summarizes the readings of the devices in the rooms `rooms` selects by `$1`, between `$2` and `$3`,
+/// reading the hourly and then daily rollups of each device where its raw readings have been pruned.
+/// Rollups count when their hour or day starts within the range.
+/// Values that aren't numbers are left out, see [`Backend::numeric`].
+fn summary_sql(backend: Backend, rooms: &str) -> String {
+    let value = backend.numeric("SensorReading.value");
+    let (reading_time, from, to) = match backend {
+        Backend::Sqlite => (
+            "datetime(SensorReading.timestamp)",
+            "datetime($2)",
+            "datetime($3)",
+        ),
+        Backend::Postgres => ("SensorReading.timestamp", "$2", "$3"),
+    };
+    format!(
+        r#"
+        WITH devices AS (
+            SELECT Device.id, ReadingRetentionState.raw_pruned_before, ReadingRetentionState.hourly_pruned_before
+            FROM Device
+            LEFT JOIN ReadingRetentionState ON ReadingRetentionState.device_id = Device.id
+            WHERE Device.room_id IN ({rooms})
+        ),
+        readings AS (
+            SELECT devices.id AS device_id, SensorReading.unit, {value} AS value
+            FROM devices
+            JOIN SensorReading ON SensorReading.device_id = devices.id
+            WHERE (devices.raw_pruned_before IS NULL OR {reading_time} >= devices.raw_pruned_before)
+                AND {reading_time} >= {from} AND {reading_time} <= {to}
+        ),
+        samples AS (
+            SELECT device_id, unit, 1 AS sample_count, value AS min_value, value AS max_value, value AS sum_value
+            FROM readings
+            WHERE value IS NOT NULL
+            UNION ALL
+            SELECT devices.id, SensorReadingRollup.unit, SensorReadingRollup.sample_count,
+                SensorReadingRollup.min_value, SensorReadingRollup.max_value, SensorReadingRollup.sum_value
+            FROM devices
+            JOIN SensorReadingRollup ON SensorReadingRollup.device_id = devices.id
+            WHERE (
+                    (SensorReadingRollup.resolution = 'Hourly' AND SensorReadingRollup.bucket_start < devices.raw_pruned_before
+                        AND (devices.hourly_pruned_before IS NULL OR SensorReadingRollup.bucket_start >= devices.hourly_pruned_before))
+                    OR (SensorReadingRollup.resolution = 'Daily' AND SensorReadingRollup.bucket_start < devices.hourly_pruned_before)
+                )
+                AND SensorReadingRollup.bucket_start >= {from} AND SensorReadingRollup.bucket_start <= {to}
+        )
+        SELECT unit, COUNT(DISTINCT device_id) AS device_count, CAST(SUM(sample_count) AS BIGINT) AS sample_count,
+            MIN(min_value) AS min_value, MAX(max_value) AS max_value,
+            SUM(sum_value) / CAST(SUM(sample_count) AS DOUBLE PRECISION) AS avg_value
+        FROM samples
+        GROUP BY unit
+        ORDER BY unit
+        "#
+    )
+}
+
+/// The latest `$4` numeric samples of device `$1` between `$2` and `$3`, oldest first,
+/// reading raw readings where they are still kept and falling back to hourly and then daily rollups for older ranges.
+fn history_sql(backend: Backend) -> String {
+    let number = backend.numeric("value");
+    match backend {
+        // SQLite stores timestamps both as `CURRENT_TIMESTAMP` text and as RFC 3339,
+        // so they are compared and ordered down to milliseconds through a common format.
+        Backend::Sqlite => format!(
+            r#"
+            WITH state AS (
+                SELECT COALESCE(raw_pruned_before, '') AS raw_pruned_before, COALESCE(hourly_pruned_before, '') AS hourly_pruned_before
+                FROM (SELECT 1)
+                LEFT JOIN ReadingRetentionState ON device_id = $1
+            ),
+            readings AS (
+                SELECT id, device_id, timestamp, unit, value, {number} AS number
+                FROM SensorReading
+                WHERE device_id = $1
+            ),
+            samples AS (
+                SELECT id, device_id, timestamp, strftime('%Y-%m-%d %H:%M:%f', timestamp) AS sort_time, 'Raw' AS resolution, unit, value,
+                    1 AS sample_count, number AS min_value, number AS max_value, number AS avg_value
+                FROM readings, state
+                WHERE datetime(timestamp) >= state.raw_pruned_before AND number IS NOT NULL
+                UNION ALL
+                SELECT NULL, device_id, bucket_start, strftime('%Y-%m-%d %H:%M:%f', bucket_start), resolution, unit, CAST(sum_value / sample_count AS TEXT),
+                    sample_count, min_value, max_value, sum_value / sample_count
+                FROM SensorReadingRollup, state
+                WHERE device_id = $1 AND resolution = 'Hourly'
+                    AND bucket_start < state.raw_pruned_before AND bucket_start >= state.hourly_pruned_before
+                UNION ALL
+                SELECT NULL, device_id, bucket_start, strftime('%Y-%m-%d %H:%M:%f', bucket_start), resolution, unit, CAST(sum_value / sample_count AS TEXT),
+                    sample_count, min_value, max_value, sum_value / sample_count
+                FROM SensorReadingRollup, state
+                WHERE device_id = $1 AND resolution = 'Daily' AND bucket_start < state.hourly_pruned_before
+            ),
+            latest AS (
+                SELECT *
+                FROM samples
+                WHERE ($2 IS NULL OR sort_time >= strftime('%Y-%m-%d %H:%M:%f', $2)) AND ($3 IS NULL OR sort_time <= strftime('%Y-%m-%d %H:%M:%f', $3))
+                ORDER BY sort_time DESC, id DESC
+                LIMIT $4
+            )
+            SELECT id, device_id, timestamp, resolution, unit, value, sample_count, min_value, max_value, avg_value
+            FROM latest
+            ORDER BY sort_time, id
+            "#
+        ),
+        // Rollups have no ID, which sorts them after raw readings of the same time, as on SQLite.
+        Backend::Postgres => format!(
+            r#"
+            WITH state AS (
+                SELECT raw_pruned_before, hourly_pruned_before
+                FROM (SELECT 1) AS one
+                LEFT JOIN ReadingRetentionState ON device_id = $1
+            ),
+            readings AS (
+                SELECT id, device_id, timestamp, unit, value, {number} AS number
+                FROM SensorReading
+                WHERE device_id = $1
+            ),
+            samples AS (
+                SELECT id, device_id, timestamp, CAST('Raw' AS ReadingResolution) AS resolution, unit, value,
+                    CAST(1 AS BIGINT) AS sample_count, number AS min_value, number AS max_value, number AS avg_value
+                FROM readings, state
+                WHERE (state.raw_pruned_before IS NULL OR timestamp >= state.raw_pruned_before) AND number IS NOT NULL
+                UNION ALL
+                SELECT NULL, device_id, bucket_start, resolution, unit, CAST(sum_value / sample_count AS TEXT),
+                    sample_count, min_value, max_value, sum_value / sample_count
+                FROM SensorReadingRollup, state
+                WHERE device_id = $1 AND resolution = 'Hourly'
+                    AND bucket_start < state.raw_pruned_before
+                    AND (state.hourly_pruned_before IS NULL OR bucket_start >= state.hourly_pruned_before)
+                UNION ALL
+                SELECT NULL, device_id, bucket_start, resolution, unit, CAST(sum_value / sample_count AS TEXT),
+                    sample_count, min_value, max_value, sum_value / sample_count
+                FROM SensorReadingRollup, state
+                WHERE device_id = $1 AND resolution = 'Daily' AND bucket_start < state.hourly_pruned_before
+            ),
+            latest AS (
+                SELECT *
+                FROM samples
+                WHERE ($2 IS NULL OR timestamp >= $2) AND ($3 IS NULL OR timestamp <= $3)
+                ORDER BY timestamp DESC, id DESC NULLS LAST
+                LIMIT $4
+            )
+            SELECT id, device_id, timestamp, resolution, unit, value, sample_count, min_value, max_value, avg_value
+            FROM latest
+            ORDER BY timestamp, id NULLS FIRST
+            "#
+        ),
+    }
+}
+
+/// Readings are looked up by device and ordered by time, which the `(device_id, timestamp)` index covers,
+/// on TimescaleDB hypertables as well.
+pub struct ReadingRepo<'a> {
+    database: &'a Database,
+}
+
+impl<'a> ReadingRepo<'a> {
+    pub(crate) fn new(database: &'a Database) -> Self {
+        Self { database }
+    }
+
+    /// The readings of a device, oldest first, or only the latest `limit` of them.
+    pub async fn for_device(
+        &self,
+        device_id: i64,
+        limit: Option<i64>,
+    ) -> sqlx::Result<Vec<SensorReading>> {
+        let time = self.database.backend().sortable_time("timestamp");
+        let sql = format!(
+            r#"
+            SELECT {columns}
+            FROM (
+                SELECT {columns}, {time} AS sort_time
+                FROM SensorReading
+                WHERE device_id = $1
+                ORDER BY sort_time DESC, id DESC
+                {limit}
+            ) AS latest
+            ORDER BY sort_time, id
+            "#,
+            columns = COLUMNS,
+            limit = if limit.is_some() { "LIMIT $2" } else { "" },
+        );
+        with_pool!(self.database, pool => {
+            let mut query = sqlx::query_as::<_, SensorReading>(&sql).bind(device_id);
+            if let Some(limit) = limit {
+                query = query.bind(limit);
+            }
+            query.fetch_all(pool).await
+        })
+    }
+
+    pub async fn latest(&self, device_id: i64) -> sqlx::Result<Option<SensorReading>> {
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM SensorReading
+            WHERE device_id = $1
+            ORDER BY {} DESC, id DESC
+            LIMIT 1
+            "#,
+            COLUMNS,
+            self.database.backend().sortable_time("timestamp")
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, SensorReading>(&sql).bind(device_id).fetch_optional(pool).await
+        })
+    }
+
+    /// The reading of a device recorded before the one with ID `id`.
+    pub async fn previous(&self, device_id: i64, id: i64) -> sqlx::Result<Option<SensorReading>> {
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM SensorReading
+            WHERE device_id = $1 AND id < $2
+            ORDER BY id DESC
+            LIMIT 1
+            "#,
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, SensorReading>(&sql)
+                .bind(device_id)
+                .bind(id)
+                .fetch_optional(pool)
+                .await
+        })
+    }
+
+    pub async fn floor_summary(
+        &self,
+        floor_id: i64,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> sqlx::Result<Vec<ReadingSummary>> {
+        let sql = summary_sql(
+            self.database.backend(),
+            "SELECT id FROM Room WHERE floor_id = $1",
+        );
+        self.summary(&sql, floor_id, from, to).await
+    }
+
+    pub async fn zone_summary(
+        &self,
+        zone_id: i64,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> sqlx::Result<Vec<ReadingSummary>> {
+        let sql = summary_sql(
+            self.database.backend(),
+            "SELECT room_id FROM RoomZone WHERE zone_id = $1",
+        );
+        self.summary(&sql, zone_id, from, to).await
+    }
+
+    async fn summary(
+        &self,
+        sql: &str,
+        id: i64,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> sqlx::Result<Vec<ReadingSummary>> {
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, ReadingSummary>(sql)
+                .bind(id)
+                .bind(from)
+                .bind(to)
+                .fetch_all(pool)
+                .await
+        })
+    }
+
+    /// Records a reading taken at `timestamp`, or right now if there is none.
+    pub async fn create(
+        &self,
+        transaction: &mut Transaction<'_>,
+        input: &SensorReadingInput,
+        timestamp: Option<DateTime<Utc>>,
+    ) -> sqlx::Result<SensorReading> {
+        let sql = format!(
+            r#"
+            INSERT INTO SensorReading (device_id, value, unit, timestamp)
+            VALUES ($1, $2, $3, COALESCE($4, CURRENT_TIMESTAMP))
+            RETURNING {}
+            "#,
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, SensorReading>(&sql)
+                .bind(input.device_id)
+                .bind(&input.value)
+                .bind(input.unit)
+                .bind(timestamp.map(StoredTime))
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    /// Records readings taken at the given times in one statement, returning how many were inserted.
+    pub async fn create_many(
+        &self,
+        transaction: &mut Transaction<'_>,
+        readings: &[(SensorReadingInput, DateTime<Utc>)],
+    ) -> sqlx::Result<u64> {
+        if readings.is_empty() {
+            return Ok(0);
+        }
+        with_transaction!(transaction, connection => {
+            Ok(sqlx::QueryBuilder::new("INSERT INTO SensorReading (device_id, value, unit, timestamp) ")
+                .push_values(readings, |mut row, (input, timestamp)| {
+                    row.push_bind(input.device_id)
+                        .push_bind(&input.value)
+                        .push_bind(input.unit)
+                        .push_bind(StoredTime(*timestamp));
+                })
+                .build()
+                .execute(connection)
+                .await?
+                .rows_affected())
+        })
+    }
+
+    /// The latest `limit` samples of a device between two points in time, oldest first,
+    /// reading raw readings where they are still kept and falling back to hourly and then daily rollups for older ranges.
+    pub async fn history(
+        &self,
+        device_id: i64,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: i64,
+    ) -> sqlx::Result<Vec<SensorReadingSample>> {
+        let sql = history_sql(self.database.backend());
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, SensorReadingSample>(&sql)
+                .bind(device_id)
+                .bind(from)
+                .bind(to)
+                .bind(limit)
+                .fetch_all(pool)
+                .await
+        })
+    }
+
+    /// Summarizes the raw readings of a device taken before `before` into hourly or daily rollups,
+    /// adding to the rollups of buckets that were partly summarized before.
+    /// Readings that aren't numbers can't be summarized and are left out.
+    pub async fn roll_up(
+        &self,
+        transaction: &mut Transaction<'_>,
+        device_id: i64,
+        resolution: ReadingResolution,
+        before: DateTime<Utc>,
+    ) -> sqlx::Result<()> {
+        let unit = match resolution {
+            ReadingResolution::Hourly => "hour",
+            ReadingResolution::Daily => "day",
+            // Raw readings are what gets rolled up.
+            ReadingResolution::Raw => return Ok(()),
+        };
+        let backend = self.database.backend();
+        let (bucket, reading_time, before_time, least, greatest) = match backend {
+            Backend::Sqlite => (
+                match resolution {
+                    ReadingResolution::Hourly => {
+                        "strftime('%Y-%m-%d %H:00:00', timestamp)".to_string()
+                    }
+                    _ => "strftime('%Y-%m-%d 00:00:00', timestamp)".to_string(),
+                },
+                "datetime(timestamp)",
+                "datetime($3)",
+                "MIN",
+                "MAX",
+            ),
+            Backend::Postgres => (
+                format!("date_trunc('{unit}', timestamp, 'UTC')"),
+                "timestamp",
+                "$3",
+                "LEAST",
+                "GREATEST",
+            ),
+        };
+        let sql = format!(
+            r#"
+            INSERT INTO SensorReadingRollup (device_id, resolution, bucket_start, unit, sample_count, min_value, max_value, sum_value)
+            SELECT device_id, $1, bucket, MAX(unit), COUNT(number), MIN(number), MAX(number), SUM(number)
+            FROM (
+                SELECT device_id, {bucket} AS bucket, unit, {number} AS number
+                FROM SensorReading
+                WHERE device_id = $2 AND {reading_time} < {before_time}
+            ) AS readings
+            GROUP BY device_id, bucket
+            HAVING COUNT(number) > 0
+            ON CONFLICT (device_id, resolution, bucket_start) DO UPDATE SET
+                sample_count = SensorReadingRollup.sample_count + excluded.sample_count,
+                min_value = {least}(SensorReadingRollup.min_value, excluded.min_value),
+                max_value = {greatest}(SensorReadingRollup.max_value, excluded.max_value),
+                sum_value = SensorReadingRollup.sum_value + excluded.sum_value,
+                updated_at = CURRENT_TIMESTAMP
+            "#,
+            number = backend.numeric("value"),
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query(&sql)
+                .bind(resolution)
+                .bind(device_id)
+                .bind(before)
+                .execute(connection)
+                .await?;
+            Ok(())
+        })
+    }
+
+    /// Deletes the raw readings of a device taken before `before`, returning how many there were.
+    pub async fn prune(
+        &self,
+        transaction: &mut Transaction<'_>,
+        device_id: i64,
+        before: DateTime<Utc>,
+    ) -> sqlx::Result<u64> {
+        let sql = match self.database.backend() {
+            Backend::Sqlite => {
+                "DELETE FROM SensorReading WHERE device_id = $1 AND datetime(timestamp) < datetime($2)"
+            }
+            Backend::Postgres => {
+                "DELETE FROM SensorReading WHERE device_id = $1 AND timestamp < $2"
+            }
+        };
+        with_transaction!(transaction, connection => {
+            Ok(sqlx::query(sql)
+                .bind(device_id)
+                .bind(before)
+                .execute(connection)
+                .await?
+                .rows_affected())
+        })
+    }
+
+    /// Deletes the rollups of a device for buckets starting before `before`.
+    pub async fn prune_rollups(
+        &self,
+        transaction: &mut Transaction<'_>,
+        device_id: i64,
+        resolution: ReadingResolution,
+        before: DateTime<Utc>,
+    ) -> sqlx::Result<()> {
+        let sql = match self.database.backend() {
+            Backend::Sqlite => {
+                "DELETE FROM SensorReadingRollup WHERE device_id = $1 AND resolution = $2 AND bucket_start < datetime($3)"
+            }
+            Backend::Postgres => {
+                "DELETE FROM SensorReadingRollup WHERE device_id = $1 AND resolution = $2 AND bucket_start < $3"
+            }
+        };
+        with_transaction!(transaction, connection => {
+            sqlx::query(sql)
+                .bind(device_id)
+                .bind(resolution)
+                .bind(before)
+                .execute(connection)
+                .await?;
+            Ok(())
+        })
+    }
+
+    /// Records how far the raw readings and hourly rollups of a device have been pruned.
+    /// The watermarks only ever move forward, as pruned data cannot come back when a policy is relaxed.
+    pub async fn advance_pruned_before(
+        &self,
+        transaction: &mut Transaction<'_>,
+        device_id: i64,
+        raw_pruned_before: DateTime<Utc>,
+        hourly_pruned_before: Option<DateTime<Utc>>,
+    ) -> sqlx::Result<()> {
+        let (raw, hourly, greatest) = match self.database.backend() {
+            Backend::Sqlite => ("datetime($2)", "datetime($3)", "MAX"),
+            Backend::Postgres => ("$2", "$3", "GREATEST"),
+        };
+        let sql = format!(
+            r#"
+            INSERT INTO ReadingRetentionState (device_id, raw_pruned_before, hourly_pruned_before)
+            VALUES ($1, {raw}, {hourly})
+            ON CONFLICT (device_id) DO UPDATE SET
+                raw_pruned_before = {greatest}(ReadingRetentionState.raw_pruned_before, excluded.raw_pruned_before),
+                hourly_pruned_before = COALESCE(
+                    {greatest}(ReadingRetentionState.hourly_pruned_before, excluded.hourly_pruned_before),
+                    ReadingRetentionState.hourly_pruned_before,
+                    excluded.hourly_pruned_before
+                ),
+                updated_at = CURRENT_TIMESTAMP
+            "#
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query(&sql)
+                .bind(device_id)
+                .bind(raw_pruned_before)
+                .bind(hourly_pruned_before)
+                .execute(connection)
+                .await?;
+            Ok(())
+        })
+    }
+}
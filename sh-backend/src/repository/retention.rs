@@ -0,0 +1,141 @@
+use crate::db::{Database, Transaction};
+use crate::models::{DeviceType, RetentionPolicy, RetentionPolicyInput};
+use crate::{with_pool, with_transaction};
+
+const COLUMNS: &str = "id, site_id, device_type, raw_retention_days, hourly_retention_days, daily_retention_days, created_at, updated_at";
+
+/// Retention policies, each applying to a site or every site, and to a device type or every device type.
+pub struct RetentionPolicyRepo<'a> {
+    database: &'a Database,
+}
+
+impl<'a> RetentionPolicyRepo<'a> {
+    pub(crate) fn new(database: &'a Database) -> Self {
+        Self { database }
+    }
+
+    pub async fn find(&self, id: i64) -> sqlx::Result<Option<RetentionPolicy>> {
+        let sql = format!("SELECT {} FROM RetentionPolicy WHERE id = $1", COLUMNS);
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, RetentionPolicy>(&sql).bind(id).fetch_optional(pool).await
+        })
+    }
+
+    /// The policies of a site, or every policy when no site is given.
+    pub async fn all(&self, site_id: Option<i64>) -> sqlx::Result<Vec<RetentionPolicy>> {
+        let sql = format!(
+            "SELECT {} FROM RetentionPolicy WHERE $1 IS NULL OR site_id = $1 ORDER BY id",
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, RetentionPolicy>(&sql).bind(site_id).fetch_all(pool).await
+        })
+    }
+
+    /// The most specific policy for a device, preferring a site's own policies over global ones,
+    /// and policies for the device type over ones for every device type.
+    pub async fn for_device(
+        &self,
+        site_id: i64,
+        device_type: DeviceType,
+    ) -> sqlx::Result<Option<RetentionPolicy>> {
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM RetentionPolicy
+            WHERE (site_id = $1 OR site_id IS NULL) AND (device_type = $2 OR device_type IS NULL)
+            ORDER BY site_id IS NULL, device_type IS NULL
+            LIMIT 1
+            "#,
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, RetentionPolicy>(&sql)
+                .bind(site_id)
+                .bind(device_type)
+                .fetch_optional(pool)
+                .await
+        })
+    }
+
+    /// The policy for exactly the site and device type of the input, if there is one.
+    pub async fn find_scope_in(
+        &self,
+        transaction: &mut Transaction<'_>,
+        input: &RetentionPolicyInput,
+    ) -> sqlx::Result<Option<RetentionPolicy>> {
+        let sql = format!(
+            "SELECT {} FROM RetentionPolicy WHERE site_id IS NOT DISTINCT FROM $1 AND device_type IS NOT DISTINCT FROM $2",
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, RetentionPolicy>(&sql)
+                .bind(input.site_id)
+                .bind(input.device_type)
+                .fetch_optional(connection)
+                .await
+        })
+    }
+
+    pub async fn create(
+        &self,
+        transaction: &mut Transaction<'_>,
+        input: &RetentionPolicyInput,
+    ) -> sqlx::Result<RetentionPolicy> {
+        let sql = format!(
+            r#"
+            INSERT INTO RetentionPolicy (site_id, device_type, raw_retention_days, hourly_retention_days, daily_retention_days)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING {}
+            "#,
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, RetentionPolicy>(&sql)
+                .bind(input.site_id)
+                .bind(input.device_type)
+                .bind(input.raw_retention_days)
+                .bind(input.hourly_retention_days)
+                .bind(input.daily_retention_days)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    /// Replaces the retention periods of a policy, which keeps its scope.
+    pub async fn update(
+        &self,
+        transaction: &mut Transaction<'_>,
+        id: i64,
+        input: &RetentionPolicyInput,
+    ) -> sqlx::Result<RetentionPolicy> {
+        let sql = format!(
+            r#"
+            UPDATE RetentionPolicy
+            SET raw_retention_days = $1, hourly_retention_days = $2, daily_retention_days = $3, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $4
+            RETURNING {}
+            "#,
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, RetentionPolicy>(&sql)
+                .bind(input.raw_retention_days)
+                .bind(input.hourly_retention_days)
+                .bind(input.daily_retention_days)
+                .bind(id)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    pub async fn delete(&self, transaction: &mut Transaction<'_>, id: i64) -> sqlx::Result<()> {
+        with_transaction!(transaction, connection => {
+            sqlx::query("DELETE FROM RetentionPolicy WHERE id = $1")
+                .bind(id)
+                .execute(connection)
+                .await?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,149 @@
+use serde_json::Value;
+
+use crate::db::{Database, Transaction};
+use crate::models::{Room, RoomInput};
+use crate::repository::parameter_list_sql;
+use crate::{with_pool, with_transaction};
+
+const COLUMNS: &str = "Room.id, Room.site_id, Room.floor_id, Room.name, Room.metadata, Room.created_at, Room.updated_at";
+
+pub struct RoomRepo<'a> {
+    database: &'a Database,
+}
+
+impl<'a> RoomRepo<'a> {
+    pub(crate) fn new(database: &'a Database) -> Self {
+        Self { database }
+    }
+
+    pub async fn find(&self, id: i64) -> sqlx::Result<Option<Room>> {
+        let sql = format!("SELECT {} FROM Room WHERE Room.id = $1", COLUMNS);
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Room>(&sql).bind(id).fetch_optional(pool).await
+        })
+    }
+
+    /// The rooms out of `ids` that exist, in no particular order.
+    pub async fn find_many(&self, ids: &[i64]) -> sqlx::Result<Vec<Room>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let sql = format!(
+            "SELECT {} FROM Room WHERE Room.id IN ({})",
+            COLUMNS,
+            parameter_list_sql(1, ids.len())
+        );
+        with_pool!(self.database, pool => {
+            let mut query = sqlx::query_as::<_, Room>(&sql);
+            for id in ids {
+                query = query.bind(id);
+            }
+            query.fetch_all(pool).await
+        })
+    }
+
+    pub async fn for_site(&self, site_id: i64) -> sqlx::Result<Vec<Room>> {
+        let sql = format!(
+            "SELECT {} FROM Room WHERE Room.site_id = $1 ORDER BY Room.id",
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Room>(&sql).bind(site_id).fetch_all(pool).await
+        })
+    }
+
+    pub async fn for_floor(&self, floor_id: i64) -> sqlx::Result<Vec<Room>> {
+        let sql = format!(
+            "SELECT {} FROM Room WHERE Room.floor_id = $1 ORDER BY Room.id",
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Room>(&sql).bind(floor_id).fetch_all(pool).await
+        })
+    }
+
+    pub async fn for_zone(&self, zone_id: i64) -> sqlx::Result<Vec<Room>> {
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM Room
+            JOIN RoomZone ON RoomZone.room_id = Room.id
+            WHERE RoomZone.zone_id = $1
+            ORDER BY Room.id
+            "#,
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Room>(&sql).bind(zone_id).fetch_all(pool).await
+        })
+    }
+
+    /// The site a room belongs to, or `None` if the room doesn't exist.
+    pub async fn site_id(&self, id: i64) -> sqlx::Result<Option<i64>> {
+        with_pool!(self.database, pool => {
+            sqlx::query_scalar::<_, i64>("SELECT site_id FROM Room WHERE id = $1")
+                .bind(id)
+                .fetch_optional(pool)
+                .await
+        })
+    }
+
+    pub async fn create(
+        &self,
+        transaction: &mut Transaction<'_>,
+        input: &RoomInput,
+    ) -> sqlx::Result<Room> {
+        let sql = format!(
+            "INSERT INTO Room (site_id, floor_id, name) VALUES ($1, $2, $3) RETURNING {}",
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, Room>(&sql)
+                .bind(input.site_id)
+                .bind(input.floor_id)
+                .bind(&input.name)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    /// Moves a room to another floor, or takes it off its floor when there is none.
+    pub async fn set_floor(
+        &self,
+        transaction: &mut Transaction<'_>,
+        id: i64,
+        floor_id: Option<i64>,
+    ) -> sqlx::Result<Room> {
+        let sql = format!(
+            "UPDATE Room SET floor_id = $1, updated_at = CURRENT_TIMESTAMP WHERE Room.id = $2 RETURNING {}",
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, Room>(&sql)
+                .bind(floor_id)
+                .bind(id)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    /// Replaces the metadata of a room.
+    pub async fn set_metadata(
+        &self,
+        transaction: &mut Transaction<'_>,
+        id: i64,
+        metadata: &Value,
+    ) -> sqlx::Result<Room> {
+        let sql = format!(
+            "UPDATE Room SET metadata = $1, updated_at = CURRENT_TIMESTAMP WHERE Room.id = $2 RETURNING {}",
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, Room>(&sql)
+                .bind(sqlx::types::Json(metadata))
+                .bind(id)
+                .fetch_one(connection)
+                .await
+        })
+    }
+}
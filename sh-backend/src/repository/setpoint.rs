@@ -0,0 +1,125 @@
+use chrono::{DateTime, Utc};
+
+use crate::db::{Database, Transaction};
+use crate::models::{ControlSetpoint, ControlSetpointInput};
+use crate::{with_pool, with_transaction};
+
+const COLUMNS: &str = "id, device_id, setpoint_type, value, unit, mode, expires_at, timestamp, created_at, updated_at";
+
+/// The setpoint in effect at `$2`, which is the latest active override or hold if there is one,
+/// and the latest scheduled setpoint otherwise.
+fn effective_sql() -> String {
+    format!(
+        r#"
+        SELECT {}
+        FROM ControlSetpoint
+        WHERE device_id = $1 AND (mode = 'Scheduled' OR expires_at IS NULL OR expires_at > $2)
+        ORDER BY mode != 'Scheduled' DESC, timestamp DESC
+        LIMIT 1
+        "#,
+        COLUMNS
+    )
+}
+
+pub struct SetpointRepo<'a> {
+    database: &'a Database,
+}
+
+impl<'a> SetpointRepo<'a> {
+    pub(crate) fn new(database: &'a Database) -> Self {
+        Self { database }
+    }
+
+    pub async fn for_device(&self, device_id: i64) -> sqlx::Result<Vec<ControlSetpoint>> {
+        let sql = format!(
+            "SELECT {} FROM ControlSetpoint WHERE device_id = $1 ORDER BY id",
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, ControlSetpoint>(&sql).bind(device_id).fetch_all(pool).await
+        })
+    }
+
+    pub async fn effective(
+        &self,
+        device_id: i64,
+        now: DateTime<Utc>,
+    ) -> sqlx::Result<Option<ControlSetpoint>> {
+        let sql = effective_sql();
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, ControlSetpoint>(&sql)
+                .bind(device_id)
+                .bind(now)
+                .fetch_optional(pool)
+                .await
+        })
+    }
+
+    /// Like [`SetpointRepo::effective`], but sees the changes made earlier in the transaction.
+    pub async fn effective_in(
+        &self,
+        transaction: &mut Transaction<'_>,
+        device_id: i64,
+        now: DateTime<Utc>,
+    ) -> sqlx::Result<Option<ControlSetpoint>> {
+        let sql = effective_sql();
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, ControlSetpoint>(&sql)
+                .bind(device_id)
+                .bind(now)
+                .fetch_optional(connection)
+                .await
+        })
+    }
+
+    /// Creates a setpoint taking effect at `now`.
+    pub async fn create(
+        &self,
+        transaction: &mut Transaction<'_>,
+        input: &ControlSetpointInput,
+        now: DateTime<Utc>,
+    ) -> sqlx::Result<ControlSetpoint> {
+        let sql = format!(
+            r#"
+            INSERT INTO ControlSetpoint (device_id, setpoint_type, value, unit, mode, expires_at, timestamp)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING {}
+            "#,
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, ControlSetpoint>(&sql)
+                .bind(input.device_id)
+                .bind(input.setpoint_type)
+                .bind(&input.value)
+                .bind(input.unit)
+                .bind(input.mode)
+                .bind(input.expires_at)
+                .bind(now)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    /// Expires the active overrides and holds of a device at `now`, returning how many there were.
+    pub async fn release_overrides(
+        &self,
+        transaction: &mut Transaction<'_>,
+        device_id: i64,
+        now: DateTime<Utc>,
+    ) -> sqlx::Result<u64> {
+        let sql = r#"
+            UPDATE ControlSetpoint
+            SET expires_at = $1, updated_at = CURRENT_TIMESTAMP
+            WHERE device_id = $2 AND mode != 'Scheduled' AND (expires_at IS NULL OR expires_at > $1)
+            "#;
+        with_transaction!(transaction, connection => {
+            Ok(sqlx::query(sql)
+                .bind(now)
+                .bind(device_id)
+                .execute(connection)
+                .await?
+                .rows_affected())
+        })
+    }
+}
@@ -0,0 +1,121 @@
+use serde_json::Value;
+
+use crate::db::{Database, Transaction};
+use crate::models::{Site, SiteInput, TagFilter, TaggedEntity};
+use crate::repository::{parameter_list_sql, tag_filter_sql};
+use crate::{with_pool, with_transaction};
+
+const COLUMNS: &str =
+    "Site.id, Site.name, Site.address, Site.metadata, Site.created_at, Site.updated_at";
+
+pub struct SiteRepo<'a> {
+    database: &'a Database,
+}
+
+impl<'a> SiteRepo<'a> {
+    pub(crate) fn new(database: &'a Database) -> Self {
+        Self { database }
+    }
+
+    /// The sites carrying every one of the tags.
+    pub async fn all(&self, tags: &[TagFilter]) -> sqlx::Result<Vec<Site>> {
+        let sql = format!(
+            "SELECT {} FROM Site WHERE {} ORDER BY Site.id",
+            COLUMNS,
+            tag_filter_sql(TaggedEntity::Site, "Site.id", tags, 1)
+        );
+        with_pool!(self.database, pool => {
+            let mut query = sqlx::query_as::<_, Site>(&sql);
+            for tag in tags {
+                query = query.bind(&tag.key).bind(&tag.value);
+            }
+            query.fetch_all(pool).await
+        })
+    }
+
+    /// The sites a user holds a role on, out of those carrying every one of the tags.
+    pub async fn for_user(&self, user_id: i64, tags: &[TagFilter]) -> sqlx::Result<Vec<Site>> {
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM Site
+            JOIN SiteRole ON SiteRole.site_id = Site.id
+            WHERE SiteRole.user_id = $1 AND {}
+            ORDER BY Site.id
+            "#,
+            COLUMNS,
+            tag_filter_sql(TaggedEntity::Site, "Site.id", tags, 2)
+        );
+        with_pool!(self.database, pool => {
+            let mut query = sqlx::query_as::<_, Site>(&sql).bind(user_id);
+            for tag in tags {
+                query = query.bind(&tag.key).bind(&tag.value);
+            }
+            query.fetch_all(pool).await
+        })
+    }
+
+    pub async fn find(&self, id: i64) -> sqlx::Result<Option<Site>> {
+        let sql = format!("SELECT {} FROM Site WHERE Site.id = $1", COLUMNS);
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Site>(&sql).bind(id).fetch_optional(pool).await
+        })
+    }
+
+    /// The sites out of `ids` that exist, in no particular order.
+    pub async fn find_many(&self, ids: &[i64]) -> sqlx::Result<Vec<Site>> {
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        let sql = format!(
+            "SELECT {} FROM Site WHERE Site.id IN ({})",
+            COLUMNS,
+            parameter_list_sql(1, ids.len())
+        );
+        with_pool!(self.database, pool => {
+            let mut query = sqlx::query_as::<_, Site>(&sql);
+            for id in ids {
+                query = query.bind(id);
+            }
+            query.fetch_all(pool).await
+        })
+    }
+
+    pub async fn create(
+        &self,
+        transaction: &mut Transaction<'_>,
+        input: &SiteInput,
+    ) -> sqlx::Result<Site> {
+        let sql = format!(
+            "INSERT INTO Site (name, address) VALUES ($1, $2) RETURNING {}",
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, Site>(&sql)
+                .bind(&input.name)
+                .bind(&input.address)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    /// Replaces the metadata of a site.
+    pub async fn set_metadata(
+        &self,
+        transaction: &mut Transaction<'_>,
+        id: i64,
+        metadata: &Value,
+    ) -> sqlx::Result<Site> {
+        let sql = format!(
+            "UPDATE Site SET metadata = $1, updated_at = CURRENT_TIMESTAMP WHERE Site.id = $2 RETURNING {}",
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, Site>(&sql)
+                .bind(sqlx::types::Json(metadata))
+                .bind(id)
+                .fetch_one(connection)
+                .await
+        })
+    }
+}
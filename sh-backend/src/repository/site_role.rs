@@ -0,0 +1,103 @@
+use crate::db::{Database, Transaction};
+use crate::models::{Role, SiteRole, SiteRoleInput};
+use crate::{with_pool, with_transaction};
+
+const COLUMNS: &str = "id, user_id, site_id, role, created_at, updated_at";
+
+/// The roles users hold on sites. Administrators hold no roles, they may do anything anywhere.
+pub struct SiteRoleRepo<'a> {
+    database: &'a Database,
+}
+
+impl<'a> SiteRoleRepo<'a> {
+    pub(crate) fn new(database: &'a Database) -> Self {
+        Self { database }
+    }
+
+    pub async fn for_site(&self, site_id: i64) -> sqlx::Result<Vec<SiteRole>> {
+        let sql = format!(
+            "SELECT {} FROM SiteRole WHERE site_id = $1 ORDER BY id",
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, SiteRole>(&sql).bind(site_id).fetch_all(pool).await
+        })
+    }
+
+    /// The role a user holds on a site, if any.
+    pub async fn role(&self, user_id: i64, site_id: i64) -> sqlx::Result<Option<Role>> {
+        with_pool!(self.database, pool => {
+            sqlx::query_scalar::<_, Role>("SELECT role FROM SiteRole WHERE user_id = $1 AND site_id = $2")
+                .bind(user_id)
+                .bind(site_id)
+                .fetch_optional(pool)
+                .await
+        })
+    }
+
+    /// The sites a user may change the data of, holding the operator or admin role on them.
+    pub async fn operated_sites(&self, user_id: i64) -> sqlx::Result<Vec<i64>> {
+        with_pool!(self.database, pool => {
+            sqlx::query_scalar::<_, i64>(
+                "SELECT site_id FROM SiteRole WHERE user_id = $1 AND role IN ('Operator', 'Admin')",
+            )
+            .bind(user_id)
+            .fetch_all(pool)
+            .await
+        })
+    }
+
+    pub async fn find_in(
+        &self,
+        transaction: &mut Transaction<'_>,
+        user_id: i64,
+        site_id: i64,
+    ) -> sqlx::Result<Option<SiteRole>> {
+        let sql = format!(
+            "SELECT {} FROM SiteRole WHERE user_id = $1 AND site_id = $2",
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, SiteRole>(&sql)
+                .bind(user_id)
+                .bind(site_id)
+                .fetch_optional(connection)
+                .await
+        })
+    }
+
+    /// Grants a user a role on a site, replacing the role they held there before.
+    pub async fn grant(
+        &self,
+        transaction: &mut Transaction<'_>,
+        input: &SiteRoleInput,
+    ) -> sqlx::Result<SiteRole> {
+        let sql = format!(
+            r#"
+            INSERT INTO SiteRole (user_id, site_id, role)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, site_id) DO UPDATE SET role = excluded.role, updated_at = CURRENT_TIMESTAMP
+            RETURNING {}
+            "#,
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, SiteRole>(&sql)
+                .bind(input.user_id)
+                .bind(input.site_id)
+                .bind(input.role)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    pub async fn delete(&self, transaction: &mut Transaction<'_>, id: i64) -> sqlx::Result<()> {
+        with_transaction!(transaction, connection => {
+            sqlx::query("DELETE FROM SiteRole WHERE id = $1")
+                .bind(id)
+                .execute(connection)
+                .await?;
+            Ok(())
+        })
+    }
+}
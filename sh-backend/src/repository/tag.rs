@@ -0,0 +1,121 @@
+use crate::db::{Database, Transaction};
+use crate::models::{Tag, TagFilter, TaggedEntity};
+use crate::{with_pool, with_transaction};
+
+impl TaggedEntity {
+    /// The table holding the tags of this kind of entity, and its column referencing the entity.
+    fn tag_table(self) -> (&'static str, &'static str) {
+        match self {
+            TaggedEntity::Site => ("SiteTag", "site_id"),
+            TaggedEntity::Room => ("RoomTag", "room_id"),
+            TaggedEntity::Device => ("DeviceTag", "device_id"),
+        }
+    }
+}
+
+/// A condition holding for the entities identified by `id` that carry every one of the tags,
+/// taking their keys and values as parameters from `$first` on. Bind them with [`TagFilter::key`]
+/// and [`TagFilter::value`] in the order of the filters.
+pub(crate) fn tag_filter_sql(
+    entity: TaggedEntity,
+    id: &str,
+    tags: &[TagFilter],
+    first: usize,
+) -> String {
+    let (table, column) = entity.tag_table();
+    let mut sql = String::from("1 = 1");
+    for index in 0..tags.len() {
+        let key = first + 2 * index;
+        let value = key + 1;
+        sql.push_str(&format!(
+            " AND EXISTS (SELECT 1 FROM {table} WHERE {table}.{column} = {id} AND {table}.key = ${key} AND (${value} IS NULL OR {table}.value = ${value}))"
+        ));
+    }
+    sql
+}
+
+/// Tags of sites, rooms and devices.
+pub struct TagRepo<'a> {
+    database: &'a Database,
+}
+
+impl<'a> TagRepo<'a> {
+    pub(crate) fn new(database: &'a Database) -> Self {
+        Self { database }
+    }
+
+    /// The tags of an entity, ordered by key.
+    pub async fn for_entity(&self, entity: TaggedEntity, id: i64) -> sqlx::Result<Vec<Tag>> {
+        let (table, column) = entity.tag_table();
+        let sql = format!("SELECT key, value FROM {table} WHERE {column} = $1 ORDER BY key");
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Tag>(&sql).bind(id).fetch_all(pool).await
+        })
+    }
+
+    pub async fn find_in(
+        &self,
+        transaction: &mut Transaction<'_>,
+        entity: TaggedEntity,
+        id: i64,
+        key: &str,
+    ) -> sqlx::Result<Option<Tag>> {
+        let (table, column) = entity.tag_table();
+        let sql = format!("SELECT key, value FROM {table} WHERE {column} = $1 AND key = $2");
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, Tag>(&sql)
+                .bind(id)
+                .bind(key)
+                .fetch_optional(connection)
+                .await
+        })
+    }
+
+    /// Tags an entity, replacing the value of the key if the entity holds it already.
+    pub async fn set(
+        &self,
+        transaction: &mut Transaction<'_>,
+        entity: TaggedEntity,
+        id: i64,
+        key: &str,
+        value: Option<&str>,
+    ) -> sqlx::Result<Tag> {
+        let (table, column) = entity.tag_table();
+        let sql = format!(
+            r#"
+            INSERT INTO {table} ({column}, key, value)
+            VALUES ($1, $2, $3)
+            ON CONFLICT ({column}, key) DO UPDATE SET value = excluded.value, updated_at = CURRENT_TIMESTAMP
+            RETURNING key, value
+            "#
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, Tag>(&sql)
+                .bind(id)
+                .bind(key)
+                .bind(value)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    /// Removes a key from an entity, returning the tag if the entity held it.
+    pub async fn remove(
+        &self,
+        transaction: &mut Transaction<'_>,
+        entity: TaggedEntity,
+        id: i64,
+        key: &str,
+    ) -> sqlx::Result<Option<Tag>> {
+        let (table, column) = entity.tag_table();
+        let sql =
+            format!("DELETE FROM {table} WHERE {column} = $1 AND key = $2 RETURNING key, value");
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, Tag>(&sql)
+                .bind(id)
+                .bind(key)
+                .fetch_optional(connection)
+                .await
+        })
+    }
+}
@@ -0,0 +1,1311 @@
+//! Runs every repository method against a freshly migrated database, so SQL that one backend
+//! rejects fails here rather than in a resolver. The PostgreSQL tests only run when
+//! `TEST_POSTGRES_URL` points at a database they may create schemas in.
+
+use async_graphql::Json;
+use chrono::{Duration, DurationRound, NaiveTime, Utc};
+use serde_json::json;
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::{Connection, Executor, PgConnection};
+use std::str::FromStr;
+
+use crate::audit::AuditRecord;
+use crate::auth::{Identity, generate_token};
+use crate::automation::{Comparison, RuleAction, RuleTrigger};
+use crate::db::Database;
+use crate::migrations::run_migrations;
+use crate::models::{
+    AlertSeverity, AuditEntityType, AuditLogFilter, AutomationRuleInput, BuildingInput,
+    ControlSetpointInput, DeviceInput, DeviceType, FloorInput, ReadingResolution,
+    RetentionPolicyInput, Role, RoomInput, RuleExecutionStatus, SensorReadingInput, SensorUnit,
+    SetpointMode, SetpointType, SiteInput, SiteRoleInput, TagFilter, TaggedEntity, WebhookDelivery,
+    WebhookDeliveryStatus, WebhookEventType, WebhookSubscriptionInput, ZoneInput,
+};
+
+/// Generates a test per repository for each backend, running the function of the same name.
+macro_rules! repository_tests {
+    ($($name:ident),* $(,)?) => {
+        mod sqlite {
+            $(
+                #[tokio::test]
+                async fn $name() {
+                    super::$name(&super::sqlite().await).await;
+                }
+            )*
+        }
+
+        mod postgres {
+            $(
+                #[tokio::test]
+                async fn $name() {
+                    if let Some(database) = super::postgres().await {
+                        super::$name(&database).await;
+                    }
+                }
+            )*
+        }
+    };
+}
+
+repository_tests!(
+    sites,
+    locations,
+    rooms,
+    devices,
+    readings,
+    reading_retention,
+    setpoints,
+    tags,
+    users_and_roles,
+    credentials,
+    automation_rules,
+    alerts,
+    webhooks,
+    retention_policies,
+    audit_logs,
+);
+
+async fn sqlite() -> Database {
+    // Every connection to `sqlite::memory:` opens a database of its own.
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect("sqlite::memory:")
+        .await
+        .expect("Failed to open the in-memory database");
+    let database = Database::Sqlite(pool);
+    run_migrations(&database)
+        .await
+        .expect("Failed to apply the migrations");
+    database
+}
+
+/// A schema of its own in the database at `TEST_POSTGRES_URL` with every migration applied,
+/// or `None` when the variable isn't set.
+async fn postgres() -> Option<Database> {
+    let url = std::env::var("TEST_POSTGRES_URL").ok()?;
+    let options = PgConnectOptions::from_str(&url).expect("Invalid TEST_POSTGRES_URL");
+    let schema = format!("test_{}", &generate_token()[..16]);
+    let mut connection = PgConnection::connect_with(&options)
+        .await
+        .expect("Failed to connect to TEST_POSTGRES_URL");
+    // Extensions belong to the database rather than a schema, the tests share the one in `public`.
+    // The lock keeps tests starting together from racing to create it.
+    let mut tx = connection.begin().await.unwrap();
+    tx.execute("SELECT pg_advisory_xact_lock(4242)")
+        .await
+        .unwrap();
+    tx.execute("CREATE EXTENSION IF NOT EXISTS unaccent SCHEMA public")
+        .await
+        .expect("Failed to create the unaccent extension");
+    tx.execute(format!("CREATE SCHEMA {}", schema).as_str())
+        .await
+        .expect("Failed to create the schema");
+    tx.commit().await.unwrap();
+
+    let pool = PgPoolOptions::new()
+        .max_connections(2)
+        .connect_with(options.options([("search_path", format!("{},public", schema))]))
+        .await
+        .expect("Failed to connect to TEST_POSTGRES_URL");
+    let database = Database::Postgres(pool);
+    run_migrations(&database)
+        .await
+        .expect("Failed to apply the migrations");
+    Some(database)
+}
+
+/// A site with a room holding a temperature sensor, returned as `(site_id, room_id, device_id)`.
+async fn fixture(database: &Database) -> (i64, i64, i64) {
+    let mut tx = database.begin().await.unwrap();
+    let site = database
+        .sites()
+        .create(
+            &mut tx,
+            &SiteInput {
+                name: "Depot".to_string(),
+                address: None,
+            },
+        )
+        .await
+        .unwrap();
+    let room = database
+        .rooms()
+        .create(
+            &mut tx,
+            &RoomInput {
+                site_id: site.id,
+                name: "Office".to_string(),
+                floor_id: None,
+            },
+        )
+        .await
+        .unwrap();
+    let device = database
+        .devices()
+        .create(
+            &mut tx,
+            &DeviceInput {
+                room_id: room.id,
+                name: "Sensor".to_string(),
+                device_type: DeviceType::TemperatureSensor,
+                unique_identifier: Some("sensor-1".to_string()),
+            },
+        )
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+    (site.id, room.id, device.id)
+}
+
+async fn sites(database: &Database) {
+    let (site_id, _, _) = fixture(database).await;
+    let sites = database.sites();
+
+    let mut tx = database.begin().await.unwrap();
+    let site = sites
+        .set_metadata(&mut tx, site_id, &json!({ "region": "north" }))
+        .await
+        .unwrap();
+    assert_eq!(site.metadata.0, json!({ "region": "north" }));
+    database
+        .tags()
+        .set(&mut tx, TaggedEntity::Site, site_id, "tier", Some("gold"))
+        .await
+        .unwrap();
+    let user = database
+        .users()
+        .create(&mut tx, "Olivia", "token-hash", false)
+        .await
+        .unwrap();
+    database
+        .site_roles()
+        .grant(
+            &mut tx,
+            &SiteRoleInput {
+                user_id: user.id,
+                site_id,
+                role: Role::Viewer,
+            },
+        )
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    assert_eq!(sites.find(site_id).await.unwrap().unwrap().name, "Depot");
+    assert_eq!(sites.all(&[]).await.unwrap().len(), 1);
+    let gold = TagFilter {
+        key: "tier".to_string(),
+        value: Some("gold".to_string()),
+    };
+    let silver = TagFilter {
+        key: "tier".to_string(),
+        value: Some("silver".to_string()),
+    };
+    assert_eq!(
+        sites.all(std::slice::from_ref(&gold)).await.unwrap().len(),
+        1
+    );
+    assert!(sites.all(&[silver]).await.unwrap().is_empty());
+    assert_eq!(sites.for_user(user.id, &[gold]).await.unwrap().len(), 1);
+    assert!(sites.for_user(user.id + 1, &[]).await.unwrap().is_empty());
+    assert_eq!(
+        sites
+            .find_many(&[site_id, site_id + 1])
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    assert!(sites.find_many(&[]).await.unwrap().is_empty());
+}
+
+async fn locations(database: &Database) {
+    let (site_id, room_id, _) = fixture(database).await;
+
+    let mut tx = database.begin().await.unwrap();
+    let building = database
+        .buildings()
+        .create(
+            &mut tx,
+            &BuildingInput {
+                site_id,
+                name: "Main".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+    let building = database
+        .buildings()
+        .rename(&mut tx, building.id, "Annex")
+        .await
+        .unwrap();
+    let floor = database
+        .floors()
+        .create(
+            &mut tx,
+            &FloorInput {
+                building_id: building.id,
+                name: "Ground".to_string(),
+                level: 0,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(floor.site_id, site_id);
+    let floor = database
+        .floors()
+        .rename(&mut tx, floor.id, "First")
+        .await
+        .unwrap();
+    let zone = database
+        .zones()
+        .create(
+            &mut tx,
+            &ZoneInput {
+                site_id,
+                name: "West".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+    let zone = database
+        .zones()
+        .rename(&mut tx, zone.id, "East")
+        .await
+        .unwrap();
+    assert!(
+        database
+            .zones()
+            .add_room(&mut tx, zone.id, room_id)
+            .await
+            .unwrap()
+    );
+    assert!(
+        !database
+            .zones()
+            .add_room(&mut tx, zone.id, room_id)
+            .await
+            .unwrap()
+    );
+    tx.commit().await.unwrap();
+
+    assert_eq!(
+        database
+            .buildings()
+            .find(building.id)
+            .await
+            .unwrap()
+            .unwrap()
+            .name,
+        "Annex"
+    );
+    assert_eq!(
+        database.buildings().for_site(site_id).await.unwrap().len(),
+        1
+    );
+    assert_eq!(
+        database.buildings().site_id(building.id).await.unwrap(),
+        Some(site_id)
+    );
+    assert_eq!(
+        database
+            .floors()
+            .find(floor.id)
+            .await
+            .unwrap()
+            .unwrap()
+            .name,
+        "First"
+    );
+    assert_eq!(
+        database
+            .floors()
+            .for_building(building.id)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(
+        database.floors().site_id(floor.id).await.unwrap(),
+        Some(site_id)
+    );
+    assert_eq!(
+        database.zones().find(zone.id).await.unwrap().unwrap().name,
+        "East"
+    );
+    assert_eq!(database.zones().for_site(site_id).await.unwrap().len(), 1);
+    assert_eq!(database.zones().for_room(room_id).await.unwrap().len(), 1);
+    assert_eq!(
+        database.zones().site_id(zone.id).await.unwrap(),
+        Some(site_id)
+    );
+
+    let mut tx = database.begin().await.unwrap();
+    assert!(
+        database
+            .zones()
+            .remove_room(&mut tx, zone.id, room_id)
+            .await
+            .unwrap()
+    );
+    assert!(
+        !database
+            .zones()
+            .remove_room(&mut tx, zone.id, room_id)
+            .await
+            .unwrap()
+    );
+    database.zones().delete(&mut tx, zone.id).await.unwrap();
+    database.floors().delete(&mut tx, floor.id).await.unwrap();
+    database
+        .buildings()
+        .delete(&mut tx, building.id)
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    assert!(database.zones().find(zone.id).await.unwrap().is_none());
+    assert!(database.floors().find(floor.id).await.unwrap().is_none());
+    assert!(
+        database
+            .buildings()
+            .find(building.id)
+            .await
+            .unwrap()
+            .is_none()
+    );
+}
+
+async fn rooms(database: &Database) {
+    let (site_id, room_id, _) = fixture(database).await;
+    let rooms = database.rooms();
+
+    let mut tx = database.begin().await.unwrap();
+    let building = database
+        .buildings()
+        .create(
+            &mut tx,
+            &BuildingInput {
+                site_id,
+                name: "Main".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+    let floor = database
+        .floors()
+        .create(
+            &mut tx,
+            &FloorInput {
+                building_id: building.id,
+                name: "Ground".to_string(),
+                level: 0,
+            },
+        )
+        .await
+        .unwrap();
+    let zone = database
+        .zones()
+        .create(
+            &mut tx,
+            &ZoneInput {
+                site_id,
+                name: "West".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+    database
+        .zones()
+        .add_room(&mut tx, zone.id, room_id)
+        .await
+        .unwrap();
+    let room = rooms
+        .set_floor(&mut tx, room_id, Some(floor.id))
+        .await
+        .unwrap();
+    assert_eq!(room.floor_id, Some(floor.id));
+    let room = rooms
+        .set_metadata(&mut tx, room_id, &json!({ "seats": 4 }))
+        .await
+        .unwrap();
+    assert_eq!(room.metadata.0, json!({ "seats": 4 }));
+    tx.commit().await.unwrap();
+
+    assert_eq!(rooms.find(room_id).await.unwrap().unwrap().name, "Office");
+    assert_eq!(rooms.find_many(&[room_id]).await.unwrap().len(), 1);
+    assert_eq!(rooms.for_site(site_id).await.unwrap().len(), 1);
+    assert_eq!(rooms.for_floor(floor.id).await.unwrap().len(), 1);
+    assert_eq!(rooms.for_zone(zone.id).await.unwrap().len(), 1);
+    assert_eq!(rooms.site_id(room_id).await.unwrap(), Some(site_id));
+}
+
+async fn devices(database: &Database) {
+    let (site_id, room_id, device_id) = fixture(database).await;
+    let devices = database.devices();
+
+    let mut tx = database.begin().await.unwrap();
+    let zone = database
+        .zones()
+        .create(
+            &mut tx,
+            &ZoneInput {
+                site_id,
+                name: "West".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+    database
+        .zones()
+        .add_room(&mut tx, zone.id, room_id)
+        .await
+        .unwrap();
+    let device = devices
+        .set_metadata(&mut tx, device_id, &json!({ "firmware": "1.2" }))
+        .await
+        .unwrap();
+    assert_eq!(device.metadata.0, json!({ "firmware": "1.2" }));
+    database
+        .tags()
+        .set(&mut tx, TaggedEntity::Device, device_id, "vendor", None)
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    assert_eq!(
+        devices.find(device_id).await.unwrap().unwrap().name,
+        "Sensor"
+    );
+    assert_eq!(devices.find_many(&[device_id]).await.unwrap().len(), 1);
+    assert_eq!(devices.for_room(room_id, &[]).await.unwrap().len(), 1);
+    let vendor = TagFilter {
+        key: "vendor".to_string(),
+        value: None,
+    };
+    assert_eq!(devices.for_room(room_id, &[vendor]).await.unwrap().len(), 1);
+    assert_eq!(devices.for_zone(zone.id).await.unwrap().len(), 1);
+    assert_eq!(devices.site_id(device_id).await.unwrap(), Some(site_id));
+    assert_eq!(
+        devices.by_identifier("sensor-1").await.unwrap(),
+        Some((device_id, site_id))
+    );
+    assert!(devices.by_identifier("sensor-2").await.unwrap().is_none());
+    assert_eq!(
+        devices.all_with_site().await.unwrap(),
+        vec![(device_id, site_id, DeviceType::TemperatureSensor)]
+    );
+}
+
+async fn readings(database: &Database) {
+    let (site_id, room_id, device_id) = fixture(database).await;
+    let readings = database.readings();
+    let now = Utc::now().duration_trunc(Duration::seconds(1)).unwrap();
+    let reading = |value: &str| SensorReadingInput {
+        device_id,
+        value: value.to_string(),
+        unit: Some(SensorUnit::Celsius),
+    };
+
+    let mut tx = database.begin().await.unwrap();
+    let building = database
+        .buildings()
+        .create(
+            &mut tx,
+            &BuildingInput {
+                site_id,
+                name: "Main".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+    let floor = database
+        .floors()
+        .create(
+            &mut tx,
+            &FloorInput {
+                building_id: building.id,
+                name: "Ground".to_string(),
+                level: 0,
+            },
+        )
+        .await
+        .unwrap();
+    database
+        .rooms()
+        .set_floor(&mut tx, room_id, Some(floor.id))
+        .await
+        .unwrap();
+    let zone = database
+        .zones()
+        .create(
+            &mut tx,
+            &ZoneInput {
+                site_id,
+                name: "West".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+    database
+        .zones()
+        .add_room(&mut tx, zone.id, room_id)
+        .await
+        .unwrap();
+    let first = readings
+        .create(&mut tx, &reading("20"), Some(now - Duration::minutes(10)))
+        .await
+        .unwrap();
+    let inserted = readings
+        .create_many(
+            &mut tx,
+            &[
+                (reading("open"), now - Duration::minutes(5)),
+                (reading("24"), now),
+            ],
+        )
+        .await
+        .unwrap();
+    assert_eq!(inserted, 2);
+    tx.commit().await.unwrap();
+
+    let latest = readings.latest(device_id).await.unwrap().unwrap();
+    assert_eq!(latest.value, "24");
+    assert_eq!(latest.timestamp, now);
+    let previous = readings
+        .previous(device_id, latest.id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(previous.value, "open");
+    assert!(
+        readings
+            .previous(device_id, first.id)
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let from = now - Duration::hours(1);
+    for summaries in [
+        readings.floor_summary(floor.id, from, now).await.unwrap(),
+        readings.zone_summary(zone.id, from, now).await.unwrap(),
+    ] {
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].device_count, 1);
+        assert_eq!(summaries[0].sample_count, 2);
+        assert_eq!(summaries[0].min_value, 20.0);
+        assert_eq!(summaries[0].max_value, 24.0);
+        assert_eq!(summaries[0].avg_value, 22.0);
+    }
+}
+
+async fn reading_retention(database: &Database) {
+    let (_, _, device_id) = fixture(database).await;
+    let readings = database.readings();
+    let now = Utc::now().duration_trunc(Duration::seconds(1)).unwrap();
+    let day = now.duration_trunc(Duration::days(1)).unwrap() - Duration::days(2);
+    let reading = |value: &str| SensorReadingInput {
+        device_id,
+        value: value.to_string(),
+        unit: Some(SensorUnit::Celsius),
+    };
+
+    let mut tx = database.begin().await.unwrap();
+    readings
+        .create_many(
+            &mut tx,
+            &[
+                (reading("20"), day + Duration::minutes(60)),
+                (reading("22"), day + Duration::minutes(90)),
+                (reading("open"), day + Duration::minutes(105)),
+                (reading("30"), day + Duration::hours(2)),
+                (reading("40"), day + Duration::hours(26)),
+                (reading("24"), now),
+            ],
+        )
+        .await
+        .unwrap();
+    let raw_cutoff = day + Duration::hours(2);
+    readings
+        .roll_up(&mut tx, device_id, ReadingResolution::Hourly, raw_cutoff)
+        .await
+        .unwrap();
+    assert_eq!(
+        readings
+            .prune(&mut tx, device_id, raw_cutoff)
+            .await
+            .unwrap(),
+        3
+    );
+    readings
+        .advance_pruned_before(&mut tx, device_id, raw_cutoff, None)
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    let history = readings.history(device_id, None, None, 10).await.unwrap();
+    let resolutions: Vec<_> = history.iter().map(|sample| sample.resolution).collect();
+    assert_eq!(
+        resolutions,
+        [
+            ReadingResolution::Hourly,
+            ReadingResolution::Raw,
+            ReadingResolution::Raw,
+            ReadingResolution::Raw,
+        ]
+    );
+    assert_eq!(history[0].timestamp, day + Duration::hours(1));
+    assert_eq!(history[0].sample_count, 2);
+    assert_eq!(history[0].avg_value, 21.0);
+    assert_eq!(history[3].value, "24");
+    let latest = readings
+        .history(device_id, Some(day + Duration::minutes(90)), None, 2)
+        .await
+        .unwrap();
+    let values: Vec<_> = latest.iter().map(|sample| sample.value.as_str()).collect();
+    assert_eq!(values, ["40", "24"]);
+
+    let hourly_cutoff = day + Duration::days(1);
+    let mut tx = database.begin().await.unwrap();
+    readings
+        .roll_up(&mut tx, device_id, ReadingResolution::Daily, hourly_cutoff)
+        .await
+        .unwrap();
+    readings
+        .prune_rollups(&mut tx, device_id, ReadingResolution::Hourly, hourly_cutoff)
+        .await
+        .unwrap();
+    assert_eq!(
+        readings
+            .prune(&mut tx, device_id, hourly_cutoff)
+            .await
+            .unwrap(),
+        1
+    );
+    readings
+        .advance_pruned_before(&mut tx, device_id, hourly_cutoff, Some(hourly_cutoff))
+        .await
+        .unwrap();
+    // The watermarks never move back.
+    readings
+        .advance_pruned_before(&mut tx, device_id, day, None)
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    let history = readings.history(device_id, None, None, 10).await.unwrap();
+    let resolutions: Vec<_> = history.iter().map(|sample| sample.resolution).collect();
+    assert_eq!(
+        resolutions,
+        [
+            ReadingResolution::Daily,
+            ReadingResolution::Raw,
+            ReadingResolution::Raw,
+        ]
+    );
+    assert_eq!(history[0].timestamp, day);
+    assert_eq!(history[0].min_value, 30.0);
+}
+
+async fn setpoints(database: &Database) {
+    let (_, _, device_id) = fixture(database).await;
+    let setpoints = database.setpoints();
+    let now = Utc::now();
+    let setpoint = |value: &str, mode, expires_at| ControlSetpointInput {
+        device_id,
+        setpoint_type: SetpointType::Temperature,
+        value: value.to_string(),
+        unit: None,
+        mode,
+        expires_at,
+    };
+
+    let mut tx = database.begin().await.unwrap();
+    setpoints
+        .create(&mut tx, &setpoint("20", SetpointMode::Scheduled, None), now)
+        .await
+        .unwrap();
+    setpoints
+        .create(
+            &mut tx,
+            &setpoint("23", SetpointMode::Override, Some(now + Duration::hours(1))),
+            now,
+        )
+        .await
+        .unwrap();
+    let effective = setpoints
+        .effective_in(&mut tx, device_id, now)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(effective.value, "23");
+    assert_eq!(
+        setpoints
+            .release_overrides(&mut tx, device_id, now)
+            .await
+            .unwrap(),
+        1
+    );
+    tx.commit().await.unwrap();
+
+    assert_eq!(setpoints.for_device(device_id).await.unwrap().len(), 2);
+    let effective = setpoints.effective(device_id, now).await.unwrap().unwrap();
+    assert_eq!(effective.value, "20");
+}
+
+async fn tags(database: &Database) {
+    let (_, room_id, _) = fixture(database).await;
+    let tags = database.tags();
+
+    let mut tx = database.begin().await.unwrap();
+    tags.set(&mut tx, TaggedEntity::Room, room_id, "wing", Some("north"))
+        .await
+        .unwrap();
+    let tag = tags
+        .set(&mut tx, TaggedEntity::Room, room_id, "wing", Some("south"))
+        .await
+        .unwrap();
+    assert_eq!(tag.value.as_deref(), Some("south"));
+    let found = tags
+        .find_in(&mut tx, TaggedEntity::Room, room_id, "wing")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.value.as_deref(), Some("south"));
+    tags.set(&mut tx, TaggedEntity::Room, room_id, "quiet", None)
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    let keys: Vec<_> = tags
+        .for_entity(TaggedEntity::Room, room_id)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|tag| tag.key)
+        .collect();
+    assert_eq!(keys, ["quiet", "wing"]);
+
+    let mut tx = database.begin().await.unwrap();
+    let removed = tags
+        .remove(&mut tx, TaggedEntity::Room, room_id, "wing")
+        .await
+        .unwrap();
+    assert_eq!(removed.unwrap().value.as_deref(), Some("south"));
+    assert!(
+        tags.remove(&mut tx, TaggedEntity::Room, room_id, "wing")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    tx.commit().await.unwrap();
+}
+
+async fn users_and_roles(database: &Database) {
+    let (site_id, _, _) = fixture(database).await;
+    let roles = database.site_roles();
+
+    let mut tx = database.begin().await.unwrap();
+    let user = database
+        .users()
+        .create(&mut tx, "Olivia", "token-hash", false)
+        .await
+        .unwrap();
+    let grant = |role| SiteRoleInput {
+        user_id: user.id,
+        site_id,
+        role,
+    };
+    roles.grant(&mut tx, &grant(Role::Viewer)).await.unwrap();
+    let role = roles.grant(&mut tx, &grant(Role::Operator)).await.unwrap();
+    let found = roles
+        .find_in(&mut tx, user.id, site_id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((found.id, found.role), (role.id, Role::Operator));
+    tx.commit().await.unwrap();
+
+    let found = database
+        .users()
+        .find_by_token_hash("token-hash")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.name, "Olivia");
+    assert!(!found.is_admin);
+    assert!(
+        database
+            .users()
+            .find_by_token_hash("other")
+            .await
+            .unwrap()
+            .is_none()
+    );
+    assert_eq!(roles.for_site(site_id).await.unwrap().len(), 1);
+    assert_eq!(
+        roles.role(user.id, site_id).await.unwrap(),
+        Some(Role::Operator)
+    );
+    assert_eq!(roles.operated_sites(user.id).await.unwrap(), [site_id]);
+
+    let mut tx = database.begin().await.unwrap();
+    roles.delete(&mut tx, role.id).await.unwrap();
+    tx.commit().await.unwrap();
+    assert!(roles.role(user.id, site_id).await.unwrap().is_none());
+}
+
+async fn credentials(database: &Database) {
+    let (_, _, device_id) = fixture(database).await;
+    let credentials = database.credentials();
+
+    let mut tx = database.begin().await.unwrap();
+    let first = credentials
+        .create(&mut tx, device_id, "shd_1", "hash-1")
+        .await
+        .unwrap();
+    let second = credentials
+        .create(&mut tx, device_id, "shd_2", "hash-2")
+        .await
+        .unwrap();
+    let revoked = credentials.revoke(&mut tx, first.id).await.unwrap();
+    let revoked_at = revoked.revoked_at.unwrap();
+    let revoked = credentials.revoke(&mut tx, first.id).await.unwrap();
+    assert_eq!(revoked.revoked_at, Some(revoked_at));
+    assert!(credentials.revoke_active(&mut tx, second.id).await.unwrap());
+    assert!(!credentials.revoke_active(&mut tx, second.id).await.unwrap());
+    tx.commit().await.unwrap();
+
+    assert_eq!(
+        credentials
+            .find(first.id)
+            .await
+            .unwrap()
+            .unwrap()
+            .key_prefix,
+        "shd_1"
+    );
+    assert_eq!(credentials.for_device(device_id).await.unwrap().len(), 2);
+    assert!(
+        credentials
+            .find_active_by_key_hash("hash-2")
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let mut tx = database.begin().await.unwrap();
+    let third = credentials
+        .create(&mut tx, device_id, "shd_3", "hash-3")
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+    let active = credentials
+        .find_active_by_key_hash("hash-3")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(active.id, third.id);
+}
+
+fn rule_input(site_id: i64, device_id: i64, enabled: bool) -> AutomationRuleInput {
+    AutomationRuleInput {
+        site_id,
+        name: "Too warm".to_string(),
+        enabled,
+        trigger: Json(RuleTrigger::SensorThreshold {
+            device_id,
+            comparison: Comparison::AtLeast,
+            value: 25.0,
+        }),
+        conditions: Json(Vec::new()),
+        actions: Json(vec![RuleAction::RaiseAlert {
+            severity: AlertSeverity::Warning,
+            message: "Too warm".to_string(),
+        }]),
+    }
+}
+
+async fn automation_rules(database: &Database) {
+    let (site_id, _, device_id) = fixture(database).await;
+    let rules = database.automation_rules();
+    let now = Utc::now().duration_trunc(Duration::seconds(1)).unwrap();
+
+    let mut tx = database.begin().await.unwrap();
+    let rule = rules
+        .create(&mut tx, &rule_input(site_id, device_id, true))
+        .await
+        .unwrap();
+    let disabled = rules
+        .create(&mut tx, &rule_input(site_id, device_id, false))
+        .await
+        .unwrap();
+    let mut input = rule_input(site_id, device_id, true);
+    input.name = "Way too warm".to_string();
+    input.trigger = Json(RuleTrigger::TimeOfDay {
+        time: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+    });
+    let rule = rules.update(&mut tx, rule.id, &input).await.unwrap();
+    assert_eq!(rule.name, "Way too warm");
+    assert!(matches!(rule.trigger.0, RuleTrigger::TimeOfDay { .. }));
+    tx.commit().await.unwrap();
+
+    rules.set_last_triggered_at(rule.id, now).await.unwrap();
+    rules
+        .record_execution(rule.id, RuleExecutionStatus::Skipped, "{}", None, now)
+        .await
+        .unwrap();
+    rules
+        .record_execution(
+            rule.id,
+            RuleExecutionStatus::Failed,
+            "{}",
+            Some("Timed out"),
+            now,
+        )
+        .await
+        .unwrap();
+
+    let found = rules.find(rule.id).await.unwrap().unwrap();
+    assert_eq!(found.last_triggered_at, Some(now));
+    assert_eq!(found.actions.0.len(), 1);
+    assert_eq!(rules.for_site(site_id).await.unwrap().len(), 2);
+    let enabled: Vec<_> = rules
+        .enabled(None)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|rule| rule.id)
+        .collect();
+    assert_eq!(enabled, [rule.id]);
+    assert_eq!(rules.enabled(Some(site_id)).await.unwrap().len(), 1);
+    assert!(rules.enabled(Some(site_id + 1)).await.unwrap().is_empty());
+    let executions = rules.executions(rule.id, 1).await.unwrap();
+    assert_eq!(executions.len(), 1);
+    assert_eq!(executions[0].status, RuleExecutionStatus::Failed);
+    assert_eq!(executions[0].message.as_deref(), Some("Timed out"));
+
+    let mut tx = database.begin().await.unwrap();
+    rules.delete(&mut tx, disabled.id).await.unwrap();
+    tx.commit().await.unwrap();
+    assert!(rules.find(disabled.id).await.unwrap().is_none());
+}
+
+async fn alerts(database: &Database) {
+    let (site_id, _, device_id) = fixture(database).await;
+    let alerts = database.alerts();
+    let now = Utc::now().duration_trunc(Duration::seconds(1)).unwrap();
+
+    let mut tx = database.begin().await.unwrap();
+    let rule = database
+        .automation_rules()
+        .create(&mut tx, &rule_input(site_id, device_id, true))
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+
+    let first = alerts
+        .raise(
+            site_id,
+            rule.id,
+            AlertSeverity::Info,
+            "Warming up",
+            now - Duration::minutes(1),
+        )
+        .await
+        .unwrap();
+    let second = alerts
+        .raise(site_id, rule.id, AlertSeverity::Critical, "Too warm", now)
+        .await
+        .unwrap();
+
+    let mut tx = database.begin().await.unwrap();
+    let acknowledged = alerts.acknowledge(&mut tx, first.id, now).await.unwrap();
+    assert_eq!(acknowledged.acknowledged_at, Some(now));
+    let acknowledged = alerts
+        .acknowledge(&mut tx, first.id, now + Duration::minutes(1))
+        .await
+        .unwrap();
+    assert_eq!(acknowledged.acknowledged_at, Some(now));
+    tx.commit().await.unwrap();
+
+    assert_eq!(
+        alerts.find(second.id).await.unwrap().unwrap().severity,
+        AlertSeverity::Critical
+    );
+    let open: Vec<_> = alerts
+        .for_site(site_id, false, 10)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|alert| alert.id)
+        .collect();
+    assert_eq!(open, [second.id]);
+    let all: Vec<_> = alerts
+        .for_site(site_id, true, 10)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|alert| alert.id)
+        .collect();
+    assert_eq!(all, [second.id, first.id]);
+    assert_eq!(alerts.for_site(site_id, true, 1).await.unwrap().len(), 1);
+}
+
+async fn webhooks(database: &Database) {
+    let (site_id, _, _) = fixture(database).await;
+    let webhooks = database.webhooks();
+    let mut input = WebhookSubscriptionInput {
+        site_id: Some(site_id),
+        url: "https://203.0.113.10/hook".to_string(),
+        event_types: vec![WebhookEventType::AlertRaised],
+        secret: None,
+        enabled: true,
+    };
+
+    let mut tx = database.begin().await.unwrap();
+    let subscription = webhooks
+        .create(&mut tx, &input, "first-secret")
+        .await
+        .unwrap();
+    let global = webhooks
+        .create(
+            &mut tx,
+            &WebhookSubscriptionInput {
+                site_id: None,
+                ..input.clone()
+            },
+            "global-secret",
+        )
+        .await
+        .unwrap();
+    input
+        .event_types
+        .push(WebhookEventType::SensorReadingCreated);
+    input.enabled = false;
+    let updated = webhooks
+        .update(&mut tx, subscription.id, &input)
+        .await
+        .unwrap();
+    assert_eq!(updated.secret, "first-secret");
+    assert_eq!(updated.event_types.0.len(), 2);
+    assert!(!updated.enabled);
+    input.secret = Some("second-secret".to_string());
+    let updated = webhooks
+        .update(&mut tx, subscription.id, &input)
+        .await
+        .unwrap();
+    assert_eq!(updated.secret, "second-secret");
+    tx.commit().await.unwrap();
+
+    assert_eq!(
+        webhooks
+            .find(subscription.id)
+            .await
+            .unwrap()
+            .unwrap()
+            .secret,
+        "second-secret"
+    );
+    assert_eq!(webhooks.all(None).await.unwrap().len(), 2);
+    assert_eq!(webhooks.all(Some(site_id)).await.unwrap().len(), 1);
+    assert!(
+        webhooks
+            .deliveries(subscription.id, None, 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(
+        webhooks
+            .deliveries(subscription.id, Some(WebhookDeliveryStatus::Failed), 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+
+    let enabled = webhooks.enabled_for_site(site_id).await.unwrap();
+    assert_eq!(enabled.len(), 1);
+    assert_eq!(enabled[0].id, global.id);
+
+    let now = Utc::now().duration_trunc(Duration::seconds(1)).unwrap();
+    webhooks
+        .queue_delivery(global.id, WebhookEventType::AlertRaised, "{}", now)
+        .await
+        .unwrap();
+    assert!(
+        webhooks
+            .due_deliveries(now - Duration::minutes(1), 10)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    let due = webhooks.due_deliveries(now, 10).await.unwrap();
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].status, WebhookDeliveryStatus::Pending);
+    webhooks
+        .save_delivery(&WebhookDelivery {
+            status: WebhookDeliveryStatus::Succeeded,
+            attempts: 1,
+            next_attempt_at: None,
+            last_response_status: Some(204),
+            delivered_at: Some(now),
+            updated_at: now,
+            ..due[0].clone()
+        })
+        .await
+        .unwrap();
+    assert!(webhooks.due_deliveries(now, 10).await.unwrap().is_empty());
+    let delivered = webhooks
+        .deliveries(global.id, Some(WebhookDeliveryStatus::Succeeded), 10)
+        .await
+        .unwrap();
+    assert_eq!(delivered.len(), 1);
+    assert_eq!(delivered[0].attempts, 1);
+    assert_eq!(delivered[0].last_response_status, Some(204));
+
+    let mut tx = database.begin().await.unwrap();
+    webhooks.delete(&mut tx, global.id).await.unwrap();
+    tx.commit().await.unwrap();
+    assert!(webhooks.find(global.id).await.unwrap().is_none());
+}
+
+async fn retention_policies(database: &Database) {
+    let (site_id, _, _) = fixture(database).await;
+    let policies = database.retention_policies();
+    let global = RetentionPolicyInput {
+        site_id: None,
+        device_type: None,
+        raw_retention_days: 30,
+        hourly_retention_days: None,
+        daily_retention_days: None,
+    };
+    let mut for_sensors = RetentionPolicyInput {
+        site_id: Some(site_id),
+        device_type: Some(DeviceType::TemperatureSensor),
+        raw_retention_days: 7,
+        hourly_retention_days: Some(90),
+        daily_retention_days: None,
+    };
+
+    let mut tx = database.begin().await.unwrap();
+    let global = policies.create(&mut tx, &global).await.unwrap();
+    let specific = policies.create(&mut tx, &for_sensors).await.unwrap();
+    let found = policies
+        .find_scope_in(&mut tx, &for_sensors)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(found.id, specific.id);
+    let scope = RetentionPolicyInput {
+        device_type: None,
+        ..for_sensors.clone()
+    };
+    assert!(
+        policies
+            .find_scope_in(&mut tx, &scope)
+            .await
+            .unwrap()
+            .is_none()
+    );
+    for_sensors.raw_retention_days = 14;
+    let updated = policies
+        .update(&mut tx, specific.id, &for_sensors)
+        .await
+        .unwrap();
+    assert_eq!(updated.raw_retention_days, 14);
+    tx.commit().await.unwrap();
+
+    assert_eq!(
+        policies
+            .find(global.id)
+            .await
+            .unwrap()
+            .unwrap()
+            .raw_retention_days,
+        30
+    );
+    assert_eq!(policies.all(None).await.unwrap().len(), 2);
+    assert_eq!(policies.all(Some(site_id)).await.unwrap().len(), 1);
+    let policy = policies
+        .for_device(site_id, DeviceType::TemperatureSensor)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(policy.id, specific.id);
+    let policy = policies
+        .for_device(site_id, DeviceType::ThermostatController)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(policy.id, global.id);
+
+    let mut tx = database.begin().await.unwrap();
+    policies.delete(&mut tx, specific.id).await.unwrap();
+    tx.commit().await.unwrap();
+    assert!(policies.find(specific.id).await.unwrap().is_none());
+}
+
+async fn audit_logs(database: &Database) {
+    let (site_id, room_id, _) = fixture(database).await;
+    let now = Utc::now();
+
+    let mut tx = database.begin().await.unwrap();
+    let user = database
+        .users()
+        .create(&mut tx, "Olivia", "token-hash", true)
+        .await
+        .unwrap();
+    AuditRecord::new(
+        "renameRoom",
+        AuditEntityType::Room,
+        &json!({ "id": room_id }),
+    )
+    .entity_id(room_id)
+    .site_id(site_id)
+    .before(&json!({ "name": "Office" }))
+    .after(&json!({ "name": "Studio" }))
+    .insert_in(&mut tx, &Identity::User(user.clone()))
+    .await
+    .unwrap();
+    tx.commit().await.unwrap();
+    AuditRecord::new("createSite", AuditEntityType::Site, &json!({}))
+        .entity_id(site_id)
+        .insert(database, &Identity::Automation(1))
+        .await
+        .unwrap();
+
+    let logs = database.audit_logs();
+    let entries = logs.list(&AuditLogFilter::default(), 10).await.unwrap();
+    assert_eq!(entries.len(), 2);
+    let filter = AuditLogFilter {
+        site_id: Some(site_id),
+        entity_type: Some(AuditEntityType::Room),
+        entity_id: Some(room_id),
+        from: Some(now - Duration::minutes(1)),
+        to: Some(now + Duration::minutes(1)),
+    };
+    let entries = logs.list(&filter, 10).await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].actor_id, Some(user.id));
+    assert_eq!(entries[0].actor_name.as_deref(), Some("Olivia"));
+    assert_eq!(entries[0].operation, "renameRoom");
+    assert!(entries[0].before.is_some());
+    let filter = AuditLogFilter {
+        to: Some(now - Duration::minutes(1)),
+        ..AuditLogFilter::default()
+    };
+    assert!(logs.list(&filter, 10).await.unwrap().is_empty());
+    assert_eq!(
+        logs.list(&AuditLogFilter::default(), 1)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+}
@@ -0,0 +1,44 @@
+use crate::db::{Database, Transaction};
+use crate::models::User;
+use crate::{with_pool, with_transaction};
+
+const COLUMNS: &str = "id, name, token_hash, is_admin, created_at, updated_at";
+
+/// Users, looked up by the hash of their API token.
+pub struct UserRepo<'a> {
+    database: &'a Database,
+}
+
+impl<'a> UserRepo<'a> {
+    pub(crate) fn new(database: &'a Database) -> Self {
+        Self { database }
+    }
+
+    pub async fn find_by_token_hash(&self, token_hash: &str) -> sqlx::Result<Option<User>> {
+        let sql = format!("SELECT {} FROM AppUser WHERE token_hash = $1", COLUMNS);
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, User>(&sql).bind(token_hash).fetch_optional(pool).await
+        })
+    }
+
+    pub async fn create(
+        &self,
+        transaction: &mut Transaction<'_>,
+        name: &str,
+        token_hash: &str,
+        is_admin: bool,
+    ) -> sqlx::Result<User> {
+        let sql = format!(
+            "INSERT INTO AppUser (name, token_hash, is_admin) VALUES ($1, $2, $3) RETURNING {}",
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, User>(&sql)
+                .bind(name)
+                .bind(token_hash)
+                .bind(is_admin)
+                .fetch_one(connection)
+                .await
+        })
+    }
+}
@@ -0,0 +1,215 @@
+use chrono::{DateTime, Utc};
+
+use crate::db::{Database, Transaction};
+use crate::models::{
+    WebhookDelivery, WebhookDeliveryStatus, WebhookEventType, WebhookSubscription,
+    WebhookSubscriptionInput,
+};
+use crate::{with_pool, with_transaction};
+
+const COLUMNS: &str = "id, site_id, url, event_types, secret, enabled, created_at, updated_at";
+
+const DELIVERY_COLUMNS: &str = "id, subscription_id, event_type, payload, status, attempts, next_attempt_at, last_response_status, last_error, delivered_at, created_at, updated_at";
+
+/// Webhook subscriptions and the log of their deliveries.
+pub struct WebhookRepo<'a> {
+    database: &'a Database,
+}
+
+impl<'a> WebhookRepo<'a> {
+    pub(crate) fn new(database: &'a Database) -> Self {
+        Self { database }
+    }
+
+    pub async fn find(&self, id: i64) -> sqlx::Result<Option<WebhookSubscription>> {
+        let sql = format!("SELECT {} FROM WebhookSubscription WHERE id = $1", COLUMNS);
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, WebhookSubscription>(&sql).bind(id).fetch_optional(pool).await
+        })
+    }
+
+    /// The subscriptions of a site, or every subscription when no site is given.
+    pub async fn all(&self, site_id: Option<i64>) -> sqlx::Result<Vec<WebhookSubscription>> {
+        let sql = format!(
+            "SELECT {} FROM WebhookSubscription WHERE $1 IS NULL OR site_id = $1 ORDER BY id",
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, WebhookSubscription>(&sql).bind(site_id).fetch_all(pool).await
+        })
+    }
+
+    /// The enabled subscriptions receiving the events of a site, which includes those of every site.
+    pub async fn enabled_for_site(&self, site_id: i64) -> sqlx::Result<Vec<WebhookSubscription>> {
+        let sql = format!(
+            "SELECT {} FROM WebhookSubscription WHERE enabled AND (site_id IS NULL OR site_id = $1) ORDER BY id",
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, WebhookSubscription>(&sql).bind(site_id).fetch_all(pool).await
+        })
+    }
+
+    pub async fn create(
+        &self,
+        transaction: &mut Transaction<'_>,
+        input: &WebhookSubscriptionInput,
+        secret: &str,
+    ) -> sqlx::Result<WebhookSubscription> {
+        let sql = format!(
+            r#"
+            INSERT INTO WebhookSubscription (site_id, url, event_types, secret, enabled)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING {}
+            "#,
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, WebhookSubscription>(&sql)
+                .bind(input.site_id)
+                .bind(&input.url)
+                .bind(sqlx::types::Json(&input.event_types))
+                .bind(secret)
+                .bind(input.enabled)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    /// Replaces the settings of a subscription, keeping its secret unless the input holds a new one.
+    pub async fn update(
+        &self,
+        transaction: &mut Transaction<'_>,
+        id: i64,
+        input: &WebhookSubscriptionInput,
+    ) -> sqlx::Result<WebhookSubscription> {
+        let sql = format!(
+            r#"
+            UPDATE WebhookSubscription
+            SET url = $1, event_types = $2, secret = COALESCE($3, secret), enabled = $4, updated_at = CURRENT_TIMESTAMP
+            WHERE id = $5
+            RETURNING {}
+            "#,
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, WebhookSubscription>(&sql)
+                .bind(&input.url)
+                .bind(sqlx::types::Json(&input.event_types))
+                .bind(&input.secret)
+                .bind(input.enabled)
+                .bind(id)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    /// Deletes a subscription along with its deliveries.
+    pub async fn delete(&self, transaction: &mut Transaction<'_>, id: i64) -> sqlx::Result<()> {
+        with_transaction!(transaction, connection => {
+            sqlx::query("DELETE FROM WebhookSubscription WHERE id = $1")
+                .bind(id)
+                .execute(connection)
+                .await?;
+            Ok(())
+        })
+    }
+
+    /// The latest deliveries of a subscription, newest first, optionally only those in a status.
+    pub async fn deliveries(
+        &self,
+        subscription_id: i64,
+        status: Option<WebhookDeliveryStatus>,
+        limit: i64,
+    ) -> sqlx::Result<Vec<WebhookDelivery>> {
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM WebhookDelivery
+            WHERE subscription_id = $1 AND ($2 IS NULL OR status = $2)
+            ORDER BY id DESC
+            LIMIT $3
+            "#,
+            DELIVERY_COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, WebhookDelivery>(&sql)
+                .bind(subscription_id)
+                .bind(status)
+                .bind(limit)
+                .fetch_all(pool)
+                .await
+        })
+    }
+
+    /// Queues a delivery of an event to a subscription, to be attempted at `next_attempt_at`.
+    pub async fn queue_delivery(
+        &self,
+        subscription_id: i64,
+        event_type: WebhookEventType,
+        payload: &str,
+        next_attempt_at: DateTime<Utc>,
+    ) -> sqlx::Result<()> {
+        with_pool!(self.database, pool => {
+            sqlx::query(
+                "INSERT INTO WebhookDelivery (subscription_id, event_type, payload, next_attempt_at) VALUES ($1, $2, $3, $4)",
+            )
+            .bind(subscription_id)
+            .bind(event_type)
+            .bind(payload)
+            .bind(next_attempt_at)
+            .execute(pool)
+            .await?;
+            Ok(())
+        })
+    }
+
+    /// The pending deliveries whose next attempt is due, the longest waiting first.
+    pub async fn due_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        limit: i64,
+    ) -> sqlx::Result<Vec<WebhookDelivery>> {
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM WebhookDelivery
+            WHERE status = 'Pending' AND next_attempt_at <= $1
+            ORDER BY next_attempt_at
+            LIMIT $2
+            "#,
+            DELIVERY_COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, WebhookDelivery>(&sql)
+                .bind(now)
+                .bind(limit)
+                .fetch_all(pool)
+                .await
+        })
+    }
+
+    /// Stores the outcome of an attempt at a delivery, as held by `delivery`.
+    pub async fn save_delivery(&self, delivery: &WebhookDelivery) -> sqlx::Result<()> {
+        with_pool!(self.database, pool => {
+            sqlx::query(
+                r#"
+                UPDATE WebhookDelivery
+                SET status = $1, attempts = $2, next_attempt_at = $3, last_response_status = $4, last_error = $5, delivered_at = $6, updated_at = $7
+                WHERE id = $8
+                "#,
+            )
+            .bind(delivery.status)
+            .bind(delivery.attempts)
+            .bind(delivery.next_attempt_at)
+            .bind(delivery.last_response_status)
+            .bind(&delivery.last_error)
+            .bind(delivery.delivered_at)
+            .bind(delivery.updated_at)
+            .bind(delivery.id)
+            .execute(pool)
+            .await?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,144 @@
+use crate::db::{Database, Transaction};
+use crate::models::{Zone, ZoneInput};
+use crate::{with_pool, with_transaction};
+
+const COLUMNS: &str = "Zone.id, Zone.site_id, Zone.name, Zone.created_at, Zone.updated_at";
+
+/// Zones, and which rooms belong to them.
+pub struct ZoneRepo<'a> {
+    database: &'a Database,
+}
+
+impl<'a> ZoneRepo<'a> {
+    pub(crate) fn new(database: &'a Database) -> Self {
+        Self { database }
+    }
+
+    pub async fn find(&self, id: i64) -> sqlx::Result<Option<Zone>> {
+        let sql = format!("SELECT {} FROM Zone WHERE Zone.id = $1", COLUMNS);
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Zone>(&sql).bind(id).fetch_optional(pool).await
+        })
+    }
+
+    pub async fn for_site(&self, site_id: i64) -> sqlx::Result<Vec<Zone>> {
+        let sql = format!(
+            "SELECT {} FROM Zone WHERE Zone.site_id = $1 ORDER BY Zone.id",
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Zone>(&sql).bind(site_id).fetch_all(pool).await
+        })
+    }
+
+    /// The zones a room belongs to.
+    pub async fn for_room(&self, room_id: i64) -> sqlx::Result<Vec<Zone>> {
+        let sql = format!(
+            r#"
+            SELECT {}
+            FROM Zone
+            JOIN RoomZone ON RoomZone.zone_id = Zone.id
+            WHERE RoomZone.room_id = $1
+            ORDER BY Zone.id
+            "#,
+            COLUMNS
+        );
+        with_pool!(self.database, pool => {
+            sqlx::query_as::<_, Zone>(&sql).bind(room_id).fetch_all(pool).await
+        })
+    }
+
+    /// The site a zone belongs to, or `None` if the zone doesn't exist.
+    pub async fn site_id(&self, id: i64) -> sqlx::Result<Option<i64>> {
+        with_pool!(self.database, pool => {
+            sqlx::query_scalar::<_, i64>("SELECT site_id FROM Zone WHERE id = $1")
+                .bind(id)
+                .fetch_optional(pool)
+                .await
+        })
+    }
+
+    pub async fn create(
+        &self,
+        transaction: &mut Transaction<'_>,
+        input: &ZoneInput,
+    ) -> sqlx::Result<Zone> {
+        let sql = format!(
+            "INSERT INTO Zone (site_id, name) VALUES ($1, $2) RETURNING {}",
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, Zone>(&sql)
+                .bind(input.site_id)
+                .bind(&input.name)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    pub async fn rename(
+        &self,
+        transaction: &mut Transaction<'_>,
+        id: i64,
+        name: &str,
+    ) -> sqlx::Result<Zone> {
+        let sql = format!(
+            "UPDATE Zone SET name = $1, updated_at = CURRENT_TIMESTAMP WHERE id = $2 RETURNING {}",
+            COLUMNS
+        );
+        with_transaction!(transaction, connection => {
+            sqlx::query_as::<_, Zone>(&sql)
+                .bind(name)
+                .bind(id)
+                .fetch_one(connection)
+                .await
+        })
+    }
+
+    /// Deletes a zone, leaving its rooms as they are.
+    pub async fn delete(&self, transaction: &mut Transaction<'_>, id: i64) -> sqlx::Result<()> {
+        with_transaction!(transaction, connection => {
+            sqlx::query("DELETE FROM Zone WHERE id = $1")
+                .bind(id)
+                .execute(connection)
+                .await?;
+            Ok(())
+        })
+    }
+
+    /// Adds a room to a zone, returning whether it wasn't in the zone already.
+    pub async fn add_room(
+        &self,
+        transaction: &mut Transaction<'_>,
+        zone_id: i64,
+        room_id: i64,
+    ) -> sqlx::Result<bool> {
+        let sql = "INSERT INTO RoomZone (room_id, zone_id) VALUES ($1, $2) ON CONFLICT DO NOTHING";
+        with_transaction!(transaction, connection => {
+            let result = sqlx::query(sql)
+                .bind(room_id)
+                .bind(zone_id)
+                .execute(connection)
+                .await?;
+            Ok(result.rows_affected() > 0)
+        })
+    }
+
+    /// Removes a room from a zone, returning whether it was in the zone.
+    pub async fn remove_room(
+        &self,
+        transaction: &mut Transaction<'_>,
+        zone_id: i64,
+        room_id: i64,
+    ) -> sqlx::Result<bool> {
+        let sql = "DELETE FROM RoomZone WHERE room_id = $1 AND zone_id = $2";
+        with_transaction!(transaction, connection => {
+            let result = sqlx::query(sql)
+                .bind(room_id)
+                .bind(zone_id)
+                .execute(connection)
+                .await?;
+            Ok(result.rows_affected() > 0)
+        })
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::Duration as StdDuration;
+
+use anyhow::{Result, bail};
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use log::{error, info};
+use tokio::task::JoinHandle;
+
+use crate::db::Database;
+use crate::models::{ReadingResolution, RetentionPolicy, RetentionPolicyInput};
+
+pub fn validate_policy(input: &RetentionPolicyInput) -> Result<()> {
+    if input.raw_retention_days < 1 {
+        bail!("Raw readings have to be kept for at least a day");
+    }
+    match (input.hourly_retention_days, input.daily_retention_days) {
+        (Some(hourly), _) if hourly < input.raw_retention_days => {
+            bail!("Hourly rollups cannot be kept for less time than raw readings")
+        }
+        (Some(hourly), Some(daily)) if daily < hourly => {
+            bail!("Daily rollups cannot be kept for less time than hourly rollups")
+        }
+        (None, Some(_)) => {
+            bail!("Daily rollups cannot be pruned while hourly rollups are kept forever")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Rolls raw readings that left their retention window up into hourly and daily summaries,
+/// and deletes the raw readings and rollups whose retention has passed.
+pub struct RetentionJob {
+    database: Database,
+    interval: StdDuration,
+}
+
+impl RetentionJob {
+    pub fn new(database: Database, interval: StdDuration) -> Self {
+        Self { database, interval }
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut clock = tokio::time::interval(self.interval);
+            loop {
+                clock.tick().await;
+                match self.run(Utc::now()).await {
+                    Ok(0) => {}
+                    Ok(pruned) => info!("Rolled up and pruned {} sensor readings", pruned),
+                    Err(err) => error!("Failed to apply retention policies: {}", err),
+                }
+            }
+        })
+    }
+
+    /// Applies the retention policy of every device, returning how many raw readings were pruned.
+    pub async fn run(&self, now: DateTime<Utc>) -> Result<u64> {
+        let devices = self.database.devices().all_with_site().await?;
+
+        let mut pruned = 0;
+        for (device_id, site_id, device_type) in devices {
+            let policy = self
+                .database
+                .retention_policies()
+                .for_device(site_id, device_type)
+                .await?;
+            if let Some(policy) = policy {
+                pruned += self.apply(device_id, &policy, now).await?;
+            }
+        }
+        Ok(pruned)
+    }
+
+    async fn apply(
+        &self,
+        device_id: i64,
+        policy: &RetentionPolicy,
+        now: DateTime<Utc>,
+    ) -> Result<u64> {
+        // Only whole hours are rolled up, and hourly rollups only pruned by whole days,
+        // so every range is covered by exactly one resolution.
+        let raw_cutoff =
+            (now - Duration::days(policy.raw_retention_days)).duration_trunc(Duration::hours(1))?;
+        let hourly_cutoff = policy
+            .hourly_retention_days
+            .map(|days| (now - Duration::days(days)).duration_trunc(Duration::days(1)))
+            .transpose()?;
+        let daily_cutoff = policy
+            .daily_retention_days
+            .map(|days| (now - Duration::days(days)).duration_trunc(Duration::days(1)))
+            .transpose()?;
+
+        let readings = self.database.readings();
+        let mut tx = self.database.begin().await?;
+        for resolution in [ReadingResolution::Hourly, ReadingResolution::Daily] {
+            readings
+                .roll_up(&mut tx, device_id, resolution, raw_cutoff)
+                .await?;
+        }
+        let pruned = readings.prune(&mut tx, device_id, raw_cutoff).await?;
+        for (resolution, cutoff) in [
+            (ReadingResolution::Hourly, hourly_cutoff),
+            (ReadingResolution::Daily, daily_cutoff),
+        ] {
+            if let Some(cutoff) = cutoff {
+                readings
+                    .prune_rollups(&mut tx, device_id, resolution, cutoff)
+                    .await?;
+            }
+        }
+        readings
+            .advance_pruned_before(&mut tx, device_id, raw_cutoff, hourly_cutoff)
+            .await?;
+        tx.commit().await?;
+        Ok(pruned)
+    }
+}
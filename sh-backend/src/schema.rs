@@ -1,68 +1,178 @@
-use async_graphql::{Context, EmptySubscription, FieldError, FieldResult, Object, Result, Schema};
+use std::collections::{HashMap, HashSet};
+use std::io::Seek;
+
+use async_graphql::{
+    Context, EmptySubscription, FieldError, FieldResult, Json, Object, Result, Schema, Upload,
+};
 use chrono::{DateTime, Utc};
-use sqlx::sqlite::SqlitePool;
+use serde_json::json;
 
+use crate::audit::AuditRecord;
+use crate::automation::validate_rule;
+use crate::config::Config;
+use crate::db::Database;
+
+use crate::auth::{
+    Identity, forbidden, generate_token, hash_token, identity, insert_device_credential,
+    require_admin, require_ingest_rights, require_site_role, require_user, site_role,
+};
+use crate::events::{Event, EventBus};
+use crate::import::{
+    ImportColumns, ImportReport, MAX_REPORTED_REJECTIONS, count_rows, import_readings,
+};
+use crate::limits::{LIST_COST, limited_list_cost, validate_limit};
 use crate::models::{
-    ControlSetpoint, ControlSetpointInput, Device, DeviceInput, DeviceType, Room, RoomInput,
-    SensorReading, SensorReadingInput, SensorUnit, SetpointType, SetpointUnit, Site, SiteInput,
+    Alert, AuditEntityType, AuditLogEntry, AuditLogFilter, AutomationRule, AutomationRuleInput,
+    Building, BuildingInput, ControlSetpoint, ControlSetpointInput, Device, DeviceCredential,
+    DeviceCredentialWithKey, DeviceInput, DeviceType, Floor, FloorInput, RetentionPolicy,
+    RetentionPolicyInput, Role, Room, RoomInput, RuleExecution, SearchResult, SensorReading,
+    SensorReadingInput, SetpointMode, Site, SiteInput, SiteRole, SiteRoleInput, Tag, TagFilter,
+    TaggedEntity, User, UserInput, UserWithToken, WebhookDelivery, WebhookDeliveryStatus,
+    WebhookSubscription, WebhookSubscriptionInput, WebhookSubscriptionWithSecret, Zone, ZoneInput,
+    ZoneSetpointInput,
 };
+use crate::rate_limits::charge_imported_readings;
+use crate::retention::validate_policy;
+use crate::search::search;
+use crate::webhooks::validate_url;
+
+const MAX_TAG_KEY_LENGTH: usize = 64;
+const MAX_TAG_VALUE_LENGTH: usize = 256;
+/// Every tag filter adds a subquery, so there can't be many of them.
+const MAX_TAG_FILTERS: usize = 10;
+/// The most bytes the metadata of an entity takes as JSON.
+const MAX_METADATA_SIZE: usize = 16 * 1024;
 
 pub struct SiteQueryRoot;
 
 #[Object]
 impl SiteQueryRoot {
-    async fn sites(&self, ctx: &Context<'_>) -> FieldResult<Vec<Site>> {
-        let pool = ctx.data::<SqlitePool>()?;
-        let sites = sqlx::query_as!(
-            Site,
-            r#"
-            SELECT id, name, address, created_at as "created_at!: DateTime<Utc>", updated_at as "updated_at!: DateTime<Utc>"
-            FROM Site
-            "#
-        )
-        .fetch_all(pool)
-        .await?;
-        Ok(sites)
+    async fn me(&self, ctx: &Context<'_>) -> Option<User> {
+        match identity(ctx) {
+            Identity::User(user) => Some(user.clone()),
+            Identity::Device(_) | Identity::Automation(_) | Identity::Anonymous => None,
+        }
+    }
+
+    /// The sites the caller can see, narrowed down to those carrying every one of the tags.
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn sites(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] tags: Vec<TagFilter>,
+    ) -> FieldResult<Vec<Site>> {
+        validate_tag_filters(&tags)?;
+        let user = require_user(ctx)?;
+        let sites = ctx.data::<Database>()?.sites();
+        if user.is_admin {
+            Ok(sites.all(&tags).await?)
+        } else {
+            Ok(sites.for_user(user.id, &tags).await?)
+        }
     }
 
     async fn site(&self, ctx: &Context<'_>, id: i64) -> Result<Option<Site>> {
-        let pool = ctx.data::<SqlitePool>()?;
-        let site = sqlx::query_as::<_, Site>("SELECT * FROM site WHERE id = ?")
-            .bind(id)
-            .fetch_optional(pool)
-            .await?;
+        require_site_role(ctx, id, Role::Viewer).await?;
+        let site = ctx.data::<Database>()?.sites().find(id).await?;
         Ok(site)
     }
 
     async fn room(&self, ctx: &Context<'_>, id: i64) -> FieldResult<Option<Room>> {
-        let pool = ctx.data::<SqlitePool>()?;
-        let room = sqlx::query_as!(
-            Room,
-            r#"
-            SELECT id, site_id, name, created_at as "created_at!: DateTime<Utc>", updated_at as "updated_at!: DateTime<Utc>"
-            FROM Room
-            WHERE id = ?
-            "#,
-            id
-        )
-        .fetch_optional(pool)
-        .await?;
+        require_user(ctx)?;
+        let Some(site_id) = ctx.data::<Database>()?.rooms().site_id(id).await? else {
+            return Ok(None);
+        };
+        require_site_role(ctx, site_id, Role::Viewer).await?;
+
+        let room = ctx.data::<Database>()?.rooms().find(id).await?;
         Ok(room)
     }
 
-    async fn devices_in_room(&self, ctx: &Context<'_>, room_id: i64) -> FieldResult<Vec<Device>> {
-        let pool = ctx.data::<SqlitePool>()?;
-        let devices = sqlx::query_as!(
-            Device,
-            r#"
-            SELECT id, room_id, name, device_type as "device_type: DeviceType", unique_identifier, created_at as "created_at!: DateTime<Utc>", updated_at as "updated_at!: DateTime<Utc>"
-            FROM Device
-            WHERE room_id = ?
-            "#,
-            room_id
-        )
-        .fetch_all(pool)
-        .await?;
+    /// Sites, rooms and devices whose names, addresses or unique identifiers hold every word of the query,
+    /// most relevant first. Words match as prefixes, so "temp sens" finds "Temperature Sensor".
+    #[graphql(complexity = "limited_list_cost(limit, child_complexity)")]
+    async fn search(
+        &self,
+        ctx: &Context<'_>,
+        query: String,
+        #[graphql(default)] tags: Vec<TagFilter>,
+        #[graphql(default = 20)] limit: i64,
+    ) -> FieldResult<Vec<SearchResult>> {
+        validate_limit(limit)?;
+        validate_tag_filters(&tags)?;
+        let user = require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let hits = search(database, user, &query, &tags, limit).await?;
+
+        let ids = |entity: TaggedEntity| -> Vec<i64> {
+            hits.iter()
+                .filter(|hit| hit.entity_type == entity)
+                .map(|hit| hit.entity_id)
+                .collect()
+        };
+        let mut found: HashMap<(TaggedEntity, i64), SearchResult> = HashMap::new();
+        for site in database.sites().find_many(&ids(TaggedEntity::Site)).await? {
+            found.insert((TaggedEntity::Site, site.id), SearchResult::Site(site));
+        }
+        for room in database.rooms().find_many(&ids(TaggedEntity::Room)).await? {
+            found.insert((TaggedEntity::Room, room.id), SearchResult::Room(room));
+        }
+        for device in database
+            .devices()
+            .find_many(&ids(TaggedEntity::Device))
+            .await?
+        {
+            found.insert(
+                (TaggedEntity::Device, device.id),
+                SearchResult::Device(device),
+            );
+        }
+
+        // Back in the order of relevance, leaving out entities deleted since they were found.
+        Ok(hits
+            .iter()
+            .filter_map(|hit| found.remove(&(hit.entity_type, hit.entity_id)))
+            .collect())
+    }
+
+    async fn floor(&self, ctx: &Context<'_>, id: i64) -> FieldResult<Option<Floor>> {
+        require_user(ctx)?;
+        let Some(floor) = ctx.data::<Database>()?.floors().find(id).await? else {
+            return Ok(None);
+        };
+        require_site_role(ctx, floor.site_id, Role::Viewer).await?;
+        Ok(Some(floor))
+    }
+
+    async fn zone(&self, ctx: &Context<'_>, id: i64) -> FieldResult<Option<Zone>> {
+        require_user(ctx)?;
+        let Some(zone) = ctx.data::<Database>()?.zones().find(id).await? else {
+            return Ok(None);
+        };
+        require_site_role(ctx, zone.site_id, Role::Viewer).await?;
+        Ok(Some(zone))
+    }
+
+    /// The devices of a room, narrowed down to those carrying every one of the tags.
+    #[graphql(complexity = "LIST_COST * child_complexity")]
+    async fn devices_in_room(
+        &self,
+        ctx: &Context<'_>,
+        room_id: i64,
+        #[graphql(default)] tags: Vec<TagFilter>,
+    ) -> FieldResult<Vec<Device>> {
+        validate_tag_filters(&tags)?;
+        require_user(ctx)?;
+        let Some(site_id) = ctx.data::<Database>()?.rooms().site_id(room_id).await? else {
+            return Ok(Vec::new());
+        };
+        require_site_role(ctx, site_id, Role::Viewer).await?;
+
+        let devices = ctx
+            .data::<Database>()?
+            .devices()
+            .for_room(room_id, &tags)
+            .await?;
         Ok(devices)
     }
 
@@ -71,44 +181,181 @@ impl SiteQueryRoot {
         ctx: &Context<'_>,
         device_id: i64,
     ) -> FieldResult<Option<SensorReading>> {
-        let pool = ctx.data::<SqlitePool>()?;
-        let reading = sqlx::query_as!(
-            SensorReading,
-            r#"
-            SELECT id, device_id, value, unit as "unit: SensorUnit", timestamp as "timestamp!: DateTime<Utc>", created_at as "created_at!: DateTime<Utc>", updated_at as "updated_at!: DateTime<Utc>"
-            FROM SensorReading
-            WHERE device_id = ?
-            ORDER BY timestamp DESC
-            LIMIT 1
-            "#,
-            device_id
-        )
-        .fetch_optional(pool)
-        .await?;
+        require_user(ctx)?;
+        let Some(site_id) = ctx.data::<Database>()?.devices().site_id(device_id).await? else {
+            return Ok(None);
+        };
+        require_site_role(ctx, site_id, Role::Viewer).await?;
+
+        let reading = ctx.data::<Database>()?.readings().latest(device_id).await?;
         Ok(reading)
     }
 
+    /// The setpoint currently in effect, which is the latest active override or hold if there is one,
+    /// and the latest scheduled setpoint otherwise.
     async fn latest_control_setpoint(
         &self,
         ctx: &Context<'_>,
         device_id: i64,
     ) -> FieldResult<Option<ControlSetpoint>> {
-        let pool = ctx.data::<SqlitePool>()?;
-        let setpoint = sqlx::query_as!(
-            ControlSetpoint,
-            r#"
-            SELECT id, device_id, setpoint_type as "setpoint_type: SetpointType", value, unit as "unit: SetpointUnit", timestamp as "timestamp!: DateTime<Utc>", created_at as "created_at!: DateTime<Utc>", updated_at as "updated_at!: DateTime<Utc>"
-            FROM ControlSetpoint
-            WHERE device_id = ?
-            ORDER BY timestamp DESC
-            LIMIT 1
-            "#,
-            device_id
-        )
-        .fetch_optional(pool)
-        .await?;
+        require_user(ctx)?;
+        let Some(site_id) = ctx.data::<Database>()?.devices().site_id(device_id).await? else {
+            return Ok(None);
+        };
+        require_site_role(ctx, site_id, Role::Viewer).await?;
+
+        let setpoint = ctx
+            .data::<Database>()?
+            .setpoints()
+            .effective(device_id, Utc::now())
+            .await?;
         Ok(setpoint)
     }
+
+    async fn device_credentials(
+        &self,
+        ctx: &Context<'_>,
+        device_id: i64,
+    ) -> FieldResult<Vec<DeviceCredential>> {
+        require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let Some(site_id) = database.devices().site_id(device_id).await? else {
+            return Ok(Vec::new());
+        };
+        require_site_role(ctx, site_id, Role::Admin).await?;
+
+        let credentials = database.credentials().for_device(device_id).await?;
+        Ok(credentials)
+    }
+
+    async fn automation_rules(
+        &self,
+        ctx: &Context<'_>,
+        site_id: i64,
+    ) -> FieldResult<Vec<AutomationRule>> {
+        require_site_role(ctx, site_id, Role::Viewer).await?;
+        let rules = ctx
+            .data::<Database>()?
+            .automation_rules()
+            .for_site(site_id)
+            .await?;
+        Ok(rules)
+    }
+
+    /// The execution log of a rule, newest first.
+    #[graphql(complexity = "limited_list_cost(limit, child_complexity)")]
+    async fn rule_executions(
+        &self,
+        ctx: &Context<'_>,
+        rule_id: i64,
+        #[graphql(default = 50)] limit: i64,
+    ) -> FieldResult<Vec<RuleExecution>> {
+        validate_limit(limit)?;
+        find_automation_rule(ctx, rule_id, Role::Viewer).await?;
+        let executions = ctx
+            .data::<Database>()?
+            .automation_rules()
+            .executions(rule_id, limit)
+            .await?;
+        Ok(executions)
+    }
+
+    /// Alerts of a site, newest first.
+    #[graphql(complexity = "limited_list_cost(limit, child_complexity)")]
+    async fn alerts(
+        &self,
+        ctx: &Context<'_>,
+        site_id: i64,
+        #[graphql(default)] include_acknowledged: bool,
+        #[graphql(default = 100)] limit: i64,
+    ) -> FieldResult<Vec<Alert>> {
+        validate_limit(limit)?;
+        require_site_role(ctx, site_id, Role::Viewer).await?;
+        let alerts = ctx
+            .data::<Database>()?
+            .alerts()
+            .for_site(site_id, include_acknowledged, limit)
+            .await?;
+        Ok(alerts)
+    }
+
+    /// Retention policies of a site, or all of them when no site is given.
+    async fn retention_policies(
+        &self,
+        ctx: &Context<'_>,
+        site_id: Option<i64>,
+    ) -> FieldResult<Vec<RetentionPolicy>> {
+        require_scoped_admin(ctx, site_id).await?;
+        let policies = ctx
+            .data::<Database>()?
+            .retention_policies()
+            .all(site_id)
+            .await?;
+        Ok(policies)
+    }
+
+    /// Webhook subscriptions of a site, or all of them when no site is given.
+    async fn webhook_subscriptions(
+        &self,
+        ctx: &Context<'_>,
+        site_id: Option<i64>,
+    ) -> FieldResult<Vec<WebhookSubscription>> {
+        require_scoped_admin(ctx, site_id).await?;
+        let subscriptions = ctx.data::<Database>()?.webhooks().all(site_id).await?;
+        Ok(subscriptions)
+    }
+
+    /// The delivery log of a webhook subscription, newest first.
+    #[graphql(complexity = "limited_list_cost(limit, child_complexity)")]
+    async fn webhook_deliveries(
+        &self,
+        ctx: &Context<'_>,
+        subscription_id: i64,
+        status: Option<WebhookDeliveryStatus>,
+        #[graphql(default = 50)] limit: i64,
+    ) -> FieldResult<Vec<WebhookDelivery>> {
+        validate_limit(limit)?;
+        find_webhook_subscription(ctx, subscription_id).await?;
+        let deliveries = ctx
+            .data::<Database>()?
+            .webhooks()
+            .deliveries(subscription_id, status, limit)
+            .await?;
+        Ok(deliveries)
+    }
+
+    /// Recorded mutations, newest first. Without a site filter this requires administrator rights.
+    #[graphql(complexity = "limited_list_cost(limit, child_complexity)")]
+    async fn audit_logs(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] filter: AuditLogFilter,
+        #[graphql(default = 100)] limit: i64,
+    ) -> FieldResult<Vec<AuditLogEntry>> {
+        validate_limit(limit)?;
+        match filter.site_id {
+            Some(site_id) => require_site_role(ctx, site_id, Role::Admin).await?,
+            None => {
+                require_admin(ctx)?;
+            }
+        }
+        let entries = ctx
+            .data::<Database>()?
+            .audit_logs()
+            .list(&filter, limit)
+            .await?;
+        Ok(entries)
+    }
+
+    async fn site_roles(&self, ctx: &Context<'_>, site_id: i64) -> FieldResult<Vec<SiteRole>> {
+        require_site_role(ctx, site_id, Role::Admin).await?;
+        let roles = ctx
+            .data::<Database>()?
+            .site_roles()
+            .for_site(site_id)
+            .await?;
+        Ok(roles)
+    }
 }
 
 pub struct SiteMutationRoot;
@@ -116,78 +363,572 @@ pub struct SiteMutationRoot;
 #[Object]
 impl SiteMutationRoot {
     async fn create_site(&self, ctx: &Context<'_>, input: SiteInput) -> FieldResult<Site> {
-        let pool = ctx.data::<SqlitePool>()?;
-        let result = sqlx::query_as!(
-            Site,
-            r#"
-            INSERT INTO Site (name, address)
-            VALUES (?, ?)
-            RETURNING id, name, address, created_at as "created_at!: DateTime<Utc>", updated_at as "updated_at!: DateTime<Utc>"
-            "#,
-            input.name,
-            input.address
-        )
-        .fetch_one(pool)
-        .await?;
+        require_admin(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let mut tx = database.begin().await?;
+        let result = database.sites().create(&mut tx, &input).await?;
+
+        AuditRecord::new("createSite", AuditEntityType::Site, &input)
+            .site_id(result.id)
+            .entity_id(result.id)
+            .after(&result)
+            .insert_in(&mut tx, identity(ctx))
+            .await?;
+        tx.commit().await?;
         Ok(result)
     }
 
     async fn create_room(&self, ctx: &Context<'_>, input: RoomInput) -> FieldResult<Room> {
-        let pool = ctx.data::<SqlitePool>()?;
-        let site_exists: (i64,) =
-            sqlx::query_as::<_, (i64,)>("SELECT COUNT(id) FROM Site WHERE id = ?")
-                .bind(input.site_id)
-                .fetch_one(pool)
-                .await?;
-        if site_exists.0 == 0 {
+        require_site_role(ctx, input.site_id, Role::Admin).await?;
+        let database = ctx.data::<Database>()?;
+        if database.sites().find(input.site_id).await?.is_none() {
             return Err(FieldError::new(format!(
                 "Site with ID {} does not exist",
                 input.site_id
             )));
         }
+        if let Some(floor_id) = input.floor_id {
+            require_floor_in_site(database, floor_id, input.site_id).await?;
+        }
+
+        let mut tx = database.begin().await?;
+        let result = database.rooms().create(&mut tx, &input).await?;
+
+        AuditRecord::new("createRoom", AuditEntityType::Room, &input)
+            .site_id(result.site_id)
+            .entity_id(result.id)
+            .after(&result)
+            .insert_in(&mut tx, identity(ctx))
+            .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn create_building(
+        &self,
+        ctx: &Context<'_>,
+        input: BuildingInput,
+    ) -> FieldResult<Building> {
+        require_site_role(ctx, input.site_id, Role::Admin).await?;
+        let database = ctx.data::<Database>()?;
+        if database.sites().find(input.site_id).await?.is_none() {
+            return Err(FieldError::new(format!(
+                "Site with ID {} does not exist",
+                input.site_id
+            )));
+        }
+
+        let mut tx = database.begin().await?;
+        let result = database.buildings().create(&mut tx, &input).await?;
+
+        AuditRecord::new("createBuilding", AuditEntityType::Building, &input)
+            .site_id(result.site_id)
+            .entity_id(result.id)
+            .after(&result)
+            .insert_in(&mut tx, identity(ctx))
+            .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn create_floor(&self, ctx: &Context<'_>, input: FloorInput) -> FieldResult<Floor> {
+        require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let Some(site_id) = database.buildings().site_id(input.building_id).await? else {
+            return Err(FieldError::new(format!(
+                "Building with ID {} does not exist",
+                input.building_id
+            )));
+        };
+        require_site_role(ctx, site_id, Role::Admin).await?;
+
+        let mut tx = database.begin().await?;
+        let result = database.floors().create(&mut tx, &input).await?;
+
+        AuditRecord::new("createFloor", AuditEntityType::Floor, &input)
+            .site_id(site_id)
+            .entity_id(result.id)
+            .after(&result)
+            .insert_in(&mut tx, identity(ctx))
+            .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
 
-        let result = sqlx::query_as::<_, Room>(
-            r#"
-            INSERT INTO Room (site_id, name)
-            VALUES (?, ?)
-            RETURNING id, site_id, name, created_at as "created_at!: DateTime<Utc>", updated_at as "updated_at!: DateTime<Utc>"
-            "#
+    async fn create_zone(&self, ctx: &Context<'_>, input: ZoneInput) -> FieldResult<Zone> {
+        require_site_role(ctx, input.site_id, Role::Admin).await?;
+        let database = ctx.data::<Database>()?;
+        if database.sites().find(input.site_id).await?.is_none() {
+            return Err(FieldError::new(format!(
+                "Site with ID {} does not exist",
+                input.site_id
+            )));
+        }
+
+        let mut tx = database.begin().await?;
+        let result = database.zones().create(&mut tx, &input).await?;
+
+        AuditRecord::new("createZone", AuditEntityType::Zone, &input)
+            .site_id(result.site_id)
+            .entity_id(result.id)
+            .after(&result)
+            .insert_in(&mut tx, identity(ctx))
+            .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn rename_building(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        name: String,
+    ) -> FieldResult<Building> {
+        require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let before =
+            database.buildings().find(id).await?.ok_or_else(|| {
+                FieldError::new(format!("Building with ID {} does not exist", id))
+            })?;
+        require_site_role(ctx, before.site_id, Role::Admin).await?;
+
+        let mut tx = database.begin().await?;
+        let result = database.buildings().rename(&mut tx, id, &name).await?;
+
+        AuditRecord::new(
+            "renameBuilding",
+            AuditEntityType::Building,
+            &json!({ "id": id, "name": &name }),
         )
-        .bind(input.site_id)
-        .bind(input.name)
-        .fetch_one(pool)
+        .site_id(before.site_id)
+        .entity_id(id)
+        .before(&before)
+        .after(&result)
+        .insert_in(&mut tx, identity(ctx))
         .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    /// Deletes a building and its floors. The rooms on them are kept without a floor.
+    async fn delete_building(&self, ctx: &Context<'_>, id: i64) -> FieldResult<bool> {
+        require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let before =
+            database.buildings().find(id).await?.ok_or_else(|| {
+                FieldError::new(format!("Building with ID {} does not exist", id))
+            })?;
+        require_site_role(ctx, before.site_id, Role::Admin).await?;
+
+        let mut tx = database.begin().await?;
+        database.buildings().delete(&mut tx, id).await?;
+
+        AuditRecord::new(
+            "deleteBuilding",
+            AuditEntityType::Building,
+            &json!({ "id": id }),
+        )
+        .site_id(before.site_id)
+        .entity_id(id)
+        .before(&before)
+        .insert_in(&mut tx, identity(ctx))
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
 
+    async fn rename_floor(&self, ctx: &Context<'_>, id: i64, name: String) -> FieldResult<Floor> {
+        require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let before = database
+            .floors()
+            .find(id)
+            .await?
+            .ok_or_else(|| FieldError::new(format!("Floor with ID {} does not exist", id)))?;
+        require_site_role(ctx, before.site_id, Role::Admin).await?;
+
+        let mut tx = database.begin().await?;
+        let result = database.floors().rename(&mut tx, id, &name).await?;
+
+        AuditRecord::new(
+            "renameFloor",
+            AuditEntityType::Floor,
+            &json!({ "id": id, "name": &name }),
+        )
+        .site_id(before.site_id)
+        .entity_id(id)
+        .before(&before)
+        .after(&result)
+        .insert_in(&mut tx, identity(ctx))
+        .await?;
+        tx.commit().await?;
         Ok(result)
     }
 
-    async fn create_device(&self, ctx: &Context<'_>, input: DeviceInput) -> FieldResult<Device> {
-        let pool = ctx.data::<SqlitePool>()?;
-        let room_exists: (i64,) =
-            sqlx::query_as::<_, (i64,)>("SELECT COUNT(id) FROM Room WHERE id = ?")
-                .bind(input.room_id)
-                .fetch_one(pool)
-                .await?;
-        if room_exists.0 == 0 {
+    /// Deletes a floor. Its rooms are kept without a floor.
+    async fn delete_floor(&self, ctx: &Context<'_>, id: i64) -> FieldResult<bool> {
+        require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let before = database
+            .floors()
+            .find(id)
+            .await?
+            .ok_or_else(|| FieldError::new(format!("Floor with ID {} does not exist", id)))?;
+        require_site_role(ctx, before.site_id, Role::Admin).await?;
+
+        let mut tx = database.begin().await?;
+        database.floors().delete(&mut tx, id).await?;
+
+        AuditRecord::new("deleteFloor", AuditEntityType::Floor, &json!({ "id": id }))
+            .site_id(before.site_id)
+            .entity_id(id)
+            .before(&before)
+            .insert_in(&mut tx, identity(ctx))
+            .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn rename_zone(&self, ctx: &Context<'_>, id: i64, name: String) -> FieldResult<Zone> {
+        require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let before = database
+            .zones()
+            .find(id)
+            .await?
+            .ok_or_else(|| FieldError::new(format!("Zone with ID {} does not exist", id)))?;
+        require_site_role(ctx, before.site_id, Role::Admin).await?;
+
+        let mut tx = database.begin().await?;
+        let result = database.zones().rename(&mut tx, id, &name).await?;
+
+        AuditRecord::new(
+            "renameZone",
+            AuditEntityType::Zone,
+            &json!({ "id": id, "name": &name }),
+        )
+        .site_id(before.site_id)
+        .entity_id(id)
+        .before(&before)
+        .after(&result)
+        .insert_in(&mut tx, identity(ctx))
+        .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    /// Deletes a zone. Its rooms are left as they are.
+    async fn delete_zone(&self, ctx: &Context<'_>, id: i64) -> FieldResult<bool> {
+        require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let before = database
+            .zones()
+            .find(id)
+            .await?
+            .ok_or_else(|| FieldError::new(format!("Zone with ID {} does not exist", id)))?;
+        require_site_role(ctx, before.site_id, Role::Admin).await?;
+
+        let mut tx = database.begin().await?;
+        database.zones().delete(&mut tx, id).await?;
+
+        AuditRecord::new("deleteZone", AuditEntityType::Zone, &json!({ "id": id }))
+            .site_id(before.site_id)
+            .entity_id(id)
+            .before(&before)
+            .insert_in(&mut tx, identity(ctx))
+            .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Moves a room to a floor of its site, or takes it off its floor when none is given.
+    async fn set_room_floor(
+        &self,
+        ctx: &Context<'_>,
+        room_id: i64,
+        floor_id: Option<i64>,
+    ) -> FieldResult<Room> {
+        require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let Some(before) = database.rooms().find(room_id).await? else {
             return Err(FieldError::new(format!(
                 "Room with ID {} does not exist",
-                input.room_id
+                room_id
             )));
+        };
+        require_site_role(ctx, before.site_id, Role::Admin).await?;
+        if let Some(floor_id) = floor_id {
+            require_floor_in_site(database, floor_id, before.site_id).await?;
         }
 
-        let result = sqlx::query_as::<_, Device>(
-            r#"
-            INSERT INTO Device (room_id, name, device_type, unique_identifier)
-            VALUES (?, ?, ?, ?)
-            RETURNING id, room_id, name, device_type as "device_type: DeviceType", unique_identifier, created_at as "created_at!: DateTime<Utc>", updated_at as "updated_at!: DateTime<Utc>"
-            "#
+        let mut tx = database.begin().await?;
+        let result = database
+            .rooms()
+            .set_floor(&mut tx, room_id, floor_id)
+            .await?;
+
+        AuditRecord::new(
+            "setRoomFloor",
+            AuditEntityType::Room,
+            &json!({ "room_id": room_id, "floor_id": floor_id }),
         )
-        .bind(input.room_id)
-        .bind(input.name)
-        .bind(input.device_type as DeviceType)
-        .bind(input.unique_identifier)
-        .fetch_one(pool)
+        .site_id(result.site_id)
+        .entity_id(result.id)
+        .before(&before)
+        .after(&result)
+        .insert_in(&mut tx, identity(ctx))
         .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    /// Adds a room to a zone of its site, doing nothing if it is in the zone already.
+    async fn add_room_to_zone(
+        &self,
+        ctx: &Context<'_>,
+        room_id: i64,
+        zone_id: i64,
+    ) -> FieldResult<Zone> {
+        let (zone, site_id) = find_zone_and_room(ctx, zone_id, room_id).await?;
+        let database = ctx.data::<Database>()?;
+        let mut tx = database.begin().await?;
+        if database.zones().add_room(&mut tx, zone_id, room_id).await? {
+            AuditRecord::new(
+                "addRoomToZone",
+                AuditEntityType::Zone,
+                &json!({ "room_id": room_id, "zone_id": zone_id }),
+            )
+            .site_id(site_id)
+            .entity_id(zone_id)
+            .insert_in(&mut tx, identity(ctx))
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(zone)
+    }
+
+    /// Removes a room from a zone, doing nothing if it isn't in the zone.
+    async fn remove_room_from_zone(
+        &self,
+        ctx: &Context<'_>,
+        room_id: i64,
+        zone_id: i64,
+    ) -> FieldResult<Zone> {
+        let (zone, site_id) = find_zone_and_room(ctx, zone_id, room_id).await?;
+        let database = ctx.data::<Database>()?;
+        let mut tx = database.begin().await?;
+        if database
+            .zones()
+            .remove_room(&mut tx, zone_id, room_id)
+            .await?
+        {
+            AuditRecord::new(
+                "removeRoomFromZone",
+                AuditEntityType::Zone,
+                &json!({ "room_id": room_id, "zone_id": zone_id }),
+            )
+            .site_id(site_id)
+            .entity_id(zone_id)
+            .insert_in(&mut tx, identity(ctx))
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(zone)
+    }
+
+    /// Tags a site, room or device, replacing the value of the key if it is tagged with it already.
+    async fn set_tag(
+        &self,
+        ctx: &Context<'_>,
+        entity: TaggedEntity,
+        entity_id: i64,
+        key: String,
+        value: Option<String>,
+    ) -> FieldResult<Tag> {
+        let database = ctx.data::<Database>()?;
+        let site_id = require_tagged_entity_admin(ctx, entity, entity_id).await?;
+        validate_tag(&key, value.as_deref())?;
+
+        let tags = database.tags();
+        let mut tx = database.begin().await?;
+        let before = tags.find_in(&mut tx, entity, entity_id, &key).await?;
+        let result = tags
+            .set(&mut tx, entity, entity_id, &key, value.as_deref())
+            .await?;
+
+        let mut record = AuditRecord::new(
+            "setTag",
+            audit_entity_type(entity),
+            &json!({ "entity_id": entity_id, "key": key, "value": value }),
+        )
+        .site_id(site_id)
+        .entity_id(entity_id)
+        .after(&result);
+        if let Some(before) = &before {
+            record = record.before(before);
+        }
+        record.insert_in(&mut tx, identity(ctx)).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    /// Removes a tag from a site, room or device, returning whether it was tagged with the key.
+    async fn remove_tag(
+        &self,
+        ctx: &Context<'_>,
+        entity: TaggedEntity,
+        entity_id: i64,
+        key: String,
+    ) -> FieldResult<bool> {
+        let database = ctx.data::<Database>()?;
+        let site_id = require_tagged_entity_admin(ctx, entity, entity_id).await?;
+
+        let mut tx = database.begin().await?;
+        let Some(before) = database
+            .tags()
+            .remove(&mut tx, entity, entity_id, &key)
+            .await?
+        else {
+            return Ok(false);
+        };
+
+        AuditRecord::new(
+            "removeTag",
+            audit_entity_type(entity),
+            &json!({ "entity_id": entity_id, "key": key }),
+        )
+        .site_id(site_id)
+        .entity_id(entity_id)
+        .before(&before)
+        .insert_in(&mut tx, identity(ctx))
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Replaces the metadata of a site with a JSON object.
+    async fn set_site_metadata(
+        &self,
+        ctx: &Context<'_>,
+        site_id: i64,
+        metadata: Json<serde_json::Value>,
+    ) -> FieldResult<Site> {
+        require_tagged_entity_admin(ctx, TaggedEntity::Site, site_id).await?;
+        validate_metadata(&metadata)?;
+        let database = ctx.data::<Database>()?;
+        let before = database.sites().find(site_id).await?;
+
+        let mut tx = database.begin().await?;
+        let result = database
+            .sites()
+            .set_metadata(&mut tx, site_id, &metadata)
+            .await?;
+
+        let mut record = AuditRecord::new(
+            "setSiteMetadata",
+            AuditEntityType::Site,
+            &json!({ "site_id": site_id, "metadata": &metadata.0 }),
+        )
+        .site_id(site_id)
+        .entity_id(site_id)
+        .after(&result);
+        if let Some(before) = &before {
+            record = record.before(before);
+        }
+        record.insert_in(&mut tx, identity(ctx)).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    /// Replaces the metadata of a room with a JSON object.
+    async fn set_room_metadata(
+        &self,
+        ctx: &Context<'_>,
+        room_id: i64,
+        metadata: Json<serde_json::Value>,
+    ) -> FieldResult<Room> {
+        let site_id = require_tagged_entity_admin(ctx, TaggedEntity::Room, room_id).await?;
+        validate_metadata(&metadata)?;
+        let database = ctx.data::<Database>()?;
+        let before = database.rooms().find(room_id).await?;
+
+        let mut tx = database.begin().await?;
+        let result = database
+            .rooms()
+            .set_metadata(&mut tx, room_id, &metadata)
+            .await?;
+
+        let mut record = AuditRecord::new(
+            "setRoomMetadata",
+            AuditEntityType::Room,
+            &json!({ "room_id": room_id, "metadata": &metadata.0 }),
+        )
+        .site_id(site_id)
+        .entity_id(room_id)
+        .after(&result);
+        if let Some(before) = &before {
+            record = record.before(before);
+        }
+        record.insert_in(&mut tx, identity(ctx)).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    /// Replaces the metadata of a device with a JSON object.
+    async fn set_device_metadata(
+        &self,
+        ctx: &Context<'_>,
+        device_id: i64,
+        metadata: Json<serde_json::Value>,
+    ) -> FieldResult<Device> {
+        let site_id = require_tagged_entity_admin(ctx, TaggedEntity::Device, device_id).await?;
+        validate_metadata(&metadata)?;
+        let database = ctx.data::<Database>()?;
+        let before = database.devices().find(device_id).await?;
+
+        let mut tx = database.begin().await?;
+        let result = database
+            .devices()
+            .set_metadata(&mut tx, device_id, &metadata)
+            .await?;
+
+        let mut record = AuditRecord::new(
+            "setDeviceMetadata",
+            AuditEntityType::Device,
+            &json!({ "device_id": device_id, "metadata": &metadata.0 }),
+        )
+        .site_id(site_id)
+        .entity_id(device_id)
+        .after(&result);
+        if let Some(before) = &before {
+            record = record.before(before);
+        }
+        record.insert_in(&mut tx, identity(ctx)).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn create_device(&self, ctx: &Context<'_>, input: DeviceInput) -> FieldResult<Device> {
+        require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let Some(site_id) = database.rooms().site_id(input.room_id).await? else {
+            return Err(FieldError::new(format!(
+                "Room with ID {} does not exist",
+                input.room_id
+            )));
+        };
+        require_site_role(ctx, site_id, Role::Admin).await?;
+
+        let mut tx = database.begin().await?;
+        let result = database.devices().create(&mut tx, &input).await?;
+
+        AuditRecord::new("createDevice", AuditEntityType::Device, &input)
+            .site_id(site_id)
+            .entity_id(result.id)
+            .after(&result)
+            .insert_in(&mut tx, identity(ctx))
+            .await?;
+        tx.commit().await?;
         Ok(result)
     }
 
@@ -196,43 +937,936 @@ impl SiteMutationRoot {
         ctx: &Context<'_>,
         input: SensorReadingInput,
     ) -> FieldResult<SensorReading> {
-        let pool = ctx.data::<SqlitePool>()?;
-        let result = sqlx::query_as::<_, SensorReading>(
-            r#"
-            INSERT INTO SensorReading (device_id, value, unit)
-            VALUES (?, ?, ?)
-            RETURNING id, device_id, value, unit as "unit: SensorUnit", timestamp as "timestamp!: DateTime<Utc>", created_at as "created_at!: DateTime<Utc>", updated_at as "updated_at!: DateTime<Utc>"
-            "#
-        )
-        .bind(input.device_id)
-        .bind(input.value)
-        .bind(input.unit as Option<SensorUnit>)
-        .fetch_one(pool)
+        require_ingest_rights(ctx, input.device_id).await?;
+        let database = ctx.data::<Database>()?;
+        let Some(site_id) = database.devices().site_id(input.device_id).await? else {
+            return Err(FieldError::new(format!(
+                "Device with ID {} does not exist",
+                input.device_id
+            )));
+        };
+
+        let mut tx = database.begin().await?;
+        let result = database.readings().create(&mut tx, &input, None).await?;
+
+        AuditRecord::new(
+            "createSensorReading",
+            AuditEntityType::SensorReading,
+            &input,
+        )
+        .site_id(site_id)
+        .entity_id(result.id)
+        .after(&result)
+        .insert_in(&mut tx, identity(ctx))
         .await?;
+        tx.commit().await?;
+
+        ctx.data::<EventBus>()?
+            .publish(Event::SensorReadingCreated {
+                site_id,
+                reading: result.clone(),
+            });
         Ok(result)
     }
 
+    /// Imports historical readings from an uploaded CSV file.
+    /// Invalid rows are rejected and reported one by one, without failing the whole import.
+    /// Only the first 100 rejected lines are listed, while `rejectedCount` counts all of them.
+    async fn import_sensor_readings(
+        &self,
+        ctx: &Context<'_>,
+        file: Upload,
+        #[graphql(default)] columns: ImportColumns,
+    ) -> FieldResult<ImportReport> {
+        let user = require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let sites = if user.is_admin {
+            None
+        } else {
+            let sites = database.site_roles().operated_sites(user.id).await?;
+            Some(sites.into_iter().collect::<HashSet<_>>())
+        };
+
+        let config = ctx.data::<Config>()?;
+        let upload = file.value(ctx)?;
+        let filename = upload.filename.clone();
+        let mut content = upload.content.try_clone()?;
+        let rows = tokio::task::spawn_blocking(move || -> anyhow::Result<u64> {
+            let rows = count_rows(&mut content)?;
+            content.rewind()?;
+            Ok(rows)
+        })
+        .await??;
+        charge_imported_readings(ctx, rows)?;
+        let report = import_readings(
+            database,
+            upload.into_read(),
+            &columns,
+            sites.as_ref(),
+            config.ingestion.import_chunk_size,
+            Some(MAX_REPORTED_REJECTIONS),
+        )
+        .await
+        .map_err(|err| FieldError::new(err.to_string()))?;
+
+        // Each site the readings went to gets an entry of its own, so its admins see the import.
+        let arguments = json!({ "filename": filename, "columns": &columns });
+        if report.imported_by_site.is_empty() {
+            AuditRecord::new(
+                "importSensorReadings",
+                AuditEntityType::SensorReading,
+                &arguments,
+            )
+            .after(&json!({ "imported": 0, "rejected": report.rejected_count }))
+            .insert(database, identity(ctx))
+            .await?;
+        }
+        for (&site_id, &imported) in &report.imported_by_site {
+            AuditRecord::new(
+                "importSensorReadings",
+                AuditEntityType::SensorReading,
+                &arguments,
+            )
+            .site_id(site_id)
+            .after(&json!({ "imported": imported, "rejected": report.rejected_count }))
+            .insert(database, identity(ctx))
+            .await?;
+        }
+        Ok(report)
+    }
+
     async fn create_control_setpoint(
         &self,
         ctx: &Context<'_>,
         input: ControlSetpointInput,
     ) -> FieldResult<ControlSetpoint> {
-        let pool = ctx.data::<SqlitePool>()?;
-        let result = sqlx::query_as::<_, ControlSetpoint>(
-            r#"
-            INSERT INTO ControlSetpoint (device_id, setpoint_type, value, unit)
-            VALUES (?, ?, ?, ?)
-            RETURNING id, device_id, setpoint_type as "setpoint_type: SetpointType", value, unit as "unit: SetpointUnit", timestamp as "timestamp!: DateTime<Utc>", created_at as "created_at!: DateTime<Utc>", updated_at as "updated_at!: DateTime<Utc>"
-            "#
-        )
-        .bind(input.device_id)
-        .bind(input.setpoint_type as SetpointType)
-        .bind(input.value)
-        .bind(input.unit as Option<SetpointUnit>)
-        .fetch_one(pool)
+        require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let Some(site_id) = database.devices().site_id(input.device_id).await? else {
+            return Err(FieldError::new(format!(
+                "Device with ID {} does not exist",
+                input.device_id
+            )));
+        };
+        require_site_role(ctx, site_id, Role::Operator).await?;
+
+        let now = Utc::now();
+        validate_setpoint_mode(input.mode, input.expires_at, now)?;
+
+        let setpoints = database.setpoints();
+        let mut tx = database.begin().await?;
+        let before = setpoints
+            .effective_in(&mut tx, input.device_id, now)
+            .await?;
+        let result = setpoints.create(&mut tx, &input, now).await?;
+
+        let mut record = AuditRecord::new(
+            "createControlSetpoint",
+            AuditEntityType::ControlSetpoint,
+            &input,
+        )
+        .site_id(site_id)
+        .entity_id(result.id)
+        .after(&result);
+        if let Some(before) = &before {
+            record = record.before(before);
+        }
+        record.insert_in(&mut tx, identity(ctx)).await?;
+        tx.commit().await?;
+
+        ctx.data::<EventBus>()?
+            .publish(Event::ControlSetpointCreated {
+                site_id,
+                setpoint: result.clone(),
+                rule_id: None,
+            });
+        Ok(result)
+    }
+
+    /// Sets every thermostat controller in the rooms of a zone at once,
+    /// returning a setpoint per device.
+    async fn create_zone_setpoint(
+        &self,
+        ctx: &Context<'_>,
+        input: ZoneSetpointInput,
+    ) -> FieldResult<Vec<ControlSetpoint>> {
+        require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let Some(site_id) = database.zones().site_id(input.zone_id).await? else {
+            return Err(FieldError::new(format!(
+                "Zone with ID {} does not exist",
+                input.zone_id
+            )));
+        };
+        require_site_role(ctx, site_id, Role::Operator).await?;
+
+        let now = Utc::now();
+        validate_setpoint_mode(input.mode, input.expires_at, now)?;
+        let thermostats: Vec<_> = database
+            .devices()
+            .for_zone(input.zone_id)
+            .await?
+            .into_iter()
+            .filter(|device| device.device_type == DeviceType::ThermostatController)
+            .collect();
+        if thermostats.is_empty() {
+            return Err(FieldError::new(format!(
+                "Zone with ID {} has no thermostat controllers",
+                input.zone_id
+            )));
+        }
+
+        let setpoints = database.setpoints();
+        let mut tx = database.begin().await?;
+        let mut results = Vec::with_capacity(thermostats.len());
+        for device in &thermostats {
+            let before = setpoints.effective_in(&mut tx, device.id, now).await?;
+            let result = setpoints
+                .create(&mut tx, &input.for_device(device.id), now)
+                .await?;
+
+            let mut record = AuditRecord::new(
+                "createZoneSetpoint",
+                AuditEntityType::ControlSetpoint,
+                &input,
+            )
+            .site_id(site_id)
+            .entity_id(result.id)
+            .after(&result);
+            if let Some(before) = &before {
+                record = record.before(before);
+            }
+            record.insert_in(&mut tx, identity(ctx)).await?;
+            results.push(result);
+        }
+        tx.commit().await?;
+
+        let events = ctx.data::<EventBus>()?;
+        for setpoint in &results {
+            events.publish(Event::ControlSetpointCreated {
+                site_id,
+                setpoint: setpoint.clone(),
+                rule_id: None,
+            });
+        }
+        Ok(results)
+    }
+
+    /// Ends the active overrides and holds of a device, reverting it to its scheduled setpoint.
+    async fn release_setpoint_override(
+        &self,
+        ctx: &Context<'_>,
+        device_id: i64,
+    ) -> FieldResult<Option<ControlSetpoint>> {
+        require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let Some(site_id) = database.devices().site_id(device_id).await? else {
+            return Err(FieldError::new(format!(
+                "Device with ID {} does not exist",
+                device_id
+            )));
+        };
+        require_site_role(ctx, site_id, Role::Operator).await?;
+
+        let now = Utc::now();
+        let setpoints = database.setpoints();
+        let mut tx = database.begin().await?;
+        let before = setpoints.effective_in(&mut tx, device_id, now).await?;
+        setpoints.release_overrides(&mut tx, device_id, now).await?;
+        let result = setpoints.effective_in(&mut tx, device_id, now).await?;
+
+        let mut record = AuditRecord::new(
+            "releaseSetpointOverride",
+            AuditEntityType::ControlSetpoint,
+            &json!({ "device_id": device_id }),
+        )
+        .site_id(site_id);
+        if let Some(before) = &before {
+            record = record.entity_id(before.id).before(before);
+        }
+        if let Some(result) = &result {
+            record = record.after(result);
+        }
+        record.insert_in(&mut tx, identity(ctx)).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn create_automation_rule(
+        &self,
+        ctx: &Context<'_>,
+        input: AutomationRuleInput,
+    ) -> FieldResult<AutomationRule> {
+        require_site_role(ctx, input.site_id, Role::Admin).await?;
+        let database = ctx.data::<Database>()?;
+        validate_rule(
+            database,
+            input.site_id,
+            &input.trigger,
+            &input.conditions,
+            &input.actions,
+        )
+        .await
+        .map_err(|err| FieldError::new(err.to_string()))?;
+
+        let mut tx = database.begin().await?;
+        let result = database.automation_rules().create(&mut tx, &input).await?;
+
+        AuditRecord::new(
+            "createAutomationRule",
+            AuditEntityType::AutomationRule,
+            &input,
+        )
+        .site_id(result.site_id)
+        .entity_id(result.id)
+        .after(&result)
+        .insert_in(&mut tx, identity(ctx))
+        .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn update_automation_rule(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        input: AutomationRuleInput,
+    ) -> FieldResult<AutomationRule> {
+        let before = find_automation_rule(ctx, id, Role::Admin).await?;
+        if input.site_id != before.site_id {
+            return Err(FieldError::new("Rules cannot be moved to another site"));
+        }
+        let database = ctx.data::<Database>()?;
+        validate_rule(
+            database,
+            input.site_id,
+            &input.trigger,
+            &input.conditions,
+            &input.actions,
+        )
+        .await
+        .map_err(|err| FieldError::new(err.to_string()))?;
+
+        let mut tx = database.begin().await?;
+        let result = database
+            .automation_rules()
+            .update(&mut tx, id, &input)
+            .await?;
+
+        AuditRecord::new(
+            "updateAutomationRule",
+            AuditEntityType::AutomationRule,
+            &json!({ "id": id, "input": &input }),
+        )
+        .site_id(result.site_id)
+        .entity_id(id)
+        .before(&before)
+        .after(&result)
+        .insert_in(&mut tx, identity(ctx))
         .await?;
+        tx.commit().await?;
         Ok(result)
     }
+
+    async fn delete_automation_rule(&self, ctx: &Context<'_>, id: i64) -> FieldResult<bool> {
+        let before = find_automation_rule(ctx, id, Role::Admin).await?;
+        let database = ctx.data::<Database>()?;
+
+        let mut tx = database.begin().await?;
+        database.automation_rules().delete(&mut tx, id).await?;
+
+        AuditRecord::new(
+            "deleteAutomationRule",
+            AuditEntityType::AutomationRule,
+            &json!({ "id": id }),
+        )
+        .site_id(before.site_id)
+        .entity_id(id)
+        .before(&before)
+        .insert_in(&mut tx, identity(ctx))
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn acknowledge_alert(&self, ctx: &Context<'_>, id: i64) -> FieldResult<Alert> {
+        require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let before = database
+            .alerts()
+            .find(id)
+            .await?
+            .ok_or_else(|| FieldError::new(format!("Alert with ID {} does not exist", id)))?;
+        require_site_role(ctx, before.site_id, Role::Operator).await?;
+
+        let mut tx = database.begin().await?;
+        let result = database
+            .alerts()
+            .acknowledge(&mut tx, id, Utc::now())
+            .await?;
+
+        AuditRecord::new(
+            "acknowledgeAlert",
+            AuditEntityType::Alert,
+            &json!({ "id": id }),
+        )
+        .site_id(result.site_id)
+        .entity_id(id)
+        .before(&before)
+        .after(&result)
+        .insert_in(&mut tx, identity(ctx))
+        .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    /// Creates or replaces the retention policy for a site and device type.
+    async fn set_retention_policy(
+        &self,
+        ctx: &Context<'_>,
+        input: RetentionPolicyInput,
+    ) -> FieldResult<RetentionPolicy> {
+        require_scoped_admin(ctx, input.site_id).await?;
+        validate_policy(&input).map_err(|err| FieldError::new(err.to_string()))?;
+        let database = ctx.data::<Database>()?;
+
+        let mut tx = database.begin().await?;
+        let policies = database.retention_policies();
+        let before = policies.find_scope_in(&mut tx, &input).await?;
+        let result = match &before {
+            Some(before) => policies.update(&mut tx, before.id, &input).await?,
+            None => policies.create(&mut tx, &input).await?,
+        };
+
+        let mut record = AuditRecord::new(
+            "setRetentionPolicy",
+            AuditEntityType::RetentionPolicy,
+            &input,
+        )
+        .entity_id(result.id)
+        .after(&result);
+        if let Some(before) = &before {
+            record = record.before(before);
+        }
+        if let Some(site_id) = result.site_id {
+            record = record.site_id(site_id);
+        }
+        record.insert_in(&mut tx, identity(ctx)).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn delete_retention_policy(&self, ctx: &Context<'_>, id: i64) -> FieldResult<bool> {
+        require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let before = database
+            .retention_policies()
+            .find(id)
+            .await?
+            .ok_or_else(|| {
+                FieldError::new(format!("Retention policy with ID {} does not exist", id))
+            })?;
+        require_scoped_admin(ctx, before.site_id).await?;
+
+        let mut tx = database.begin().await?;
+        database.retention_policies().delete(&mut tx, id).await?;
+
+        let mut record = AuditRecord::new(
+            "deleteRetentionPolicy",
+            AuditEntityType::RetentionPolicy,
+            &json!({ "id": id }),
+        )
+        .entity_id(id)
+        .before(&before);
+        if let Some(site_id) = before.site_id {
+            record = record.site_id(site_id);
+        }
+        record.insert_in(&mut tx, identity(ctx)).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn create_webhook_subscription(
+        &self,
+        ctx: &Context<'_>,
+        input: WebhookSubscriptionInput,
+    ) -> FieldResult<WebhookSubscriptionWithSecret> {
+        require_scoped_admin(ctx, input.site_id).await?;
+        validate_url(&input.url)
+            .await
+            .map_err(|err| FieldError::new(err.to_string()))?;
+        if input.event_types.is_empty() {
+            return Err(FieldError::new(
+                "Webhook subscriptions need at least one event type",
+            ));
+        }
+        let database = ctx.data::<Database>()?;
+        let secret = input.secret.clone().unwrap_or_else(generate_token);
+
+        let mut tx = database.begin().await?;
+        let subscription = database.webhooks().create(&mut tx, &input, &secret).await?;
+
+        let mut record = AuditRecord::new(
+            "createWebhookSubscription",
+            AuditEntityType::WebhookSubscription,
+            &input,
+        )
+        .entity_id(subscription.id)
+        .after(&subscription);
+        if let Some(site_id) = subscription.site_id {
+            record = record.site_id(site_id);
+        }
+        record.insert_in(&mut tx, identity(ctx)).await?;
+        tx.commit().await?;
+        Ok(WebhookSubscriptionWithSecret {
+            subscription,
+            secret,
+        })
+    }
+
+    /// Replaces a subscription's settings, keeping its secret unless a new one is given.
+    async fn update_webhook_subscription(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        input: WebhookSubscriptionInput,
+    ) -> FieldResult<WebhookSubscription> {
+        let before = find_webhook_subscription(ctx, id).await?;
+        if input.site_id != before.site_id {
+            return Err(FieldError::new(
+                "Webhook subscriptions cannot be moved to another site",
+            ));
+        }
+        validate_url(&input.url)
+            .await
+            .map_err(|err| FieldError::new(err.to_string()))?;
+        if input.event_types.is_empty() {
+            return Err(FieldError::new(
+                "Webhook subscriptions need at least one event type",
+            ));
+        }
+        let database = ctx.data::<Database>()?;
+
+        let mut tx = database.begin().await?;
+        let result = database.webhooks().update(&mut tx, id, &input).await?;
+
+        let mut record = AuditRecord::new(
+            "updateWebhookSubscription",
+            AuditEntityType::WebhookSubscription,
+            &json!({ "id": id, "input": &input }),
+        )
+        .entity_id(id)
+        .before(&before)
+        .after(&result);
+        if let Some(site_id) = result.site_id {
+            record = record.site_id(site_id);
+        }
+        record.insert_in(&mut tx, identity(ctx)).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn delete_webhook_subscription(&self, ctx: &Context<'_>, id: i64) -> FieldResult<bool> {
+        let before = find_webhook_subscription(ctx, id).await?;
+        let database = ctx.data::<Database>()?;
+
+        let mut tx = database.begin().await?;
+        database.webhooks().delete(&mut tx, id).await?;
+
+        let mut record = AuditRecord::new(
+            "deleteWebhookSubscription",
+            AuditEntityType::WebhookSubscription,
+            &json!({ "id": id }),
+        )
+        .entity_id(id)
+        .before(&before);
+        if let Some(site_id) = before.site_id {
+            record = record.site_id(site_id);
+        }
+        record.insert_in(&mut tx, identity(ctx)).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn create_device_credential(
+        &self,
+        ctx: &Context<'_>,
+        device_id: i64,
+    ) -> FieldResult<DeviceCredentialWithKey> {
+        require_user(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let Some(site_id) = database.devices().site_id(device_id).await? else {
+            return Err(FieldError::new(format!(
+                "Device with ID {} does not exist",
+                device_id
+            )));
+        };
+        require_site_role(ctx, site_id, Role::Admin).await?;
+
+        let mut tx = database.begin().await?;
+        let result = insert_device_credential(database, &mut tx, device_id).await?;
+
+        AuditRecord::new(
+            "createDeviceCredential",
+            AuditEntityType::DeviceCredential,
+            &json!({ "device_id": device_id }),
+        )
+        .site_id(site_id)
+        .entity_id(result.credential.id)
+        .after(&result.credential)
+        .insert_in(&mut tx, identity(ctx))
+        .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    /// Revokes the given credential and issues a new key for the same device.
+    async fn rotate_device_credential(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+    ) -> FieldResult<DeviceCredentialWithKey> {
+        let (credential, site_id) = device_credential_for_admin(ctx, id).await?;
+        if credential.revoked_at.is_some() {
+            return Err(FieldError::new(format!(
+                "Device credential with ID {} is already revoked",
+                id
+            )));
+        }
+
+        let database = ctx.data::<Database>()?;
+        let mut tx = database.begin().await?;
+        // Rotating the same credential twice at once revokes it once, the other rotation fails.
+        if !database.credentials().revoke_active(&mut tx, id).await? {
+            return Err(FieldError::new(format!(
+                "Device credential with ID {} is already revoked",
+                id
+            )));
+        }
+        let result = insert_device_credential(database, &mut tx, credential.device_id).await?;
+
+        AuditRecord::new(
+            "rotateDeviceCredential",
+            AuditEntityType::DeviceCredential,
+            &json!({ "id": id }),
+        )
+        .site_id(site_id)
+        .entity_id(result.credential.id)
+        .before(&credential)
+        .after(&result.credential)
+        .insert_in(&mut tx, identity(ctx))
+        .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn revoke_device_credential(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+    ) -> FieldResult<DeviceCredential> {
+        let (credential, site_id) = device_credential_for_admin(ctx, id).await?;
+        let database = ctx.data::<Database>()?;
+        let mut tx = database.begin().await?;
+        let result = database.credentials().revoke(&mut tx, id).await?;
+
+        AuditRecord::new(
+            "revokeDeviceCredential",
+            AuditEntityType::DeviceCredential,
+            &json!({ "id": id }),
+        )
+        .site_id(site_id)
+        .entity_id(id)
+        .before(&credential)
+        .after(&result)
+        .insert_in(&mut tx, identity(ctx))
+        .await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn create_user(&self, ctx: &Context<'_>, input: UserInput) -> FieldResult<UserWithToken> {
+        require_admin(ctx)?;
+        let database = ctx.data::<Database>()?;
+        let token = generate_token();
+        let mut tx = database.begin().await?;
+        let user = database
+            .users()
+            .create(&mut tx, &input.name, &hash_token(&token), input.is_admin)
+            .await?;
+
+        AuditRecord::new("createUser", AuditEntityType::User, &input)
+            .entity_id(user.id)
+            .after(&user)
+            .insert_in(&mut tx, identity(ctx))
+            .await?;
+        tx.commit().await?;
+        Ok(UserWithToken { user, token })
+    }
+
+    async fn grant_site_role(
+        &self,
+        ctx: &Context<'_>,
+        input: SiteRoleInput,
+    ) -> FieldResult<SiteRole> {
+        require_site_role(ctx, input.site_id, Role::Admin).await?;
+        let database = ctx.data::<Database>()?;
+        let mut tx = database.begin().await?;
+        let roles = database.site_roles();
+        let before = roles.find_in(&mut tx, input.user_id, input.site_id).await?;
+        let result = roles.grant(&mut tx, &input).await?;
+
+        let mut record = AuditRecord::new("grantSiteRole", AuditEntityType::SiteRole, &input)
+            .site_id(input.site_id)
+            .entity_id(result.id)
+            .after(&result);
+        if let Some(before) = &before {
+            record = record.before(before);
+        }
+        record.insert_in(&mut tx, identity(ctx)).await?;
+        tx.commit().await?;
+        Ok(result)
+    }
+
+    async fn revoke_site_role(
+        &self,
+        ctx: &Context<'_>,
+        user_id: i64,
+        site_id: i64,
+    ) -> FieldResult<bool> {
+        require_site_role(ctx, site_id, Role::Admin).await?;
+        let database = ctx.data::<Database>()?;
+        let mut tx = database.begin().await?;
+        let roles = database.site_roles();
+        let Some(before) = roles.find_in(&mut tx, user_id, site_id).await? else {
+            return Ok(false);
+        };
+        roles.delete(&mut tx, before.id).await?;
+
+        AuditRecord::new(
+            "revokeSiteRole",
+            AuditEntityType::SiteRole,
+            &json!({ "user_id": user_id, "site_id": site_id }),
+        )
+        .site_id(site_id)
+        .entity_id(before.id)
+        .before(&before)
+        .insert_in(&mut tx, identity(ctx))
+        .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+}
+
+/// Looks up a device credential and the site of its device, making sure the caller administers that site.
+async fn device_credential_for_admin(
+    ctx: &Context<'_>,
+    id: i64,
+) -> Result<(DeviceCredential, i64)> {
+    require_user(ctx)?;
+    let database = ctx.data::<Database>()?;
+    let credential = database.credentials().find(id).await?.ok_or_else(|| {
+        FieldError::new(format!("Device credential with ID {} does not exist", id))
+    })?;
+
+    let site_id = database
+        .devices()
+        .site_id(credential.device_id)
+        .await?
+        .ok_or_else(|| {
+            FieldError::new(format!(
+                "Device with ID {} does not exist",
+                credential.device_id
+            ))
+        })?;
+    require_site_role(ctx, site_id, Role::Admin).await?;
+    Ok((credential, site_id))
+}
+
+/// Looks up a rule the caller holds `required` rights on. Callers without them get the same error
+/// whether the rule exists or not, so they can't probe for the rules of other sites.
+async fn find_automation_rule(
+    ctx: &Context<'_>,
+    id: i64,
+    required: Role,
+) -> Result<AutomationRule> {
+    let user = require_user(ctx)?;
+    let database = ctx.data::<Database>()?;
+    let rule = database.automation_rules().find(id).await?;
+    if user.is_admin {
+        return rule.ok_or_else(|| {
+            FieldError::new(format!("Automation rule with ID {} does not exist", id))
+        });
+    }
+    match rule {
+        Some(rule) if site_role(database, user, rule.site_id).await? >= Some(required) => Ok(rule),
+        _ => Err(forbidden(format!(
+            "{:?} rights on the site of automation rule {} required",
+            required, id
+        ))),
+    }
+}
+
+/// Looks up a zone and the site of a room, making sure the caller administers the site and both belong to it.
+async fn find_zone_and_room(ctx: &Context<'_>, zone_id: i64, room_id: i64) -> Result<(Zone, i64)> {
+    require_user(ctx)?;
+    let database = ctx.data::<Database>()?;
+    let zone = database
+        .zones()
+        .find(zone_id)
+        .await?
+        .ok_or_else(|| FieldError::new(format!("Zone with ID {} does not exist", zone_id)))?;
+    require_site_role(ctx, zone.site_id, Role::Admin).await?;
+    match database.rooms().site_id(room_id).await? {
+        Some(site_id) if site_id == zone.site_id => Ok((zone, site_id)),
+        Some(_) => Err(FieldError::new(format!(
+            "Room with ID {} belongs to another site than zone {}",
+            room_id, zone_id
+        ))),
+        None => Err(FieldError::new(format!(
+            "Room with ID {} does not exist",
+            room_id
+        ))),
+    }
+}
+
+/// Rooms can only be put on the floors of their own site.
+async fn require_floor_in_site(database: &Database, floor_id: i64, site_id: i64) -> Result<()> {
+    match database.floors().site_id(floor_id).await? {
+        Some(floor_site_id) if floor_site_id == site_id => Ok(()),
+        Some(_) => Err(FieldError::new(format!(
+            "Floor with ID {} belongs to another site",
+            floor_id
+        ))),
+        None => Err(FieldError::new(format!(
+            "Floor with ID {} does not exist",
+            floor_id
+        ))),
+    }
+}
+
+/// Looks up the site of a site, room or device, making sure the caller administers it.
+async fn require_tagged_entity_admin(
+    ctx: &Context<'_>,
+    entity: TaggedEntity,
+    id: i64,
+) -> Result<i64> {
+    require_user(ctx)?;
+    let database = ctx.data::<Database>()?;
+    let site_id = match entity {
+        TaggedEntity::Site => database.sites().find(id).await?.map(|site| site.id),
+        TaggedEntity::Room => database.rooms().site_id(id).await?,
+        TaggedEntity::Device => database.devices().site_id(id).await?,
+    };
+    let Some(site_id) = site_id else {
+        return Err(FieldError::new(format!(
+            "{:?} with ID {} does not exist",
+            entity, id
+        )));
+    };
+    require_site_role(ctx, site_id, Role::Admin).await?;
+    Ok(site_id)
+}
+
+fn audit_entity_type(entity: TaggedEntity) -> AuditEntityType {
+    match entity {
+        TaggedEntity::Site => AuditEntityType::Site,
+        TaggedEntity::Room => AuditEntityType::Room,
+        TaggedEntity::Device => AuditEntityType::Device,
+    }
+}
+
+fn validate_tag(key: &str, value: Option<&str>) -> Result<()> {
+    if key.trim().is_empty() {
+        return Err(FieldError::new("Tag keys cannot be empty"));
+    }
+    if key.chars().count() > MAX_TAG_KEY_LENGTH {
+        return Err(FieldError::new(format!(
+            "Tag keys cannot be longer than {} characters",
+            MAX_TAG_KEY_LENGTH
+        )));
+    }
+    if value.is_some_and(|value| value.chars().count() > MAX_TAG_VALUE_LENGTH) {
+        return Err(FieldError::new(format!(
+            "Tag values cannot be longer than {} characters",
+            MAX_TAG_VALUE_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+fn validate_tag_filters(tags: &[TagFilter]) -> Result<()> {
+    if tags.len() > MAX_TAG_FILTERS {
+        return Err(FieldError::new(format!(
+            "Cannot filter by more than {} tags",
+            MAX_TAG_FILTERS
+        )));
+    }
+    Ok(())
+}
+
+fn validate_metadata(metadata: &serde_json::Value) -> Result<()> {
+    if !metadata.is_object() {
+        return Err(FieldError::new("Metadata has to be a JSON object"));
+    }
+    if metadata.to_string().len() > MAX_METADATA_SIZE {
+        return Err(FieldError::new(format!(
+            "Metadata cannot take more than {} bytes as JSON",
+            MAX_METADATA_SIZE
+        )));
+    }
+    Ok(())
+}
+
+/// Overrides have to expire in the future, and other setpoints can't expire at all.
+fn validate_setpoint_mode(
+    mode: SetpointMode,
+    expires_at: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    match (mode, expires_at) {
+        (SetpointMode::Override, Some(expires_at)) if expires_at > now => Ok(()),
+        (SetpointMode::Override, _) => Err(FieldError::new(
+            "Overrides need an expiry time in the future",
+        )),
+        (_, Some(_)) => Err(FieldError::new("Only overrides can have an expiry time")),
+        (_, None) => Ok(()),
+    }
+}
+
+/// Settings of a site are managed by the site's admins, settings for every site only by administrators.
+async fn require_scoped_admin(ctx: &Context<'_>, site_id: Option<i64>) -> Result<()> {
+    match site_id {
+        Some(site_id) => require_site_role(ctx, site_id, Role::Admin).await,
+        None => require_admin(ctx).map(|_| ()),
+    }
+}
+
+/// Looks up a subscription the caller administers, like [`find_automation_rule`] does for rules.
+async fn find_webhook_subscription(ctx: &Context<'_>, id: i64) -> Result<WebhookSubscription> {
+    let user = require_user(ctx)?;
+    let database = ctx.data::<Database>()?;
+    let subscription = database.webhooks().find(id).await?;
+    if user.is_admin {
+        return subscription.ok_or_else(|| {
+            FieldError::new(format!(
+                "Webhook subscription with ID {} does not exist",
+                id
+            ))
+        });
+    }
+    match subscription {
+        Some(
+            subscription @ WebhookSubscription {
+                site_id: Some(site_id),
+                ..
+            },
+        ) if site_role(database, user, site_id).await? == Some(Role::Admin) => Ok(subscription),
+        _ => Err(forbidden(format!(
+            "Admin rights on the site of webhook subscription {} required",
+            id
+        ))),
+    }
 }
 
 pub type AppSchema = Schema<SiteQueryRoot, SiteMutationRoot, EmptySubscription>;
@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Context, Result};
+use async_graphql::parser::parse_schema;
+use async_graphql::parser::types::{
+    BaseType, FieldDefinition, InputValueDefinition, Type, TypeKind, TypeSystemDefinition,
+};
+use async_graphql::{EmptySubscription, Positioned, Schema};
+
+use crate::schema::{SiteMutationRoot, SiteQueryRoot};
+
+/// The SDL of the GraphQL API, the same one the server introspects to.
+pub fn export_sdl() -> String {
+    Schema::build(SiteQueryRoot, SiteMutationRoot, EmptySubscription)
+        .finish()
+        .sdl()
+}
+
+/// Lists the changes from `old` to `new` that can break existing clients,
+/// such as removed types, fields, arguments and enum values, fields that became nullable,
+/// and arguments or input fields that became required.
+pub fn breaking_changes(old: &str, new: &str) -> Result<Vec<String>> {
+    let old = types(old).context("Failed to parse the old schema")?;
+    let new = types(new).context("Failed to parse the new schema")?;
+
+    let mut changes = Vec::new();
+    for (name, old_kind) in &old {
+        let Some(new_kind) = new.get(name) else {
+            changes.push(format!("Type `{}` was removed", name));
+            continue;
+        };
+        match (old_kind, new_kind) {
+            (TypeKind::Object(old_type), TypeKind::Object(new_type)) => {
+                compare_fields(name, &old_type.fields, &new_type.fields, &mut changes)
+            }
+            (TypeKind::Interface(old_type), TypeKind::Interface(new_type)) => {
+                compare_fields(name, &old_type.fields, &new_type.fields, &mut changes)
+            }
+            (TypeKind::InputObject(old_type), TypeKind::InputObject(new_type)) => compare_inputs(
+                &old_type.fields,
+                &new_type.fields,
+                |field| format!("Input field `{}.{}`", name, field),
+                &mut changes,
+            ),
+            (TypeKind::Enum(old_type), TypeKind::Enum(new_type)) => {
+                for value in &old_type.values {
+                    let value = &value.node.value.node;
+                    if !new_type
+                        .values
+                        .iter()
+                        .any(|new_value| &new_value.node.value.node == value)
+                    {
+                        changes.push(format!("Enum value `{}.{}` was removed", name, value));
+                    }
+                }
+            }
+            (TypeKind::Union(old_type), TypeKind::Union(new_type)) => {
+                for member in &old_type.members {
+                    if !new_type
+                        .members
+                        .iter()
+                        .any(|new_member| new_member.node == member.node)
+                    {
+                        changes.push(format!(
+                            "Type `{}` was removed from union `{}`",
+                            member.node, name
+                        ));
+                    }
+                }
+            }
+            (TypeKind::Scalar, TypeKind::Scalar) => {}
+            _ => changes.push(format!(
+                "Type `{}` changed from {} to {}",
+                name,
+                kind_name(old_kind),
+                kind_name(new_kind)
+            )),
+        }
+    }
+    Ok(changes)
+}
+
+fn types(sdl: &str) -> Result<BTreeMap<String, TypeKind>> {
+    let document = parse_schema(sdl)?;
+    Ok(document
+        .definitions
+        .into_iter()
+        .filter_map(|definition| match definition {
+            TypeSystemDefinition::Type(ty) => Some((ty.node.name.node.to_string(), ty.node.kind)),
+            _ => None,
+        })
+        .collect())
+}
+
+fn kind_name(kind: &TypeKind) -> &'static str {
+    match kind {
+        TypeKind::Scalar => "a scalar",
+        TypeKind::Object(_) => "an object",
+        TypeKind::Interface(_) => "an interface",
+        TypeKind::Union(_) => "a union",
+        TypeKind::Enum(_) => "an enum",
+        TypeKind::InputObject(_) => "an input object",
+    }
+}
+
+fn compare_fields(
+    type_name: &str,
+    old: &[Positioned<FieldDefinition>],
+    new: &[Positioned<FieldDefinition>],
+    changes: &mut Vec<String>,
+) {
+    for old_field in old {
+        let old_field = &old_field.node;
+        let name = format!("{}.{}", type_name, old_field.name.node);
+        let Some(new_field) = new
+            .iter()
+            .find(|new_field| new_field.node.name.node == old_field.name.node)
+        else {
+            changes.push(format!("Field `{}` was removed", name));
+            continue;
+        };
+        let new_field = &new_field.node;
+        if !output_compatible(&old_field.ty.node, &new_field.ty.node) {
+            changes.push(format!(
+                "Field `{}` changed type from `{}` to `{}`",
+                name, old_field.ty.node, new_field.ty.node
+            ));
+        }
+        compare_inputs(
+            &old_field.arguments,
+            &new_field.arguments,
+            |argument| format!("Argument `{}({})`", name, argument),
+            changes,
+        );
+    }
+}
+
+/// Compares the arguments of a field or the fields of an input object, which break clients
+/// when they disappear, accept fewer values than before or are newly required.
+fn compare_inputs(
+    old: &[Positioned<InputValueDefinition>],
+    new: &[Positioned<InputValueDefinition>],
+    describe: impl Fn(&str) -> String,
+    changes: &mut Vec<String>,
+) {
+    for old_input in old {
+        let old_input = &old_input.node;
+        match new
+            .iter()
+            .find(|new_input| new_input.node.name.node == old_input.name.node)
+        {
+            None => changes.push(format!("{} was removed", describe(&old_input.name.node))),
+            Some(new_input) if !input_compatible(&old_input.ty.node, &new_input.node.ty.node) => {
+                changes.push(format!(
+                    "{} changed type from `{}` to `{}`",
+                    describe(&old_input.name.node),
+                    old_input.ty.node,
+                    new_input.node.ty.node
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+    for new_input in new {
+        let new_input = &new_input.node;
+        let required = !new_input.ty.node.nullable && new_input.default_value.is_none();
+        if required
+            && !old
+                .iter()
+                .any(|old_input| old_input.node.name.node == new_input.name.node)
+        {
+            changes.push(format!(
+                "{} was added as required",
+                describe(&new_input.name.node)
+            ));
+        }
+    }
+}
+
+/// Whether clients expecting the old type of a field can handle the new one,
+/// which holds as long as it doesn't become nullable anywhere.
+fn output_compatible(old: &Type, new: &Type) -> bool {
+    if !old.nullable && new.nullable {
+        return false;
+    }
+    match (&old.base, &new.base) {
+        (BaseType::Named(old), BaseType::Named(new)) => old == new,
+        (BaseType::List(old), BaseType::List(new)) => output_compatible(old, new),
+        _ => false,
+    }
+}
+
+/// Whether every value clients could send for the old type of an input is still accepted,
+/// which holds as long as it doesn't become non-null anywhere.
+fn input_compatible(old: &Type, new: &Type) -> bool {
+    if old.nullable && !new.nullable {
+        return false;
+    }
+    match (&old.base, &new.base) {
+        (BaseType::Named(old), BaseType::Named(new)) => old == new,
+        (BaseType::List(old), BaseType::List(new)) => input_compatible(old, new),
+        _ => false,
+    }
+}
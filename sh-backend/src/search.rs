@@ -0,0 +1,117 @@
+//! Full-text search over sites, rooms and devices.
+//!
+//! The index is kept up to date by triggers, see the migrations. On SQLite it is an FTS5 table ranked with `bm25`,
+//! on PostgreSQL a `tsvector` per entity ranked with `ts_rank`.
+
+use sqlx::FromRow;
+
+use crate::db::{Backend, Database};
+use crate::models::{TagFilter, TaggedEntity, User};
+use crate::repository::tag_filter_sql;
+use crate::with_pool;
+
+/// The weights `bm25` ranks matches by, one per column of the index.
+/// A match in a name counts for more than one in the details, the unindexed columns don't count at all.
+const COLUMN_WEIGHTS: &str = "0.0, 0.0, 0.0, 10.0, 1.0";
+
+/// The weights `ts_rank` ranks matches by, for the D, C, B and A weighted words of a document.
+/// Names weigh A and details D, so a match in a name counts ten times as much as in the details, like on SQLite.
+const DOCUMENT_WEIGHTS: &str = "'{0.1, 0, 0, 1}'";
+
+/// Divides the rank by 1 + the logarithm of the length of the document,
+/// so shorter documents rank higher like they do with `bm25`.
+const LENGTH_NORMALIZATION: i32 = 1;
+
+/// A site, room or device matching a search, in the order of relevance.
+#[derive(Debug, Clone, Copy, FromRow)]
+pub struct SearchHit {
+    pub entity_type: TaggedEntity,
+    pub entity_id: i64,
+}
+
+/// Turns what a user typed into an FTS5 query matching every word as a prefix,
+/// quoting the words so that operators and punctuation in them are taken literally.
+/// Returns `None` if there is nothing to search for.
+fn match_expression(query: &str) -> Option<String> {
+    let terms: Vec<_> = query
+        .split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .map(|word| format!("\"{}\"*", word.replace('"', "\"\"")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" "))
+}
+
+/// Turns what a user typed into a `tsquery` with the same meaning as [`match_expression`]:
+/// the parts of a word between punctuation have to follow each other, the last of them as a prefix,
+/// so that "ab:cd:ef-01" matches like the FTS5 phrase it becomes on SQLite.
+fn ts_query_expression(query: &str) -> Option<String> {
+    let terms: Vec<_> = query
+        .split_whitespace()
+        .filter_map(|word| {
+            let parts: Vec<_> = word
+                .split(|c: char| !c.is_alphanumeric())
+                .filter(|part| !part.is_empty())
+                .map(|part| format!("'{}'", part.to_lowercase()))
+                .collect();
+            (!parts.is_empty()).then(|| format!("({}:*)", parts.join(" <-> ")))
+        })
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" & "))
+}
+
+/// Searches the names and addresses of sites, the names of rooms, and the names and unique identifiers of devices,
+/// out of the sites the user holds a role on, returning the most relevant entities carrying every one of the tags.
+pub async fn search(
+    database: &Database,
+    user: &User,
+    query: &str,
+    tags: &[TagFilter],
+    limit: i64,
+) -> sqlx::Result<Vec<SearchHit>> {
+    let backend = database.backend();
+    let (expression, matches, rank) = match backend {
+        Backend::Sqlite => (
+            match_expression(query),
+            "SearchIndex MATCH $1".to_string(),
+            format!("bm25(SearchIndex, {})", COLUMN_WEIGHTS),
+        ),
+        Backend::Postgres => (
+            ts_query_expression(query),
+            "document @@ to_tsquery('simple', unaccent($1))".to_string(),
+            format!(
+                "-ts_rank({}, document, to_tsquery('simple', unaccent($1)), {})",
+                DOCUMENT_WEIGHTS, LENGTH_NORMALIZATION
+            ),
+        ),
+    };
+    let Some(expression) = expression else {
+        return Ok(Vec::new());
+    };
+    let sql = format!(
+        r#"
+        SELECT entity_type, entity_id
+        FROM SearchIndex
+        WHERE {}
+            AND ($2 OR site_id IN (SELECT site_id FROM SiteRole WHERE user_id = $3))
+            AND ((entity_type = 'Site' AND {}) OR (entity_type = 'Room' AND {}) OR (entity_type = 'Device' AND {}))
+        ORDER BY {}, entity_id
+        LIMIT $4
+        "#,
+        matches,
+        tag_filter_sql(TaggedEntity::Site, "entity_id", tags, 5),
+        tag_filter_sql(TaggedEntity::Room, "entity_id", tags, 5),
+        tag_filter_sql(TaggedEntity::Device, "entity_id", tags, 5),
+        rank
+    );
+    with_pool!(database, pool => {
+        let mut query = sqlx::query_as::<_, SearchHit>(&sql)
+            .bind(&expression)
+            .bind(user.is_admin)
+            .bind(user.id)
+            .bind(limit);
+        for tag in tags {
+            query = query.bind(&tag.key).bind(&tag.value);
+        }
+        query.fetch_all(pool).await
+    })
+}
@@ -1,58 +1,73 @@
 use anyhow::Result;
 use chrono::{Duration, Utc};
-use log::debug;
+use log::{debug, info};
 use rand::Rng;
-use sqlx::sqlite::SqlitePool;
 
+use crate::auth::{generate_token, hash_token};
+use crate::db::Database;
 use crate::models::{
-    ControlSetpoint, ControlSetpointInput, Device, DeviceInput, DeviceType, Room, RoomInput,
-    SensorReading, SensorReadingInput, SensorUnit, SetpointType, SetpointUnit, Site, SiteInput,
+    BuildingInput, ControlSetpointInput, DeviceInput, DeviceType, FloorInput, RoomInput,
+    SensorReadingInput, SensorUnit, SetpointMode, SetpointType, SetpointUnit, SiteInput, UserInput,
 };
 
 // TODO: implement extending existing site
-pub async fn seed_db(pool: &SqlitePool, _should_extend: bool) -> Result<()> {
-    let mut tx = pool.begin().await?;
+pub async fn seed_db(database: &Database, _should_extend: bool) -> Result<()> {
+    let mut tx = database.begin().await?;
 
     let now = Utc::now();
 
     debug!("Starting database seeding...");
 
+    let user_input = UserInput {
+        name: "Admin".to_string(),
+        is_admin: true,
+    };
+    let admin_token = generate_token();
+    let admin = database
+        .users()
+        .create(
+            &mut tx,
+            &user_input.name,
+            &hash_token(&admin_token),
+            user_input.is_admin,
+        )
+        .await?;
+    info!(
+        "Created admin User {} with ID {}, API token (shown only once): {}",
+        admin.name, admin.id, admin_token
+    );
+
     let site_input = SiteInput {
         name: "Nordstan Göteborg".to_string(),
         address: Some("Götgatan 11, 411 05 Göteborg, Sweden".to_string()),
     };
-    let site = sqlx::query_as::<_, Site>(
-        r#"
-        INSERT INTO Site (name, address, created_at, updated_at)
-        VALUES (?, ?, ?, ?)
-        RETURNING id, name, address, created_at, updated_at
-        "#,
-    )
-    .bind(site_input.name)
-    .bind(site_input.address)
-    .bind(now)
-    .bind(now)
-    .fetch_one(&mut *tx)
-    .await?;
+    let site = database.sites().create(&mut tx, &site_input).await?;
     debug!("Created Site: {:?}", site);
 
+    let building_input = BuildingInput {
+        site_id: site.id,
+        name: "Nordstan".to_string(),
+    };
+    let building = database
+        .buildings()
+        .create(&mut tx, &building_input)
+        .await?;
+    debug!("Created Building: {:?}", building);
+
+    let floor_input = FloorInput {
+        building_id: building.id,
+        name: "Ground floor".to_string(),
+        level: 0,
+    };
+    let floor = database.floors().create(&mut tx, &floor_input).await?;
+    debug!("Created Floor: {:?}", floor);
+
     let room_input = RoomInput {
         site_id: site.id,
         name: "Systembolaget Main Room".to_string(),
+        floor_id: Some(floor.id),
     };
-    let room = sqlx::query_as::<_, Room>(
-        r#"
-        INSERT INTO Room (site_id, name, created_at, updated_at)
-        VALUES (?, ?, ?, ?)
-        RETURNING id, site_id, name, created_at, updated_at
-        "#,
-    )
-    .bind(room_input.site_id)
-    .bind(room_input.name)
-    .bind(now)
-    .bind(now)
-    .fetch_one(&mut *tx)
-    .await?;
+    let room = database.rooms().create(&mut tx, &room_input).await?;
     debug!("Created Room: {:?}", room);
 
     let device_input = DeviceInput {
@@ -61,21 +76,7 @@ pub async fn seed_db(pool: &SqlitePool, _should_extend: bool) -> Result<()> {
         device_type: DeviceType::TemperatureSensor,
         unique_identifier: None,
     };
-    let device = sqlx::query_as::<_, Device>(
-        r#"
-        INSERT INTO Device (room_id, name, device_type, unique_identifier, created_at, updated_at)
-        VALUES (?, ?, ?, ?, ?, ?)
-        RETURNING id, room_id, name, device_type, unique_identifier, created_at, updated_at
-        "#,
-    )
-    .bind(device_input.room_id)
-    .bind(device_input.name)
-    .bind(device_input.device_type as DeviceType)
-    .bind(device_input.unique_identifier)
-    .bind(now)
-    .bind(now)
-    .fetch_one(&mut *tx)
-    .await?;
+    let device = database.devices().create(&mut tx, &device_input).await?;
     debug!("Created Device: {:?}", device);
 
     let control_setpoint_input = ControlSetpointInput {
@@ -83,23 +84,13 @@ pub async fn seed_db(pool: &SqlitePool, _should_extend: bool) -> Result<()> {
         setpoint_type: SetpointType::Temperature,
         value: "22.5".to_string(),
         unit: Some(SetpointUnit::Celsius),
+        mode: SetpointMode::Scheduled,
+        expires_at: None,
     };
-    let control_setpoint = sqlx::query_as::<_, ControlSetpoint>(
-        r#"
-        INSERT INTO ControlSetpoint (device_id, setpoint_type, value, unit, timestamp, created_at, updated_at)
-        VALUES (?, ?, ?, ?, ?, ?, ?)
-        RETURNING id, device_id, setpoint_type, value, unit, timestamp, created_at, updated_at
-        "#
-    )
-    .bind(control_setpoint_input.device_id)
-    .bind(control_setpoint_input.setpoint_type as SetpointType)
-    .bind(control_setpoint_input.value)
-    .bind(control_setpoint_input.unit as Option<SetpointUnit>)
-    .bind(now)
-    .bind(now)
-    .bind(now)
-    .fetch_one(&mut *tx)
-    .await?;
+    let control_setpoint = database
+        .setpoints()
+        .create(&mut tx, &control_setpoint_input, now)
+        .await?;
     debug!("Created ControlSetpoint: {:?}", control_setpoint);
 
     let mut current_timestamp = now - Duration::minutes(100 * 5);
@@ -115,21 +106,10 @@ pub async fn seed_db(pool: &SqlitePool, _should_extend: bool) -> Result<()> {
             unit: Some(SensorUnit::Celsius),
         };
 
-        sqlx::query_as::<_, SensorReading>(
-            r#"
-            INSERT INTO SensorReading (device_id, value, unit, timestamp, created_at, updated_at)
-            VALUES (?, ?, ?, ?, ?, ?)
-            RETURNING id, device_id, value, unit, timestamp, created_at, updated_at
-            "#,
-        )
-        .bind(sensor_reading_input.device_id)
-        .bind(sensor_reading_input.value)
-        .bind(sensor_reading_input.unit as Option<SensorUnit>)
-        .bind(current_timestamp)
-        .bind(now)
-        .bind(now)
-        .fetch_one(&mut *tx)
-        .await?;
+        database
+            .readings()
+            .create(&mut tx, &sensor_reading_input, Some(current_timestamp))
+            .await?;
 
         current_timestamp += Duration::minutes(5);
 
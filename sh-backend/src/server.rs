@@ -0,0 +1,189 @@
+use anyhow::Result;
+use async_graphql::{BatchResponse, EmptySubscription, Request, Schema, http::GraphiQLSource};
+use async_graphql_rocket::{GraphQLRequest, GraphQLResponse};
+use log::error;
+use rocket::http::Status;
+use rocket::{Build, FromForm, Rocket, State, response::content, routes};
+use serde_json::{Map, Value};
+
+use crate::auth::Identity;
+use crate::caching::ETags;
+use crate::compression::Compression;
+use crate::config::Config;
+use crate::cors::Cors;
+use crate::db::Database;
+use crate::events::EventBus;
+use crate::export::export_readings;
+use crate::health::{Health, healthz, readyz};
+use crate::limits::QueryLimits;
+use crate::metrics::Metrics;
+use crate::persisted_queries::PersistedQueries;
+use crate::rate_limits::{ClientAddress, RateLimits};
+use crate::schema::{AppSchema, SiteMutationRoot, SiteQueryRoot};
+
+#[rocket::get("/graphiql")]
+async fn graphiql() -> content::RawHtml<String> {
+    content::RawHtml(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+/// An operation sent in the query string, where `variables` and `extensions` are JSON.
+/// The query is left out when the `persistedQuery` extension only sends its hash.
+#[derive(FromForm)]
+struct GraphQLQuery {
+    query: Option<String>,
+    #[field(name = "operationName")]
+    operation_name: Option<String>,
+    variables: Option<String>,
+    extensions: Option<String>,
+}
+
+impl GraphQLQuery {
+    fn into_request(self) -> Option<Request> {
+        let mut request = Map::new();
+        request.insert("query".into(), self.query.unwrap_or_default().into());
+        if let Some(operation_name) = self.operation_name {
+            request.insert("operationName".into(), operation_name.into());
+        }
+        for (key, json) in [
+            ("variables", self.variables),
+            ("extensions", self.extensions),
+        ] {
+            if let Some(json) = json {
+                request.insert(key.into(), serde_json::from_str(&json).ok()?);
+            }
+        }
+        serde_json::from_value(Value::Object(request)).ok()
+    }
+}
+
+#[rocket::get("/graphql?<query..>")]
+async fn graphql_query(
+    schema: &State<AppSchema>,
+    identity: Identity,
+    address: ClientAddress,
+    query: GraphQLQuery,
+) -> Result<GraphQLResponse, Status> {
+    let request = query.into_request().ok_or(Status::BadRequest)?;
+    let mut response = GraphQLRequest(request)
+        .data(identity)
+        .data(address)
+        .execute(schema.inner())
+        .await;
+    // Answers depend on who asks, so only the browser may keep them,
+    // and has to revalidate them with their ETag unless a type allows otherwise.
+    if let BatchResponse::Single(response) = &mut response.0 {
+        response.cache_control.public = false;
+        if response.cache_control.max_age == 0 {
+            response.cache_control.max_age = -1;
+        }
+    }
+    Ok(response)
+}
+
+#[rocket::post("/graphql", data = "<request>", format = "application/json")]
+async fn graphql_request(
+    schema: &State<AppSchema>,
+    identity: Identity,
+    address: ClientAddress,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    request
+        .data(identity)
+        .data(address)
+        .execute(schema.inner())
+        .await
+}
+
+/// File uploads arrive as multipart requests, see the GraphQL multipart request spec.
+#[rocket::post(
+    "/graphql",
+    data = "<request>",
+    format = "multipart/form-data",
+    rank = 2
+)]
+async fn graphql_upload(
+    schema: &State<AppSchema>,
+    identity: Identity,
+    address: ClientAddress,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    request
+        .data(identity)
+        .data(address)
+        .execute(schema.inner())
+        .await
+}
+
+#[rocket::get("/metrics")]
+async fn metrics(
+    metrics: &State<Metrics>,
+    database: &State<Database>,
+) -> Result<content::RawText<String>, Status> {
+    match metrics.render(database).await {
+        Ok(rendered) => Ok(content::RawText(rendered)),
+        Err(err) => {
+            error!("Failed to render metrics: {}", err);
+            Err(Status::InternalServerError)
+        }
+    }
+}
+
+/// The GraphQL schema, holding everything the resolvers take from their context.
+pub fn build_schema(
+    database: Database,
+    events: EventBus,
+    config: Config,
+    metrics: &Metrics,
+) -> Result<AppSchema> {
+    let persisted_queries = PersistedQueries::new(&config.graphql)?;
+    let mut schema = Schema::build(SiteQueryRoot, SiteMutationRoot, EmptySubscription)
+        .data(database)
+        .data(events)
+        .extension(metrics.extension())
+        .extension(persisted_queries)
+        .extension(QueryLimits::new(&config.graphql));
+    if config.rate_limits.enabled {
+        // Also handed to the resolvers, which charge imports once they know how many rows they hold.
+        let rate_limits = RateLimits::new(&config.rate_limits);
+        schema = schema.data(rate_limits.clone()).extension(rate_limits);
+    }
+    Ok(schema.data(config).finish())
+}
+
+/// The server with every route mounted, ready to be launched.
+/// The workers are spawned by the caller, which hands their handles over in `health`.
+pub fn build_rocket(
+    database: Database,
+    events: EventBus,
+    config: Config,
+    metrics: Metrics,
+    health: Health,
+) -> Result<Rocket<Build>> {
+    let schema = build_schema(database.clone(), events.clone(), config.clone(), &metrics)?;
+    let mut rocket = rocket::custom(config.rocket_figment())
+        .attach(Cors::new(&config.cors))
+        .attach(ETags);
+    if config.http.compression {
+        rocket = rocket.attach(Compression);
+    }
+    Ok(rocket
+        .manage(database)
+        .manage(events)
+        .manage(schema)
+        .manage(metrics)
+        .manage(health)
+        .manage(config)
+        .mount(
+            "/",
+            routes![
+                graphql_query,
+                graphql_request,
+                graphql_upload,
+                graphiql,
+                export_readings,
+                metrics,
+                healthz,
+                readyz
+            ],
+        ))
+}
@@ -0,0 +1,372 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration as StdDuration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use log::{debug, error, warn};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use rocket::futures::{StreamExt, stream};
+use serde_json::json;
+use sha2::Sha256;
+use tokio::sync::Notify;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinHandle;
+
+use crate::db::Database;
+use crate::events::{Event, EventBus};
+use crate::models::{
+    WebhookDelivery, WebhookDeliveryStatus, WebhookEventType, WebhookSubscription,
+};
+
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+
+/// How often the queue is checked for due retries when nothing new comes in.
+const POLL_INTERVAL: StdDuration = StdDuration::from_secs(5);
+const DELIVERY_TIMEOUT: StdDuration = StdDuration::from_secs(10);
+const BATCH_SIZE: i64 = 50;
+/// How many deliveries of a batch are sent at the same time, so one slow endpoint doesn't hold up the others.
+const CONCURRENT_DELIVERIES: usize = 8;
+const INITIAL_BACKOFF_SECONDS: i64 = 10;
+const MAX_BACKOFF_SECONDS: i64 = 60 * 60;
+
+pub fn event_type(event: &Event) -> WebhookEventType {
+    match event {
+        Event::SensorReadingCreated { .. } => WebhookEventType::SensorReadingCreated,
+        Event::ControlSetpointCreated { .. } => WebhookEventType::ControlSetpointCreated,
+        Event::AlertRaised { .. } => WebhookEventType::AlertRaised,
+    }
+}
+
+/// The `sha256=<hex>` HMAC of a payload, sent in the `X-Webhook-Signature` header.
+pub fn sign(secret: &str, payload: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(payload.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// The delay before the next attempt, doubling with every failed attempt.
+pub fn backoff(attempts: i64) -> Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 20) as u32;
+    let seconds = INITIAL_BACKOFF_SECONDS.saturating_mul(2_i64.pow(exponent));
+    Duration::seconds(seconds.min(MAX_BACKOFF_SECONDS))
+}
+
+/// Whether an address is reachable from the internet, rather than the host itself, its local network,
+/// or a private network the server happens to be on, such as the one of a cloud metadata service.
+pub fn is_public(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            let [first, second, ..] = address.octets();
+            !(address.is_loopback()
+                || address.is_private()
+                || address.is_link_local()
+                || address.is_unspecified()
+                || address.is_broadcast()
+                || address.is_multicast()
+                // Shared address space, used for carrier-grade NAT.
+                || (first == 100 && (64..128).contains(&second))
+                // "This network", which some systems route to the host itself.
+                || first == 0)
+        }
+        IpAddr::V6(address) => match address.to_ipv4_mapped() {
+            Some(mapped) => is_public(IpAddr::V4(mapped)),
+            None => {
+                !(address.is_loopback()
+                    || address.is_unspecified()
+                    || address.is_multicast()
+                    || address.is_unique_local()
+                    || address.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+/// Checks that a URL uses http or https and that its host only resolves to public addresses,
+/// so webhooks can't be pointed at the server itself or the network behind it.
+/// The addresses are checked again whenever a webhook is called, see [`http_client`].
+pub async fn validate_url(url: &str) -> Result<()> {
+    let url = reqwest::Url::parse(url)?;
+    if !matches!(url.scheme(), "http" | "https") {
+        bail!("Webhook URLs have to use http or https");
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("Webhook URLs need a host"))?
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addresses = tokio::net::lookup_host((host, port))
+        .await
+        .with_context(|| format!("Could not resolve the webhook host {}", host))?;
+    for address in addresses {
+        if !is_public(address.ip()) {
+            bail!("Webhook URLs cannot point to private, loopback or link-local addresses");
+        }
+    }
+    Ok(())
+}
+
+/// Checks the host of a URL about to be called when it is an IP address,
+/// which the client connects to without resolving it.
+pub(crate) fn check_address(url: &reqwest::Url) -> Result<()> {
+    let host = url
+        .host_str()
+        .unwrap_or_default()
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    if host.parse().is_ok_and(|address| !is_public(address)) {
+        bail!("Webhook URLs cannot point to private, loopback or link-local addresses");
+    }
+    Ok(())
+}
+
+/// Resolves host names like the system does, leaving out the addresses that aren't public.
+/// Connecting to the addresses checked, rather than resolving the name again, keeps a host
+/// from passing the check with one address and being called at another.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|address| is_public(address.ip()))
+                .collect();
+            if addresses.is_empty() {
+                return Err(
+                    format!("{} does not resolve to any public address", name.as_str()).into(),
+                );
+            }
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// The client webhooks are called with, which only connects to public addresses and doesn't follow redirects,
+/// as those could lead anywhere.
+pub fn http_client(timeout: StdDuration) -> reqwest::Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .redirect(reqwest::redirect::Policy::none())
+        .dns_resolver(Arc::new(PublicResolver))
+        .build()
+}
+
+/// Queues a delivery for every subscription interested in a published event,
+/// and delivers the queue in the background, retrying failures with exponential backoff.
+pub struct WebhookDispatcher {
+    database: Database,
+    events: EventBus,
+    http: reqwest::Client,
+    queued: Arc<Notify>,
+    /// Deliveries are attempted this many times before they are given up as failed.
+    max_attempts: i64,
+}
+
+impl WebhookDispatcher {
+    pub fn new(database: Database, events: EventBus, max_attempts: u32) -> Result<Self> {
+        let http = http_client(DELIVERY_TIMEOUT).context("Failed to build the HTTP client")?;
+        Ok(Self {
+            database,
+            events,
+            http,
+            queued: Arc::new(Notify::new()),
+            max_attempts: max_attempts.into(),
+        })
+    }
+
+    pub fn spawn(self) -> JoinHandle<()> {
+        let dispatcher = Arc::new(self);
+        let receiver = dispatcher.events.subscribe();
+        tokio::spawn(async move {
+            tokio::join!(
+                Arc::clone(&dispatcher).enqueue_events(receiver),
+                dispatcher.deliver_queue()
+            );
+        })
+    }
+
+    async fn enqueue_events(
+        self: Arc<Self>,
+        mut receiver: tokio::sync::broadcast::Receiver<Event>,
+    ) {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => match self.enqueue(&event).await {
+                    Ok(0) => {}
+                    Ok(_) => self.queued.notify_one(),
+                    Err(err) => error!("Failed to queue webhook deliveries: {}", err),
+                },
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Webhook dispatcher fell behind, skipped {} events", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            }
+        }
+    }
+
+    /// Stores a delivery for each matching subscription, returning how many were queued.
+    pub async fn enqueue(&self, event: &Event) -> Result<usize> {
+        let event_type = event_type(event);
+        let subscriptions = self
+            .database
+            .webhooks()
+            .enabled_for_site(event.site_id())
+            .await?;
+
+        let now = Utc::now();
+        let payload = json!({
+            "event_type": event_type.name(),
+            "site_id": event.site_id(),
+            "occurred_at": now,
+            "data": event,
+        })
+        .to_string();
+
+        let mut queued = 0;
+        for subscription in subscriptions
+            .iter()
+            .filter(|subscription| subscription.event_types.contains(&event_type))
+        {
+            self.database
+                .webhooks()
+                .queue_delivery(subscription.id, event_type, &payload, now)
+                .await?;
+            queued += 1;
+        }
+        Ok(queued)
+    }
+
+    async fn deliver_queue(self: Arc<Self>) {
+        loop {
+            match self.deliver_due(Utc::now()).await {
+                // A full batch likely means more deliveries are waiting.
+                Ok(delivered) if delivered as i64 >= BATCH_SIZE => continue,
+                Ok(_) => {}
+                Err(err) => error!("Failed to deliver webhooks: {}", err),
+            }
+            tokio::select! {
+                _ = self.queued.notified() => {}
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+    /// Attempts every delivery that is due, returning how many were attempted.
+    ///
+    /// A delivery that fails to be recorded is logged and left for a later attempt,
+    /// without holding up the other deliveries of the batch.
+    pub async fn deliver_due(&self, now: DateTime<Utc>) -> Result<usize> {
+        let deliveries = self
+            .database
+            .webhooks()
+            .due_deliveries(now, BATCH_SIZE)
+            .await?;
+
+        let attempted = deliveries.len();
+        let mut results = stream::iter(deliveries)
+            .map(|delivery| async move { (delivery.id, self.deliver(&delivery).await) })
+            .buffer_unordered(CONCURRENT_DELIVERIES);
+        while let Some((delivery_id, result)) = results.next().await {
+            if let Err(err) = result {
+                error!(
+                    "Failed to deliver webhook delivery {}: {}",
+                    delivery_id, err
+                );
+            }
+        }
+        Ok(attempted)
+    }
+
+    async fn deliver(&self, delivery: &WebhookDelivery) -> Result<()> {
+        let subscription = self
+            .database
+            .webhooks()
+            .find(delivery.subscription_id)
+            .await?
+            .context("The subscription of the delivery no longer exists")?;
+
+        // The subscription may have been disabled since the delivery was queued,
+        // in which case the delivery is given up without calling the endpoint.
+        if !subscription.enabled {
+            debug!(
+                "Webhook delivery {} dropped, subscription {} is disabled",
+                delivery.id, subscription.id
+            );
+            let dropped = WebhookDelivery {
+                status: WebhookDeliveryStatus::Failed,
+                next_attempt_at: None,
+                last_error: Some("The subscription is disabled".to_string()),
+                updated_at: Utc::now(),
+                ..delivery.clone()
+            };
+            self.database.webhooks().save_delivery(&dropped).await?;
+            return Ok(());
+        }
+
+        let (response_status, error) = match self.send(&subscription, delivery).await {
+            Ok(response) if response.status().is_success() => (Some(response.status()), None),
+            Ok(response) => (
+                Some(response.status()),
+                Some(format!("Responded with {}", response.status())),
+            ),
+            Err(err) => (None, Some(err.to_string())),
+        };
+
+        let now = Utc::now();
+        let attempts = delivery.attempts + 1;
+        let (status, next_attempt_at, delivered_at) = match &error {
+            None => (WebhookDeliveryStatus::Succeeded, None, Some(now)),
+            Some(_) if attempts >= self.max_attempts => (WebhookDeliveryStatus::Failed, None, None),
+            Some(_) => (
+                WebhookDeliveryStatus::Pending,
+                Some(now + backoff(attempts)),
+                None,
+            ),
+        };
+        debug!(
+            "Webhook delivery {} to {} attempt {}: {:?}",
+            delivery.id, subscription.url, attempts, status
+        );
+        let attempted = WebhookDelivery {
+            status,
+            attempts,
+            next_attempt_at,
+            last_response_status: response_status.map(|status| status.as_u16() as i64),
+            last_error: error,
+            delivered_at,
+            updated_at: now,
+            ..delivery.clone()
+        };
+        self.database.webhooks().save_delivery(&attempted).await?;
+        Ok(())
+    }
+
+    async fn send(
+        &self,
+        subscription: &WebhookSubscription,
+        delivery: &WebhookDelivery,
+    ) -> Result<reqwest::Response> {
+        let url = reqwest::Url::parse(&subscription.url)?;
+        check_address(&url)?;
+        let response = self
+            .http
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(
+                SIGNATURE_HEADER,
+                sign(&subscription.secret, &delivery.payload),
+            )
+            .header(EVENT_HEADER, delivery.event_type.name())
+            .header(DELIVERY_HEADER, delivery.id.to_string())
+            .body(delivery.payload.clone())
+            .send()
+            .await?;
+        Ok(response)
+    }
+}
@@ -0,0 +1,466 @@
+mod common;
+
+use std::time::Duration as StdDuration;
+
+use chrono::{Duration, Utc};
+use common::{TestApp, data, error, error_code, id};
+use rocket::http::Status;
+use serde_json::{Value, json};
+use sh_backend::auth::Identity;
+use sh_backend::models::Role;
+use sh_backend::retention::RetentionJob;
+
+const GRANT: &str = r#"
+    mutation($userId: Int!, $siteId: Int!, $role: Role!) {
+        grantSiteRole(input: { userId: $userId, siteId: $siteId, role: $role }) { userId siteId role }
+    }
+"#;
+
+const SET_POLICY: &str = r#"
+    mutation($input: RetentionPolicyInput!) {
+        setRetentionPolicy(input: $input) { id siteId deviceType rawRetentionDays hourlyRetentionDays dailyRetentionDays }
+    }
+"#;
+
+fn policy_input(site_id: Option<i64>, raw: i64, hourly: Option<i64>, daily: Option<i64>) -> Value {
+    json!({ "input": {
+        "siteId": site_id,
+        "rawRetentionDays": raw,
+        "hourlyRetentionDays": hourly,
+        "dailyRetentionDays": daily,
+    } })
+}
+
+#[tokio::test]
+async fn created_users_authenticate_with_their_token() {
+    let app = TestApp::new().await;
+    let create = r#"
+        mutation($name: String!) { createUser(input: { name: $name }) { token user { id name isAdmin } } }
+    "#;
+    let data = app.admin(create, json!({ "name": "Alice" })).await;
+    let created = &data["createUser"];
+    assert_eq!(created["user"]["name"], "Alice");
+    assert_eq!(created["user"]["isAdmin"], false);
+
+    let token = created["token"].as_str().unwrap();
+    let (status, body) = app.post(Some(token), "{ me { id name } }", json!({})).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(
+        body["data"]["me"],
+        json!({ "id": id(&created["user"]), "name": "Alice" })
+    );
+
+    let (user, _) = app.create_user("Bob").await;
+    let response = app.as_user(&user, create, json!({ "name": "Eve" })).await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+}
+
+#[tokio::test]
+async fn site_roles_are_granted_updated_and_revoked() {
+    let app = TestApp::new().await;
+    let site_id = app.create_site("Site").await;
+    let (user, _) = app.create_user("Alice").await;
+    let vars = |role: &str| json!({ "userId": user.id, "siteId": site_id, "role": role });
+
+    let data = app.admin(GRANT, vars("VIEWER")).await;
+    assert_eq!(
+        data["grantSiteRole"],
+        json!({ "userId": user.id, "siteId": site_id, "role": "VIEWER" })
+    );
+    let data = app.admin(GRANT, vars("OPERATOR")).await;
+    assert_eq!(data["grantSiteRole"]["role"], "OPERATOR");
+
+    let roles = "query($siteId: Int!) { siteRoles(siteId: $siteId) { userId role } }";
+    let data = app.admin(roles, json!({ "siteId": site_id })).await;
+    assert_eq!(
+        data["siteRoles"],
+        json!([{ "userId": user.id, "role": "OPERATOR" }])
+    );
+
+    let revoke = "mutation($userId: Int!, $siteId: Int!) { revokeSiteRole(userId: $userId, siteId: $siteId) }";
+    let revoke_vars = json!({ "userId": user.id, "siteId": site_id });
+    let data = app.admin(revoke, revoke_vars.clone()).await;
+    assert_eq!(data["revokeSiteRole"], true);
+    let data = app.admin(revoke, revoke_vars).await;
+    assert_eq!(data["revokeSiteRole"], false);
+    let data = app.admin(roles, json!({ "siteId": site_id })).await;
+    assert_eq!(data["siteRoles"], json!([]));
+}
+
+#[tokio::test]
+async fn site_roles_are_managed_by_site_admins_only() {
+    let app = TestApp::new().await;
+    let site_id = app.create_site("Site").await;
+    let other_site = app.create_site("Other").await;
+    let (site_admin, _) = app.create_user("Site admin").await;
+    app.grant(&site_admin, site_id, Role::Admin).await;
+    let (user, _) = app.create_user("Alice").await;
+
+    let response = app
+        .as_user(
+            &site_admin,
+            GRANT,
+            json!({ "userId": user.id, "siteId": site_id, "role": "VIEWER" }),
+        )
+        .await;
+    assert_eq!(data(response)["grantSiteRole"]["role"], "VIEWER");
+
+    let response = app
+        .as_user(
+            &site_admin,
+            GRANT,
+            json!({ "userId": user.id, "siteId": other_site, "role": "VIEWER" }),
+        )
+        .await;
+    assert_eq!(
+        error(response),
+        format!("Admin rights on site {} required", other_site)
+    );
+}
+
+#[tokio::test]
+async fn device_credentials_are_created_rotated_and_revoked() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let create = r#"
+        mutation($deviceId: Int!) { createDeviceCredential(deviceId: $deviceId) { key credential { id keyPrefix revokedAt } } }
+    "#;
+    let data = app
+        .admin(create, json!({ "deviceId": fixture.device_id }))
+        .await;
+    let created = &data["createDeviceCredential"];
+    let key = created["key"].as_str().unwrap();
+    assert!(key.starts_with("shd_"));
+    assert!(key.starts_with(created["credential"]["keyPrefix"].as_str().unwrap()));
+    let credential_id = id(&created["credential"]);
+
+    let rotate = r#"
+        mutation($id: Int!) { rotateDeviceCredential(id: $id) { key credential { id } } }
+    "#;
+    let data = app.admin(rotate, json!({ "id": credential_id })).await;
+    let rotated = &data["rotateDeviceCredential"];
+    assert_ne!(rotated["key"].as_str().unwrap(), key);
+    let rotated_id = id(&rotated["credential"]);
+
+    // The old key stops working as soon as it is rotated.
+    let (status, _) = app.post(Some(key), "{ me { id } }", json!({})).await;
+    assert_eq!(status, Status::Unauthorized);
+
+    let response = app
+        .execute(
+            Identity::User(app.admin.clone()),
+            rotate,
+            json!({ "id": credential_id }),
+        )
+        .await;
+    assert_eq!(
+        error(response),
+        format!(
+            "Device credential with ID {} is already revoked",
+            credential_id
+        )
+    );
+
+    let revoke = "mutation($id: Int!) { revokeDeviceCredential(id: $id) { revokedAt } }";
+    let data = app.admin(revoke, json!({ "id": rotated_id })).await;
+    assert!(data["revokeDeviceCredential"]["revokedAt"].is_string());
+
+    let credentials =
+        "query($deviceId: Int!) { deviceCredentials(deviceId: $deviceId) { id revokedAt } }";
+    let data = app
+        .admin(credentials, json!({ "deviceId": fixture.device_id }))
+        .await;
+    let listed = data["deviceCredentials"].as_array().unwrap();
+    assert_eq!(listed.len(), 2);
+    assert!(
+        listed
+            .iter()
+            .all(|credential| credential["revokedAt"].is_string())
+    );
+}
+
+#[tokio::test]
+async fn device_credentials_need_admin_rights_on_the_site() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let (operator, _) = app.create_user("Operator").await;
+    app.grant(&operator, fixture.site_id, Role::Operator).await;
+
+    let response = app
+        .as_user(
+            &operator,
+            "mutation($deviceId: Int!) { createDeviceCredential(deviceId: $deviceId) { key } }",
+            json!({ "deviceId": fixture.device_id }),
+        )
+        .await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+
+    let response = app
+        .as_user(
+            &operator,
+            "mutation { revokeDeviceCredential(id: 404) { id } }",
+            json!({}),
+        )
+        .await;
+    assert_eq!(
+        error(response),
+        "Device credential with ID 404 does not exist"
+    );
+
+    let data = app
+        .admin("{ deviceCredentials(deviceId: 404) { id } }", json!({}))
+        .await;
+    assert_eq!(data["deviceCredentials"], json!([]));
+}
+
+#[tokio::test]
+async fn retention_policies_are_upserted_per_scope() {
+    let app = TestApp::new().await;
+    let site_id = app.create_site("Site").await;
+
+    let data = app
+        .admin(SET_POLICY, policy_input(Some(site_id), 7, Some(90), None))
+        .await;
+    let policy_id = id(&data["setRetentionPolicy"]);
+    let data = app
+        .admin(
+            SET_POLICY,
+            policy_input(Some(site_id), 14, Some(90), Some(730)),
+        )
+        .await;
+    assert_eq!(
+        data["setRetentionPolicy"],
+        json!({
+            "id": policy_id,
+            "siteId": site_id,
+            "deviceType": null,
+            "rawRetentionDays": 14,
+            "hourlyRetentionDays": 90,
+            "dailyRetentionDays": 730,
+        })
+    );
+    app.admin(SET_POLICY, policy_input(None, 30, None, None))
+        .await;
+
+    let policies = "query($siteId: Int) { retentionPolicies(siteId: $siteId) { id } }";
+    let data = app.admin(policies, json!({ "siteId": site_id })).await;
+    assert_eq!(data["retentionPolicies"], json!([{ "id": policy_id }]));
+    let data = app.admin(policies, json!({})).await;
+    assert_eq!(data["retentionPolicies"].as_array().unwrap().len(), 2);
+
+    let delete = "mutation($id: Int!) { deleteRetentionPolicy(id: $id) }";
+    let data = app.admin(delete, json!({ "id": policy_id })).await;
+    assert_eq!(data["deleteRetentionPolicy"], true);
+    let response = app
+        .execute(
+            Identity::User(app.admin.clone()),
+            delete,
+            json!({ "id": policy_id }),
+        )
+        .await;
+    assert_eq!(
+        error(response),
+        format!("Retention policy with ID {} does not exist", policy_id)
+    );
+}
+
+#[tokio::test]
+async fn retention_policies_are_validated() {
+    let app = TestApp::new().await;
+    let cases = [
+        (
+            policy_input(None, 0, None, None),
+            "Raw readings have to be kept for at least a day",
+        ),
+        (
+            policy_input(None, 7, Some(3), None),
+            "Hourly rollups cannot be kept for less time than raw readings",
+        ),
+        (
+            policy_input(None, 7, Some(90), Some(30)),
+            "Daily rollups cannot be kept for less time than hourly rollups",
+        ),
+        (
+            policy_input(None, 7, None, Some(30)),
+            "Daily rollups cannot be pruned while hourly rollups are kept forever",
+        ),
+    ];
+    for (input, message) in cases {
+        let response = app
+            .execute(Identity::User(app.admin.clone()), SET_POLICY, input)
+            .await;
+        assert_eq!(error(response), message);
+    }
+}
+
+#[tokio::test]
+async fn pruned_readings_are_read_from_numeric_rollups() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let day = (Utc::now() - Duration::days(3)).format("%Y-%m-%d");
+    for (time, value) in [
+        ("10:00:10", "20"),
+        ("10:00:40", "22"),
+        ("10:30:00", "open"),
+        ("11:00:00", "open"),
+    ] {
+        sqlx::query("INSERT INTO SensorReading (device_id, value, timestamp) VALUES (?, ?, ?)")
+            .bind(fixture.device_id)
+            .bind(value)
+            .bind(format!("{} {}", day, time))
+            .execute(app.pool())
+            .await
+            .unwrap();
+    }
+    let reading_id = app.create_reading(fixture.device_id, "21.5").await;
+    app.create_reading(fixture.device_id, "open").await;
+    app.admin(
+        SET_POLICY,
+        policy_input(Some(fixture.site_id), 1, None, None),
+    )
+    .await;
+
+    let job = RetentionJob::new(app.database.clone(), StdDuration::from_secs(3600));
+    assert_eq!(job.run(Utc::now()).await.unwrap(), 4);
+
+    let readings = r#"
+        query($id: Int!, $limit: Int!) {
+            site(id: $id) {
+                rooms {
+                    devices {
+                        readingSamples(limit: $limit) { id resolution value sampleCount minValue maxValue avgValue }
+                    }
+                }
+            }
+        }
+    "#;
+    let data = app
+        .admin(readings, json!({ "id": fixture.site_id, "limit": 10 }))
+        .await;
+    assert_eq!(
+        data["site"]["rooms"][0]["devices"][0]["readingSamples"],
+        json!([
+            {
+                "id": null, "resolution": "HOURLY", "value": "21.0", "sampleCount": 2,
+                "minValue": 20.0, "maxValue": 22.0, "avgValue": 21.0,
+            },
+            {
+                "id": reading_id, "resolution": "RAW", "value": "21.5", "sampleCount": 1,
+                "minValue": 21.5, "maxValue": 21.5, "avgValue": 21.5,
+            },
+        ])
+    );
+
+    let data = app
+        .admin(readings, json!({ "id": fixture.site_id, "limit": 1 }))
+        .await;
+    assert_eq!(
+        data["site"]["rooms"][0]["devices"][0]["readingSamples"][0]["value"],
+        "21.5"
+    );
+
+    // The raw readings left hold what wasn't rolled up, numbers or not.
+    let data = app
+        .admin(
+            r#"
+            query($id: Int!) {
+                site(id: $id) { rooms { devices { all: sensorReadings { value } latest: sensorReadings(limit: 1) { value } } } }
+            }
+            "#,
+            json!({ "id": fixture.site_id }),
+        )
+        .await;
+    assert_eq!(
+        data["site"]["rooms"][0]["devices"][0],
+        json!({
+            "all": [{ "value": "21.5" }, { "value": "open" }],
+            "latest": [{ "value": "open" }],
+        })
+    );
+}
+
+#[tokio::test]
+async fn audit_logs_are_filtered_by_site_and_entity() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let other_site = app.create_site("Other").await;
+    app.create_reading(fixture.device_id, "21.5").await;
+
+    let logs = r#"
+        query($filter: AuditLogFilter!) {
+            auditLogs(filter: $filter) { operation siteId entityType entityId actorType actorName arguments }
+        }
+    "#;
+    let data = app
+        .admin(
+            logs,
+            json!({ "filter": { "siteId": fixture.site_id, "entityType": "DEVICE" } }),
+        )
+        .await;
+    assert_eq!(
+        data["auditLogs"],
+        json!([{
+            "operation": "createDevice",
+            "siteId": fixture.site_id,
+            "entityType": "DEVICE",
+            "entityId": fixture.device_id,
+            "actorType": "USER",
+            "actorName": "Admin",
+            "arguments": {
+                "room_id": fixture.room_id,
+                "name": "sensor-1",
+                "device_type": "TemperatureSensor",
+                "unique_identifier": "sensor-1",
+            },
+        }])
+    );
+
+    let data = app
+        .admin(logs, json!({ "filter": { "siteId": fixture.site_id } }))
+        .await;
+    let operations: Vec<_> = data["auditLogs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["operation"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        operations,
+        [
+            "createSensorReading",
+            "createDevice",
+            "createRoom",
+            "createSite"
+        ]
+    );
+
+    let data = app
+        .admin(logs, json!({ "filter": { "siteId": other_site } }))
+        .await;
+    assert_eq!(data["auditLogs"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn audit_logs_of_every_site_need_an_administrator() {
+    let app = TestApp::new().await;
+    let site_id = app.create_site("Site").await;
+    let (site_admin, _) = app.create_user("Site admin").await;
+    app.grant(&site_admin, site_id, Role::Admin).await;
+
+    let response = app
+        .as_user(&site_admin, "{ auditLogs { id } }", json!({}))
+        .await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+
+    let response = app
+        .as_user(
+            &site_admin,
+            "query($siteId: Int!) { auditLogs(filter: { siteId: $siteId }, limit: 1) { operation } }",
+            json!({ "siteId": site_id }),
+        )
+        .await;
+    assert_eq!(
+        data(response)["auditLogs"],
+        json!([{ "operation": "createSite" }])
+    );
+}
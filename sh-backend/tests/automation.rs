@@ -0,0 +1,349 @@
+mod common;
+
+use common::{TestApp, data, error, error_code, id};
+use serde_json::{Value, json};
+use sh_backend::auth::Identity;
+use sh_backend::models::{AlertSeverity, Role, RuleExecutionStatus};
+
+const CREATE_RULE: &str = r#"
+    mutation($input: AutomationRuleInput!) {
+        createAutomationRule(input: $input) { id name enabled trigger conditions actions }
+    }
+"#;
+
+fn rule_input(site_id: i64, device_id: i64) -> Value {
+    json!({ "input": {
+        "siteId": site_id,
+        "name": "Too hot",
+        "trigger": { "type": "sensor_threshold", "device_id": device_id, "comparison": "above", "value": 25.0 },
+        "actions": [{ "type": "raise_alert", "severity": "Warning", "message": "It is too hot" }],
+    } })
+}
+
+async fn insert_alert(app: &TestApp, site_id: i64, message: &str) -> i64 {
+    sqlx::query_scalar::<_, i64>(
+        "INSERT INTO Alert (site_id, severity, message) VALUES (?, ?, ?) RETURNING id",
+    )
+    .bind(site_id)
+    .bind(AlertSeverity::Warning)
+    .bind(message)
+    .fetch_one(app.pool())
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn create_and_list_automation_rules() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let data = app
+        .admin(CREATE_RULE, rule_input(fixture.site_id, fixture.device_id))
+        .await;
+    let rule = &data["createAutomationRule"];
+    assert_eq!(rule["name"], "Too hot");
+    assert_eq!(rule["enabled"], true);
+    assert_eq!(rule["conditions"], json!([]));
+    assert_eq!(rule["trigger"]["device_id"], fixture.device_id);
+
+    let (viewer, _) = app.create_user("Viewer").await;
+    app.grant(&viewer, fixture.site_id, Role::Viewer).await;
+    let response = app
+        .as_user(
+            &viewer,
+            "query($siteId: Int!) { automationRules(siteId: $siteId) { id name } }",
+            json!({ "siteId": fixture.site_id }),
+        )
+        .await;
+    assert_eq!(
+        common::data(response)["automationRules"],
+        json!([{ "id": id(rule), "name": "Too hot" }])
+    );
+}
+
+#[tokio::test]
+async fn create_automation_rule_validates_the_rule() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let other_site = app.create_site("Other").await;
+    let admin = || Identity::User(app.admin.clone());
+
+    let mut input = rule_input(fixture.site_id, fixture.device_id);
+    input["input"]["actions"] = json!([]);
+    let response = app.execute(admin(), CREATE_RULE, input).await;
+    assert_eq!(error(response), "Rules need at least one action");
+
+    let response = app
+        .execute(
+            admin(),
+            CREATE_RULE,
+            rule_input(other_site, fixture.device_id),
+        )
+        .await;
+    assert_eq!(
+        error(response),
+        format!(
+            "Device with ID {} does not belong to site {}",
+            fixture.device_id, other_site
+        )
+    );
+
+    let mut input = rule_input(fixture.site_id, fixture.device_id);
+    input["input"]["actions"] = json!([{ "type": "create_setpoint", "device_id": fixture.device_id, "value": "20", "mode": "Override" }]);
+    let response = app.execute(admin(), CREATE_RULE, input).await;
+    assert_eq!(
+        error(response),
+        "Override setpoints need a positive duration_minutes"
+    );
+}
+
+#[tokio::test]
+async fn create_automation_rule_requires_admin_rights_on_the_site() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let (operator, _) = app.create_user("Operator").await;
+    app.grant(&operator, fixture.site_id, Role::Operator).await;
+
+    let response = app
+        .as_user(
+            &operator,
+            CREATE_RULE,
+            rule_input(fixture.site_id, fixture.device_id),
+        )
+        .await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+}
+
+#[tokio::test]
+async fn rules_of_other_sites_cannot_be_probed() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let data = app
+        .admin(CREATE_RULE, rule_input(fixture.site_id, fixture.device_id))
+        .await;
+    let rule_id = id(&data["createAutomationRule"]);
+    let (stranger, _) = app.create_user("Stranger").await;
+
+    let delete = "mutation($id: Int!) { deleteAutomationRule(id: $id) }";
+    for id in [rule_id, 404] {
+        let response = app.as_user(&stranger, delete, json!({ "id": id })).await;
+        assert_eq!(error_code(response), "FORBIDDEN");
+    }
+    let existing = app
+        .as_user(&stranger, delete, json!({ "id": rule_id }))
+        .await;
+    let missing = app.as_user(&stranger, delete, json!({ "id": 404 })).await;
+    assert_eq!(
+        error(existing),
+        error(missing).replace("404", &rule_id.to_string())
+    );
+}
+
+#[tokio::test]
+async fn call_webhook_actions_cannot_point_to_private_addresses() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let mut input = rule_input(fixture.site_id, fixture.device_id);
+    input["input"]["actions"] =
+        json!([{ "type": "call_webhook", "url": "http://169.254.169.254/latest" }]);
+    let response = app
+        .execute(Identity::User(app.admin.clone()), CREATE_RULE, input)
+        .await;
+    assert_eq!(
+        error(response),
+        "Webhook URLs cannot point to private, loopback or link-local addresses"
+    );
+}
+
+#[tokio::test]
+async fn update_and_delete_automation_rules() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let data = app
+        .admin(CREATE_RULE, rule_input(fixture.site_id, fixture.device_id))
+        .await;
+    let rule_id = id(&data["createAutomationRule"]);
+
+    let mut input = rule_input(fixture.site_id, fixture.device_id);
+    input["id"] = json!(rule_id);
+    input["input"]["name"] = json!("Far too hot");
+    input["input"]["enabled"] = json!(false);
+    let data = app
+        .admin(
+            r#"
+            mutation($id: Int!, $input: AutomationRuleInput!) {
+                updateAutomationRule(id: $id, input: $input) { name enabled }
+            }
+            "#,
+            input,
+        )
+        .await;
+    assert_eq!(
+        data["updateAutomationRule"],
+        json!({ "name": "Far too hot", "enabled": false })
+    );
+
+    let data = app
+        .admin(
+            "mutation($id: Int!) { deleteAutomationRule(id: $id) }",
+            json!({ "id": rule_id }),
+        )
+        .await;
+    assert_eq!(data["deleteAutomationRule"], true);
+    let data = app
+        .admin(
+            "query($siteId: Int!) { automationRules(siteId: $siteId) { id } }",
+            json!({ "siteId": fixture.site_id }),
+        )
+        .await;
+    assert_eq!(data["automationRules"], json!([]));
+}
+
+#[tokio::test]
+async fn rules_cannot_be_moved_or_found_when_missing() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let other_site = app.create_site("Other").await;
+    let data = app
+        .admin(CREATE_RULE, rule_input(fixture.site_id, fixture.device_id))
+        .await;
+    let rule_id = id(&data["createAutomationRule"]);
+    let update = r#"
+        mutation($id: Int!, $input: AutomationRuleInput!) { updateAutomationRule(id: $id, input: $input) { id } }
+    "#;
+    let admin = || Identity::User(app.admin.clone());
+
+    let mut input = rule_input(other_site, fixture.device_id);
+    input["id"] = json!(rule_id);
+    let response = app.execute(admin(), update, input).await;
+    assert_eq!(error(response), "Rules cannot be moved to another site");
+
+    let mut input = rule_input(fixture.site_id, fixture.device_id);
+    input["id"] = json!(404);
+    let response = app.execute(admin(), update, input).await;
+    assert_eq!(
+        error(response),
+        "Automation rule with ID 404 does not exist"
+    );
+
+    let response = app
+        .execute(
+            admin(),
+            "mutation { deleteAutomationRule(id: 404) }",
+            json!({}),
+        )
+        .await;
+    assert_eq!(
+        error(response),
+        "Automation rule with ID 404 does not exist"
+    );
+}
+
+#[tokio::test]
+async fn rule_executions_are_listed_newest_first() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let data = app
+        .admin(CREATE_RULE, rule_input(fixture.site_id, fixture.device_id))
+        .await;
+    let rule_id = id(&data["createAutomationRule"]);
+    for (status, message) in [
+        (RuleExecutionStatus::Skipped, "first"),
+        (RuleExecutionStatus::Succeeded, "second"),
+    ] {
+        sqlx::query(
+            "INSERT INTO RuleExecution (rule_id, status, event, message) VALUES (?, ?, '{}', ?)",
+        )
+        .bind(rule_id)
+        .bind(status)
+        .bind(message)
+        .execute(app.pool())
+        .await
+        .unwrap();
+    }
+
+    let data = app
+        .admin(
+            "query($ruleId: Int!) { ruleExecutions(ruleId: $ruleId, limit: 1) { status message event } }",
+            json!({ "ruleId": rule_id }),
+        )
+        .await;
+    assert_eq!(
+        data["ruleExecutions"],
+        json!([{ "status": "SUCCEEDED", "message": "second", "event": {} }])
+    );
+
+    let response = app
+        .execute(
+            Identity::User(app.admin.clone()),
+            "{ ruleExecutions(ruleId: 404) { id } }",
+            json!({}),
+        )
+        .await;
+    assert_eq!(
+        error(response),
+        "Automation rule with ID 404 does not exist"
+    );
+}
+
+#[tokio::test]
+async fn alerts_are_acknowledged_by_operators() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let alert_id = insert_alert(&app, fixture.site_id, "It is too hot").await;
+    let (operator, _) = app.create_user("Operator").await;
+    app.grant(&operator, fixture.site_id, Role::Operator).await;
+
+    let alerts = "query($siteId: Int!, $all: Boolean!) { alerts(siteId: $siteId, includeAcknowledged: $all) { id message acknowledgedAt } }";
+    let response = app
+        .as_user(
+            &operator,
+            alerts,
+            json!({ "siteId": fixture.site_id, "all": false }),
+        )
+        .await;
+    let listed = common::data(response);
+    assert_eq!(listed["alerts"][0]["id"], alert_id);
+    assert_eq!(listed["alerts"][0]["acknowledgedAt"], json!(null));
+
+    let response = app
+        .as_user(
+            &operator,
+            "mutation($id: Int!) { acknowledgeAlert(id: $id) { acknowledgedAt } }",
+            json!({ "id": alert_id }),
+        )
+        .await;
+    assert!(data(response)["acknowledgeAlert"]["acknowledgedAt"].is_string());
+
+    let open = app
+        .admin(alerts, json!({ "siteId": fixture.site_id, "all": false }))
+        .await;
+    assert_eq!(open["alerts"], json!([]));
+    let all = app
+        .admin(alerts, json!({ "siteId": fixture.site_id, "all": true }))
+        .await;
+    assert_eq!(all["alerts"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn acknowledge_alert_checks_the_alert_and_the_role() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let alert_id = insert_alert(&app, fixture.site_id, "It is too hot").await;
+    let (viewer, _) = app.create_user("Viewer").await;
+    app.grant(&viewer, fixture.site_id, Role::Viewer).await;
+    let acknowledge = "mutation($id: Int!) { acknowledgeAlert(id: $id) { id } }";
+
+    let response = app
+        .as_user(&viewer, acknowledge, json!({ "id": alert_id }))
+        .await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+
+    let response = app
+        .execute(
+            Identity::User(app.admin.clone()),
+            acknowledge,
+            json!({ "id": 404 }),
+        )
+        .await;
+    assert_eq!(error(response), "Alert with ID 404 does not exist");
+}
@@ -0,0 +1,349 @@
+//! The API on a fresh in-memory SQLite database with every migration applied,
+//! reachable through the schema directly or through Rocket's local client.
+//! [`TestApp::postgres`] runs it on PostgreSQL instead, when `TEST_POSTGRES_URL` is set.
+
+// Every test binary compiles this module, but none of them uses all of it.
+#![allow(dead_code)]
+
+use async_graphql::{Request, Response, Variables};
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::Client;
+use serde_json::{Value, json};
+use sh_backend::auth::{Identity, generate_token, hash_token};
+use sh_backend::config::Config;
+use sh_backend::db::Database;
+use sh_backend::events::EventBus;
+use sh_backend::health::Health;
+use sh_backend::metrics::Metrics;
+use sh_backend::migrations::run_migrations;
+use sh_backend::models::{Role, SiteRoleInput, User};
+use sh_backend::schema::AppSchema;
+use sh_backend::server::{build_rocket, build_schema};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::{Connection, Executor, PgConnection, SqlitePool};
+use std::str::FromStr;
+
+/// A site with a room holding a temperature sensor.
+pub struct Fixture {
+    pub site_id: i64,
+    pub room_id: i64,
+    pub device_id: i64,
+}
+
+pub struct TestApp {
+    pub database: Database,
+    pub events: EventBus,
+    pub schema: AppSchema,
+    pub client: Client,
+    pub admin: User,
+    pub admin_token: String,
+}
+
+impl TestApp {
+    pub async fn new() -> Self {
+        Self::with_config(Config::default()).await
+    }
+
+    pub async fn with_config(config: Config) -> Self {
+        // Every connection to `sqlite::memory:` opens a database of its own,
+        // so the pool keeps exactly one connection open for the whole test.
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect("sqlite::memory:")
+            .await
+            .expect("Failed to open the in-memory database");
+        Self::with_database(Database::Sqlite(pool), config).await
+    }
+
+    /// The API on a schema of its own in the database at `TEST_POSTGRES_URL`,
+    /// or `None` when the variable isn't set.
+    pub async fn postgres() -> Option<Self> {
+        let url = std::env::var("TEST_POSTGRES_URL").ok()?;
+        let options = PgConnectOptions::from_str(&url).expect("Invalid TEST_POSTGRES_URL");
+        let schema = format!("test_{}", &generate_token()[..16]);
+        let mut connection = PgConnection::connect_with(&options)
+            .await
+            .expect("Failed to connect to TEST_POSTGRES_URL");
+        // Extensions belong to the database rather than a schema, the tests share the one in `public`.
+        // The lock keeps tests starting together from racing to create it.
+        let mut tx = connection.begin().await.unwrap();
+        tx.execute("SELECT pg_advisory_xact_lock(4242)")
+            .await
+            .unwrap();
+        tx.execute("CREATE EXTENSION IF NOT EXISTS unaccent SCHEMA public")
+            .await
+            .expect("Failed to create the unaccent extension");
+        tx.execute(format!("CREATE SCHEMA {}", schema).as_str())
+            .await
+            .expect("Failed to create the schema");
+        tx.commit().await.unwrap();
+
+        let pool = PgPoolOptions::new()
+            .max_connections(4)
+            .connect_with(options.options([("search_path", format!("{},public", schema))]))
+            .await
+            .expect("Failed to connect to TEST_POSTGRES_URL");
+        Some(Self::with_database(Database::Postgres(pool), Config::default()).await)
+    }
+
+    async fn with_database(database: Database, config: Config) -> Self {
+        run_migrations(&database)
+            .await
+            .expect("Failed to apply the migrations");
+
+        let events = EventBus::new();
+        let metrics = Metrics::new().expect("Failed to register the metrics");
+        let schema = build_schema(database.clone(), events.clone(), config.clone(), &metrics)
+            .expect("Failed to build the schema");
+        let rocket = build_rocket(
+            database.clone(),
+            events.clone(),
+            config,
+            metrics,
+            Health::new(Vec::new()),
+        )
+        .expect("Failed to build the server");
+        let client = Client::tracked(rocket)
+            .await
+            .expect("Failed to build the Rocket client");
+
+        let (admin, admin_token) = insert_user(&database, "Admin", true).await;
+        Self {
+            database,
+            events,
+            schema,
+            client,
+            admin,
+            admin_token,
+        }
+    }
+
+    /// The SQLite pool, for tests that set up rows the API has no way to.
+    pub fn pool(&self) -> &SqlitePool {
+        match &self.database {
+            Database::Sqlite(pool) => pool,
+            Database::Postgres(_) => panic!("The test app runs on PostgreSQL"),
+        }
+    }
+
+    /// Runs an operation against the schema as the given caller.
+    pub async fn execute(&self, identity: Identity, query: &str, variables: Value) -> Response {
+        let request = Request::new(query)
+            .variables(Variables::from_json(variables))
+            .data(identity);
+        self.schema.execute(request).await
+    }
+
+    /// Runs an operation as the administrator and returns its data, failing the test on errors.
+    pub async fn admin(&self, query: &str, variables: Value) -> Value {
+        data(
+            self.execute(Identity::User(self.admin.clone()), query, variables)
+                .await,
+        )
+    }
+
+    /// Runs an operation as the given user.
+    pub async fn as_user(&self, user: &User, query: &str, variables: Value) -> Response {
+        self.execute(Identity::User(user.clone()), query, variables)
+            .await
+    }
+
+    /// Posts an operation to `/graphql` like a client would, with an optional bearer token.
+    pub async fn post(
+        &self,
+        token: Option<&str>,
+        query: &str,
+        variables: Value,
+    ) -> (Status, Value) {
+        let mut request = self
+            .client
+            .post("/graphql")
+            .header(ContentType::JSON)
+            .body(json!({ "query": query, "variables": variables }).to_string());
+        if let Some(token) = token {
+            request = request.header(Header::new("Authorization", format!("Bearer {}", token)));
+        }
+        let response = request.dispatch().await;
+        let status = response.status();
+        let body = response.into_string().await.unwrap_or_default();
+        (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+    }
+
+    /// Posts an operation with a single file bound to the `$file` variable,
+    /// following the GraphQL multipart request spec.
+    pub async fn upload(
+        &self,
+        token: &str,
+        query: &str,
+        filename: &str,
+        content: &str,
+    ) -> (Status, Value) {
+        const BOUNDARY: &str = "sh-backend-test-boundary";
+        let operations = json!({ "query": query, "variables": { "file": null } });
+        let body = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"operations\"\r\n\r\n{operations}\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"map\"\r\n\r\n{{\"0\": [\"variables.file\"]}}\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"0\"; filename=\"{filename}\"\r\n\
+             Content-Type: text/csv\r\n\r\n{content}\r\n--{b}--\r\n",
+            b = BOUNDARY,
+        );
+        let response = self
+            .client
+            .post("/graphql")
+            .header(ContentType::new("multipart", "form-data").with_params(("boundary", BOUNDARY)))
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .body(body)
+            .dispatch()
+            .await;
+        let status = response.status();
+        let body = response.into_string().await.unwrap_or_default();
+        (status, serde_json::from_str(&body).unwrap_or(Value::Null))
+    }
+
+    /// Creates a user who holds no roles, returning it with its API token.
+    pub async fn create_user(&self, name: &str) -> (User, String) {
+        insert_user(&self.database, name, false).await
+    }
+
+    pub async fn grant(&self, user: &User, site_id: i64, role: Role) {
+        let mut tx = self.database.begin().await.unwrap();
+        self.database
+            .site_roles()
+            .grant(
+                &mut tx,
+                &SiteRoleInput {
+                    user_id: user.id,
+                    site_id,
+                    role,
+                },
+            )
+            .await
+            .expect("Failed to grant the role");
+        tx.commit().await.unwrap();
+    }
+
+    pub async fn create_site(&self, name: &str) -> i64 {
+        let data = self
+            .admin(
+                "mutation($name: String!) { createSite(input: { name: $name }) { id } }",
+                json!({ "name": name }),
+            )
+            .await;
+        id(&data["createSite"])
+    }
+
+    pub async fn create_room(&self, site_id: i64, name: &str) -> i64 {
+        let data = self
+            .admin(
+                "mutation($siteId: Int!, $name: String!) { createRoom(input: { siteId: $siteId, name: $name }) { id } }",
+                json!({ "siteId": site_id, "name": name }),
+            )
+            .await;
+        id(&data["createRoom"])
+    }
+
+    pub async fn create_device(&self, room_id: i64, name: &str) -> i64 {
+        let data = self
+            .admin(
+                r#"
+                mutation($roomId: Int!, $name: String!) {
+                    createDevice(input: { roomId: $roomId, name: $name, deviceType: TEMPERATURE_SENSOR, uniqueIdentifier: $name }) { id }
+                }
+                "#,
+                json!({ "roomId": room_id, "name": name }),
+            )
+            .await;
+        id(&data["createDevice"])
+    }
+
+    pub async fn create_reading(&self, device_id: i64, value: &str) -> i64 {
+        let data = self
+            .admin(
+                r#"
+                mutation($deviceId: Int!, $value: String!) {
+                    createSensorReading(input: { deviceId: $deviceId, value: $value, unit: CELSIUS }) { id }
+                }
+                "#,
+                json!({ "deviceId": device_id, "value": value }),
+            )
+            .await;
+        id(&data["createSensorReading"])
+    }
+
+    pub async fn fixture(&self) -> Fixture {
+        let site_id = self.create_site("Site").await;
+        let room_id = self.create_room(site_id, "Room").await;
+        let device_id = self.create_device(room_id, "sensor-1").await;
+        Fixture {
+            site_id,
+            room_id,
+            device_id,
+        }
+    }
+}
+
+async fn insert_user(database: &Database, name: &str, is_admin: bool) -> (User, String) {
+    let token = generate_token();
+    let mut tx = database.begin().await.unwrap();
+    let user = database
+        .users()
+        .create(&mut tx, name, &hash_token(&token), is_admin)
+        .await
+        .expect("Failed to create the user");
+    tx.commit().await.unwrap();
+    (user, token)
+}
+
+/// The data of a response, failing the test if it has errors.
+pub fn data(response: Response) -> Value {
+    assert!(
+        response.errors.is_empty(),
+        "Unexpected errors: {:?}",
+        response.errors
+    );
+    response.data.into_json().expect("Invalid response data")
+}
+
+/// The message of the only error of a response.
+pub fn error(response: Response) -> String {
+    assert_eq!(
+        response.errors.len(),
+        1,
+        "Expected exactly one error, got {:?}",
+        response.errors
+    );
+    response.errors[0].message.clone()
+}
+
+/// The `code` extension of the only error of a response, such as `FORBIDDEN`.
+pub fn error_code(response: Response) -> String {
+    let response = response.into_result().expect_err("Expected an error");
+    let extensions = response[0]
+        .extensions
+        .as_ref()
+        .expect("The error has no extensions");
+    match extensions.get("code") {
+        Some(async_graphql::Value::String(code)) => code.clone(),
+        other => panic!("Unexpected error code {:?}", other),
+    }
+}
+
+pub fn id(value: &Value) -> i64 {
+    value["id"].as_i64().expect("The value has no ID")
+}
+
+/// Percent-encodes a value for the query string.
+pub fn urlencode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
@@ -0,0 +1,245 @@
+mod common;
+
+use std::io::Read;
+
+use common::{TestApp, urlencode};
+use rocket::http::{ContentType, Header, Status};
+use serde_json::{Value, json};
+use sh_backend::config::Config;
+
+const DASHBOARD: &str = "http://localhost:5173";
+
+/// Large enough to be compressed.
+const TYPES: &str = "{ __schema { types { name description } } }";
+
+fn with_dashboard() -> Config {
+    let mut config = Config::default();
+    config.cors.allowed_origins = vec![DASHBOARD.into()];
+    config
+}
+
+fn graphql_uri(query: &str) -> String {
+    format!("/graphql?query={}", urlencode(query))
+}
+
+#[tokio::test]
+async fn allowed_origins_pass_the_preflight() {
+    let app = TestApp::with_config(with_dashboard()).await;
+    let response = app
+        .client
+        .options("/graphql")
+        .header(Header::new("Origin", DASHBOARD))
+        .header(Header::new("Access-Control-Request-Method", "POST"))
+        .header(Header::new(
+            "Access-Control-Request-Headers",
+            "authorization, content-type",
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::NoContent);
+    let headers = response.headers();
+    assert_eq!(
+        headers.get_one("Access-Control-Allow-Origin"),
+        Some(DASHBOARD)
+    );
+    assert_eq!(
+        headers.get_one("Access-Control-Allow-Methods"),
+        Some("GET, POST")
+    );
+    assert_eq!(
+        headers.get_one("Access-Control-Allow-Headers"),
+        Some("Authorization, Content-Type, If-None-Match")
+    );
+}
+
+#[tokio::test]
+async fn other_origins_are_not_allowed() {
+    let app = TestApp::with_config(with_dashboard()).await;
+    let preflight = app
+        .client
+        .options("/graphql")
+        .header(Header::new("Origin", "https://evil.example.com"))
+        .header(Header::new("Access-Control-Request-Method", "POST"))
+        .dispatch()
+        .await;
+    assert!(!preflight.headers().contains("Access-Control-Allow-Origin"));
+
+    let response = app
+        .client
+        .post("/graphql")
+        .header(ContentType::JSON)
+        .header(Header::new("Origin", "https://evil.example.com"))
+        .body(json!({ "query": "{ __typename }" }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    assert!(!response.headers().contains("Access-Control-Allow-Origin"));
+}
+
+#[tokio::test]
+async fn requests_from_allowed_origins_can_read_the_response() {
+    let app = TestApp::with_config(with_dashboard()).await;
+    let response = app
+        .client
+        .post("/graphql")
+        .header(ContentType::JSON)
+        .header(Header::new("Origin", DASHBOARD))
+        .body(json!({ "query": "{ __typename }" }).to_string())
+        .dispatch()
+        .await;
+    assert_eq!(
+        response.headers().get_one("Access-Control-Allow-Origin"),
+        Some(DASHBOARD)
+    );
+    assert_eq!(
+        response.headers().get_one("Access-Control-Expose-Headers"),
+        Some("ETag")
+    );
+}
+
+#[tokio::test]
+async fn responses_are_compressed_as_the_client_prefers() {
+    let app = TestApp::new().await;
+
+    let gzip = app
+        .client
+        .get(graphql_uri(TYPES))
+        .header(Header::new("Accept-Encoding", "gzip, deflate"))
+        .dispatch()
+        .await;
+    assert_eq!(gzip.headers().get_one("Content-Encoding"), Some("gzip"));
+    let mut body = String::new();
+    flate2::read::GzDecoder::new(&gzip.into_bytes().await.unwrap()[..])
+        .read_to_string(&mut body)
+        .unwrap();
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert!(body["data"]["__schema"]["types"].is_array());
+
+    let brotli = app
+        .client
+        .get(graphql_uri(TYPES))
+        .header(Header::new("Accept-Encoding", "gzip, br"))
+        .dispatch()
+        .await;
+    assert_eq!(brotli.headers().get_one("Content-Encoding"), Some("br"));
+    let mut decompressed = String::new();
+    brotli::Decompressor::new(&brotli.into_bytes().await.unwrap()[..], 4096)
+        .read_to_string(&mut decompressed)
+        .unwrap();
+    assert_eq!(serde_json::from_str::<Value>(&decompressed).unwrap(), body);
+
+    let refused = app
+        .client
+        .get(graphql_uri(TYPES))
+        .header(Header::new("Accept-Encoding", "br;q=0, gzip;q=0.5"))
+        .dispatch()
+        .await;
+    assert_eq!(refused.headers().get_one("Content-Encoding"), Some("gzip"));
+}
+
+#[tokio::test]
+async fn small_responses_and_disabled_compression_are_sent_as_they_are() {
+    let app = TestApp::new().await;
+    let small = app
+        .client
+        .get(graphql_uri("{ __typename }"))
+        .header(Header::new("Accept-Encoding", "gzip"))
+        .dispatch()
+        .await;
+    assert!(!small.headers().contains("Content-Encoding"));
+
+    let mut config = Config::default();
+    config.http.compression = false;
+    let app = TestApp::with_config(config).await;
+    let disabled = app
+        .client
+        .get(graphql_uri(TYPES))
+        .header(Header::new("Accept-Encoding", "gzip"))
+        .dispatch()
+        .await;
+    assert!(!disabled.headers().contains("Content-Encoding"));
+}
+
+#[tokio::test]
+async fn unchanged_queries_are_not_sent_again() {
+    let app = TestApp::new().await;
+    app.fixture().await;
+    let uri = graphql_uri("{ sites { id name } }");
+    let authorization = Header::new("Authorization", format!("Bearer {}", app.admin_token));
+
+    let first = app
+        .client
+        .get(uri.clone())
+        .header(authorization.clone())
+        .dispatch()
+        .await;
+    assert_eq!(
+        first.headers().get_one("Cache-Control"),
+        Some("no-cache, private")
+    );
+    let etag = first.headers().get_one("ETag").unwrap().to_string();
+
+    let unchanged = app
+        .client
+        .get(uri.clone())
+        .header(authorization.clone())
+        .header(Header::new("If-None-Match", etag.clone()))
+        .dispatch()
+        .await;
+    assert_eq!(unchanged.status(), Status::NotModified);
+    assert_eq!(unchanged.headers().get_one("ETag"), Some(etag.as_str()));
+    assert!(unchanged.into_bytes().await.unwrap_or_default().is_empty());
+
+    app.create_site("Another site").await;
+    let changed = app
+        .client
+        .get(uri)
+        .header(authorization)
+        .header(Header::new("If-None-Match", etag.clone()))
+        .dispatch()
+        .await;
+    assert_eq!(changed.status(), Status::Ok);
+    assert_ne!(changed.headers().get_one("ETag"), Some(etag.as_str()));
+}
+
+#[tokio::test]
+async fn failed_queries_are_not_cached() {
+    let app = TestApp::new().await;
+    let response = app
+        .client
+        .get(graphql_uri("{ sites { id } }"))
+        .dispatch()
+        .await;
+    assert!(!response.headers().contains("Cache-Control"));
+    assert!(!response.headers().contains("ETag"));
+}
+
+#[tokio::test]
+async fn metrics_are_labelled_by_root_field() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    app.create_reading(fixture.device_id, "21.5").await;
+    app.post(
+        Some(&app.admin_token),
+        "query ClientChosenName { first: sites { id } second: sites { name } }",
+        json!({}),
+    )
+    .await;
+    app.post(
+        Some(&app.admin_token),
+        "query Another { sites { id } me { id } }",
+        json!({}),
+    )
+    .await;
+
+    let response = app.client.get("/metrics").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let metrics = response.into_string().await.unwrap();
+    assert!(metrics.contains(r#"sh_backend_graphql_requests_total{field="sites",status="ok"} 1"#));
+    assert!(metrics.contains(r#"sh_backend_graphql_requests_total{field="other",status="ok"} 1"#));
+    assert!(!metrics.contains("ClientChosenName"));
+    assert!(metrics.contains(&format!(
+        r#"sh_backend_sensor_reading_latest_value{{device_id="{}",device_name="sensor-1",room_id="{}",site_id="{}",unit="Celsius"}} 21.5"#,
+        fixture.device_id, fixture.room_id, fixture.site_id
+    )));
+}
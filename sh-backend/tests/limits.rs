@@ -0,0 +1,223 @@
+mod common;
+
+use std::time::Duration;
+
+use async_graphql::{EmptyMutation, EmptySubscription, Object, Schema};
+use common::{TestApp, data, error_code};
+use serde_json::json;
+use sh_backend::auth::Identity;
+use sh_backend::config::{Config, GraphqlConfig};
+use sh_backend::limits::QueryLimits;
+
+/// The query the dashboard loads a site with.
+const SITE_WITH_ALL_MODELS: &str = r#"
+    query($siteId: Int!) {
+        site(id: $siteId) {
+            id name address
+            rooms {
+                id name
+                devices {
+                    id name deviceType uniqueIdentifier
+                    sensorReadings { id value unit timestamp }
+                    controlSetpoints { id setpointType value unit timestamp }
+                }
+            }
+        }
+    }
+"#;
+
+const INTROSPECTION: &str = r#"
+    query IntrospectionQuery {
+        __schema {
+            queryType { name }
+            types {
+                kind name
+                fields(includeDeprecated: true) {
+                    name
+                    args { name type { ...TypeRef } }
+                    type { ...TypeRef }
+                }
+            }
+        }
+    }
+    fragment TypeRef on __Type {
+        kind name
+        ofType { kind name ofType { kind name ofType { kind name ofType { kind name ofType { kind name ofType { kind name } } } } } }
+    }
+"#;
+
+#[tokio::test]
+async fn the_dashboard_query_is_within_the_default_limits() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let data = app
+        .admin(SITE_WITH_ALL_MODELS, json!({ "siteId": fixture.site_id }))
+        .await;
+    assert_eq!(data["site"]["rooms"][0]["devices"][0]["name"], "sensor-1");
+}
+
+#[tokio::test]
+async fn loading_every_reading_of_every_site_is_too_complex() {
+    let app = TestApp::new().await;
+    let response = app
+        .execute(
+            Identity::User(app.admin.clone()),
+            "{ sites { rooms { devices { sensorReadings { id value unit timestamp } } } } }",
+            json!({}),
+        )
+        .await;
+    assert_eq!(
+        response.errors[0].message,
+        "The query has a complexity of 400000, more than the 200000 allowed, request fewer fields or smaller lists"
+    );
+    assert_eq!(error_code(response), "QUERY_TOO_COMPLEX");
+}
+
+#[tokio::test]
+async fn limits_make_lists_cheaper() {
+    let mut config = Config::default();
+    config.graphql.max_complexity = 1_000;
+    let app = TestApp::with_config(config).await;
+    let logs =
+        "query($limit: Int!) { auditLogs(limit: $limit) { id operation actorName timestamp } }";
+    app.admin(logs, json!({ "limit": 100 })).await;
+
+    // Limits the resolvers reject still cost as much as the largest list.
+    for limit in [-1, 0, 1_000_000] {
+        let response = app
+            .execute(
+                Identity::User(app.admin.clone()),
+                logs,
+                json!({ "limit": limit }),
+            )
+            .await;
+        assert_eq!(error_code(response), "QUERY_TOO_COMPLEX");
+    }
+}
+
+#[tokio::test]
+async fn limits_outside_the_allowed_range_are_rejected() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let queries = [
+        "query($limit: Int!) { auditLogs(limit: $limit) { id } }",
+        "query($limit: Int!, $siteId: Int!) { alerts(siteId: $siteId, limit: $limit) { id } }",
+        "query($limit: Int!) { ruleExecutions(ruleId: 1, limit: $limit) { id } }",
+        "query($limit: Int!) { webhookDeliveries(subscriptionId: 1, limit: $limit) { id } }",
+        "query($limit: Int!) { search(query: \"sensor\", limit: $limit) { __typename } }",
+        "query($limit: Int!, $siteId: Int!) { site(id: $siteId) { rooms { devices { sensorReadings(limit: $limit) { id } } } } }",
+    ];
+    for query in queries {
+        for limit in [-1, 0, 501] {
+            let response = app
+                .execute(
+                    Identity::User(app.admin.clone()),
+                    query,
+                    json!({ "limit": limit, "siteId": fixture.site_id }),
+                )
+                .await;
+            assert_eq!(
+                response.errors.first().map(|error| error.message.as_str()),
+                Some("The limit has to be between 1 and 500"),
+                "{} with a limit of {}",
+                query,
+                limit
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn deep_queries_are_rejected() {
+    let mut config = Config::default();
+    config.graphql.max_depth = 3;
+    let app = TestApp::with_config(config).await;
+
+    app.admin("{ sites { rooms { id } } }", json!({})).await;
+    let response = app
+        .execute(
+            Identity::User(app.admin.clone()),
+            "{ sites { rooms { devices { id } } } }",
+            json!({}),
+        )
+        .await;
+    assert_eq!(
+        response.errors[0].message,
+        "The query is nested 4 levels deep, more than the 3 allowed"
+    );
+    assert_eq!(error_code(response), "QUERY_TOO_DEEP");
+}
+
+#[tokio::test]
+async fn introspection_is_not_limited() {
+    let mut config = Config::default();
+    config.graphql.max_depth = 3;
+    config.graphql.max_complexity = 10;
+    let app = TestApp::with_config(config).await;
+
+    let response = app
+        .execute(Identity::Anonymous, INTROSPECTION, json!({}))
+        .await;
+    assert_eq!(
+        data(response)["__schema"]["queryType"]["name"],
+        "SiteQueryRoot"
+    );
+}
+
+#[tokio::test]
+async fn deep_introspection_is_rejected() {
+    let app = TestApp::new().await;
+    let of_type = "ofType { ".repeat(13) + "name" + &" }".repeat(13);
+    let query = format!("{{ __schema {{ types {{ {} }} }} }}", of_type);
+
+    let response = app.execute(Identity::Anonymous, &query, json!({})).await;
+    assert_eq!(
+        response.errors[0].message,
+        "The query is nested 16 levels deep, more than the 15 allowed"
+    );
+    assert_eq!(error_code(response), "QUERY_TOO_DEEP");
+}
+
+#[tokio::test]
+async fn the_limits_apply_over_http() {
+    let mut config = Config::default();
+    config.graphql.max_depth = 2;
+    let app = TestApp::with_config(config).await;
+
+    let (_, body) = app
+        .post(
+            Some(&app.admin_token),
+            "{ sites { rooms { id } } }",
+            json!({}),
+        )
+        .await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "QUERY_TOO_DEEP");
+}
+
+struct SlowQuery;
+
+#[Object]
+impl SlowQuery {
+    async fn slow(&self) -> bool {
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        true
+    }
+}
+
+#[tokio::test]
+async fn slow_operations_are_cancelled() {
+    let config = GraphqlConfig {
+        timeout_seconds: 1,
+        ..GraphqlConfig::default()
+    };
+    let schema = Schema::build(SlowQuery, EmptyMutation, EmptySubscription)
+        .extension(QueryLimits::new(&config))
+        .finish();
+
+    let response = schema.execute("{ slow }").await;
+    assert_eq!(
+        response.errors[0].message,
+        "The request was cancelled after exceeding the 1s timeout"
+    );
+    assert_eq!(error_code(response), "TIMEOUT");
+}
@@ -0,0 +1,498 @@
+mod common;
+
+use std::time::Duration as StdDuration;
+
+use chrono::{Duration, Utc};
+use common::{TestApp, data, error, error_code, id};
+use serde_json::json;
+use sh_backend::models::Role;
+use sh_backend::retention::RetentionJob;
+
+impl TestApp {
+    async fn create_building(&self, site_id: i64, name: &str) -> i64 {
+        let data = self
+            .admin(
+                "mutation($siteId: Int!, $name: String!) { createBuilding(input: { siteId: $siteId, name: $name }) { id } }",
+                json!({ "siteId": site_id, "name": name }),
+            )
+            .await;
+        id(&data["createBuilding"])
+    }
+
+    async fn create_floor(&self, building_id: i64, name: &str, level: i64) -> i64 {
+        let data = self
+            .admin(
+                r#"
+                mutation($buildingId: Int!, $name: String!, $level: Int!) {
+                    createFloor(input: { buildingId: $buildingId, name: $name, level: $level }) { id }
+                }
+                "#,
+                json!({ "buildingId": building_id, "name": name, "level": level }),
+            )
+            .await;
+        id(&data["createFloor"])
+    }
+
+    async fn create_zone(&self, site_id: i64, name: &str) -> i64 {
+        let data = self
+            .admin(
+                "mutation($siteId: Int!, $name: String!) { createZone(input: { siteId: $siteId, name: $name }) { id } }",
+                json!({ "siteId": site_id, "name": name }),
+            )
+            .await;
+        id(&data["createZone"])
+    }
+
+    async fn set_room_floor(&self, room_id: i64, floor_id: i64) {
+        self.admin(
+            "mutation($roomId: Int!, $floorId: Int) { setRoomFloor(roomId: $roomId, floorId: $floorId) { id } }",
+            json!({ "roomId": room_id, "floorId": floor_id }),
+        )
+        .await;
+    }
+
+    async fn add_room_to_zone(&self, room_id: i64, zone_id: i64) {
+        self.admin(
+            "mutation($roomId: Int!, $zoneId: Int!) { addRoomToZone(roomId: $roomId, zoneId: $zoneId) { id } }",
+            json!({ "roomId": room_id, "zoneId": zone_id }),
+        )
+        .await;
+    }
+
+    async fn create_thermostat(&self, room_id: i64, name: &str) -> i64 {
+        let data = self
+            .admin(
+                r#"
+                mutation($roomId: Int!, $name: String!) {
+                    createDevice(input: { roomId: $roomId, name: $name, deviceType: THERMOSTAT_CONTROLLER }) { id }
+                }
+                "#,
+                json!({ "roomId": room_id, "name": name }),
+            )
+            .await;
+        id(&data["createDevice"])
+    }
+}
+
+const ZONE_SETPOINT: &str = r#"
+    mutation($zoneId: Int!) {
+        createZoneSetpoint(input: { zoneId: $zoneId, setpointType: TEMPERATURE, value: "21", unit: CELSIUS }) {
+            deviceId
+            value
+        }
+    }
+"#;
+
+#[tokio::test]
+async fn sites_resolve_their_buildings_floors_and_zones() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let building = app.create_building(fixture.site_id, "Main").await;
+    let first = app.create_floor(building, "First floor", 1).await;
+    app.create_floor(building, "Basement", -1).await;
+    app.set_room_floor(fixture.room_id, first).await;
+    let zone = app.create_zone(fixture.site_id, "East wing").await;
+    app.add_room_to_zone(fixture.room_id, zone).await;
+    // Adding a room twice leaves it in the zone once.
+    app.add_room_to_zone(fixture.room_id, zone).await;
+
+    let data = app
+        .admin(
+            r#"
+            query($id: Int!, $roomId: Int!) {
+                site(id: $id) {
+                    buildings { name floors { name level rooms { name } } }
+                    zones { name rooms { name } }
+                }
+                room(id: $roomId) { floorId zones { name } }
+            }
+            "#,
+            json!({ "id": fixture.site_id, "roomId": fixture.room_id }),
+        )
+        .await;
+    assert_eq!(
+        data["site"],
+        json!({
+            "buildings": [{
+                "name": "Main",
+                "floors": [
+                    { "name": "Basement", "level": -1, "rooms": [] },
+                    { "name": "First floor", "level": 1, "rooms": [{ "name": "Room" }] },
+                ],
+            }],
+            "zones": [{ "name": "East wing", "rooms": [{ "name": "Room" }] }],
+        })
+    );
+    assert_eq!(
+        data["room"],
+        json!({ "floorId": first, "zones": [{ "name": "East wing" }] })
+    );
+}
+
+#[tokio::test]
+async fn readings_are_summarized_per_floor_and_zone() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let building = app.create_building(fixture.site_id, "Main").await;
+    let floor = app.create_floor(building, "Ground floor", 0).await;
+    let other_room = app.create_room(fixture.site_id, "Other room").await;
+    let other_device = app.create_device(other_room, "sensor-2").await;
+    app.set_room_floor(fixture.room_id, floor).await;
+    app.set_room_floor(other_room, floor).await;
+    let zone = app.create_zone(fixture.site_id, "North").await;
+    app.add_room_to_zone(other_room, zone).await;
+
+    app.create_reading(fixture.device_id, "20").await;
+    app.create_reading(fixture.device_id, "22").await;
+    app.create_reading(other_device, "26").await;
+    // Readings that aren't numbers are left out rather than counted as 0.
+    app.create_reading(fixture.device_id, "open").await;
+
+    let query = r#"
+        query($floorId: Int!, $zoneId: Int!, $from: DateTime, $to: DateTime) {
+            floor(id: $floorId) { readingSummary(from: $from, to: $to) { unit deviceCount sampleCount minValue maxValue avgValue } }
+            zone(id: $zoneId) { readingSummary(from: $from, to: $to) { deviceCount sampleCount avgValue } }
+        }
+    "#;
+    let data = app
+        .admin(query, json!({ "floorId": floor, "zoneId": zone }))
+        .await;
+    assert_eq!(
+        data["floor"]["readingSummary"],
+        json!([{
+            "unit": "CELSIUS",
+            "deviceCount": 2,
+            "sampleCount": 3,
+            "minValue": 20.0,
+            "maxValue": 26.0,
+            "avgValue": 68.0 / 3.0,
+        }])
+    );
+    assert_eq!(
+        data["zone"]["readingSummary"],
+        json!([{ "deviceCount": 1, "sampleCount": 1, "avgValue": 26.0 }])
+    );
+
+    let later = Utc::now() + Duration::hours(1);
+    let data = app
+        .admin(
+            query,
+            json!({
+                "floorId": floor,
+                "zoneId": zone,
+                "from": later.to_rfc3339(),
+                "to": (later + Duration::hours(1)).to_rfc3339(),
+            }),
+        )
+        .await;
+    assert_eq!(data["floor"]["readingSummary"], json!([]));
+    assert_eq!(data["zone"]["readingSummary"], json!([]));
+
+    for (from, to, message) in [
+        (later, Utc::now(), "A summary cannot end before it starts"),
+        (
+            Utc::now() - Duration::days(40),
+            Utc::now(),
+            "A summary cannot span more than 31 days",
+        ),
+    ] {
+        let response = app
+            .as_user(
+                &app.admin,
+                query,
+                json!({ "floorId": floor, "zoneId": zone, "from": from.to_rfc3339(), "to": to.to_rfc3339() }),
+            )
+            .await;
+        assert_eq!(response.errors[0].message, message);
+    }
+}
+
+#[tokio::test]
+async fn summaries_read_rollups_of_pruned_readings() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let building = app.create_building(fixture.site_id, "Main").await;
+    let floor = app.create_floor(building, "Ground floor", 0).await;
+    app.set_room_floor(fixture.room_id, floor).await;
+
+    let old = (Utc::now() - Duration::days(3)).format("%Y-%m-%d 10:00:00");
+    for value in ["20", "24", "open"] {
+        sqlx::query(
+            "INSERT INTO SensorReading (device_id, value, unit, timestamp) VALUES (?, ?, 'Celsius', ?)",
+        )
+        .bind(fixture.device_id)
+        .bind(value)
+        .bind(old.to_string())
+        .execute(app.pool())
+        .await
+        .unwrap();
+    }
+    app.create_reading(fixture.device_id, "26").await;
+    app.admin(
+        r#"
+        mutation($siteId: Int!) {
+            setRetentionPolicy(input: { siteId: $siteId, rawRetentionDays: 1 }) { id }
+        }
+        "#,
+        json!({ "siteId": fixture.site_id }),
+    )
+    .await;
+    let job = RetentionJob::new(app.database.clone(), StdDuration::from_secs(3600));
+    assert_eq!(job.run(Utc::now()).await.unwrap(), 3);
+
+    let data = app
+        .admin(
+            r#"
+            query($floorId: Int!, $from: DateTime!) {
+                floor(id: $floorId) { readingSummary(from: $from) { deviceCount sampleCount minValue maxValue avgValue } }
+            }
+            "#,
+            json!({ "floorId": floor, "from": (Utc::now() - Duration::days(4)).to_rfc3339() }),
+        )
+        .await;
+    assert_eq!(
+        data["floor"]["readingSummary"],
+        json!([{ "deviceCount": 1, "sampleCount": 3, "minValue": 20.0, "maxValue": 26.0, "avgValue": 70.0 / 3.0 }])
+    );
+}
+
+#[tokio::test]
+async fn locations_are_renamed_and_deleted() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let building = app.create_building(fixture.site_id, "Main").await;
+    let floor = app.create_floor(building, "Ground floor", 0).await;
+    let zone = app.create_zone(fixture.site_id, "North").await;
+    app.set_room_floor(fixture.room_id, floor).await;
+    app.add_room_to_zone(fixture.room_id, zone).await;
+
+    let data = app
+        .admin(
+            r#"
+            mutation($building: Int!, $floor: Int!, $zone: Int!) {
+                renameBuilding(id: $building, name: "Annex") { id name }
+                renameFloor(id: $floor, name: "Lobby") { id name level }
+                renameZone(id: $zone, name: "South") { id name }
+            }
+            "#,
+            json!({ "building": building, "floor": floor, "zone": zone }),
+        )
+        .await;
+    assert_eq!(
+        data,
+        json!({
+            "renameBuilding": { "id": building, "name": "Annex" },
+            "renameFloor": { "id": floor, "name": "Lobby", "level": 0 },
+            "renameZone": { "id": zone, "name": "South" },
+        })
+    );
+
+    let (operator, _) = app.create_user("Operator").await;
+    app.grant(&operator, fixture.site_id, Role::Operator).await;
+    let delete_zone = "mutation($id: Int!) { deleteZone(id: $id) }";
+    let response = app
+        .as_user(&operator, delete_zone, json!({ "id": zone }))
+        .await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+
+    let data = app
+        .admin(
+            r#"
+            mutation($building: Int!, $zone: Int!) {
+                deleteZone(id: $zone)
+                deleteBuilding(id: $building)
+            }
+            "#,
+            json!({ "building": building, "zone": zone }),
+        )
+        .await;
+    assert_eq!(data, json!({ "deleteZone": true, "deleteBuilding": true }));
+
+    // The room outlives its floor and zone.
+    let data = app
+        .admin(
+            "query($id: Int!) { room(id: $id) { floorId zones { id } } }",
+            json!({ "id": fixture.room_id }),
+        )
+        .await;
+    assert_eq!(data["room"], json!({ "floorId": null, "zones": [] }));
+
+    let response = app
+        .as_user(
+            &app.admin,
+            "mutation($id: Int!) { deleteFloor(id: $id) }",
+            json!({ "id": floor }),
+        )
+        .await;
+    assert_eq!(
+        error(response),
+        format!("Floor with ID {} does not exist", floor)
+    );
+}
+
+#[tokio::test]
+async fn zone_setpoints_apply_to_every_thermostat_in_the_zone() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let zone = app.create_zone(fixture.site_id, "Offices").await;
+    let variables = json!({ "zoneId": zone });
+
+    let response = app
+        .as_user(&app.admin, ZONE_SETPOINT, variables.clone())
+        .await;
+    assert_eq!(
+        error(response),
+        format!("Zone with ID {} has no thermostat controllers", zone)
+    );
+
+    let other_room = app.create_room(fixture.site_id, "Meeting room").await;
+    let outside = app.create_room(fixture.site_id, "Hallway").await;
+    let first = app.create_thermostat(fixture.room_id, "thermostat-1").await;
+    let second = app.create_thermostat(other_room, "thermostat-2").await;
+    app.create_thermostat(outside, "thermostat-3").await;
+    app.add_room_to_zone(fixture.room_id, zone).await;
+    app.add_room_to_zone(other_room, zone).await;
+
+    let data = app.admin(ZONE_SETPOINT, variables.clone()).await;
+    assert_eq!(
+        data["createZoneSetpoint"],
+        json!([
+            { "deviceId": first, "value": "21" },
+            { "deviceId": second, "value": "21" },
+        ])
+    );
+
+    let audit = app
+        .admin(
+            r#"
+            query($siteId: Int!) {
+                auditLogs(filter: { siteId: $siteId, entityType: CONTROL_SETPOINT }) { operation }
+            }
+            "#,
+            json!({ "siteId": fixture.site_id }),
+        )
+        .await;
+    assert_eq!(
+        audit["auditLogs"],
+        json!([{ "operation": "createZoneSetpoint" }, { "operation": "createZoneSetpoint" }])
+    );
+
+    let (viewer, _) = app.create_user("Viewer").await;
+    app.grant(&viewer, fixture.site_id, Role::Viewer).await;
+    let response = app.as_user(&viewer, ZONE_SETPOINT, variables).await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+}
+
+#[tokio::test]
+async fn rooms_stay_within_the_locations_of_their_site() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let other_site = app.create_site("Other site").await;
+    let building = app.create_building(other_site, "Elsewhere").await;
+    let floor = app.create_floor(building, "Ground floor", 0).await;
+    let zone = app.create_zone(other_site, "Elsewhere").await;
+
+    let response = app
+        .as_user(
+            &app.admin,
+            "mutation($roomId: Int!, $floorId: Int) { setRoomFloor(roomId: $roomId, floorId: $floorId) { id } }",
+            json!({ "roomId": fixture.room_id, "floorId": floor }),
+        )
+        .await;
+    assert_eq!(
+        error(response),
+        format!("Floor with ID {} belongs to another site", floor)
+    );
+
+    let response = app
+        .as_user(
+            &app.admin,
+            "mutation($siteId: Int!, $floorId: Int!) { createRoom(input: { siteId: $siteId, name: \"Room\", floorId: $floorId }) { id } }",
+            json!({ "siteId": fixture.site_id, "floorId": floor }),
+        )
+        .await;
+    assert_eq!(
+        error(response),
+        format!("Floor with ID {} belongs to another site", floor)
+    );
+
+    let response = app
+        .as_user(
+            &app.admin,
+            "mutation($roomId: Int!, $zoneId: Int!) { addRoomToZone(roomId: $roomId, zoneId: $zoneId) { id } }",
+            json!({ "roomId": fixture.room_id, "zoneId": zone }),
+        )
+        .await;
+    assert_eq!(
+        error(response),
+        format!(
+            "Room with ID {} belongs to another site than zone {}",
+            fixture.room_id, zone
+        )
+    );
+}
+
+#[tokio::test]
+async fn rooms_can_leave_their_floor_and_zones() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let building = app.create_building(fixture.site_id, "Main").await;
+    let floor = app.create_floor(building, "Ground floor", 0).await;
+    let zone = app.create_zone(fixture.site_id, "South").await;
+    app.set_room_floor(fixture.room_id, floor).await;
+    app.add_room_to_zone(fixture.room_id, zone).await;
+
+    let data = app
+        .admin(
+            r#"
+            mutation($roomId: Int!, $zoneId: Int!) {
+                setRoomFloor(roomId: $roomId) { floorId }
+                removeRoomFromZone(roomId: $roomId, zoneId: $zoneId) { rooms { id } }
+            }
+            "#,
+            json!({ "roomId": fixture.room_id, "zoneId": zone }),
+        )
+        .await;
+    assert_eq!(
+        data,
+        json!({
+            "setRoomFloor": { "floorId": null },
+            "removeRoomFromZone": { "rooms": [] },
+        })
+    );
+}
+
+#[tokio::test]
+async fn only_site_admins_lay_out_a_site() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let (operator, _) = app.create_user("Operator").await;
+    app.grant(&operator, fixture.site_id, Role::Operator).await;
+
+    let response = app
+        .as_user(
+            &operator,
+            "mutation($siteId: Int!) { createZone(input: { siteId: $siteId, name: \"Zone\" }) { id } }",
+            json!({ "siteId": fixture.site_id }),
+        )
+        .await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+
+    let response = app
+        .as_user(
+            &operator,
+            "mutation($siteId: Int!) { createBuilding(input: { siteId: $siteId, name: \"Building\" }) { id } }",
+            json!({ "siteId": fixture.site_id }),
+        )
+        .await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+    let site = data(
+        app.as_user(
+            &operator,
+            "query($id: Int!) { site(id: $id) { buildings { id } } }",
+            json!({ "id": fixture.site_id }),
+        )
+        .await,
+    );
+    assert_eq!(site["site"]["buildings"], json!([]));
+}
@@ -0,0 +1,171 @@
+mod common;
+
+use common::{TestApp, urlencode};
+use rocket::http::{ContentType, Header};
+use serde_json::{Value, json};
+use sh_backend::config::Config;
+use sh_backend::persisted_queries::{PersistedQueries, query_hash};
+
+const SITES: &str = "query Sites { sites { id name } }";
+
+impl TestApp {
+    /// Posts an operation with the `persistedQuery` extension, leaving the query out when it is `None`.
+    async fn post_persisted(&self, query: Option<&str>, hash: &str) -> Value {
+        let mut body = json!({
+            "extensions": { "persistedQuery": { "version": 1, "sha256Hash": hash } },
+        });
+        if let Some(query) = query {
+            body["query"] = query.into();
+        }
+        let response = self
+            .client
+            .post("/graphql")
+            .header(ContentType::JSON)
+            .header(self.authorization())
+            .body(body.to_string())
+            .dispatch()
+            .await;
+        serde_json::from_str(&response.into_string().await.unwrap()).unwrap()
+    }
+
+    /// Sends an operation by hash only in the query string, the way tablets do.
+    async fn get_persisted(&self, hash: &str) -> Value {
+        let extensions = json!({ "persistedQuery": { "version": 1, "sha256Hash": hash } });
+        let uri = format!("/graphql?extensions={}", urlencode(&extensions.to_string()));
+        let response = self
+            .client
+            .get(uri)
+            .header(self.authorization())
+            .dispatch()
+            .await;
+        serde_json::from_str(&response.into_string().await.unwrap()).unwrap()
+    }
+
+    fn authorization(&self) -> Header<'static> {
+        Header::new("Authorization", format!("Bearer {}", self.admin_token))
+    }
+}
+
+/// Writes an allowlist manifest holding `queries` to a file of its own.
+fn allowlist(name: &str, queries: &[&str]) -> Config {
+    let manifest: serde_json::Map<String, Value> = queries
+        .iter()
+        .map(|query| (query_hash(query), Value::from(*query)))
+        .collect();
+    let path =
+        std::env::temp_dir().join(format!("sh-backend-{}-{}.json", name, std::process::id()));
+    std::fs::write(&path, Value::Object(manifest).to_string()).unwrap();
+    let mut config = Config::default();
+    config.graphql.operation_allowlist = Some(path);
+    config
+}
+
+#[tokio::test]
+async fn unknown_hashes_are_registered_with_the_full_query() {
+    let app = TestApp::new().await;
+    app.fixture().await;
+    let hash = query_hash(SITES);
+
+    let missed = app.post_persisted(None, &hash).await;
+    assert_eq!(missed["errors"][0]["message"], "PersistedQueryNotFound");
+    assert_eq!(
+        missed["errors"][0]["extensions"]["code"],
+        "PERSISTED_QUERY_NOT_FOUND"
+    );
+
+    let registered = app.post_persisted(Some(SITES), &hash).await;
+    assert_eq!(registered["data"]["sites"][0]["name"], "Site");
+
+    let hit = app.post_persisted(None, &hash).await;
+    assert_eq!(hit["data"], registered["data"]);
+}
+
+#[tokio::test]
+async fn registered_queries_can_be_sent_by_hash_over_get() {
+    let app = TestApp::new().await;
+    app.fixture().await;
+    let hash = query_hash(SITES);
+
+    let missed = app.get_persisted(&hash).await;
+    assert_eq!(missed["errors"][0]["message"], "PersistedQueryNotFound");
+
+    app.post_persisted(Some(SITES), &hash).await;
+    let hit = app.get_persisted(&hash).await;
+    assert_eq!(hit["data"]["sites"][0]["name"], "Site");
+}
+
+#[tokio::test]
+async fn queries_not_matching_their_hash_are_rejected() {
+    let app = TestApp::new().await;
+    let response = app
+        .post_persisted(Some(SITES), &query_hash("{ sites { id } }"))
+        .await;
+    assert_eq!(
+        response["errors"][0]["extensions"]["code"],
+        "PERSISTED_QUERY_HASH_MISMATCH"
+    );
+}
+
+#[tokio::test]
+async fn the_oldest_registered_queries_are_forgotten() {
+    let mut config = Config::default();
+    config.graphql.persisted_query_cache_size = 1;
+    let app = TestApp::with_config(config).await;
+    let other = "{ sites { id } }";
+
+    app.post_persisted(Some(SITES), &query_hash(SITES)).await;
+    app.post_persisted(Some(other), &query_hash(other)).await;
+
+    let forgotten = app.post_persisted(None, &query_hash(SITES)).await;
+    assert_eq!(forgotten["errors"][0]["message"], "PersistedQueryNotFound");
+    let kept = app.post_persisted(None, &query_hash(other)).await;
+    assert!(kept["errors"].is_null());
+}
+
+#[tokio::test]
+async fn only_allowlisted_operations_are_accepted() {
+    let app = TestApp::with_config(allowlist("allowlisted", &[SITES])).await;
+
+    let by_hash = app.get_persisted(&query_hash(SITES)).await;
+    assert_eq!(by_hash["data"]["sites"], json!([]));
+    let (_, in_full) = app.post(Some(&app.admin_token), SITES, json!({})).await;
+    assert_eq!(in_full["data"], by_hash["data"]);
+
+    let (_, other) = app
+        .post(Some(&app.admin_token), "{ sites { id } }", json!({}))
+        .await;
+    assert_eq!(
+        other["errors"][0]["extensions"]["code"],
+        "PERSISTED_QUERY_NOT_ALLOWED"
+    );
+}
+
+#[tokio::test]
+async fn the_allowlist_mode_refuses_registrations() {
+    let app = TestApp::with_config(allowlist("registrations", &[SITES])).await;
+    let other = "{ sites { id } }";
+
+    let registered = app.post_persisted(Some(other), &query_hash(other)).await;
+    assert_eq!(
+        registered["errors"][0]["extensions"]["code"],
+        "PERSISTED_QUERY_NOT_ALLOWED"
+    );
+    let missed = app.post_persisted(None, &query_hash(other)).await;
+    assert_eq!(missed["errors"][0]["message"], "PersistedQueryNotFound");
+}
+
+#[test]
+fn allowlists_with_wrong_hashes_are_refused() {
+    let config = allowlist("wrong-hashes", &[SITES]);
+    let path = config.graphql.operation_allowlist.as_ref().unwrap();
+    std::fs::write(path, json!({ "0123abcd": SITES }).to_string()).unwrap();
+
+    let error = PersistedQueries::new(&config.graphql).err().unwrap();
+    assert_eq!(
+        format!("{:#}", error),
+        format!(
+            "Failed to load the operation allowlist {}: The hashes 0123abcd don't match their queries",
+            path.display()
+        )
+    );
+}
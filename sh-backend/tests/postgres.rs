@@ -0,0 +1,695 @@
+//! The flows of the other suites on PostgreSQL, run when `TEST_POSTGRES_URL` points at a database
+//! the tests may create schemas in, and skipped otherwise.
+
+mod common;
+
+use std::time::Duration as StdDuration;
+
+use arrow_array::TimestampMicrosecondArray;
+use chrono::{Duration, Utc};
+use common::{TestApp, data, error_code, id};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use rocket::http::{Header, Status};
+use serde_json::{Value, json};
+use sh_backend::automation::AutomationEngine;
+use sh_backend::events::{Event, EventBus};
+use sh_backend::export::ExportFilter;
+use sh_backend::models::{Role, SensorReading};
+use sh_backend::parquet_export::export_parquet;
+use sh_backend::retention::RetentionJob;
+use sh_backend::webhooks::WebhookDispatcher;
+
+const IMPORT: &str = "mutation($file: Upload!) { importSensorReadings(file: $file) { imported rejected { line reason } } }";
+
+#[tokio::test]
+async fn sites_resolve_rooms_devices_readings_and_setpoints() {
+    let Some(app) = TestApp::postgres().await else {
+        return;
+    };
+    let fixture = app.fixture().await;
+    app.create_site("Other").await;
+    app.create_reading(fixture.device_id, "21.5").await;
+    let create = r#"
+        mutation($input: ControlSetpointInput!) { createControlSetpoint(input: $input) { id mode } }
+    "#;
+    app.admin(
+        create,
+        json!({ "input": { "deviceId": fixture.device_id, "setpointType": "TEMPERATURE", "value": "22", "unit": "CELSIUS" } }),
+    )
+    .await;
+    app.admin(
+        create,
+        json!({ "input": {
+            "deviceId": fixture.device_id,
+            "setpointType": "TEMPERATURE",
+            "value": "25",
+            "mode": "OVERRIDE",
+            "expiresAt": (Utc::now() + Duration::hours(1)).to_rfc3339(),
+        } }),
+    )
+    .await;
+
+    let data = app
+        .admin(
+            r#"
+            query($id: Int!, $deviceId: Int!) {
+                site(id: $id) {
+                    name
+                    rooms {
+                        name
+                        devices {
+                            name
+                            deviceType
+                            sensorReadings { value unit }
+                            readingSamples { resolution avgValue }
+                        }
+                    }
+                }
+                latestSensorReading(deviceId: $deviceId) { value }
+                latestControlSetpoint(deviceId: $deviceId) { value mode }
+            }
+            "#,
+            json!({ "id": fixture.site_id, "deviceId": fixture.device_id }),
+        )
+        .await;
+    assert_eq!(
+        data,
+        json!({
+            "site": {
+                "name": "Site",
+                "rooms": [{
+                    "name": "Room",
+                    "devices": [{
+                        "name": "sensor-1",
+                        "deviceType": "TEMPERATURE_SENSOR",
+                        "sensorReadings": [{ "value": "21.5", "unit": "CELSIUS" }],
+                        "readingSamples": [{ "resolution": "RAW", "avgValue": 21.5 }],
+                    }],
+                }],
+            },
+            "latestSensorReading": { "value": "21.5" },
+            "latestControlSetpoint": { "value": "25", "mode": "OVERRIDE" },
+        })
+    );
+
+    let data = app
+        .admin(
+            "mutation($id: Int!) { releaseSetpointOverride(deviceId: $id) { value mode } }",
+            json!({ "id": fixture.device_id }),
+        )
+        .await;
+    assert_eq!(
+        data["releaseSetpointOverride"],
+        json!({ "value": "22", "mode": "SCHEDULED" })
+    );
+
+    let (viewer, _) = app.create_user("Viewer").await;
+    app.grant(&viewer, fixture.site_id, Role::Viewer).await;
+    let own = common::data(app.as_user(&viewer, "{ sites { name } }", json!({})).await);
+    assert_eq!(own["sites"], json!([{ "name": "Site" }]));
+    let response = app
+        .as_user(
+            &viewer,
+            r#"mutation($id: Int!) { createSensorReading(input: { deviceId: $id, value: "20" }) { id } }"#,
+            json!({ "id": fixture.device_id }),
+        )
+        .await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+}
+
+#[tokio::test]
+async fn locations_are_laid_out_and_summarized() {
+    let Some(app) = TestApp::postgres().await else {
+        return;
+    };
+    let fixture = app.fixture().await;
+    let data = app
+        .admin(
+            r#"
+            mutation($siteId: Int!) {
+                createBuilding(input: { siteId: $siteId, name: "Main" }) { id }
+                createZone(input: { siteId: $siteId, name: "North" }) { id }
+            }
+            "#,
+            json!({ "siteId": fixture.site_id }),
+        )
+        .await;
+    let building = id(&data["createBuilding"]);
+    let zone = id(&data["createZone"]);
+    let data = app
+        .admin(
+            "mutation($id: Int!) { createFloor(input: { buildingId: $id, name: \"Ground\", level: 0 }) { id } }",
+            json!({ "id": building }),
+        )
+        .await;
+    let floor = id(&data["createFloor"]);
+    app.admin(
+        r#"
+        mutation($roomId: Int!, $floorId: Int!, $zoneId: Int!) {
+            setRoomFloor(roomId: $roomId, floorId: $floorId) { id }
+            addRoomToZone(roomId: $roomId, zoneId: $zoneId) { id }
+        }
+        "#,
+        json!({ "roomId": fixture.room_id, "floorId": floor, "zoneId": zone }),
+    )
+    .await;
+    app.create_reading(fixture.device_id, "20").await;
+    app.create_reading(fixture.device_id, "24").await;
+    app.create_reading(fixture.device_id, "open").await;
+
+    let data = app
+        .admin(
+            r#"
+            query($floorId: Int!, $zoneId: Int!) {
+                floor(id: $floorId) { readingSummary { unit deviceCount sampleCount minValue maxValue avgValue } }
+                zone(id: $zoneId) { readingSummary { sampleCount avgValue } }
+            }
+            "#,
+            json!({ "floorId": floor, "zoneId": zone }),
+        )
+        .await;
+    assert_eq!(
+        data,
+        json!({
+            "floor": { "readingSummary": [{
+                "unit": "CELSIUS", "deviceCount": 1, "sampleCount": 2,
+                "minValue": 20.0, "maxValue": 24.0, "avgValue": 22.0,
+            }] },
+            "zone": { "readingSummary": [{ "sampleCount": 2, "avgValue": 22.0 }] },
+        })
+    );
+
+    let data = app
+        .admin(
+            r#"
+            mutation($building: Int!, $floor: Int!, $zone: Int!) {
+                renameFloor(id: $floor, name: "Lobby") { name }
+                deleteZone(id: $zone)
+                deleteBuilding(id: $building)
+            }
+            "#,
+            json!({ "building": building, "floor": floor, "zone": zone }),
+        )
+        .await;
+    assert_eq!(
+        data,
+        json!({ "renameFloor": { "name": "Lobby" }, "deleteZone": true, "deleteBuilding": true })
+    );
+    let data = app
+        .admin(
+            "query($id: Int!) { room(id: $id) { floorId zones { id } } }",
+            json!({ "id": fixture.room_id }),
+        )
+        .await;
+    assert_eq!(data["room"], json!({ "floorId": null, "zones": [] }));
+}
+
+#[tokio::test]
+async fn devices_record_readings_with_their_key() {
+    let Some(app) = TestApp::postgres().await else {
+        return;
+    };
+    let fixture = app.fixture().await;
+    let data = app
+        .admin(
+            "mutation($id: Int!) { createDeviceCredential(deviceId: $id) { key credential { id } } }",
+            json!({ "id": fixture.device_id }),
+        )
+        .await;
+    let key = data["createDeviceCredential"]["key"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let credential_id = id(&data["createDeviceCredential"]["credential"]);
+
+    let record = r#"mutation($id: Int!) { createSensorReading(input: { deviceId: $id, value: "20.5" }) { value } }"#;
+    let (status, body) = app
+        .post(Some(&key), record, json!({ "id": fixture.device_id }))
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["data"]["createSensorReading"]["value"], "20.5");
+
+    let data = app
+        .admin(
+            "mutation($id: Int!) { rotateDeviceCredential(id: $id) { key } }",
+            json!({ "id": credential_id }),
+        )
+        .await;
+    let rotated = data["rotateDeviceCredential"]["key"].as_str().unwrap();
+    let (status, _) = app
+        .post(Some(&key), record, json!({ "id": fixture.device_id }))
+        .await;
+    assert_eq!(status, Status::Unauthorized);
+    let (status, _) = app
+        .post(Some(rotated), record, json!({ "id": fixture.device_id }))
+        .await;
+    assert_eq!(status, Status::Ok);
+
+    let data = app
+        .admin(
+            "query($id: Int!) { deviceCredentials(deviceId: $id) { revokedAt } }",
+            json!({ "id": fixture.device_id }),
+        )
+        .await;
+    assert_eq!(data["deviceCredentials"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn imported_readings_are_exported_and_rolled_up() {
+    let Some(app) = TestApp::postgres().await else {
+        return;
+    };
+    let fixture = app.fixture().await;
+    let day = (Utc::now() - Duration::days(3)).format("%Y-%m-%d");
+    let csv = format!(
+        "device,value,unit,timestamp\n\
+         sensor-1,20,C,{day}T10:00:10.123456Z\n\
+         sensor-1,22,C,{day}T10:00:40.5Z\n\
+         sensor-1,warm,C,{day}T11:00:00Z\n\
+         unknown,20.5,C,{day}T11:00:00Z\n"
+    );
+    let (status, body) = app
+        .upload(&app.admin_token, IMPORT, "readings.csv", &csv)
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(
+        body["data"]["importSensorReadings"],
+        json!({
+            "imported": 2,
+            "rejected": [
+                { "line": 4, "reason": "Invalid value \"warm\"" },
+                { "line": 5, "reason": "Unknown device \"unknown\"" },
+            ],
+        })
+    );
+
+    let response = app
+        .client
+        .get(format!(
+            "/export/readings?site_id={}&format=jsonl",
+            fixture.site_id
+        ))
+        .header(Header::new(
+            "Authorization",
+            format!("Bearer {}", app.admin_token),
+        ))
+        .dispatch()
+        .await;
+    assert_eq!(response.status(), Status::Ok);
+    let body = response.into_string().await.unwrap();
+    let exported: Vec<_> = body
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .collect();
+    let timestamps: Vec<_> = exported
+        .iter()
+        .map(|record| record["timestamp"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        timestamps,
+        [
+            format!("{day}T10:00:10.123456Z"),
+            format!("{day}T10:00:40.500Z"),
+        ]
+    );
+    assert_eq!(exported[0]["unit"], "Celsius");
+
+    let output =
+        std::env::temp_dir().join(format!("sh-backend-postgres-export-{}", std::process::id()));
+    let partitions = export_parquet(&app.database, &ExportFilter::default(), &output)
+        .await
+        .unwrap();
+    assert_eq!(partitions.len(), 1);
+    let file = std::fs::File::open(&partitions[0].path).unwrap();
+    let batch = ParquetRecordBatchReaderBuilder::try_new(file)
+        .unwrap()
+        .build()
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let column = batch.column_by_name("timestamp").unwrap();
+    let timestamps = column
+        .as_any()
+        .downcast_ref::<TimestampMicrosecondArray>()
+        .unwrap();
+    assert_eq!(timestamps.len(), 2);
+    std::fs::remove_dir_all(output).unwrap();
+
+    let reading_id = app.create_reading(fixture.device_id, "21.5").await;
+    app.admin(
+        r#"
+        mutation($siteId: Int!) {
+            setRetentionPolicy(input: { siteId: $siteId, rawRetentionDays: 1, hourlyRetentionDays: 30 }) { id }
+        }
+        "#,
+        json!({ "siteId": fixture.site_id }),
+    )
+    .await;
+    let job = RetentionJob::new(app.database.clone(), StdDuration::from_secs(3600));
+    assert_eq!(job.run(Utc::now()).await.unwrap(), 2);
+    // Running again finds nothing left to prune.
+    assert_eq!(job.run(Utc::now()).await.unwrap(), 0);
+
+    let data = app
+        .admin(
+            r#"
+            query($id: Int!) {
+                site(id: $id) {
+                    rooms { devices { readingSamples { id resolution sampleCount minValue maxValue avgValue } } }
+                }
+            }
+            "#,
+            json!({ "id": fixture.site_id }),
+        )
+        .await;
+    assert_eq!(
+        data["site"]["rooms"][0]["devices"][0]["readingSamples"],
+        json!([
+            {
+                "id": null, "resolution": "HOURLY", "sampleCount": 2,
+                "minValue": 20.0, "maxValue": 22.0, "avgValue": 21.0,
+            },
+            {
+                "id": reading_id, "resolution": "RAW", "sampleCount": 1,
+                "minValue": 21.5, "maxValue": 21.5, "avgValue": 21.5,
+            },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn rules_raise_alerts_and_changes_are_audited() {
+    let Some(app) = TestApp::postgres().await else {
+        return;
+    };
+    let fixture = app.fixture().await;
+    let engine = AutomationEngine::new(app.database.clone(), app.events.clone())
+        .unwrap()
+        .spawn();
+    let data = app
+        .admin(
+            r#"
+            mutation($input: AutomationRuleInput!) {
+                createAutomationRule(input: $input) { id trigger }
+            }
+            "#,
+            json!({ "input": {
+                "siteId": fixture.site_id,
+                "name": "Too hot",
+                "trigger": { "type": "sensor_threshold", "device_id": fixture.device_id, "comparison": "above", "value": 25.0 },
+                "actions": [{ "type": "raise_alert", "severity": "Warning", "message": "It is too hot" }],
+            } }),
+        )
+        .await;
+    let rule_id = id(&data["createAutomationRule"]);
+    assert_eq!(
+        data["createAutomationRule"]["trigger"]["device_id"],
+        fixture.device_id
+    );
+    app.create_reading(fixture.device_id, "30").await;
+
+    let alerts = "query($siteId: Int!) { alerts(siteId: $siteId) { id message acknowledgedAt } }";
+    let mut listed = Value::Null;
+    for _ in 0..50 {
+        listed = app
+            .admin(alerts, json!({ "siteId": fixture.site_id }))
+            .await["alerts"]
+            .clone();
+        if !listed.as_array().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(StdDuration::from_millis(20)).await;
+    }
+    engine.abort();
+    assert_eq!(listed[0]["message"], "It is too hot");
+    let data = app
+        .admin(
+            r#"
+            query($ruleId: Int!) { ruleExecutions(ruleId: $ruleId) { status } }
+            "#,
+            json!({ "ruleId": rule_id }),
+        )
+        .await;
+    assert_eq!(data["ruleExecutions"], json!([{ "status": "SUCCEEDED" }]));
+    let data = app
+        .admin(
+            "mutation($id: Int!) { acknowledgeAlert(id: $id) { acknowledgedAt } }",
+            json!({ "id": id(&listed[0]) }),
+        )
+        .await;
+    assert!(data["acknowledgeAlert"]["acknowledgedAt"].is_string());
+
+    let data = app
+        .admin(
+            "mutation($id: Int!) { deleteAutomationRule(id: $id) }",
+            json!({ "id": rule_id }),
+        )
+        .await;
+    assert_eq!(data["deleteAutomationRule"], true);
+
+    let data = app
+        .admin(
+            r#"
+            query($siteId: Int!) {
+                auditLogs(filter: { siteId: $siteId, entityType: DEVICE }) { operation entityId actorName arguments }
+            }
+            "#,
+            json!({ "siteId": fixture.site_id }),
+        )
+        .await;
+    assert_eq!(
+        data["auditLogs"],
+        json!([{
+            "operation": "createDevice",
+            "entityId": fixture.device_id,
+            "actorName": "Admin",
+            "arguments": {
+                "room_id": fixture.room_id,
+                "name": "sensor-1",
+                "device_type": "TemperatureSensor",
+                "unique_identifier": "sensor-1",
+            },
+        }])
+    );
+}
+
+#[tokio::test]
+async fn webhook_deliveries_are_queued_and_dropped_when_disabled() {
+    let Some(app) = TestApp::postgres().await else {
+        return;
+    };
+    let fixture = app.fixture().await;
+    let subscribe = r#"
+        mutation($input: WebhookSubscriptionInput!) {
+            createWebhookSubscription(input: $input) { subscription { id eventTypes } }
+        }
+    "#;
+    let mut input = json!({ "input": {
+        "siteId": fixture.site_id,
+        "url": "https://203.0.113.10/hook",
+        "eventTypes": ["SENSOR_READING_CREATED"],
+    } });
+    let data = app.admin(subscribe, input.clone()).await;
+    let subscription_id = id(&data["createWebhookSubscription"]["subscription"]);
+
+    let dispatcher = WebhookDispatcher::new(app.database.clone(), EventBus::new(), 3).unwrap();
+    let now = Utc::now();
+    let event = Event::SensorReadingCreated {
+        site_id: fixture.site_id,
+        reading: SensorReading {
+            id: 1,
+            device_id: fixture.device_id,
+            value: "21.5".to_string(),
+            unit: None,
+            timestamp: now,
+            created_at: now,
+            updated_at: now,
+        },
+    };
+    assert_eq!(dispatcher.enqueue(&event).await.unwrap(), 1);
+
+    input["input"]["enabled"] = json!(false);
+    input["id"] = json!(subscription_id);
+    app.admin(
+        r#"
+        mutation($id: Int!, $input: WebhookSubscriptionInput!) {
+            updateWebhookSubscription(id: $id, input: $input) { enabled }
+        }
+        "#,
+        input,
+    )
+    .await;
+    assert_eq!(dispatcher.deliver_due(Utc::now()).await.unwrap(), 1);
+
+    let data = app
+        .admin(
+            r#"
+            query($id: Int!) {
+                webhookDeliveries(subscriptionId: $id, status: FAILED) { status eventType attempts lastError }
+            }
+            "#,
+            json!({ "id": subscription_id }),
+        )
+        .await;
+    assert_eq!(
+        data["webhookDeliveries"],
+        json!([{
+            "status": "FAILED",
+            "eventType": "SENSOR_READING_CREATED",
+            "attempts": 0,
+            "lastError": "The subscription is disabled",
+        }])
+    );
+}
+
+#[tokio::test]
+async fn entities_are_searched_and_filtered_by_tags() {
+    let Some(app) = TestApp::postgres().await else {
+        return;
+    };
+    let data = app
+        .admin(
+            r#"mutation { createSite(input: { name: "Nordstan", address: "Götgatan 11, Göteborg" }) { id } }"#,
+            json!({}),
+        )
+        .await;
+    let site = id(&data["createSite"]);
+    app.create_site("Harbour Office").await;
+    let room = app.create_room(site, "Systembolaget Main Room").await;
+    let device = app
+        .create_device(room, "Systembolaget Main Temperature Sensor")
+        .await;
+    app.create_device(room, "ab:cd:ef-01").await;
+    let search = r#"
+        query($query: String!, $tags: [TagFilter!]! = []) {
+            search(query: $query, tags: $tags) {
+                __typename
+                ... on Site { name }
+                ... on Room { name }
+                ... on Device { name }
+            }
+        }
+    "#;
+    let found = |query: &'static str, tags: Value| {
+        let app = &app;
+        async move {
+            app.admin(search, json!({ "query": query, "tags": tags }))
+                .await["search"]
+                .clone()
+        }
+    };
+
+    assert_eq!(
+        found("systembolaget temp", json!([])).await,
+        json!([{ "__typename": "Device", "name": "Systembolaget Main Temperature Sensor" }])
+    );
+    assert_eq!(
+        found("Systembolaget main", json!([])).await,
+        json!([
+            { "__typename": "Room", "name": "Systembolaget Main Room" },
+            { "__typename": "Device", "name": "Systembolaget Main Temperature Sensor" },
+        ])
+    );
+    assert_eq!(
+        found("goteborg", json!([])).await,
+        json!([{ "__typename": "Site", "name": "Nordstan" }])
+    );
+    assert_eq!(
+        found("ab:cd:ef-01", json!([])).await,
+        json!([{ "__typename": "Device", "name": "ab:cd:ef-01" }])
+    );
+    assert_eq!(found("  \"* ", json!([])).await, json!([]));
+
+    app.admin(
+        r#"mutation { createSite(input: { name: "Depot", address: "Harbour Road 1" }) { id } }"#,
+        json!({}),
+    )
+    .await;
+    assert_eq!(
+        found("harbour", json!([])).await,
+        json!([
+            { "__typename": "Site", "name": "Harbour Office" },
+            { "__typename": "Site", "name": "Depot" },
+        ])
+    );
+
+    app.admin(
+        r#"
+        mutation($site: Int!, $device: Int!) {
+            region: setTag(entity: SITE, entityId: $site, key: "region", value: "west") { key }
+            vendor: setTag(entity: DEVICE, entityId: $device, key: "vendor", value: "Acme") { key }
+        }
+        "#,
+        json!({ "site": site, "device": device }),
+    )
+    .await;
+    assert_eq!(
+        found(
+            "systembolaget",
+            json!([{ "key": "vendor", "value": "Acme" }])
+        )
+        .await,
+        json!([{ "__typename": "Device", "name": "Systembolaget Main Temperature Sensor" }])
+    );
+    let data = app
+        .admin(
+            r#"{ sites(tags: [{ key: "region" }]) { name } }"#,
+            json!({}),
+        )
+        .await;
+    assert_eq!(data["sites"], json!([{ "name": "Nordstan" }]));
+
+    let (viewer, _) = app.create_user("Viewer").await;
+    let response = app
+        .as_user(&viewer, search, json!({ "query": "nordstan" }))
+        .await;
+    assert_eq!(common::data(response)["search"], json!([]));
+}
+
+#[tokio::test]
+async fn roles_are_granted_and_metrics_are_rendered() {
+    let Some(app) = TestApp::postgres().await else {
+        return;
+    };
+    let fixture = app.fixture().await;
+    let (user, _) = app.create_user("Operator").await;
+    let grant = r#"
+        mutation($userId: Int!, $siteId: Int!, $role: Role!) {
+            grantSiteRole(input: { userId: $userId, siteId: $siteId, role: $role }) { role }
+        }
+    "#;
+    for role in ["VIEWER", "OPERATOR"] {
+        let data = app
+            .admin(
+                grant,
+                json!({ "userId": user.id, "siteId": fixture.site_id, "role": role }),
+            )
+            .await;
+        assert_eq!(data["grantSiteRole"]["role"], role);
+    }
+    let response = app
+        .as_user(
+            &user,
+            "mutation($id: Int!) { createSensorReading(input: { deviceId: $id, value: \"21.5\", unit: CELSIUS }) { id } }",
+            json!({ "id": fixture.device_id }),
+        )
+        .await;
+    data(response);
+    let response = app
+        .as_user(
+            &user,
+            grant,
+            json!({ "userId": user.id, "siteId": fixture.site_id, "role": "ADMIN" }),
+        )
+        .await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+
+    let response = app.client.get("/metrics").dispatch().await;
+    assert_eq!(response.status(), Status::Ok);
+    let metrics = response.into_string().await.unwrap();
+    assert!(metrics.contains(&format!(
+        r#"sh_backend_sensor_reading_latest_value{{device_id="{}",device_name="sensor-1",room_id="{}",site_id="{}",unit="Celsius"}} 21.5"#,
+        fixture.device_id, fixture.room_id, fixture.site_id
+    )));
+}
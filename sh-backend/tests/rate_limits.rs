@@ -0,0 +1,280 @@
+mod common;
+
+use common::{TestApp, error_code};
+use rocket::http::{ContentType, Header, Status};
+use serde_json::{Value, json};
+use sh_backend::config::Config;
+use sh_backend::models::Role;
+
+const RECORD_READING: &str = r#"mutation($deviceId: Int!) { createSensorReading(input: { deviceId: $deviceId, value: "20" }) { id } }"#;
+
+fn limits(ingest_burst: u32, query_burst: u32) -> Config {
+    let mut config = Config::default();
+    config.rate_limits.ingest_per_minute = 1;
+    config.rate_limits.ingest_burst = ingest_burst;
+    config.rate_limits.query_per_minute = 1;
+    config.rate_limits.query_burst = query_burst;
+    config
+}
+
+impl TestApp {
+    async fn device_key(&self, device_id: i64) -> String {
+        let data = self
+            .admin(
+                "mutation($deviceId: Int!) { createDeviceCredential(deviceId: $deviceId) { key } }",
+                json!({ "deviceId": device_id }),
+            )
+            .await;
+        data["createDeviceCredential"]["key"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    /// Posts an operation from the given address, returning the `Retry-After` header along with the body.
+    async fn post_from(
+        &self,
+        address: &str,
+        token: Option<&str>,
+        query: &str,
+        variables: Value,
+    ) -> (Option<String>, Value) {
+        let mut request = self
+            .client
+            .post("/graphql")
+            .remote(address.parse().unwrap())
+            .header(ContentType::JSON)
+            .body(json!({ "query": query, "variables": variables }).to_string());
+        if let Some(token) = token {
+            request = request.header(Header::new("Authorization", format!("Bearer {}", token)));
+        }
+        let response = request.dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let retry_after = response.headers().get_one("Retry-After").map(String::from);
+        let body = response.into_string().await.unwrap();
+        (retry_after, serde_json::from_str(&body).unwrap())
+    }
+}
+
+#[tokio::test]
+async fn device_keys_recording_too_many_readings_are_told_when_to_retry() {
+    let app = TestApp::with_config(limits(2, 100)).await;
+    let fixture = app.fixture().await;
+    let key = app.device_key(fixture.device_id).await;
+    let variables = json!({ "deviceId": fixture.device_id });
+
+    for _ in 0..2 {
+        let (retry_after, body) = app
+            .post_from(
+                "10.0.0.1:1000",
+                Some(&key),
+                RECORD_READING,
+                variables.clone(),
+            )
+            .await;
+        assert!(body["errors"].is_null(), "{}", body);
+        assert_eq!(retry_after, None);
+    }
+    let (retry_after, body) = app
+        .post_from(
+            "10.0.0.1:1000",
+            Some(&key),
+            RECORD_READING,
+            variables.clone(),
+        )
+        .await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "RATE_LIMITED");
+    assert_eq!(
+        body["errors"][0]["message"],
+        "Too many readings, retry in 60s"
+    );
+    assert_eq!(body["errors"][0]["extensions"]["retryAfter"], 60);
+    assert_eq!(retry_after.as_deref(), Some("60"));
+
+    // Other devices on the same gateway have keys of their own.
+    let other_device = app.create_device(fixture.room_id, "sensor-2").await;
+    let other_key = app.device_key(other_device).await;
+    let (_, body) = app
+        .post_from(
+            "10.0.0.1:1000",
+            Some(&other_key),
+            RECORD_READING,
+            json!({ "deviceId": other_device }),
+        )
+        .await;
+    assert!(body["errors"].is_null(), "{}", body);
+}
+
+#[tokio::test]
+async fn ingest_and_queries_are_limited_separately() {
+    // Leaves room for the administrator to set up the fixture.
+    let app = TestApp::with_config(limits(1, 4)).await;
+    let fixture = app.fixture().await;
+    let (user, _) = app.create_user("Gateway").await;
+    app.grant(&user, fixture.site_id, Role::Operator).await;
+    let variables = json!({ "deviceId": fixture.device_id });
+
+    let response = app.as_user(&user, RECORD_READING, variables.clone()).await;
+    assert!(response.errors.is_empty());
+    let response = app.as_user(&user, RECORD_READING, variables).await;
+    assert_eq!(error_code(response), "RATE_LIMITED");
+
+    let sites = "{ sites { id } }";
+    for _ in 0..4 {
+        let response = app.as_user(&user, sites, json!({})).await;
+        assert!(response.errors.is_empty());
+    }
+    let response = app.as_user(&user, sites, json!({})).await;
+    assert_eq!(
+        response.errors[0].message,
+        "Too many requests, retry in 60s"
+    );
+    assert_eq!(error_code(response), "RATE_LIMITED");
+
+    // The limits are per user.
+    let (other, _) = app.create_user("Dashboard").await;
+    let response = app.as_user(&other, sites, json!({})).await;
+    assert!(response.errors.is_empty());
+}
+
+#[tokio::test]
+async fn anonymous_requests_are_limited_by_address() {
+    let app = TestApp::with_config(limits(1, 1)).await;
+    let query = "{ __typename }";
+
+    let (_, body) = app.post_from("10.0.0.1:1000", None, query, json!({})).await;
+    assert_eq!(body["data"]["__typename"], "SiteQueryRoot");
+    let (retry_after, body) = app.post_from("10.0.0.1:2000", None, query, json!({})).await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "RATE_LIMITED");
+    assert_eq!(retry_after.as_deref(), Some("60"));
+
+    let (_, body) = app.post_from("10.0.0.2:1000", None, query, json!({})).await;
+    assert_eq!(body["data"]["__typename"], "SiteQueryRoot");
+}
+
+#[tokio::test]
+async fn rate_limits_can_be_turned_off() {
+    let mut config = limits(1, 1);
+    config.rate_limits.enabled = false;
+    let app = TestApp::with_config(config).await;
+
+    for _ in 0..5 {
+        let (retry_after, body) = app
+            .post_from("10.0.0.1:1000", None, "{ __typename }", json!({}))
+            .await;
+        assert_eq!(body["data"]["__typename"], "SiteQueryRoot");
+        assert_eq!(retry_after, None);
+    }
+}
+
+#[tokio::test]
+async fn every_recorded_reading_counts_against_the_limit() {
+    let app = TestApp::with_config(limits(3, 100)).await;
+    let fixture = app.fixture().await;
+    let key = app.device_key(fixture.device_id).await;
+    let variables = json!({ "deviceId": fixture.device_id });
+
+    let aliased = r#"
+        mutation($deviceId: Int!) {
+            first: createSensorReading(input: { deviceId: $deviceId, value: "20" }) { id }
+            second: createSensorReading(input: { deviceId: $deviceId, value: "21" }) { id }
+        }
+    "#;
+    let (_, body) = app
+        .post_from("10.0.0.1:1000", Some(&key), aliased, variables.clone())
+        .await;
+    assert!(body["errors"].is_null(), "{}", body);
+
+    // Fragments don't hide readings either, and one of the three is left.
+    let fragments = r#"
+        mutation($deviceId: Int!) { ...Record ... on SiteMutationRoot { other: createSensorReading(input: { deviceId: $deviceId, value: "23" }) { id } } }
+        fragment Record on SiteMutationRoot { createSensorReading(input: { deviceId: $deviceId, value: "22" }) { id } }
+    "#;
+    let (retry_after, body) = app
+        .post_from("10.0.0.1:1000", Some(&key), fragments, variables.clone())
+        .await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "RATE_LIMITED");
+    assert_eq!(retry_after.as_deref(), Some("60"));
+
+    let five = format!("mutation($deviceId: Int!) {{ {} }}", (0..5)
+        .map(|index| format!("r{}: createSensorReading(input: {{ deviceId: $deviceId, value: \"20\" }}) {{ id }}", index))
+        .collect::<Vec<_>>()
+        .join(" "));
+    let (retry_after, body) = app
+        .post_from("10.0.0.1:1000", Some(&key), &five, variables)
+        .await;
+    assert_eq!(
+        body["errors"][0]["message"],
+        "Too many readings at once, at most 3 are allowed"
+    );
+    assert_eq!(retry_after, None);
+}
+
+#[tokio::test]
+async fn imports_count_every_row_against_the_limit() {
+    let app = TestApp::with_config(limits(3, 100)).await;
+    app.fixture().await;
+    let import = "mutation($file: Upload!) { importSensorReadings(file: $file) { imported } }";
+    let rows = |count: usize| {
+        let mut csv = String::from("device,value,timestamp\n");
+        for hour in 0..count {
+            csv.push_str(&format!("sensor-1,20,2025-01-01T{:02}:00:00Z\n", hour));
+        }
+        csv
+    };
+
+    let (_, body) = app
+        .upload(&app.admin_token, import, "readings.csv", &rows(4))
+        .await;
+    assert_eq!(
+        body["errors"][0]["message"],
+        "Too many readings at once, at most 3 are allowed"
+    );
+
+    let (_, body) = app
+        .upload(&app.admin_token, import, "readings.csv", &rows(2))
+        .await;
+    assert_eq!(body["data"]["importSensorReadings"]["imported"], 2);
+    let (_, body) = app
+        .upload(&app.admin_token, import, "readings.csv", &rows(2))
+        .await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "RATE_LIMITED");
+    assert_eq!(body["errors"][0]["extensions"]["retryAfter"], 60);
+}
+
+#[tokio::test]
+async fn forwarded_addresses_are_only_taken_from_trusted_proxies() {
+    let mut config = limits(1, 1);
+    config.http.trusted_proxies = vec!["10.0.0.9".parse().unwrap()];
+    let app = TestApp::with_config(config).await;
+    let post = |remote: &'static str, real_ip: &'static str| {
+        let app = &app;
+        async move {
+            let response = app
+                .client
+                .post("/graphql")
+                .remote(remote.parse().unwrap())
+                .header(ContentType::JSON)
+                .header(Header::new("X-Real-IP", real_ip))
+                .body(json!({ "query": "{ __typename }" }).to_string())
+                .dispatch()
+                .await;
+            let body = response.into_string().await.unwrap();
+            serde_json::from_str::<Value>(&body).unwrap()
+        }
+    };
+
+    // Clients connecting directly can't pose as others.
+    let body = post("10.0.0.1:1000", "192.168.0.1").await;
+    assert_eq!(body["data"]["__typename"], "SiteQueryRoot");
+    let body = post("10.0.0.1:1000", "192.168.0.2").await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "RATE_LIMITED");
+
+    // Behind the proxy every client has a bucket of its own.
+    let body = post("10.0.0.9:1000", "192.168.0.1").await;
+    assert_eq!(body["data"]["__typename"], "SiteQueryRoot");
+    let body = post("10.0.0.9:1000", "192.168.0.2").await;
+    assert_eq!(body["data"]["__typename"], "SiteQueryRoot");
+    let body = post("10.0.0.9:1000", "192.168.0.2").await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "RATE_LIMITED");
+}
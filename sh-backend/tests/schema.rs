@@ -0,0 +1,111 @@
+use sh_backend::sdl::{breaking_changes, export_sdl};
+
+const OLD: &str = r#"
+type Query {
+    site(id: Int!): Site
+    sites(limit: Int): [Site!]!
+}
+
+type Site {
+    id: Int!
+    name: String!
+    rooms: [Room!]!
+}
+
+type Room {
+    id: Int!
+}
+
+enum Role {
+    VIEWER
+    ADMIN
+}
+
+input SiteInput {
+    name: String!
+    timezone: String
+}
+"#;
+
+#[test]
+fn the_committed_schema_is_up_to_date() {
+    let committed = include_str!("../schema.graphql");
+    assert!(
+        committed == export_sdl(),
+        "schema.graphql is out of date, run `cargo run -- export-schema --output schema.graphql`"
+    );
+}
+
+#[test]
+fn additions_are_not_breaking() {
+    let new = OLD
+        .replace("rooms: [Room!]!", "rooms: [Room!]!\n    address: String")
+        .replace("sites(limit: Int)", "sites(limit: Int, offset: Int = 0)")
+        .replace("ADMIN", "ADMIN\n    OPERATOR")
+        .replace("timezone: String", "timezone: String\n    address: String");
+    assert_eq!(breaking_changes(OLD, &new).unwrap(), Vec::<String>::new());
+}
+
+#[test]
+fn outputs_can_become_non_null_and_inputs_nullable() {
+    let new = OLD
+        .replace("site(id: Int!): Site", "site(id: Int): Site!")
+        .replace("name: String!\n    timezone", "name: String\n    timezone");
+    assert_eq!(breaking_changes(OLD, &new).unwrap(), Vec::<String>::new());
+}
+
+#[test]
+fn removals_are_breaking() {
+    let new = OLD
+        .replace("    rooms: [Room!]!\n", "")
+        .replace("sites(limit: Int)", "sites")
+        .replace("    ADMIN\n", "")
+        .replace("    timezone: String\n", "")
+        .replace("type Room {\n    id: Int!\n}\n", "");
+    assert_eq!(
+        breaking_changes(OLD, &new).unwrap(),
+        [
+            "Argument `Query.sites(limit)` was removed",
+            "Enum value `Role.ADMIN` was removed",
+            "Type `Room` was removed",
+            "Field `Site.rooms` was removed",
+            "Input field `SiteInput.timezone` was removed",
+        ]
+    );
+}
+
+#[test]
+fn nullability_changes_are_breaking() {
+    let new = OLD
+        .replace(
+            "name: String!\n    rooms: [Room!]!",
+            "name: String\n    rooms: [Room]!",
+        )
+        .replace("sites(limit: Int)", "sites(limit: Int!)")
+        .replace("timezone: String", "timezone: String!");
+    assert_eq!(
+        breaking_changes(OLD, &new).unwrap(),
+        [
+            "Argument `Query.sites(limit)` changed type from `Int` to `Int!`",
+            "Field `Site.name` changed type from `String!` to `String`",
+            "Field `Site.rooms` changed type from `[Room!]!` to `[Room]!`",
+            "Input field `SiteInput.timezone` changed type from `String` to `String!`",
+        ]
+    );
+}
+
+#[test]
+fn new_required_inputs_and_changed_kinds_are_breaking() {
+    let new = OLD
+        .replace("site(id: Int!)", "site(id: Int!, siteKey: String!)")
+        .replace("timezone: String", "timezone: String\n    owner: Int!")
+        .replace("enum Role {\n    VIEWER\n    ADMIN\n}", "scalar Role");
+    assert_eq!(
+        breaking_changes(OLD, &new).unwrap(),
+        [
+            "Argument `Query.site(siteKey)` was added as required",
+            "Type `Role` changed from an enum to a scalar",
+            "Input field `SiteInput.owner` was added as required",
+        ]
+    );
+}
@@ -0,0 +1,181 @@
+mod common;
+
+use common::{TestApp, data};
+use serde_json::{Value, json};
+use sh_backend::models::Role;
+
+const SEARCH: &str = r#"
+    query($query: String!, $tags: [TagFilter!]! = []) {
+        search(query: $query, tags: $tags) {
+            __typename
+            ... on Site { name }
+            ... on Room { name }
+            ... on Device { name }
+        }
+    }
+"#;
+
+impl TestApp {
+    async fn search(&self, query: &str) -> Value {
+        self.admin(SEARCH, json!({ "query": query })).await["search"].clone()
+    }
+}
+
+#[tokio::test]
+async fn sites_rooms_and_devices_are_found_by_name() {
+    let app = TestApp::new().await;
+    let site = app.create_site("Nordstan").await;
+    let room = app.create_room(site, "Systembolaget Main Room").await;
+    app.create_device(room, "Systembolaget Main Temperature Sensor")
+        .await;
+    app.create_device(room, "Backroom Sensor").await;
+
+    assert_eq!(
+        app.search("systembolaget temp").await,
+        json!([{ "__typename": "Device", "name": "Systembolaget Main Temperature Sensor" }])
+    );
+    assert_eq!(
+        app.search("Systembolaget main").await,
+        json!([
+            { "__typename": "Room", "name": "Systembolaget Main Room" },
+            { "__typename": "Device", "name": "Systembolaget Main Temperature Sensor" },
+        ])
+    );
+    assert_eq!(
+        app.search("nord").await,
+        json!([{ "__typename": "Site", "name": "Nordstan" }])
+    );
+    assert_eq!(app.search("kitchen").await, json!([]));
+    assert_eq!(app.search("  \"* ").await, json!([]));
+}
+
+#[tokio::test]
+async fn addresses_and_unique_identifiers_are_searched() {
+    let app = TestApp::new().await;
+    let data = app
+        .admin(
+            r#"mutation { createSite(input: { name: "Nordstan", address: "Götgatan 11, Göteborg" }) { id } }"#,
+            json!({}),
+        )
+        .await;
+    let site = common::id(&data["createSite"]);
+    let room = app.create_room(site, "Hall").await;
+    // The identifier doubles as the name in the fixture helper.
+    app.create_device(room, "ab:cd:ef-01").await;
+
+    assert_eq!(
+        app.search("goteborg").await,
+        json!([{ "__typename": "Site", "name": "Nordstan" }])
+    );
+    assert_eq!(
+        app.search("ab:cd:ef-01").await,
+        json!([{ "__typename": "Device", "name": "ab:cd:ef-01" }])
+    );
+}
+
+#[tokio::test]
+async fn matches_in_names_rank_above_matches_in_details() {
+    let app = TestApp::new().await;
+    app.admin(
+        r#"mutation { createSite(input: { name: "Depot", address: "Harbour Road 1" }) { id } }"#,
+        json!({}),
+    )
+    .await;
+    app.create_site("Harbour Office").await;
+
+    assert_eq!(
+        app.search("harbour").await,
+        json!([
+            { "__typename": "Site", "name": "Harbour Office" },
+            { "__typename": "Site", "name": "Depot" },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn results_of_different_kinds_keep_the_order_of_relevance() {
+    let app = TestApp::new().await;
+    let data = app
+        .admin(
+            r#"mutation { createSite(input: { name: "Depot", address: "Harbour Road 1" }) { id } }"#,
+            json!({}),
+        )
+        .await;
+    let site = common::id(&data["createSite"]);
+    app.create_room(site, "Harbour").await;
+
+    assert_eq!(
+        app.search("harbour").await,
+        json!([
+            { "__typename": "Room", "name": "Harbour" },
+            { "__typename": "Site", "name": "Depot" },
+        ])
+    );
+}
+
+#[tokio::test]
+async fn renamed_and_deleted_entities_are_reindexed() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    sqlx::query("UPDATE Room SET name = 'Server Room' WHERE id = ?")
+        .bind(fixture.room_id)
+        .execute(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(
+        app.search("server").await,
+        json!([{ "__typename": "Room", "name": "Server Room" }])
+    );
+
+    sqlx::query("DELETE FROM Device WHERE id = ?")
+        .bind(fixture.device_id)
+        .execute(app.pool())
+        .await
+        .unwrap();
+    assert_eq!(app.search("sensor").await, json!([]));
+}
+
+#[tokio::test]
+async fn users_only_find_what_their_roles_cover() {
+    let app = TestApp::new().await;
+    let visible = app.create_site("Visible Site").await;
+    app.create_site("Hidden Site").await;
+    let (viewer, _) = app.create_user("Viewer").await;
+    app.grant(&viewer, visible, Role::Viewer).await;
+
+    let found = data(
+        app.as_user(&viewer, SEARCH, json!({ "query": "site" }))
+            .await,
+    );
+    assert_eq!(
+        found["search"],
+        json!([{ "__typename": "Site", "name": "Visible Site" }])
+    );
+}
+
+#[tokio::test]
+async fn results_are_filtered_by_tags() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let other = app.create_device(fixture.room_id, "sensor-2").await;
+    app.admin(
+        r#"
+        mutation($id: Int!) {
+            setTag(entity: DEVICE, entityId: $id, key: "vendor", value: "Danfoss") { key }
+        }
+        "#,
+        json!({ "id": other }),
+    )
+    .await;
+
+    let data = app
+        .admin(
+            SEARCH,
+            json!({ "query": "sensor", "tags": [{ "key": "vendor", "value": "Danfoss" }] }),
+        )
+        .await;
+    assert_eq!(
+        data["search"],
+        json!([{ "__typename": "Device", "name": "sensor-2" }])
+    );
+}
@@ -0,0 +1,715 @@
+mod common;
+
+use arrow_array::TimestampMicrosecondArray;
+use chrono::{Duration, SecondsFormat, Utc};
+use common::{TestApp, data, error, error_code};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use rocket::http::{Header, Status};
+use serde_json::{Value, json};
+use sh_backend::auth::Identity;
+use sh_backend::config::Config;
+use sh_backend::export::ExportFilter;
+use sh_backend::models::Role;
+use sh_backend::parquet_export::export_parquet;
+
+#[tokio::test]
+async fn me_returns_the_caller() {
+    let app = TestApp::new().await;
+    let data = app.admin("{ me { name isAdmin } }", json!({})).await;
+    assert_eq!(data["me"], json!({ "name": "Admin", "isAdmin": true }));
+
+    let response = app
+        .execute(Identity::Anonymous, "{ me { name } }", json!({}))
+        .await;
+    assert_eq!(common::data(response)["me"], json!(null));
+}
+
+#[tokio::test]
+async fn sites_are_limited_to_the_roles_of_the_user() {
+    let app = TestApp::new().await;
+    let first = app.create_site("First").await;
+    app.create_site("Second").await;
+    let (viewer, _) = app.create_user("Viewer").await;
+    app.grant(&viewer, first, Role::Viewer).await;
+
+    let all = app.admin("{ sites { name } }", json!({})).await;
+    assert_eq!(
+        all["sites"],
+        json!([{ "name": "First" }, { "name": "Second" }])
+    );
+
+    let own = data(app.as_user(&viewer, "{ sites { name } }", json!({})).await);
+    assert_eq!(own["sites"], json!([{ "name": "First" }]));
+}
+
+#[tokio::test]
+async fn sites_require_authentication() {
+    let app = TestApp::new().await;
+    let response = app
+        .execute(Identity::Anonymous, "{ sites { id } }", json!({}))
+        .await;
+    assert_eq!(error_code(response), "UNAUTHENTICATED");
+}
+
+#[tokio::test]
+async fn site_resolves_rooms_devices_readings_and_setpoints() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    app.create_reading(fixture.device_id, "21.5").await;
+    app.admin(
+        r#"
+        mutation($deviceId: Int!) {
+            createControlSetpoint(input: { deviceId: $deviceId, setpointType: TEMPERATURE, value: "22", unit: CELSIUS }) { id }
+        }
+        "#,
+        json!({ "deviceId": fixture.device_id }),
+    )
+    .await;
+
+    let data = app
+        .admin(
+            r#"
+            query($id: Int!) {
+                site(id: $id) {
+                    name
+                    rooms {
+                        name
+                        devices {
+                            name
+                            deviceType
+                            sensorReadings { value unit }
+                            controlSetpoints { value mode }
+                            readingSamples { resolution sampleCount avgValue }
+                        }
+                    }
+                }
+            }
+            "#,
+            json!({ "id": fixture.site_id }),
+        )
+        .await;
+    assert_eq!(
+        data["site"],
+        json!({
+            "name": "Site",
+            "rooms": [{
+                "name": "Room",
+                "devices": [{
+                    "name": "sensor-1",
+                    "deviceType": "TEMPERATURE_SENSOR",
+                    "sensorReadings": [{ "value": "21.5", "unit": "CELSIUS" }],
+                    "controlSetpoints": [{ "value": "22", "mode": "SCHEDULED" }],
+                    "readingSamples": [{ "resolution": "RAW", "sampleCount": 1, "avgValue": 21.5 }],
+                }],
+            }],
+        })
+    );
+}
+
+#[tokio::test]
+async fn site_is_forbidden_without_a_role() {
+    let app = TestApp::new().await;
+    let site_id = app.create_site("Site").await;
+    let (user, _) = app.create_user("Stranger").await;
+
+    let response = app
+        .as_user(
+            &user,
+            "query($id: Int!) { site(id: $id) { name } }",
+            json!({ "id": site_id }),
+        )
+        .await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+}
+
+#[tokio::test]
+async fn missing_entities_resolve_to_nothing() {
+    let app = TestApp::new().await;
+    let data = app
+        .admin(
+            r#"
+            {
+                site(id: 404) { id }
+                room(id: 404) { id }
+                devicesInRoom(roomId: 404) { id }
+                latestSensorReading(deviceId: 404) { id }
+                latestControlSetpoint(deviceId: 404) { id }
+            }
+            "#,
+            json!({}),
+        )
+        .await;
+    assert_eq!(
+        data,
+        json!({
+            "site": null,
+            "room": null,
+            "devicesInRoom": [],
+            "latestSensorReading": null,
+            "latestControlSetpoint": null,
+        })
+    );
+}
+
+#[tokio::test]
+async fn room_and_devices_in_room() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let (viewer, _) = app.create_user("Viewer").await;
+    app.grant(&viewer, fixture.site_id, Role::Viewer).await;
+
+    let response = app
+        .as_user(
+            &viewer,
+            "query($id: Int!) { room(id: $id) { name siteId } devicesInRoom(roomId: $id) { name } }",
+            json!({ "id": fixture.room_id }),
+        )
+        .await;
+    assert_eq!(
+        data(response),
+        json!({
+            "room": { "name": "Room", "siteId": fixture.site_id },
+            "devicesInRoom": [{ "name": "sensor-1" }],
+        })
+    );
+}
+
+#[tokio::test]
+async fn latest_sensor_reading_is_the_newest() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    sqlx::query(
+        "INSERT INTO SensorReading (device_id, value, timestamp) VALUES (?, '19.0', ?), (?, '20.0', ?)",
+    )
+    .bind(fixture.device_id)
+    .bind(Utc::now() - Duration::hours(2))
+    .bind(fixture.device_id)
+    .bind(Utc::now() - Duration::hours(1))
+    .execute(app.pool())
+    .await
+    .unwrap();
+
+    let data = app
+        .admin(
+            "query($id: Int!) { latestSensorReading(deviceId: $id) { value } }",
+            json!({ "id": fixture.device_id }),
+        )
+        .await;
+    assert_eq!(data["latestSensorReading"]["value"], "20.0");
+}
+
+#[tokio::test]
+async fn create_site_requires_an_administrator() {
+    let app = TestApp::new().await;
+    let data = app
+        .admin(
+            r#"mutation { createSite(input: { name: "Depot", address: "Road 1" }) { name address } }"#,
+            json!({}),
+        )
+        .await;
+    assert_eq!(
+        data["createSite"],
+        json!({ "name": "Depot", "address": "Road 1" })
+    );
+
+    let (user, _) = app.create_user("User").await;
+    let response = app
+        .as_user(
+            &user,
+            r#"mutation { createSite(input: { name: "Depot" }) { id } }"#,
+            json!({}),
+        )
+        .await;
+    assert_eq!(error(response), "Administrator rights required");
+}
+
+#[tokio::test]
+async fn create_room_for_a_missing_site_fails() {
+    let app = TestApp::new().await;
+    let response = app
+        .execute(
+            Identity::User(app.admin.clone()),
+            r#"mutation { createRoom(input: { siteId: 404, name: "Hall" }) { id } }"#,
+            json!({}),
+        )
+        .await;
+    assert_eq!(error(response), "Site with ID 404 does not exist");
+}
+
+#[tokio::test]
+async fn create_room_requires_admin_rights_on_the_site() {
+    let app = TestApp::new().await;
+    let site_id = app.create_site("Site").await;
+    let (operator, _) = app.create_user("Operator").await;
+    app.grant(&operator, site_id, Role::Operator).await;
+
+    let response = app
+        .as_user(
+            &operator,
+            r#"mutation($siteId: Int!) { createRoom(input: { siteId: $siteId, name: "Hall" }) { id } }"#,
+            json!({ "siteId": site_id }),
+        )
+        .await;
+    assert_eq!(
+        error(response),
+        format!("Admin rights on site {} required", site_id)
+    );
+}
+
+#[tokio::test]
+async fn create_device_for_a_missing_room_fails() {
+    let app = TestApp::new().await;
+    let response = app
+        .execute(
+            Identity::User(app.admin.clone()),
+            r#"mutation { createDevice(input: { roomId: 404, name: "Sensor", deviceType: TEMPERATURE_SENSOR }) { id } }"#,
+            json!({}),
+        )
+        .await;
+    assert_eq!(error(response), "Room with ID 404 does not exist");
+}
+
+#[tokio::test]
+async fn create_sensor_reading_is_audited() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let reading_id = app.create_reading(fixture.device_id, "21.0").await;
+
+    let data = app
+        .admin(
+            r#"
+            query($siteId: Int!) {
+                auditLogs(filter: { siteId: $siteId, entityType: SENSOR_READING }) { operation entityId actorName }
+            }
+            "#,
+            json!({ "siteId": fixture.site_id }),
+        )
+        .await;
+    assert_eq!(
+        data["auditLogs"],
+        json!([{ "operation": "createSensorReading", "entityId": reading_id, "actorName": "Admin" }])
+    );
+}
+
+#[tokio::test]
+async fn create_sensor_reading_for_a_missing_device_fails() {
+    let app = TestApp::new().await;
+    let response = app
+        .execute(
+            Identity::User(app.admin.clone()),
+            r#"mutation { createSensorReading(input: { deviceId: 404, value: "20" }) { id } }"#,
+            json!({}),
+        )
+        .await;
+    assert_eq!(error(response), "Device with ID 404 does not exist");
+}
+
+#[tokio::test]
+async fn viewers_cannot_record_readings() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let (viewer, _) = app.create_user("Viewer").await;
+    app.grant(&viewer, fixture.site_id, Role::Viewer).await;
+
+    let response = app
+        .as_user(
+            &viewer,
+            r#"mutation($id: Int!) { createSensorReading(input: { deviceId: $id, value: "20" }) { id } }"#,
+            json!({ "id": fixture.device_id }),
+        )
+        .await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+}
+
+#[tokio::test]
+async fn overrides_take_precedence_until_released() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let create = r#"
+        mutation($input: ControlSetpointInput!) { createControlSetpoint(input: $input) { id mode } }
+    "#;
+    app.admin(
+        create,
+        json!({ "input": { "deviceId": fixture.device_id, "setpointType": "TEMPERATURE", "value": "21" } }),
+    )
+    .await;
+    let expires_at = (Utc::now() + Duration::hours(1)).to_rfc3339();
+    app.admin(
+        create,
+        json!({ "input": {
+            "deviceId": fixture.device_id,
+            "setpointType": "TEMPERATURE",
+            "value": "25",
+            "mode": "OVERRIDE",
+            "expiresAt": expires_at,
+        } }),
+    )
+    .await;
+
+    let latest = "query($id: Int!) { latestControlSetpoint(deviceId: $id) { value mode } }";
+    let data = app.admin(latest, json!({ "id": fixture.device_id })).await;
+    assert_eq!(
+        data["latestControlSetpoint"],
+        json!({ "value": "25", "mode": "OVERRIDE" })
+    );
+
+    let data = app
+        .admin(
+            "mutation($id: Int!) { releaseSetpointOverride(deviceId: $id) { value mode } }",
+            json!({ "id": fixture.device_id }),
+        )
+        .await;
+    assert_eq!(
+        data["releaseSetpointOverride"],
+        json!({ "value": "21", "mode": "SCHEDULED" })
+    );
+    let data = app.admin(latest, json!({ "id": fixture.device_id })).await;
+    assert_eq!(data["latestControlSetpoint"]["mode"], "SCHEDULED");
+}
+
+#[tokio::test]
+async fn create_control_setpoint_validates_expiry() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let create = r#"
+        mutation($input: ControlSetpointInput!) { createControlSetpoint(input: $input) { id } }
+    "#;
+    let input = |mode: &str, expires_at: Option<String>| {
+        json!({ "input": {
+            "deviceId": fixture.device_id,
+            "setpointType": "TEMPERATURE",
+            "value": "25",
+            "mode": mode,
+            "expiresAt": expires_at,
+        } })
+    };
+    let admin = || Identity::User(app.admin.clone());
+
+    let response = app.execute(admin(), create, input("OVERRIDE", None)).await;
+    assert_eq!(
+        error(response),
+        "Overrides need an expiry time in the future"
+    );
+
+    let past = Some((Utc::now() - Duration::hours(1)).to_rfc3339());
+    let response = app.execute(admin(), create, input("OVERRIDE", past)).await;
+    assert_eq!(
+        error(response),
+        "Overrides need an expiry time in the future"
+    );
+
+    let future = Some((Utc::now() + Duration::hours(1)).to_rfc3339());
+    let response = app.execute(admin(), create, input("HOLD", future)).await;
+    assert_eq!(error(response), "Only overrides can have an expiry time");
+
+    let response = app
+        .execute(
+            admin(),
+            create,
+            json!({ "input": { "deviceId": 404, "setpointType": "TEMPERATURE", "value": "25" } }),
+        )
+        .await;
+    assert_eq!(error(response), "Device with ID 404 does not exist");
+}
+
+#[tokio::test]
+async fn release_setpoint_override_of_a_missing_device_fails() {
+    let app = TestApp::new().await;
+    let response = app
+        .execute(
+            Identity::User(app.admin.clone()),
+            "mutation { releaseSetpointOverride(deviceId: 404) { id } }",
+            json!({}),
+        )
+        .await;
+    assert_eq!(error(response), "Device with ID 404 does not exist");
+}
+
+#[tokio::test]
+async fn requests_are_authenticated_by_bearer_token() {
+    let app = TestApp::new().await;
+    let (status, body) = app
+        .post(Some(&app.admin_token), "{ me { name } }", json!({}))
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["data"]["me"]["name"], "Admin");
+
+    let (status, body) = app.post(None, "{ me { name } }", json!({})).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["data"]["me"], json!(null));
+
+    let (status, _) = app
+        .post(Some("not-a-token"), "{ me { name } }", json!({}))
+        .await;
+    assert_eq!(status, Status::Unauthorized);
+}
+
+#[tokio::test]
+async fn devices_record_readings_with_their_key() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let other = app.create_device(fixture.room_id, "sensor-2").await;
+    let data = app
+        .admin(
+            "mutation($id: Int!) { createDeviceCredential(deviceId: $id) { key } }",
+            json!({ "id": fixture.device_id }),
+        )
+        .await;
+    let key = data["createDeviceCredential"]["key"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let record = r#"mutation($id: Int!) { createSensorReading(input: { deviceId: $id, value: "20.5" }) { value } }"#;
+    let (status, body) = app
+        .post(Some(&key), record, json!({ "id": fixture.device_id }))
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(body["data"]["createSensorReading"]["value"], "20.5");
+
+    let (_, body) = app.post(Some(&key), record, json!({ "id": other })).await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "FORBIDDEN");
+
+    let (_, body) = app.post(Some(&key), "{ sites { id } }", json!({})).await;
+    assert_eq!(body["errors"][0]["extensions"]["code"], "FORBIDDEN");
+}
+
+#[tokio::test]
+async fn import_sensor_readings_reports_rejected_lines() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let csv = "device,value,unit,timestamp\n\
+               sensor-1,20.5,C,2025-01-01T00:00:00Z\n\
+               sensor-1,warm,C,2025-01-01T01:00:00Z\n\
+               unknown,20.5,C,2025-01-01T02:00:00Z\n";
+
+    let (status, body) = app
+        .upload(
+            &app.admin_token,
+            "mutation($file: Upload!) { importSensorReadings(file: $file) { imported rejected { line reason } rejectedCount } }",
+            "readings.csv",
+            csv,
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(
+        body["data"]["importSensorReadings"],
+        json!({
+            "imported": 1,
+            "rejected": [
+                { "line": 3, "reason": "Invalid value \"warm\"" },
+                { "line": 4, "reason": "Unknown device \"unknown\"" },
+            ],
+            "rejectedCount": 2,
+        })
+    );
+
+    let data = app
+        .admin(
+            "query($id: Int!) { latestSensorReading(deviceId: $id) { value unit } }",
+            json!({ "id": fixture.device_id }),
+        )
+        .await;
+    assert_eq!(
+        data["latestSensorReading"],
+        json!({ "value": "20.5", "unit": "CELSIUS" })
+    );
+}
+
+#[tokio::test]
+async fn import_sensor_readings_is_audited_on_each_site() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let other_site = app.create_site("Other").await;
+    let other_room = app.create_room(other_site, "Hall").await;
+    app.create_device(other_room, "sensor-2").await;
+    let csv = "device,value,unit,timestamp\n\
+               sensor-1,20.5,C,2025-01-01T00:00:00Z\n\
+               sensor-2,19.5,C,2025-01-01T00:00:00Z\n\
+               sensor-2,19.0,C,2025-01-01T01:00:00Z\n\
+               sensor-2,warm,C,2025-01-01T02:00:00Z\n";
+    let (status, _) = app
+        .upload(
+            &app.admin_token,
+            "mutation($file: Upload!) { importSensorReadings(file: $file) { imported } }",
+            "readings.csv",
+            csv,
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+
+    let logs = r#"
+        query($siteId: Int!) {
+            auditLogs(filter: { siteId: $siteId, entityType: SENSOR_READING }) { operation siteId after }
+        }
+    "#;
+    for (site_id, imported) in [(fixture.site_id, 1), (other_site, 2)] {
+        let data = app.admin(logs, json!({ "siteId": site_id })).await;
+        assert_eq!(
+            data["auditLogs"],
+            json!([{
+                "operation": "importSensorReadings",
+                "siteId": site_id,
+                "after": { "imported": imported, "rejected": 1 },
+            }])
+        );
+    }
+}
+
+#[tokio::test]
+async fn import_sensor_readings_lists_the_first_rejected_lines() {
+    let mut config = Config::default();
+    config.rate_limits.ingest_burst = 150;
+    let app = TestApp::with_config(config).await;
+    app.fixture().await;
+    let mut csv = "device,value,unit,timestamp\n".to_string();
+    for _ in 0..150 {
+        csv.push_str("sensor-1,warm,C,2025-01-01T00:00:00Z\n");
+    }
+
+    let (status, body) = app
+        .upload(
+            &app.admin_token,
+            "mutation($file: Upload!) { importSensorReadings(file: $file) { imported rejected { line } rejectedCount } }",
+            "readings.csv",
+            &csv,
+        )
+        .await;
+    assert_eq!(status, Status::Ok);
+    let report = &body["data"]["importSensorReadings"];
+    assert_eq!(report["imported"], 0);
+    assert_eq!(report["rejectedCount"], 150);
+    let rejected = report["rejected"].as_array().unwrap();
+    assert_eq!(rejected.len(), 100);
+    assert_eq!(rejected[0]["line"], 2);
+    assert_eq!(rejected[99]["line"], 101);
+}
+
+#[tokio::test]
+async fn imported_readings_sort_by_time_with_live_ones() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    app.create_reading(fixture.device_id, "21").await;
+    // Earlier the same day, which sorted after readings recorded live when compared as stored.
+    let earlier = Utc::now()
+        .date_naive()
+        .and_hms_micro_opt(0, 0, 1, 250_000)
+        .unwrap()
+        .and_utc();
+    let csv = format!(
+        "device,value,timestamp\nsensor-1,19,{}\n",
+        earlier.to_rfc3339()
+    );
+    let (_, body) = app
+        .upload(
+            &app.admin_token,
+            "mutation($file: Upload!) { importSensorReadings(file: $file) { imported } }",
+            "readings.csv",
+            &csv,
+        )
+        .await;
+    assert_eq!(body["data"]["importSensorReadings"]["imported"], 1);
+
+    let data = app
+        .admin(
+            r#"
+            query($id: Int!, $roomId: Int!) {
+                latestSensorReading(deviceId: $id) { value }
+                room(id: $roomId) { devices { sensorReadings { value timestamp } } }
+            }
+            "#,
+            json!({ "id": fixture.device_id, "roomId": fixture.room_id }),
+        )
+        .await;
+    assert_eq!(data["latestSensorReading"]["value"], "21");
+    let readings = data["room"]["devices"][0]["sensorReadings"]
+        .as_array()
+        .unwrap();
+    assert_eq!(readings[0]["value"], "19");
+    assert_eq!(
+        readings[0]["timestamp"],
+        earlier.to_rfc3339_opts(SecondsFormat::AutoSi, false)
+    );
+    assert_eq!(readings[1]["value"], "21");
+}
+
+#[tokio::test]
+async fn import_sensor_readings_needs_the_required_columns() {
+    let app = TestApp::new().await;
+    let (_, body) = app
+        .upload(
+            &app.admin_token,
+            "mutation($file: Upload!) { importSensorReadings(file: $file) { imported } }",
+            "readings.csv",
+            "device,value\nsensor-1,20.5\n",
+        )
+        .await;
+    assert_eq!(
+        body["errors"][0]["message"],
+        "The file has no \"timestamp\" column"
+    );
+}
+
+#[tokio::test]
+async fn exports_keep_the_recorded_timestamps() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let csv = "device,value,timestamp\n\
+               sensor-1,20.5,2025-01-01T10:00:00.123456Z\n\
+               sensor-1,21.5,2025-01-01T10:00:00.5Z\n";
+    let (_, body) = app
+        .upload(
+            &app.admin_token,
+            "mutation($file: Upload!) { importSensorReadings(file: $file) { imported } }",
+            "readings.csv",
+            csv,
+        )
+        .await;
+    assert_eq!(body["data"]["importSensorReadings"]["imported"], 2);
+
+    let response = app
+        .client
+        .get(format!(
+            "/export/readings?site_id={}&format=jsonl",
+            fixture.site_id
+        ))
+        .header(Header::new(
+            "Authorization",
+            format!("Bearer {}", app.admin_token),
+        ))
+        .dispatch()
+        .await;
+    let body = response.into_string().await.unwrap();
+    let timestamps: Vec<_> = body
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["timestamp"].clone())
+        .collect();
+    assert_eq!(
+        timestamps,
+        ["2025-01-01T10:00:00.123456Z", "2025-01-01T10:00:00.500Z"]
+    );
+
+    let output = std::env::temp_dir().join(format!("sh-backend-export-{}", std::process::id()));
+    let partitions = export_parquet(&app.database, &ExportFilter::default(), &output)
+        .await
+        .unwrap();
+    assert_eq!(partitions.len(), 1);
+    let file = std::fs::File::open(&partitions[0].path).unwrap();
+    let batch = ParquetRecordBatchReaderBuilder::try_new(file)
+        .unwrap()
+        .build()
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    let column = batch.column_by_name("timestamp").unwrap();
+    let timestamps = column
+        .as_any()
+        .downcast_ref::<TimestampMicrosecondArray>()
+        .unwrap();
+    assert_eq!(
+        timestamps.values().to_vec(),
+        [1_735_725_600_123_456, 1_735_725_600_500_000]
+    );
+    std::fs::remove_dir_all(output).unwrap();
+}
@@ -0,0 +1,318 @@
+mod common;
+
+use common::{TestApp, data, error, error_code};
+use serde_json::{Value, json};
+use sh_backend::models::Role;
+
+const SET_TAG: &str = r#"
+    mutation($entity: TaggedEntity!, $id: Int!, $key: String!, $value: String) {
+        setTag(entity: $entity, entityId: $id, key: $key, value: $value) { key value }
+    }
+"#;
+
+impl TestApp {
+    async fn tag(&self, entity: &str, id: i64, key: &str, value: Option<&str>) -> Value {
+        let data = self
+            .admin(
+                SET_TAG,
+                json!({ "entity": entity, "id": id, "key": key, "value": value }),
+            )
+            .await;
+        data["setTag"].clone()
+    }
+}
+
+#[tokio::test]
+async fn sites_rooms_and_devices_carry_tags() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    app.tag("SITE", fixture.site_id, "region", Some("west"))
+        .await;
+    app.tag("ROOM", fixture.room_id, "north-facing", None).await;
+    app.tag("DEVICE", fixture.device_id, "vendor", Some("Acme"))
+        .await;
+    // Tagging with a key again replaces its value.
+    let replaced = app
+        .tag("DEVICE", fixture.device_id, "vendor", Some("Danfoss"))
+        .await;
+    assert_eq!(replaced, json!({ "key": "vendor", "value": "Danfoss" }));
+    app.tag("DEVICE", fixture.device_id, "warranty-2027", None)
+        .await;
+
+    let data = app
+        .admin(
+            r#"
+            query($id: Int!) {
+                site(id: $id) {
+                    tags { key value }
+                    rooms { tags { key value } devices { tags { key value } } }
+                }
+            }
+            "#,
+            json!({ "id": fixture.site_id }),
+        )
+        .await;
+    assert_eq!(
+        data["site"],
+        json!({
+            "tags": [{ "key": "region", "value": "west" }],
+            "rooms": [{
+                "tags": [{ "key": "north-facing", "value": null }],
+                "devices": [{
+                    "tags": [
+                        { "key": "vendor", "value": "Danfoss" },
+                        { "key": "warranty-2027", "value": null },
+                    ],
+                }],
+            }],
+        })
+    );
+
+    let removed = app
+        .admin(
+            r#"
+            mutation($id: Int!) {
+                first: removeTag(entity: DEVICE, entityId: $id, key: "vendor")
+                again: removeTag(entity: DEVICE, entityId: $id, key: "vendor")
+            }
+            "#,
+            json!({ "id": fixture.device_id }),
+        )
+        .await;
+    assert_eq!(removed, json!({ "first": true, "again": false }));
+}
+
+#[tokio::test]
+async fn sites_and_devices_are_filtered_by_tags() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let other_site = app.create_site("Other").await;
+    let other_device = app.create_device(fixture.room_id, "sensor-2").await;
+    app.tag("SITE", fixture.site_id, "region", Some("west"))
+        .await;
+    app.tag("SITE", other_site, "region", Some("east")).await;
+    app.tag("DEVICE", fixture.device_id, "vendor", Some("Danfoss"))
+        .await;
+    app.tag("DEVICE", fixture.device_id, "north-facing", None)
+        .await;
+    app.tag("DEVICE", other_device, "vendor", Some("Acme"))
+        .await;
+
+    let sites = |tags: Value| {
+        let app = &app;
+        async move {
+            let data = app
+                .admin(
+                    "query($tags: [TagFilter!]!) { sites(tags: $tags) { name } }",
+                    json!({ "tags": tags }),
+                )
+                .await;
+            data["sites"].clone()
+        }
+    };
+    assert_eq!(
+        sites(json!([{ "key": "region" }])).await,
+        json!([{ "name": "Site" }, { "name": "Other" }])
+    );
+    assert_eq!(
+        sites(json!([{ "key": "region", "value": "east" }])).await,
+        json!([{ "name": "Other" }])
+    );
+    assert_eq!(sites(json!([{ "key": "vendor" }])).await, json!([]));
+
+    let devices = |tags: Value| {
+        let app = &app;
+        async move {
+            let data = app
+                .admin(
+                    "query($roomId: Int!, $tags: [TagFilter!]!) { devicesInRoom(roomId: $roomId, tags: $tags) { id } }",
+                    json!({ "roomId": fixture.room_id, "tags": tags }),
+                )
+                .await;
+            data["devicesInRoom"].clone()
+        }
+    };
+    assert_eq!(
+        devices(json!([{ "key": "vendor" }])).await,
+        json!([{ "id": fixture.device_id }, { "id": other_device }])
+    );
+    assert_eq!(
+        devices(json!([{ "key": "vendor", "value": "Danfoss" }, { "key": "north-facing" }])).await,
+        json!([{ "id": fixture.device_id }])
+    );
+    assert_eq!(
+        devices(json!([{ "key": "vendor", "value": "Acme" }, { "key": "north-facing" }])).await,
+        json!([])
+    );
+}
+
+#[tokio::test]
+async fn metadata_is_replaced_as_a_whole() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let metadata = json!({ "vendor": { "name": "Danfoss", "model": "Ally" }, "installed": 2024 });
+
+    let data = app
+        .admin(
+            r#"
+            mutation($siteId: Int!, $roomId: Int!, $deviceId: Int!, $metadata: JSON!) {
+                setSiteMetadata(siteId: $siteId, metadata: { notes: "Loading dock" }) { metadata }
+                setRoomMetadata(roomId: $roomId, metadata: { area: 42 }) { metadata }
+                setDeviceMetadata(deviceId: $deviceId, metadata: $metadata) { metadata }
+            }
+            "#,
+            json!({
+                "siteId": fixture.site_id,
+                "roomId": fixture.room_id,
+                "deviceId": fixture.device_id,
+                "metadata": metadata,
+            }),
+        )
+        .await;
+    assert_eq!(
+        data,
+        json!({
+            "setSiteMetadata": { "metadata": { "notes": "Loading dock" } },
+            "setRoomMetadata": { "metadata": { "area": 42 } },
+            "setDeviceMetadata": { "metadata": metadata },
+        })
+    );
+
+    let data = app
+        .admin(
+            "query($id: Int!) { site(id: $id) { metadata rooms { devices { metadata } } } }",
+            json!({ "id": fixture.site_id }),
+        )
+        .await;
+    assert_eq!(data["site"]["metadata"], json!({ "notes": "Loading dock" }));
+    assert_eq!(data["site"]["rooms"][0]["devices"][0]["metadata"], metadata);
+
+    let response = app
+        .as_user(
+            &app.admin,
+            "mutation($id: Int!) { setDeviceMetadata(deviceId: $id, metadata: [1, 2]) { id } }",
+            json!({ "id": fixture.device_id }),
+        )
+        .await;
+    assert_eq!(error(response), "Metadata has to be a JSON object");
+}
+
+#[tokio::test]
+async fn new_entities_have_empty_metadata() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let data = app
+        .admin(
+            "query($id: Int!) { room(id: $id) { metadata tags { key } } }",
+            json!({ "id": fixture.room_id }),
+        )
+        .await;
+    assert_eq!(data["room"], json!({ "metadata": {}, "tags": [] }));
+}
+
+#[tokio::test]
+async fn tags_are_validated_and_audited() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+
+    let response = app
+        .as_user(
+            &app.admin,
+            SET_TAG,
+            json!({ "entity": "ROOM", "id": fixture.room_id, "key": " " }),
+        )
+        .await;
+    assert_eq!(error(response), "Tag keys cannot be empty");
+    let response = app
+        .as_user(
+            &app.admin,
+            SET_TAG,
+            json!({ "entity": "ROOM", "id": 404, "key": "north-facing" }),
+        )
+        .await;
+    assert_eq!(error(response), "Room with ID 404 does not exist");
+
+    app.tag("ROOM", fixture.room_id, "floor", Some("1")).await;
+    app.tag("ROOM", fixture.room_id, "floor", Some("2")).await;
+    let data = app
+        .admin(
+            r#"
+            query($siteId: Int!, $roomId: Int!) {
+                auditLogs(filter: { siteId: $siteId, entityType: ROOM, entityId: $roomId }) { operation before after }
+            }
+            "#,
+            json!({ "siteId": fixture.site_id, "roomId": fixture.room_id }),
+        )
+        .await;
+    assert_eq!(data["auditLogs"][0]["operation"], "setTag");
+    assert_eq!(
+        data["auditLogs"][0]["before"],
+        json!({ "key": "floor", "value": "1" })
+    );
+    assert_eq!(
+        data["auditLogs"][0]["after"],
+        json!({ "key": "floor", "value": "2" })
+    );
+}
+
+#[tokio::test]
+async fn only_site_admins_tag_their_site() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+    let (operator, _) = app.create_user("Operator").await;
+    app.grant(&operator, fixture.site_id, Role::Operator).await;
+
+    let response = app
+        .as_user(
+            &operator,
+            SET_TAG,
+            json!({ "entity": "DEVICE", "id": fixture.device_id, "key": "vendor", "value": "Acme" }),
+        )
+        .await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+
+    app.tag("SITE", fixture.site_id, "region", Some("west"))
+        .await;
+    let visible = data(
+        app.as_user(
+            &operator,
+            r#"{ sites(tags: [{ key: "region" }]) { name tags { value } } }"#,
+            json!({}),
+        )
+        .await,
+    );
+    assert_eq!(
+        visible["sites"],
+        json!([{ "name": "Site", "tags": [{ "value": "west" }] }])
+    );
+}
+
+#[tokio::test]
+async fn tag_filters_and_metadata_are_bounded() {
+    let app = TestApp::new().await;
+    let fixture = app.fixture().await;
+
+    let tags: Vec<Value> = (0..11)
+        .map(|index| json!({ "key": format!("key-{}", index) }))
+        .collect();
+    let response = app
+        .as_user(
+            &app.admin,
+            "query($tags: [TagFilter!]!) { sites(tags: $tags) { id } }",
+            json!({ "tags": tags }),
+        )
+        .await;
+    assert_eq!(error(response), "Cannot filter by more than 10 tags");
+
+    let response = app
+        .as_user(
+            &app.admin,
+            "mutation($id: Int!, $metadata: JSON!) { setDeviceMetadata(deviceId: $id, metadata: $metadata) { id } }",
+            json!({ "id": fixture.device_id, "metadata": { "notes": "x".repeat(16 * 1024) } }),
+        )
+        .await;
+    assert_eq!(
+        error(response),
+        "Metadata cannot take more than 16384 bytes as JSON"
+    );
+}
@@ -0,0 +1,390 @@
+mod common;
+
+use chrono::Utc;
+use common::{TestApp, data, error, error_code, id};
+use serde_json::{Value, json};
+use sh_backend::auth::Identity;
+use sh_backend::events::{Event, EventBus};
+use sh_backend::models::{Role, SensorReading, WebhookDeliveryStatus, WebhookEventType};
+use sh_backend::webhooks::WebhookDispatcher;
+
+const CREATE_SUBSCRIPTION: &str = r#"
+    mutation($input: WebhookSubscriptionInput!) {
+        createWebhookSubscription(input: $input) { secret subscription { id siteId url eventTypes enabled } }
+    }
+"#;
+
+const UPDATE_SUBSCRIPTION: &str = r#"
+    mutation($id: Int!, $input: WebhookSubscriptionInput!) {
+        updateWebhookSubscription(id: $id, input: $input) { id url eventTypes enabled }
+    }
+"#;
+
+/// A public address, as webhook hosts are resolved and the tests run without DNS.
+const HOOK_URL: &str = "https://203.0.113.10/hook";
+
+fn subscription_input(site_id: Option<i64>) -> Value {
+    json!({ "input": {
+        "siteId": site_id,
+        "url": HOOK_URL,
+        "eventTypes": ["SENSOR_READING_CREATED"],
+    } })
+}
+
+async fn insert_delivery(app: &TestApp, subscription_id: i64, status: WebhookDeliveryStatus) {
+    sqlx::query(
+        "INSERT INTO WebhookDelivery (subscription_id, event_type, payload, status) VALUES (?, ?, '{}', ?)",
+    )
+    .bind(subscription_id)
+    .bind(WebhookEventType::SensorReadingCreated)
+    .bind(status)
+    .execute(app.pool())
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn site_admins_subscribe_their_site() {
+    let app = TestApp::new().await;
+    let site_id = app.create_site("Site").await;
+    let (site_admin, _) = app.create_user("Site admin").await;
+    app.grant(&site_admin, site_id, Role::Admin).await;
+
+    let response = app
+        .as_user(
+            &site_admin,
+            CREATE_SUBSCRIPTION,
+            subscription_input(Some(site_id)),
+        )
+        .await;
+    let created = data(response)["createWebhookSubscription"].clone();
+    assert_eq!(created["secret"].as_str().unwrap().len(), 64);
+    assert_eq!(
+        created["subscription"],
+        json!({
+            "id": id(&created["subscription"]),
+            "siteId": site_id,
+            "url": HOOK_URL,
+            "eventTypes": ["SENSOR_READING_CREATED"],
+            "enabled": true,
+        })
+    );
+
+    let response = app
+        .as_user(
+            &site_admin,
+            "query($siteId: Int) { webhookSubscriptions(siteId: $siteId) { id } }",
+            json!({ "siteId": site_id }),
+        )
+        .await;
+    assert_eq!(
+        data(response)["webhookSubscriptions"],
+        json!([{ "id": id(&created["subscription"]) }])
+    );
+}
+
+#[tokio::test]
+async fn global_subscriptions_need_an_administrator() {
+    let app = TestApp::new().await;
+    let site_id = app.create_site("Site").await;
+    let (site_admin, _) = app.create_user("Site admin").await;
+    app.grant(&site_admin, site_id, Role::Admin).await;
+
+    let response = app
+        .as_user(&site_admin, CREATE_SUBSCRIPTION, subscription_input(None))
+        .await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+    let response = app
+        .as_user(&site_admin, "{ webhookSubscriptions { id } }", json!({}))
+        .await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+
+    let data = app
+        .admin(CREATE_SUBSCRIPTION, subscription_input(None))
+        .await;
+    assert_eq!(
+        data["createWebhookSubscription"]["subscription"]["siteId"],
+        json!(null)
+    );
+}
+
+#[tokio::test]
+async fn create_webhook_subscription_validates_the_input() {
+    let app = TestApp::new().await;
+    let admin = || Identity::User(app.admin.clone());
+
+    let mut input = subscription_input(None);
+    input["input"]["url"] = json!("ftp://example.com/hook");
+    let response = app.execute(admin(), CREATE_SUBSCRIPTION, input).await;
+    assert_eq!(error(response), "Webhook URLs have to use http or https");
+
+    let mut input = subscription_input(None);
+    input["input"]["eventTypes"] = json!([]);
+    let response = app.execute(admin(), CREATE_SUBSCRIPTION, input).await;
+    assert_eq!(
+        error(response),
+        "Webhook subscriptions need at least one event type"
+    );
+}
+
+#[tokio::test]
+async fn update_and_delete_webhook_subscriptions() {
+    let app = TestApp::new().await;
+    let site_id = app.create_site("Site").await;
+    let other_site = app.create_site("Other").await;
+    let data = app
+        .admin(CREATE_SUBSCRIPTION, subscription_input(Some(site_id)))
+        .await;
+    let subscription_id = id(&data["createWebhookSubscription"]["subscription"]);
+
+    let mut input = subscription_input(Some(site_id));
+    input["id"] = json!(subscription_id);
+    input["input"]["eventTypes"] = json!(["ALERT_RAISED", "CONTROL_SETPOINT_CREATED"]);
+    input["input"]["enabled"] = json!(false);
+    let data = app.admin(UPDATE_SUBSCRIPTION, input).await;
+    assert_eq!(
+        data["updateWebhookSubscription"],
+        json!({
+            "id": subscription_id,
+            "url": HOOK_URL,
+            "eventTypes": ["ALERT_RAISED", "CONTROL_SETPOINT_CREATED"],
+            "enabled": false,
+        })
+    );
+
+    let mut input = subscription_input(Some(other_site));
+    input["id"] = json!(subscription_id);
+    let response = app
+        .execute(
+            Identity::User(app.admin.clone()),
+            UPDATE_SUBSCRIPTION,
+            input,
+        )
+        .await;
+    assert_eq!(
+        error(response),
+        "Webhook subscriptions cannot be moved to another site"
+    );
+
+    let delete = "mutation($id: Int!) { deleteWebhookSubscription(id: $id) }";
+    let data = app.admin(delete, json!({ "id": subscription_id })).await;
+    assert_eq!(data["deleteWebhookSubscription"], true);
+    let response = app
+        .execute(
+            Identity::User(app.admin.clone()),
+            delete,
+            json!({ "id": subscription_id }),
+        )
+        .await;
+    assert_eq!(
+        error(response),
+        format!(
+            "Webhook subscription with ID {} does not exist",
+            subscription_id
+        )
+    );
+}
+
+#[tokio::test]
+async fn webhook_deliveries_are_filtered_by_status() {
+    let app = TestApp::new().await;
+    let site_id = app.create_site("Site").await;
+    let data = app
+        .admin(CREATE_SUBSCRIPTION, subscription_input(Some(site_id)))
+        .await;
+    let subscription_id = id(&data["createWebhookSubscription"]["subscription"]);
+    insert_delivery(&app, subscription_id, WebhookDeliveryStatus::Succeeded).await;
+    insert_delivery(&app, subscription_id, WebhookDeliveryStatus::Failed).await;
+    insert_delivery(&app, subscription_id, WebhookDeliveryStatus::Pending).await;
+
+    let deliveries = r#"
+        query($id: Int!, $status: WebhookDeliveryStatus, $limit: Int! = 50) {
+            webhookDeliveries(subscriptionId: $id, status: $status, limit: $limit) { status eventType attempts }
+        }
+    "#;
+    let data = app
+        .admin(
+            deliveries,
+            json!({ "id": subscription_id, "status": "FAILED" }),
+        )
+        .await;
+    assert_eq!(
+        data["webhookDeliveries"],
+        json!([{ "status": "FAILED", "eventType": "SENSOR_READING_CREATED", "attempts": 0 }])
+    );
+
+    let data = app
+        .admin(
+            deliveries,
+            json!({ "id": subscription_id, "status": null, "limit": 2 }),
+        )
+        .await;
+    let statuses: Vec<_> = data["webhookDeliveries"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|delivery| delivery["status"].clone())
+        .collect();
+    assert_eq!(statuses, vec![json!("PENDING"), json!("FAILED")]);
+}
+
+#[tokio::test]
+async fn webhook_deliveries_need_admin_rights_on_the_site() {
+    let app = TestApp::new().await;
+    let site_id = app.create_site("Site").await;
+    let data = app
+        .admin(CREATE_SUBSCRIPTION, subscription_input(Some(site_id)))
+        .await;
+    let subscription_id = id(&data["createWebhookSubscription"]["subscription"]);
+    let (operator, _) = app.create_user("Operator").await;
+    app.grant(&operator, site_id, Role::Operator).await;
+
+    let deliveries = "query($id: Int!) { webhookDeliveries(subscriptionId: $id) { id } }";
+    let response = app
+        .as_user(&operator, deliveries, json!({ "id": subscription_id }))
+        .await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+
+    // Whether the subscription exists or not, the operator learns nothing.
+    let missing = json!({ "id": 404 });
+    let response = app.as_user(&operator, deliveries, missing.clone()).await;
+    assert_eq!(error_code(response), "FORBIDDEN");
+    let existing = app
+        .as_user(&operator, deliveries, json!({ "id": subscription_id }))
+        .await;
+    let response = app.as_user(&operator, deliveries, missing).await;
+    assert_eq!(
+        error(existing),
+        error(response).replace("404", &subscription_id.to_string())
+    );
+}
+
+#[tokio::test]
+async fn webhooks_cannot_point_to_private_addresses() {
+    let app = TestApp::new().await;
+    for url in [
+        "http://127.0.0.1:8000/graphql",
+        "http://localhost/hook",
+        "http://10.0.0.1/hook",
+        "http://192.168.1.1/hook",
+        "http://169.254.169.254/latest/meta-data",
+        "http://[::1]/hook",
+        "http://[::ffff:127.0.0.1]/hook",
+        "http://[fd00::1]/hook",
+    ] {
+        let mut input = subscription_input(None);
+        input["input"]["url"] = json!(url);
+        let response = app
+            .execute(
+                Identity::User(app.admin.clone()),
+                CREATE_SUBSCRIPTION,
+                input,
+            )
+            .await;
+        assert_eq!(
+            error(response),
+            "Webhook URLs cannot point to private, loopback or link-local addresses",
+            "{}",
+            url
+        );
+    }
+}
+
+#[tokio::test]
+async fn deliveries_of_disabled_subscriptions_are_dropped() {
+    let app = TestApp::new().await;
+    let data = app
+        .admin(CREATE_SUBSCRIPTION, subscription_input(None))
+        .await;
+    let subscription_id = id(&data["createWebhookSubscription"]["subscription"]);
+    let dispatcher = WebhookDispatcher::new(app.database.clone(), EventBus::new(), 3).unwrap();
+    let now = Utc::now();
+    let event = Event::SensorReadingCreated {
+        site_id: 1,
+        reading: SensorReading {
+            id: 1,
+            device_id: 1,
+            value: "21.5".to_string(),
+            unit: None,
+            timestamp: now,
+            created_at: now,
+            updated_at: now,
+        },
+    };
+    assert_eq!(dispatcher.enqueue(&event).await.unwrap(), 1);
+
+    let mut input = subscription_input(None);
+    input["input"]["enabled"] = json!(false);
+    input["id"] = json!(subscription_id);
+    app.admin(UPDATE_SUBSCRIPTION, input).await;
+
+    assert_eq!(dispatcher.deliver_due(Utc::now()).await.unwrap(), 1);
+    let (status, attempts, last_error): (WebhookDeliveryStatus, i64, String) =
+        sqlx::query_as("SELECT status, attempts, last_error FROM WebhookDelivery")
+            .fetch_one(app.pool())
+            .await
+            .unwrap();
+    assert_eq!(status, WebhookDeliveryStatus::Failed);
+    assert_eq!(attempts, 0);
+    assert_eq!(last_error, "The subscription is disabled");
+}
+
+#[tokio::test]
+async fn a_failing_delivery_does_not_stop_the_others() {
+    let app = TestApp::new().await;
+    let mut subscription_ids = Vec::new();
+    for _ in 0..3 {
+        let data = app
+            .admin(CREATE_SUBSCRIPTION, subscription_input(None))
+            .await;
+        subscription_ids.push(id(&data["createWebhookSubscription"]["subscription"]));
+    }
+    let dispatcher = WebhookDispatcher::new(app.database.clone(), EventBus::new(), 3).unwrap();
+    let now = Utc::now();
+    let event = Event::SensorReadingCreated {
+        site_id: 1,
+        reading: SensorReading {
+            id: 1,
+            device_id: 1,
+            value: "21.5".to_string(),
+            unit: None,
+            timestamp: now,
+            created_at: now,
+            updated_at: now,
+        },
+    };
+    assert_eq!(dispatcher.enqueue(&event).await.unwrap(), 3);
+
+    // Disabled subscriptions keep the endpoints from being called.
+    for subscription_id in &subscription_ids {
+        let mut input = subscription_input(None);
+        input["input"]["enabled"] = json!(false);
+        input["id"] = json!(subscription_id);
+        app.admin(UPDATE_SUBSCRIPTION, input).await;
+    }
+    // The delivery of the second subscription cannot be recorded.
+    sqlx::query(&format!(
+        "CREATE TRIGGER fail_delivery BEFORE UPDATE ON WebhookDelivery WHEN OLD.subscription_id = {} \
+         BEGIN SELECT RAISE(ABORT, 'unavailable'); END",
+        subscription_ids[1]
+    ))
+    .execute(app.pool())
+    .await
+    .unwrap();
+
+    assert_eq!(dispatcher.deliver_due(Utc::now()).await.unwrap(), 3);
+    let statuses: Vec<(i64, WebhookDeliveryStatus)> = sqlx::query_as(
+        "SELECT subscription_id, status FROM WebhookDelivery ORDER BY subscription_id",
+    )
+    .fetch_all(app.pool())
+    .await
+    .unwrap();
+    assert_eq!(
+        statuses,
+        vec![
+            (subscription_ids[0], WebhookDeliveryStatus::Failed),
+            (subscription_ids[1], WebhookDeliveryStatus::Pending),
+            (subscription_ids[2], WebhookDeliveryStatus::Failed),
+        ]
+    );
+}
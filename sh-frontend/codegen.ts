@@ -2,12 +2,17 @@ import type { CodegenConfig } from "@graphql-codegen/cli";
 
 const config: CodegenConfig = {
   overwrite: true,
-  schema: "http://localhost:8000/graphql",
+  // Generated by the backend with `cargo run -- export-schema --output schema.graphql`
+  schema: "../sh-backend/schema.graphql",
   documents: ["src/**/*.{ts,tsx}"],
   generates: {
     "./src/gql/": {
       preset: "client",
       plugins: [],
+      presetConfig: {
+        // Writes src/gql/persisted-documents.json, the backend's graphql.operation_allowlist
+        persistedDocuments: { hashAlgorithm: "sha256" },
+      },
     },
   },
 };
@@ -13,6 +13,10 @@ export const Route = createFileRoute("/")({
   component: App,
 });
 
+const requestHeaders = {
+  Authorization: `Bearer ${import.meta.env.VITE_API_TOKEN}`,
+};
+
 const GET_SITES = graphql(`
   query GetSites {
     sites {
@@ -69,10 +73,11 @@ function App() {
   } = useQuery({
     queryKey: ["sites"],
     queryFn: async () => {
-      const result = await request(
-        import.meta.env.VITE_GRAPHQL_ENDPOINT,
-        GET_SITES,
-      );
+      const result = await request({
+        url: import.meta.env.VITE_GRAPHQL_ENDPOINT,
+        document: GET_SITES,
+        requestHeaders,
+      });
       return result.sites;
     },
   });
@@ -84,13 +89,14 @@ function App() {
   } = useQuery({
     queryKey: ["site", selectedSiteId],
     queryFn: async () => {
-      const result = await request(
-        import.meta.env.VITE_GRAPHQL_ENDPOINT,
-        GET_SITE_WITH_ALL_MODELS,
-        {
+      const result = await request({
+        url: import.meta.env.VITE_GRAPHQL_ENDPOINT,
+        document: GET_SITE_WITH_ALL_MODELS,
+        variables: {
           siteId: selectedSiteId as number,
         },
-      );
+        requestHeaders,
+      });
       return result.site;
     },
     enabled: selectedSiteId !== null,
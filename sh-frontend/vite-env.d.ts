@@ -8,6 +8,7 @@ interface ViteTypeOptions {
 
 interface ImportMetaEnv {
   readonly VITE_GRAPHQL_ENDPOINT: string;
+  readonly VITE_API_TOKEN: string;
 }
 
 interface ImportMeta {